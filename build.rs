@@ -0,0 +1,15 @@
+//! Generates the messages and the server of `proto/rinha.proto`, parsed with `protox` so
+//! that the build doesn't need `protoc`.
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let descriptors = protox::compile(["rinha.proto"], ["proto"])?;
+
+    tonic_build::configure()
+        .build_client(false)
+        .build_transport(false)
+        .compile_fds(descriptors)?;
+
+    println!("cargo:rerun-if-changed=proto");
+
+    Ok(())
+}
@@ -0,0 +1,18 @@
+-- IF NOT EXISTS adopts databases created by the former sql/init.sql
+CREATE UNLOGGED TABLE IF NOT EXISTS clientes (
+	id SMALLSERIAL PRIMARY KEY,
+	saldo INTEGER NOT NULL,
+	limite INTEGER NOT NULL
+);
+
+CREATE UNLOGGED TABLE IF NOT EXISTS transacoes (
+  id SERIAL PRIMARY KEY,
+  cliente_id SMALLINT NOT NULL,
+  valor SMALLINT NOT NULL,
+  tipo CHAR(1) NOT NULL,
+  descricao VARCHAR(10) NOT NULL,
+  realizada_em TIMESTAMP NOT NULL DEFAULT NOW(),
+  CONSTRAINT chave_cliente_id FOREIGN KEY (cliente_id) REFERENCES clientes(id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_transacoes_cliente_id ON transacoes (cliente_id);
@@ -1,20 +1,26 @@
-CREATE UNLOGGED TABLE clientes (
-	id SMALLSERIAL PRIMARY KEY,
-	saldo INTEGER NOT NULL,
-	limite INTEGER NOT NULL
-);
-
-CREATE UNLOGGED TABLE transacoes (
-  id SERIAL PRIMARY KEY,
-  cliente_id SMALLINT NOT NULL,
-  valor SMALLINT NOT NULL,
-  tipo CHAR(1) NOT NULL,
-  descricao VARCHAR(10) NOT NULL,
-  realizada_em TIMESTAMP NOT NULL DEFAULT NOW(),
-  CONSTRAINT chave_cliente_id FOREIGN KEY (cliente_id) REFERENCES clientes(id)
-);
-
-CREATE INDEX idx_transacoes_cliente_id ON transacoes (cliente_id);
+-- notify other api instances that a client's balance changed, delivered on commit
+CREATE OR REPLACE FUNCTION notificar(
+  param_tipo CHAR(1),
+  param_cliente_id SMALLINT,
+  param_valor SMALLINT,
+  param_descricao VARCHAR(10),
+  param_saldo INTEGER,
+  param_limite INTEGER
+)
+RETURNS VOID
+AS $$
+BEGIN
+  PERFORM pg_notify('transacoes', json_build_object(
+    'cliente_id', param_cliente_id,
+    'valor', param_valor,
+    'tipo', param_tipo,
+    'descricao', param_descricao,
+    'saldo', param_saldo,
+    'limite', param_limite,
+    'origem', current_setting('application_name')
+  )::text);
+END;
+$$ LANGUAGE plpgsql;
 
 CREATE OR REPLACE FUNCTION debitar(
   param_cliente_id SMALLINT,
@@ -62,6 +68,8 @@ BEGIN
       NOW()
     );
 
+    PERFORM notificar('d', param_cliente_id, param_valor, param_descricao, resultado_saldo, resultado_limite);
+
     resultado_codigo := 0; -- success
   END IF;
 END;
@@ -108,14 +116,8 @@ BEGIN
     NOW()
   );
 
+  PERFORM notificar('c', param_cliente_id, param_valor, param_descricao, resultado_saldo, resultado_limite);
+
   resultado_codigo := 0; -- success
 END;
 $$ LANGUAGE plpgsql;
-
-BEGIN;
-  INSERT INTO clientes (id, saldo, limite) VALUES (1, 0, 100000);
-  INSERT INTO clientes (id, saldo, limite) VALUES (2, 0, 80000);
-  INSERT INTO clientes (id, saldo, limite) VALUES (3, 0, 1000000);
-  INSERT INTO clientes (id, saldo, limite) VALUES (4, 0, 10000000);
-  INSERT INTO clientes (id, saldo, limite) VALUES (5, 0, 500000);
-END;
@@ -0,0 +1,182 @@
+-- logged, unlike the other tables: the audit trail has to survive a crash
+CREATE TABLE auditoria (
+  id BIGSERIAL PRIMARY KEY,
+  realizada_em TIMESTAMP NOT NULL DEFAULT NOW(),
+  request_id TEXT,
+  ip INET,
+  principal TEXT,
+  cliente_id SMALLINT NOT NULL,
+  payload TEXT NOT NULL,
+  resultado_codigo SMALLINT NOT NULL,
+  saldo_antes INTEGER,
+  saldo_depois INTEGER
+);
+
+CREATE INDEX idx_auditoria_cliente_id ON auditoria (cliente_id, id);
+
+CREATE OR REPLACE FUNCTION auditoria_imutavel()
+RETURNS TRIGGER
+AS $$
+BEGIN
+  RAISE EXCEPTION 'auditoria is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER auditoria_imutavel
+BEFORE UPDATE OR DELETE OR TRUNCATE ON auditoria
+FOR EACH STATEMENT EXECUTE FUNCTION auditoria_imutavel();
+
+CREATE OR REPLACE FUNCTION auditar(
+  param_cliente_id SMALLINT,
+  param_request_id TEXT,
+  param_ip INET,
+  param_principal TEXT,
+  param_payload TEXT,
+  param_codigo SMALLINT,
+  param_saldo_antes INTEGER,
+  param_saldo_depois INTEGER
+)
+RETURNS VOID
+AS $$
+BEGIN
+  INSERT INTO auditoria (
+    request_id,
+    ip,
+    principal,
+    cliente_id,
+    payload,
+    resultado_codigo,
+    saldo_antes,
+    saldo_depois)
+  VALUES (
+    param_request_id,
+    param_ip,
+    param_principal,
+    param_cliente_id,
+    param_payload,
+    param_codigo,
+    param_saldo_antes,
+    param_saldo_depois
+  );
+END;
+$$ LANGUAGE plpgsql;
+
+-- the audited versions take who asked and what they sent
+DROP FUNCTION debitar(SMALLINT, SMALLINT, VARCHAR);
+DROP FUNCTION creditar(SMALLINT, SMALLINT, VARCHAR);
+
+CREATE FUNCTION debitar(
+  param_cliente_id SMALLINT,
+  param_valor SMALLINT,
+  param_descricao VARCHAR(10),
+  param_request_id TEXT,
+  param_ip INET,
+  param_principal TEXT,
+  param_payload TEXT,
+  OUT resultado_codigo SMALLINT,
+  OUT resultado_saldo INTEGER,
+  OUT resultado_limite INTEGER
+)
+AS $$
+DECLARE
+  saldo_antes INTEGER;
+BEGIN
+  -- initialize out parameters
+  resultado_codigo := 0; -- assume success
+  resultado_saldo := NULL;
+  resultado_limite := NULL;
+
+  -- check if the client exists and fetch their balance_limit
+  SELECT saldo, limite INTO saldo_antes, resultado_limite FROM clientes WHERE id = param_cliente_id;
+
+  IF resultado_limite IS NULL THEN
+    resultado_codigo := 1; -- client does not exist
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, NULL, NULL);
+    RETURN;
+  END IF;
+
+  -- attempt to update the client's balance only if the new balance is within limits
+  UPDATE clientes SET saldo = saldo - param_valor
+  WHERE id = param_cliente_id AND saldo - param_valor >= -limite
+  RETURNING saldo INTO resultado_saldo;
+
+  -- insert the record if the update was successful
+  IF NOT FOUND THEN
+    resultado_codigo := 2; -- Update failed due to balance constraints.
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, saldo_antes, saldo_antes);
+  ELSE
+    INSERT INTO transacoes (
+      cliente_id,
+      valor,
+      tipo,
+      descricao,
+      realizada_em)
+    VALUES (
+      param_cliente_id,
+      param_valor,
+      'd',
+      param_descricao,
+      NOW()
+    );
+
+    PERFORM notificar('d', param_cliente_id, param_valor, param_descricao, resultado_saldo, resultado_limite);
+
+    resultado_codigo := 0; -- success
+    -- the balance read above may predate concurrent updates, the one returned doesn't
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, resultado_saldo + param_valor, resultado_saldo);
+  END IF;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE FUNCTION creditar(
+  param_cliente_id SMALLINT,
+  param_valor SMALLINT,
+  param_descricao VARCHAR(10),
+  param_request_id TEXT,
+  param_ip INET,
+  param_principal TEXT,
+  param_payload TEXT,
+  OUT resultado_codigo SMALLINT,
+  OUT resultado_saldo INTEGER,
+  OUT resultado_limite INTEGER
+)
+AS $$
+BEGIN
+  -- initialize out parameters
+  resultado_codigo := 0; -- assume success
+  resultado_saldo := NULL;
+  resultado_limite := NULL;
+
+  -- check if the client exists and fetch their balance_limit
+  SELECT limite INTO resultado_limite FROM clientes WHERE id = param_cliente_id;
+
+  IF resultado_limite IS NULL THEN
+    resultado_codigo := 1; -- client does not exist
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, NULL, NULL);
+    RETURN;
+  END IF;
+
+  UPDATE clientes SET saldo = saldo + param_valor
+  WHERE id = param_cliente_id
+  RETURNING saldo INTO resultado_saldo;
+
+  INSERT INTO transacoes (
+    cliente_id,
+    valor,
+    tipo,
+    descricao,
+    realizada_em)
+  VALUES (
+    param_cliente_id,
+    param_valor,
+    'c',
+    param_descricao,
+    NOW()
+  );
+
+  PERFORM notificar('c', param_cliente_id, param_valor, param_descricao, resultado_saldo, resultado_limite);
+
+  resultado_codigo := 0; -- success
+  PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, resultado_saldo - param_valor, resultado_saldo);
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,258 @@
+-- double-entry ledger: every movement is a pair of postings summing to zero, and
+-- clientes.saldo is the cached sum of the postings on the client's account
+CREATE UNLOGGED TABLE contas (
+  id SERIAL PRIMARY KEY,
+  tipo TEXT NOT NULL CHECK (tipo IN ('cliente', 'liquidacao', 'receita', 'abertura')),
+  nome TEXT NOT NULL,
+  cliente_id SMALLINT UNIQUE REFERENCES clientes(id),
+  CHECK ((tipo = 'cliente') = (cliente_id IS NOT NULL))
+);
+
+-- system accounts have fixed ids, the functions below refer to them
+INSERT INTO contas (id, tipo, nome) VALUES
+  (1, 'liquidacao', 'Liquidação'),
+  (2, 'receita', 'Receitas de tarifas'),
+  (3, 'abertura', 'Saldos de abertura');
+
+SELECT setval('contas_id_seq', 100);
+
+INSERT INTO contas (tipo, nome, cliente_id)
+SELECT 'cliente', 'Cliente ' || id, id FROM clientes;
+
+CREATE UNLOGGED TABLE lancamentos (
+  id BIGSERIAL PRIMARY KEY,
+  movimento BIGINT NOT NULL,
+  transacao_id INTEGER REFERENCES transacoes(id),
+  conta_id INTEGER NOT NULL REFERENCES contas(id),
+  -- positive increases the account's balance, negative decreases it
+  valor INTEGER NOT NULL CHECK (valor <> 0),
+  realizada_em TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE SEQUENCE movimentos;
+
+CREATE INDEX idx_lancamentos_movimento ON lancamentos (movimento);
+CREATE INDEX idx_lancamentos_conta_id ON lancamentos (conta_id);
+
+CREATE OR REPLACE FUNCTION lancamentos_balanceados()
+RETURNS TRIGGER
+AS $$
+BEGIN
+  IF (SELECT SUM(valor) FROM lancamentos WHERE movimento = NEW.movimento) <> 0 THEN
+    RAISE EXCEPTION 'movimento % is unbalanced', NEW.movimento;
+  END IF;
+
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+-- checked at commit, once both postings of a movement are in
+CREATE CONSTRAINT TRIGGER lancamentos_balanceados
+AFTER INSERT ON lancamentos
+DEFERRABLE INITIALLY DEFERRED
+FOR EACH ROW EXECUTE FUNCTION lancamentos_balanceados();
+
+-- moves param_valor from one account to another as one movement
+CREATE OR REPLACE FUNCTION lancar(
+  param_origem INTEGER,
+  param_destino INTEGER,
+  param_valor INTEGER,
+  param_transacao_id INTEGER
+)
+RETURNS VOID
+AS $$
+DECLARE
+  movimento_id BIGINT := nextval('movimentos');
+BEGIN
+  INSERT INTO lancamentos (movimento, transacao_id, conta_id, valor) VALUES
+    (movimento_id, param_transacao_id, param_origem, -param_valor),
+    (movimento_id, param_transacao_id, param_destino, param_valor);
+END;
+$$ LANGUAGE plpgsql;
+
+-- postings for the transactions made so far
+WITH t AS (
+  SELECT
+    id,
+    cliente_id,
+    CASE tipo WHEN 'c' THEN valor ELSE -valor END AS valor,
+    realizada_em,
+    nextval('movimentos') AS movimento
+  FROM transacoes
+)
+INSERT INTO lancamentos (movimento, transacao_id, conta_id, valor, realizada_em)
+SELECT t.movimento, t.id, k.id, t.valor, t.realizada_em
+FROM t JOIN contas k ON k.cliente_id = t.cliente_id
+UNION ALL
+SELECT t.movimento, t.id, 1, -t.valor, t.realizada_em
+FROM t;
+
+-- whatever the transactions don't explain becomes an opening balance
+WITH abertura AS (
+  SELECT
+    k.id AS conta_id,
+    c.saldo - COALESCE(SUM(CASE t.tipo WHEN 'c' THEN t.valor ELSE -t.valor END), 0) AS valor
+  FROM clientes c
+  JOIN contas k ON k.cliente_id = c.id
+  LEFT JOIN transacoes t ON t.cliente_id = c.id
+  GROUP BY k.id, c.saldo
+)
+SELECT lancar(3, conta_id, valor::INTEGER, NULL)
+FROM abertura
+WHERE valor <> 0;
+
+-- new clients get an account, and their initial balance an opening movement
+CREATE OR REPLACE FUNCTION abrir_conta()
+RETURNS TRIGGER
+AS $$
+DECLARE
+  conta INTEGER;
+BEGIN
+  INSERT INTO contas (tipo, nome, cliente_id)
+  VALUES ('cliente', 'Cliente ' || NEW.id, NEW.id)
+  RETURNING id INTO conta;
+
+  IF NEW.saldo <> 0 THEN
+    PERFORM lancar(3, conta, NEW.saldo, NULL);
+  END IF;
+
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER abrir_conta
+AFTER INSERT ON clientes
+FOR EACH ROW EXECUTE FUNCTION abrir_conta();
+
+CREATE OR REPLACE FUNCTION debitar(
+  param_cliente_id SMALLINT,
+  param_valor SMALLINT,
+  param_descricao VARCHAR(10),
+  param_request_id TEXT,
+  param_ip INET,
+  param_principal TEXT,
+  param_payload TEXT,
+  OUT resultado_codigo SMALLINT,
+  OUT resultado_saldo INTEGER,
+  OUT resultado_limite INTEGER
+)
+AS $$
+DECLARE
+  saldo_antes INTEGER;
+  conta INTEGER;
+  transacao INTEGER;
+BEGIN
+  -- initialize out parameters
+  resultado_codigo := 0; -- assume success
+  resultado_saldo := NULL;
+  resultado_limite := NULL;
+
+  -- check if the client exists and fetch their balance_limit and account
+  SELECT c.saldo, c.limite, k.id INTO saldo_antes, resultado_limite, conta
+  FROM clientes c JOIN contas k ON k.cliente_id = c.id
+  WHERE c.id = param_cliente_id;
+
+  IF resultado_limite IS NULL THEN
+    resultado_codigo := 1; -- client does not exist
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, NULL, NULL);
+    RETURN;
+  END IF;
+
+  -- attempt to update the client's balance only if the new balance is within limits
+  UPDATE clientes SET saldo = saldo - param_valor
+  WHERE id = param_cliente_id AND saldo - param_valor >= -limite
+  RETURNING saldo INTO resultado_saldo;
+
+  -- insert the record if the update was successful
+  IF NOT FOUND THEN
+    resultado_codigo := 2; -- Update failed due to balance constraints.
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, saldo_antes, saldo_antes);
+  ELSE
+    INSERT INTO transacoes (
+      cliente_id,
+      valor,
+      tipo,
+      descricao,
+      realizada_em)
+    VALUES (
+      param_cliente_id,
+      param_valor,
+      'd',
+      param_descricao,
+      NOW()
+    )
+    RETURNING id INTO transacao;
+
+    -- the client pays out to settlement
+    PERFORM lancar(conta, 1, param_valor, transacao);
+
+    PERFORM notificar('d', param_cliente_id, param_valor, param_descricao, resultado_saldo, resultado_limite);
+
+    resultado_codigo := 0; -- success
+    -- the balance read above may predate concurrent updates, the one returned doesn't
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, resultado_saldo + param_valor, resultado_saldo);
+  END IF;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION creditar(
+  param_cliente_id SMALLINT,
+  param_valor SMALLINT,
+  param_descricao VARCHAR(10),
+  param_request_id TEXT,
+  param_ip INET,
+  param_principal TEXT,
+  param_payload TEXT,
+  OUT resultado_codigo SMALLINT,
+  OUT resultado_saldo INTEGER,
+  OUT resultado_limite INTEGER
+)
+AS $$
+DECLARE
+  conta INTEGER;
+  transacao INTEGER;
+BEGIN
+  -- initialize out parameters
+  resultado_codigo := 0; -- assume success
+  resultado_saldo := NULL;
+  resultado_limite := NULL;
+
+  -- check if the client exists and fetch their balance_limit and account
+  SELECT c.limite, k.id INTO resultado_limite, conta
+  FROM clientes c JOIN contas k ON k.cliente_id = c.id
+  WHERE c.id = param_cliente_id;
+
+  IF resultado_limite IS NULL THEN
+    resultado_codigo := 1; -- client does not exist
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, NULL, NULL);
+    RETURN;
+  END IF;
+
+  UPDATE clientes SET saldo = saldo + param_valor
+  WHERE id = param_cliente_id
+  RETURNING saldo INTO resultado_saldo;
+
+  INSERT INTO transacoes (
+    cliente_id,
+    valor,
+    tipo,
+    descricao,
+    realizada_em)
+  VALUES (
+    param_cliente_id,
+    param_valor,
+    'c',
+    param_descricao,
+    NOW()
+  )
+  RETURNING id INTO transacao;
+
+  -- settlement pays in to the client
+  PERFORM lancar(1, conta, param_valor, transacao);
+
+  PERFORM notificar('c', param_cliente_id, param_valor, param_descricao, resultado_saldo, resultado_limite);
+
+  resultado_codigo := 0; -- success
+  PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, resultado_saldo - param_valor, resultado_saldo);
+END;
+$$ LANGUAGE plpgsql;
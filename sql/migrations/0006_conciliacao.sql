@@ -0,0 +1,72 @@
+-- corrections made by reconciliation are posted against their own account
+ALTER TABLE contas
+  DROP CONSTRAINT contas_tipo_check,
+  ADD CONSTRAINT contas_tipo_check
+    CHECK (tipo IN ('cliente', 'liquidacao', 'receita', 'abertura', 'ajuste'));
+
+INSERT INTO contas (id, tipo, nome) VALUES (4, 'ajuste', 'Ajustes de conciliação');
+
+-- each client's cached balance next to what its history says it should be
+CREATE VIEW conciliacao AS
+SELECT
+  c.id AS cliente_id,
+  c.saldo,
+  c.limite,
+  -- opening balance plus the signed sum of the transactions
+  (a.abertura + t.total)::BIGINT AS esperado,
+  r.total::BIGINT AS razao,
+  -- lowest balance the transactions ever took the client to
+  (a.abertura + LEAST(t.minimo, 0))::BIGINT AS saldo_minimo
+FROM clientes c
+JOIN contas k ON k.cliente_id = c.id
+CROSS JOIN LATERAL (
+  SELECT COALESCE(SUM(l.valor), 0) AS abertura
+  FROM lancamentos l
+  WHERE l.conta_id = k.id
+    AND EXISTS (
+      SELECT 1 FROM lancamentos o WHERE o.movimento = l.movimento AND o.conta_id = 3
+    )
+) a
+CROSS JOIN LATERAL (
+  SELECT
+    COALESCE(SUM(s.valor), 0) AS total,
+    COALESCE(MIN(s.acumulado), 0) AS minimo
+  FROM (
+    SELECT
+      CASE tipo WHEN 'c' THEN valor ELSE -valor END AS valor,
+      SUM(CASE tipo WHEN 'c' THEN valor ELSE -valor END) OVER (ORDER BY id) AS acumulado
+    FROM transacoes
+    WHERE transacoes.cliente_id = c.id
+  ) s
+) t
+CROSS JOIN LATERAL (
+  SELECT COALESCE(SUM(l.valor), 0) AS total
+  FROM lancamentos l
+  WHERE l.conta_id = k.id
+) r;
+
+-- brings a client's cached balance and ledger account back in line with its
+-- history, returning whether anything had to change
+CREATE OR REPLACE FUNCTION corrigir_saldo(param_cliente_id SMALLINT, OUT corrigido BOOLEAN)
+AS $$
+DECLARE
+  r RECORD;
+  conta INTEGER;
+BEGIN
+  -- hold off transactions on this client while it's corrected
+  PERFORM 1 FROM clientes WHERE id = param_cliente_id FOR UPDATE;
+
+  SELECT * INTO r FROM conciliacao WHERE cliente_id = param_cliente_id;
+  SELECT id INTO conta FROM contas WHERE cliente_id = param_cliente_id;
+
+  corrigido := r.saldo <> r.esperado OR r.razao <> r.esperado;
+
+  IF r.razao <> r.esperado THEN
+    PERFORM lancar(4, conta, (r.esperado - r.razao)::INTEGER, NULL);
+  END IF;
+
+  IF r.saldo <> r.esperado THEN
+    UPDATE clientes SET saldo = r.esperado WHERE id = param_cliente_id;
+  END IF;
+END;
+$$ LANGUAGE plpgsql;
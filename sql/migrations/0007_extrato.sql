@@ -0,0 +1,2 @@
+-- exports walk a client's transactions in id order
+CREATE INDEX IF NOT EXISTS idx_transacoes_cliente_id_id ON transacoes (cliente_id, id);
@@ -0,0 +1,227 @@
+-- imported transactions keep the time they were made at, live ones are made now
+DROP FUNCTION debitar(SMALLINT, SMALLINT, VARCHAR, TEXT, INET, TEXT, TEXT);
+DROP FUNCTION creditar(SMALLINT, SMALLINT, VARCHAR, TEXT, INET, TEXT, TEXT);
+
+CREATE FUNCTION debitar(
+  param_cliente_id SMALLINT,
+  param_valor SMALLINT,
+  param_descricao VARCHAR(10),
+  param_request_id TEXT,
+  param_ip INET,
+  param_principal TEXT,
+  param_payload TEXT,
+  param_realizada_em TIMESTAMP DEFAULT NULL,
+  OUT resultado_codigo SMALLINT,
+  OUT resultado_saldo INTEGER,
+  OUT resultado_limite INTEGER
+)
+AS $$
+DECLARE
+  saldo_antes INTEGER;
+  conta INTEGER;
+  transacao INTEGER;
+BEGIN
+  -- initialize out parameters
+  resultado_codigo := 0; -- assume success
+  resultado_saldo := NULL;
+  resultado_limite := NULL;
+
+  -- check if the client exists and fetch their balance_limit and account
+  SELECT c.saldo, c.limite, k.id INTO saldo_antes, resultado_limite, conta
+  FROM clientes c JOIN contas k ON k.cliente_id = c.id
+  WHERE c.id = param_cliente_id;
+
+  IF resultado_limite IS NULL THEN
+    resultado_codigo := 1; -- client does not exist
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, NULL, NULL);
+    RETURN;
+  END IF;
+
+  -- attempt to update the client's balance only if the new balance is within limits
+  UPDATE clientes SET saldo = saldo - param_valor
+  WHERE id = param_cliente_id AND saldo - param_valor >= -limite
+  RETURNING saldo INTO resultado_saldo;
+
+  -- insert the record if the update was successful
+  IF NOT FOUND THEN
+    resultado_codigo := 2; -- Update failed due to balance constraints.
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, saldo_antes, saldo_antes);
+  ELSE
+    INSERT INTO transacoes (
+      cliente_id,
+      valor,
+      tipo,
+      descricao,
+      realizada_em)
+    VALUES (
+      param_cliente_id,
+      param_valor,
+      'd',
+      param_descricao,
+      COALESCE(param_realizada_em, NOW())
+    )
+    RETURNING id INTO transacao;
+
+    -- the client pays out to settlement
+    PERFORM lancar(conta, 1, param_valor, transacao);
+
+    PERFORM notificar('d', param_cliente_id, param_valor, param_descricao, resultado_saldo, resultado_limite);
+
+    resultado_codigo := 0; -- success
+    -- the balance read above may predate concurrent updates, the one returned doesn't
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, resultado_saldo + param_valor, resultado_saldo);
+  END IF;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE FUNCTION creditar(
+  param_cliente_id SMALLINT,
+  param_valor SMALLINT,
+  param_descricao VARCHAR(10),
+  param_request_id TEXT,
+  param_ip INET,
+  param_principal TEXT,
+  param_payload TEXT,
+  param_realizada_em TIMESTAMP DEFAULT NULL,
+  OUT resultado_codigo SMALLINT,
+  OUT resultado_saldo INTEGER,
+  OUT resultado_limite INTEGER
+)
+AS $$
+DECLARE
+  conta INTEGER;
+  transacao INTEGER;
+BEGIN
+  -- initialize out parameters
+  resultado_codigo := 0; -- assume success
+  resultado_saldo := NULL;
+  resultado_limite := NULL;
+
+  -- check if the client exists and fetch their balance_limit and account
+  SELECT c.limite, k.id INTO resultado_limite, conta
+  FROM clientes c JOIN contas k ON k.cliente_id = c.id
+  WHERE c.id = param_cliente_id;
+
+  IF resultado_limite IS NULL THEN
+    resultado_codigo := 1; -- client does not exist
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, NULL, NULL);
+    RETURN;
+  END IF;
+
+  UPDATE clientes SET saldo = saldo + param_valor
+  WHERE id = param_cliente_id
+  RETURNING saldo INTO resultado_saldo;
+
+  INSERT INTO transacoes (
+    cliente_id,
+    valor,
+    tipo,
+    descricao,
+    realizada_em)
+  VALUES (
+    param_cliente_id,
+    param_valor,
+    'c',
+    param_descricao,
+    COALESCE(param_realizada_em, NOW())
+  )
+  RETURNING id INTO transacao;
+
+  -- settlement pays in to the client
+  PERFORM lancar(1, conta, param_valor, transacao);
+
+  PERFORM notificar('c', param_cliente_id, param_valor, param_descricao, resultado_saldo, resultado_limite);
+
+  resultado_codigo := 0; -- success
+  PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, resultado_saldo - param_valor, resultado_saldo);
+END;
+$$ LANGUAGE plpgsql;
+
+-- the CSV rows of an import, as text so that COPY loads every row and bad values
+-- are reported by importar() rather than failing the load
+CREATE OR REPLACE FUNCTION preparar_importacao()
+RETURNS VOID
+AS $$
+BEGIN
+  CREATE TEMP TABLE importacao (
+    linha BIGINT GENERATED ALWAYS AS IDENTITY,
+    cliente_id TEXT,
+    tipo TEXT,
+    valor TEXT,
+    descricao TEXT,
+    realizada_em TEXT
+  ) ON COMMIT DROP;
+END;
+$$ LANGUAGE plpgsql;
+
+-- NULL for text that isn't a timestamp, rather than an error
+CREATE OR REPLACE FUNCTION como_timestamp(param_texto TEXT)
+RETURNS TIMESTAMP
+AS $$
+BEGIN
+  RETURN param_texto::TIMESTAMP;
+EXCEPTION WHEN data_exception THEN
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql STABLE;
+
+-- applies the rows of importacao in order through debitar/creditar, returning the
+-- ones rejected and why; rows are checked as the API checks a transaction request
+CREATE OR REPLACE FUNCTION importar(
+  param_request_id TEXT,
+  param_ip INET,
+  param_principal TEXT
+)
+RETURNS TABLE (linha BIGINT, motivo TEXT)
+AS $$
+DECLARE
+  r RECORD;
+  realizada TIMESTAMP;
+  payload TEXT;
+  resultado SMALLINT;
+BEGIN
+  FOR r IN SELECT * FROM importacao i ORDER BY i.linha LOOP
+    linha := r.linha;
+    motivo := NULL;
+    realizada := como_timestamp(r.realizada_em);
+
+    -- CASE rather than AND, so the casts only see text that matched
+    IF NOT (CASE WHEN r.cliente_id ~ '^[0-9]{1,5}$' THEN r.cliente_id::INTEGER <= 32767 ELSE FALSE END) THEN
+      motivo := 'cliente_invalido';
+    ELSIF NOT COALESCE(r.tipo IN ('c', 'd'), FALSE) THEN
+      motivo := 'tipo_invalido';
+    ELSIF NOT (CASE WHEN r.valor ~ '^-?[0-9]{1,5}$' THEN r.valor::INTEGER BETWEEN -32768 AND 32767 ELSE FALSE END) THEN
+      motivo := 'valor_invalido';
+    ELSIF NOT COALESCE(octet_length(r.descricao) BETWEEN 1 AND 10, FALSE) THEN
+      motivo := 'descricao_invalida';
+    ELSIF r.realizada_em IS NOT NULL AND realizada IS NULL THEN
+      motivo := 'data_invalida';
+    ELSE
+      payload := json_build_object(
+        'cliente_id', r.cliente_id,
+        'tipo', r.tipo,
+        'valor', r.valor,
+        'descricao', r.descricao,
+        'realizada_em', r.realizada_em
+      )::TEXT;
+
+      IF r.tipo = 'c' THEN
+        SELECT c.resultado_codigo INTO resultado
+        FROM creditar(r.cliente_id::SMALLINT, r.valor::SMALLINT, r.descricao, param_request_id, param_ip, param_principal, payload, realizada) c;
+      ELSE
+        SELECT d.resultado_codigo INTO resultado
+        FROM debitar(r.cliente_id::SMALLINT, r.valor::SMALLINT, r.descricao, param_request_id, param_ip, param_principal, payload, realizada) d;
+      END IF;
+
+      motivo := CASE resultado
+        WHEN 1 THEN 'cliente_nao_encontrado'
+        WHEN 2 THEN 'limite_excedido'
+      END;
+    END IF;
+
+    IF motivo IS NOT NULL THEN
+      RETURN NEXT;
+    END IF;
+  END LOOP;
+END;
+$$ LANGUAGE plpgsql;
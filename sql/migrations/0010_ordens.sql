@@ -0,0 +1,35 @@
+-- standing orders: a transaction the scheduler makes for the client on a schedule,
+-- written as in the API ('diaria', 'semanal', 'mensal' or a cron expression)
+CREATE UNLOGGED TABLE ordens (
+  id SERIAL PRIMARY KEY,
+  cliente_id SMALLINT NOT NULL REFERENCES clientes(id),
+  tipo CHAR(1) NOT NULL CHECK (tipo IN ('c', 'd')),
+  valor SMALLINT NOT NULL,
+  descricao VARCHAR(10) NOT NULL,
+  agenda TEXT NOT NULL,
+  inicio TIMESTAMP NOT NULL,
+  -- runs are made before fim, exclusive
+  fim TIMESTAMP,
+  -- NULL once the schedule ran out or the order was cancelled
+  proxima_execucao TIMESTAMP,
+  cancelada_em TIMESTAMP,
+  criada_em TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_ordens_cliente_id ON ordens (cliente_id, id);
+CREATE INDEX idx_ordens_proxima_execucao ON ordens (proxima_execucao)
+WHERE proxima_execucao IS NOT NULL;
+
+-- one row per run, claimed before the transaction is made so that a run is made
+-- at most once even by two schedulers at a time; resultado stays NULL when the
+-- scheduler stopped before learning the outcome
+CREATE UNLOGGED TABLE execucoes (
+  id BIGSERIAL PRIMARY KEY,
+  ordem_id INTEGER NOT NULL REFERENCES ordens(id),
+  agendada_para TIMESTAMP NOT NULL,
+  iniciada_em TIMESTAMP NOT NULL DEFAULT NOW(),
+  instancia TEXT NOT NULL,
+  resultado TEXT,
+  saldo INTEGER,
+  UNIQUE (ordem_id, agendada_para)
+);
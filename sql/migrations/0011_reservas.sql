@@ -0,0 +1,219 @@
+-- holds: funds set aside for a debit whose final amount isn't known yet, pending
+-- until captured, voided or past expira_em, whichever comes first
+CREATE UNLOGGED TABLE reservas (
+  id SERIAL PRIMARY KEY,
+  cliente_id SMALLINT NOT NULL REFERENCES clientes(id),
+  valor SMALLINT NOT NULL CHECK (valor > 0),
+  descricao VARCHAR(10) NOT NULL,
+  criada_em TIMESTAMP NOT NULL DEFAULT NOW(),
+  expira_em TIMESTAMP NOT NULL,
+  capturada_em TIMESTAMP,
+  -- what was debited, the rest of valor was released
+  valor_capturado SMALLINT CHECK (valor_capturado BETWEEN 1 AND valor),
+  cancelada_em TIMESTAMP
+);
+
+CREATE INDEX idx_reservas_pendentes ON reservas (cliente_id, id)
+WHERE capturada_em IS NULL AND cancelada_em IS NULL;
+
+-- what the client's pending holds set aside, which debits and holds can't use
+CREATE OR REPLACE FUNCTION reservado(param_cliente_id SMALLINT)
+RETURNS INTEGER
+AS $$
+  SELECT COALESCE(SUM(valor), 0)::INTEGER
+  FROM reservas
+  WHERE cliente_id = param_cliente_id
+    AND capturada_em IS NULL
+    AND cancelada_em IS NULL
+    AND expira_em > NOW();
+$$ LANGUAGE sql STABLE;
+
+-- the client is locked before the limit is checked, as in reservar, so that a debit
+-- and a hold can't both be checked against funds only one of them can have
+CREATE OR REPLACE FUNCTION debitar(
+  param_cliente_id SMALLINT,
+  param_valor SMALLINT,
+  param_descricao VARCHAR(10),
+  param_request_id TEXT,
+  param_ip INET,
+  param_principal TEXT,
+  param_payload TEXT,
+  param_realizada_em TIMESTAMP DEFAULT NULL,
+  OUT resultado_codigo SMALLINT,
+  OUT resultado_saldo INTEGER,
+  OUT resultado_limite INTEGER
+)
+AS $$
+DECLARE
+  saldo_antes INTEGER;
+  conta INTEGER;
+  transacao INTEGER;
+BEGIN
+  -- initialize out parameters
+  resultado_codigo := 0; -- assume success
+  resultado_saldo := NULL;
+  resultado_limite := NULL;
+
+  -- check if the client exists and fetch their balance_limit and account
+  SELECT c.saldo, c.limite, k.id INTO saldo_antes, resultado_limite, conta
+  FROM clientes c JOIN contas k ON k.cliente_id = c.id
+  WHERE c.id = param_cliente_id
+  FOR UPDATE OF c;
+
+  IF resultado_limite IS NULL THEN
+    resultado_codigo := 1; -- client does not exist
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, NULL, NULL);
+    RETURN;
+  END IF;
+
+  resultado_codigo := violacao_politica(param_cliente_id, 'd', param_valor, param_descricao);
+
+  IF resultado_codigo <> 0 THEN
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, saldo_antes, saldo_antes);
+    RETURN;
+  END IF;
+
+  -- attempt to update the client's balance only if the new balance, less what is
+  -- held, is within limits
+  UPDATE clientes SET saldo = saldo - param_valor
+  WHERE id = param_cliente_id AND saldo - param_valor - reservado(param_cliente_id) >= -limite
+  RETURNING saldo INTO resultado_saldo;
+
+  -- insert the record if the update was successful
+  IF NOT FOUND THEN
+    resultado_codigo := 2; -- Update failed due to balance constraints.
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, saldo_antes, saldo_antes);
+  ELSE
+    INSERT INTO transacoes (
+      cliente_id,
+      valor,
+      tipo,
+      descricao,
+      realizada_em)
+    VALUES (
+      param_cliente_id,
+      param_valor,
+      'd',
+      param_descricao,
+      COALESCE(param_realizada_em, NOW())
+    )
+    RETURNING id INTO transacao;
+
+    -- the client pays out to settlement
+    PERFORM lancar(conta, 1, param_valor, transacao);
+
+    PERFORM notificar('d', param_cliente_id, param_valor, param_descricao, resultado_saldo, resultado_limite);
+
+    resultado_codigo := 0; -- success
+    -- the balance read above may predate concurrent updates, the one returned doesn't
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, resultado_saldo + param_valor, resultado_saldo);
+  END IF;
+END;
+$$ LANGUAGE plpgsql;
+
+-- places a hold of param_validade_s seconds, refused with the codes of debitar as a
+-- debit of the same amount would be; holds aren't transactions, so aren't audited
+CREATE OR REPLACE FUNCTION reservar(
+  param_cliente_id SMALLINT,
+  param_valor SMALLINT,
+  param_descricao VARCHAR(10),
+  param_validade_s INTEGER,
+  OUT resultado_codigo SMALLINT,
+  OUT resultado_reserva INTEGER
+)
+AS $$
+DECLARE
+  saldo_atual INTEGER;
+  limite_atual INTEGER;
+BEGIN
+  resultado_codigo := 0;
+  resultado_reserva := NULL;
+
+  SELECT c.saldo, c.limite INTO saldo_atual, limite_atual
+  FROM clientes c
+  WHERE c.id = param_cliente_id
+  FOR UPDATE;
+
+  IF limite_atual IS NULL THEN
+    resultado_codigo := 1;
+    RETURN;
+  END IF;
+
+  resultado_codigo := violacao_politica(param_cliente_id, 'd', param_valor, param_descricao);
+
+  IF resultado_codigo <> 0 THEN
+    RETURN;
+  END IF;
+
+  IF saldo_atual - param_valor - reservado(param_cliente_id) < -limite_atual THEN
+    resultado_codigo := 2;
+    RETURN;
+  END IF;
+
+  INSERT INTO reservas (cliente_id, valor, descricao, expira_em)
+  VALUES (param_cliente_id, param_valor, param_descricao, NOW() + make_interval(secs => param_validade_s))
+  RETURNING id INTO resultado_reserva;
+END;
+$$ LANGUAGE plpgsql;
+
+-- debits param_valor of a pending hold, all of it when NULL, through debitar and
+-- releases the rest; on top of the codes of debitar, 7 when the hold is no longer
+-- pending and 8 when param_valor is more than was held
+CREATE OR REPLACE FUNCTION capturar(
+  param_cliente_id SMALLINT,
+  param_reserva_id INTEGER,
+  param_valor SMALLINT,
+  param_request_id TEXT,
+  param_ip INET,
+  param_principal TEXT,
+  param_payload TEXT,
+  OUT resultado_codigo SMALLINT,
+  OUT resultado_saldo INTEGER,
+  OUT resultado_limite INTEGER
+)
+AS $$
+DECLARE
+  r reservas%ROWTYPE;
+  valor_debito SMALLINT;
+BEGIN
+  resultado_codigo := 0;
+  resultado_saldo := NULL;
+  resultado_limite := NULL;
+
+  SELECT * INTO r
+  FROM reservas
+  WHERE id = param_reserva_id AND cliente_id = param_cliente_id
+  FOR UPDATE;
+
+  IF NOT FOUND THEN
+    resultado_codigo := 1;
+    RETURN;
+  END IF;
+
+  IF r.capturada_em IS NOT NULL OR r.cancelada_em IS NOT NULL OR r.expira_em <= NOW() THEN
+    resultado_codigo := 7;
+    RETURN;
+  END IF;
+
+  valor_debito := COALESCE(param_valor, r.valor);
+
+  IF valor_debito > r.valor THEN
+    resultado_codigo := 8;
+    RETURN;
+  END IF;
+
+  -- captured before debiting, so that debitar doesn't count the hold against itself
+  UPDATE reservas SET capturada_em = NOW(), valor_capturado = valor_debito
+  WHERE id = r.id;
+
+  SELECT d.resultado_codigo, d.resultado_saldo, d.resultado_limite
+  INTO resultado_codigo, resultado_saldo, resultado_limite
+  FROM debitar(param_cliente_id, valor_debito, r.descricao, param_request_id, param_ip, param_principal, param_payload) d;
+
+  -- refused, say by a policy changed since, the hold stays pending
+  IF resultado_codigo <> 0 THEN
+    UPDATE reservas SET capturada_em = NULL, valor_capturado = NULL
+    WHERE id = r.id;
+  END IF;
+END;
+$$ LANGUAGE plpgsql;
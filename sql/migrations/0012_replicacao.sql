@@ -0,0 +1,8 @@
+-- unlogged tables aren't written to the WAL, so a replica sees them empty; these are
+-- the ones statements read from the replica, clientes first since a logged table
+-- can't reference an unlogged one
+ALTER TABLE clientes SET LOGGED;
+ALTER TABLE transacoes SET LOGGED;
+ALTER TABLE contas SET LOGGED;
+ALTER TABLE lancamentos SET LOGGED;
+ALTER TABLE reservas SET LOGGED;
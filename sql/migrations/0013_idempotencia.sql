@@ -0,0 +1,204 @@
+-- transactions made under a key, so that one whose outcome was lost with the
+-- connection can be sent again without being applied twice
+CREATE TABLE idempotencia (
+  cliente_id SMALLINT NOT NULL REFERENCES clientes(id),
+  chave TEXT NOT NULL,
+  resultado_saldo INTEGER NOT NULL,
+  resultado_limite INTEGER NOT NULL,
+  realizada_em TIMESTAMP NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (cliente_id, chave)
+);
+
+-- the key is checked once the client is locked, so a repeat sent while the first
+-- is still running waits for it and then finds its outcome
+DROP FUNCTION debitar(SMALLINT, SMALLINT, VARCHAR, TEXT, INET, TEXT, TEXT, TIMESTAMP);
+DROP FUNCTION creditar(SMALLINT, SMALLINT, VARCHAR, TEXT, INET, TEXT, TEXT, TIMESTAMP);
+
+CREATE FUNCTION debitar(
+  param_cliente_id SMALLINT,
+  param_valor SMALLINT,
+  param_descricao VARCHAR(10),
+  param_request_id TEXT,
+  param_ip INET,
+  param_principal TEXT,
+  param_payload TEXT,
+  param_realizada_em TIMESTAMP DEFAULT NULL,
+  param_chave TEXT DEFAULT NULL,
+  OUT resultado_codigo SMALLINT,
+  OUT resultado_saldo INTEGER,
+  OUT resultado_limite INTEGER
+)
+AS $$
+DECLARE
+  saldo_antes INTEGER;
+  conta INTEGER;
+  transacao INTEGER;
+  anterior idempotencia%ROWTYPE;
+BEGIN
+  -- initialize out parameters
+  resultado_codigo := 0; -- assume success
+  resultado_saldo := NULL;
+  resultado_limite := NULL;
+
+  -- check if the client exists and fetch their balance_limit and account
+  SELECT c.saldo, c.limite, k.id INTO saldo_antes, resultado_limite, conta
+  FROM clientes c JOIN contas k ON k.cliente_id = c.id
+  WHERE c.id = param_cliente_id
+  FOR UPDATE OF c;
+
+  IF resultado_limite IS NULL THEN
+    resultado_codigo := 1; -- client does not exist
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, NULL, NULL);
+    RETURN;
+  END IF;
+
+  -- already made under this key, answered as it was then
+  SELECT * INTO anterior
+  FROM idempotencia i
+  WHERE i.cliente_id = param_cliente_id AND i.chave = param_chave;
+
+  IF FOUND THEN
+    resultado_saldo := anterior.resultado_saldo;
+    resultado_limite := anterior.resultado_limite;
+    RETURN;
+  END IF;
+
+  resultado_codigo := violacao_politica(param_cliente_id, 'd', param_valor, param_descricao);
+
+  IF resultado_codigo <> 0 THEN
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, saldo_antes, saldo_antes);
+    RETURN;
+  END IF;
+
+  -- attempt to update the client's balance only if the new balance, less what is
+  -- held, is within limits
+  UPDATE clientes SET saldo = saldo - param_valor
+  WHERE id = param_cliente_id AND saldo - param_valor - reservado(param_cliente_id) >= -limite
+  RETURNING saldo INTO resultado_saldo;
+
+  -- insert the record if the update was successful
+  IF NOT FOUND THEN
+    resultado_codigo := 2; -- Update failed due to balance constraints.
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, saldo_antes, saldo_antes);
+  ELSE
+    INSERT INTO transacoes (
+      cliente_id,
+      valor,
+      tipo,
+      descricao,
+      realizada_em)
+    VALUES (
+      param_cliente_id,
+      param_valor,
+      'd',
+      param_descricao,
+      COALESCE(param_realizada_em, NOW())
+    )
+    RETURNING id INTO transacao;
+
+    -- the client pays out to settlement
+    PERFORM lancar(conta, 1, param_valor, transacao);
+
+    PERFORM notificar('d', param_cliente_id, param_valor, param_descricao, resultado_saldo, resultado_limite);
+
+    IF param_chave IS NOT NULL THEN
+      INSERT INTO idempotencia (cliente_id, chave, resultado_saldo, resultado_limite)
+      VALUES (param_cliente_id, param_chave, resultado_saldo, resultado_limite);
+    END IF;
+
+    resultado_codigo := 0; -- success
+    -- the balance read above may predate concurrent updates, the one returned doesn't
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, resultado_saldo + param_valor, resultado_saldo);
+  END IF;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE FUNCTION creditar(
+  param_cliente_id SMALLINT,
+  param_valor SMALLINT,
+  param_descricao VARCHAR(10),
+  param_request_id TEXT,
+  param_ip INET,
+  param_principal TEXT,
+  param_payload TEXT,
+  param_realizada_em TIMESTAMP DEFAULT NULL,
+  param_chave TEXT DEFAULT NULL,
+  OUT resultado_codigo SMALLINT,
+  OUT resultado_saldo INTEGER,
+  OUT resultado_limite INTEGER
+)
+AS $$
+DECLARE
+  saldo_antes INTEGER;
+  conta INTEGER;
+  transacao INTEGER;
+  anterior idempotencia%ROWTYPE;
+BEGIN
+  -- initialize out parameters
+  resultado_codigo := 0; -- assume success
+  resultado_saldo := NULL;
+  resultado_limite := NULL;
+
+  -- check if the client exists and fetch their balance_limit and account
+  SELECT c.saldo, c.limite, k.id INTO saldo_antes, resultado_limite, conta
+  FROM clientes c JOIN contas k ON k.cliente_id = c.id
+  WHERE c.id = param_cliente_id
+  FOR UPDATE OF c;
+
+  IF resultado_limite IS NULL THEN
+    resultado_codigo := 1; -- client does not exist
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, NULL, NULL);
+    RETURN;
+  END IF;
+
+  -- already made under this key, answered as it was then
+  SELECT * INTO anterior
+  FROM idempotencia i
+  WHERE i.cliente_id = param_cliente_id AND i.chave = param_chave;
+
+  IF FOUND THEN
+    resultado_saldo := anterior.resultado_saldo;
+    resultado_limite := anterior.resultado_limite;
+    RETURN;
+  END IF;
+
+  resultado_codigo := violacao_politica(param_cliente_id, 'c', param_valor, param_descricao);
+
+  IF resultado_codigo <> 0 THEN
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, saldo_antes, saldo_antes);
+    RETURN;
+  END IF;
+
+  UPDATE clientes SET saldo = saldo + param_valor
+  WHERE id = param_cliente_id
+  RETURNING saldo INTO resultado_saldo;
+
+  INSERT INTO transacoes (
+    cliente_id,
+    valor,
+    tipo,
+    descricao,
+    realizada_em)
+  VALUES (
+    param_cliente_id,
+    param_valor,
+    'c',
+    param_descricao,
+    COALESCE(param_realizada_em, NOW())
+  )
+  RETURNING id INTO transacao;
+
+  -- settlement pays in to the client
+  PERFORM lancar(1, conta, param_valor, transacao);
+
+  PERFORM notificar('c', param_cliente_id, param_valor, param_descricao, resultado_saldo, resultado_limite);
+
+  IF param_chave IS NOT NULL THEN
+    INSERT INTO idempotencia (cliente_id, chave, resultado_saldo, resultado_limite)
+    VALUES (param_cliente_id, param_chave, resultado_saldo, resultado_limite);
+  END IF;
+
+  resultado_codigo := 0; -- success
+  PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, resultado_saldo - param_valor, resultado_saldo);
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,252 @@
+-- clientes.saldo follows the postings on the client's account instead of being
+-- written next to them, so the cached balance and the ledger can't disagree
+CREATE OR REPLACE FUNCTION manter_saldo()
+RETURNS TRIGGER
+AS $$
+BEGIN
+  UPDATE clientes c SET saldo = c.saldo + NEW.valor
+  FROM contas k
+  WHERE k.id = NEW.conta_id AND c.id = k.cliente_id;
+
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER manter_saldo
+AFTER INSERT ON lancamentos
+FOR EACH ROW EXECUTE FUNCTION manter_saldo();
+
+-- a new client's initial balance comes from its opening movement, like any other
+CREATE OR REPLACE FUNCTION abrir_conta()
+RETURNS TRIGGER
+AS $$
+DECLARE
+  conta INTEGER;
+BEGIN
+  INSERT INTO contas (tipo, nome, cliente_id)
+  VALUES ('cliente', 'Cliente ' || NEW.id, NEW.id)
+  RETURNING id INTO conta;
+
+  IF NEW.saldo <> 0 THEN
+    UPDATE clientes SET saldo = 0 WHERE id = NEW.id;
+    PERFORM lancar(3, conta, NEW.saldo, NULL);
+  END IF;
+
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+-- the adjustment moves the balance along with the account, what is left to fix is
+-- a balance that drifted from the ledger on its own
+CREATE OR REPLACE FUNCTION corrigir_saldo(param_cliente_id SMALLINT, OUT corrigido BOOLEAN)
+AS $$
+DECLARE
+  r RECORD;
+  conta INTEGER;
+BEGIN
+  -- hold off transactions on this client while it's corrected
+  PERFORM 1 FROM clientes WHERE id = param_cliente_id FOR UPDATE;
+
+  SELECT * INTO r FROM conciliacao WHERE cliente_id = param_cliente_id;
+  SELECT id INTO conta FROM contas WHERE cliente_id = param_cliente_id;
+
+  corrigido := r.saldo <> r.esperado OR r.razao <> r.esperado;
+
+  IF r.razao <> r.esperado THEN
+    PERFORM lancar(4, conta, (r.esperado - r.razao)::INTEGER, NULL);
+  END IF;
+
+  UPDATE clientes SET saldo = r.esperado
+  WHERE id = param_cliente_id AND saldo <> r.esperado;
+END;
+$$ LANGUAGE plpgsql;
+
+-- nothing charges fees, so nothing was ever posted to receita
+DELETE FROM contas WHERE id = 2 AND tipo = 'receita';
+
+ALTER TABLE contas
+  DROP CONSTRAINT contas_tipo_check,
+  ADD CONSTRAINT contas_tipo_check
+    CHECK (tipo IN ('cliente', 'liquidacao', 'abertura', 'ajuste'));
+
+-- debits and credits post the movement and leave the balance to it
+CREATE OR REPLACE FUNCTION debitar(
+  param_cliente_id SMALLINT,
+  param_valor SMALLINT,
+  param_descricao VARCHAR(10),
+  param_request_id TEXT,
+  param_ip INET,
+  param_principal TEXT,
+  param_payload TEXT,
+  param_realizada_em TIMESTAMP DEFAULT NULL,
+  param_chave TEXT DEFAULT NULL,
+  OUT resultado_codigo SMALLINT,
+  OUT resultado_saldo INTEGER,
+  OUT resultado_limite INTEGER
+)
+AS $$
+DECLARE
+  saldo_antes INTEGER;
+  conta INTEGER;
+  transacao INTEGER;
+  anterior idempotencia%ROWTYPE;
+BEGIN
+  -- initialize out parameters
+  resultado_codigo := 0; -- assume success
+  resultado_saldo := NULL;
+  resultado_limite := NULL;
+
+  -- check if the client exists and fetch their balance_limit and account
+  SELECT c.saldo, c.limite, k.id INTO saldo_antes, resultado_limite, conta
+  FROM clientes c JOIN contas k ON k.cliente_id = c.id
+  WHERE c.id = param_cliente_id
+  FOR UPDATE OF c;
+
+  IF resultado_limite IS NULL THEN
+    resultado_codigo := 1; -- client does not exist
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, NULL, NULL);
+    RETURN;
+  END IF;
+
+  -- already made under this key, answered as it was then
+  SELECT * INTO anterior
+  FROM idempotencia i
+  WHERE i.cliente_id = param_cliente_id AND i.chave = param_chave;
+
+  IF FOUND THEN
+    resultado_saldo := anterior.resultado_saldo;
+    resultado_limite := anterior.resultado_limite;
+    RETURN;
+  END IF;
+
+  resultado_codigo := violacao_politica(param_cliente_id, 'd', param_valor, param_descricao);
+
+  IF resultado_codigo <> 0 THEN
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, saldo_antes, saldo_antes);
+    RETURN;
+  END IF;
+
+  -- the new balance, less what is held, has to be within limits; the client is
+  -- locked, so the balance read above is the one the debit applies to
+  IF saldo_antes - param_valor - reservado(param_cliente_id) < -resultado_limite THEN
+    resultado_codigo := 2; -- debit past the limit
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, saldo_antes, saldo_antes);
+  ELSE
+    INSERT INTO transacoes (
+      cliente_id,
+      valor,
+      tipo,
+      descricao,
+      realizada_em)
+    VALUES (
+      param_cliente_id,
+      param_valor,
+      'd',
+      param_descricao,
+      COALESCE(param_realizada_em, NOW())
+    )
+    RETURNING id INTO transacao;
+
+    -- the client pays out to settlement, which is what moves the balance
+    PERFORM lancar(conta, 1, param_valor, transacao);
+    resultado_saldo := saldo_antes - param_valor;
+
+    PERFORM notificar('d', param_cliente_id, param_valor, param_descricao, resultado_saldo, resultado_limite);
+
+    IF param_chave IS NOT NULL THEN
+      INSERT INTO idempotencia (cliente_id, chave, resultado_saldo, resultado_limite)
+      VALUES (param_cliente_id, param_chave, resultado_saldo, resultado_limite);
+    END IF;
+
+    resultado_codigo := 0; -- success
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, saldo_antes, resultado_saldo);
+  END IF;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION creditar(
+  param_cliente_id SMALLINT,
+  param_valor SMALLINT,
+  param_descricao VARCHAR(10),
+  param_request_id TEXT,
+  param_ip INET,
+  param_principal TEXT,
+  param_payload TEXT,
+  param_realizada_em TIMESTAMP DEFAULT NULL,
+  param_chave TEXT DEFAULT NULL,
+  OUT resultado_codigo SMALLINT,
+  OUT resultado_saldo INTEGER,
+  OUT resultado_limite INTEGER
+)
+AS $$
+DECLARE
+  saldo_antes INTEGER;
+  conta INTEGER;
+  transacao INTEGER;
+  anterior idempotencia%ROWTYPE;
+BEGIN
+  -- initialize out parameters
+  resultado_codigo := 0; -- assume success
+  resultado_saldo := NULL;
+  resultado_limite := NULL;
+
+  -- check if the client exists and fetch their balance_limit and account
+  SELECT c.saldo, c.limite, k.id INTO saldo_antes, resultado_limite, conta
+  FROM clientes c JOIN contas k ON k.cliente_id = c.id
+  WHERE c.id = param_cliente_id
+  FOR UPDATE OF c;
+
+  IF resultado_limite IS NULL THEN
+    resultado_codigo := 1; -- client does not exist
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, NULL, NULL);
+    RETURN;
+  END IF;
+
+  -- already made under this key, answered as it was then
+  SELECT * INTO anterior
+  FROM idempotencia i
+  WHERE i.cliente_id = param_cliente_id AND i.chave = param_chave;
+
+  IF FOUND THEN
+    resultado_saldo := anterior.resultado_saldo;
+    resultado_limite := anterior.resultado_limite;
+    RETURN;
+  END IF;
+
+  resultado_codigo := violacao_politica(param_cliente_id, 'c', param_valor, param_descricao);
+
+  IF resultado_codigo <> 0 THEN
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, saldo_antes, saldo_antes);
+    RETURN;
+  END IF;
+
+  INSERT INTO transacoes (
+    cliente_id,
+    valor,
+    tipo,
+    descricao,
+    realizada_em)
+  VALUES (
+    param_cliente_id,
+    param_valor,
+    'c',
+    param_descricao,
+    COALESCE(param_realizada_em, NOW())
+  )
+  RETURNING id INTO transacao;
+
+  -- settlement pays in to the client, which is what moves the balance
+  PERFORM lancar(1, conta, param_valor, transacao);
+  resultado_saldo := saldo_antes + param_valor;
+
+  PERFORM notificar('c', param_cliente_id, param_valor, param_descricao, resultado_saldo, resultado_limite);
+
+  IF param_chave IS NOT NULL THEN
+    INSERT INTO idempotencia (cliente_id, chave, resultado_saldo, resultado_limite)
+    VALUES (param_cliente_id, param_chave, resultado_saldo, resultado_limite);
+  END IF;
+
+  resultado_codigo := 0; -- success
+  PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, saldo_antes, resultado_saldo);
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,92 @@
+-- the staged rows outlive the transaction that loaded them, so that they can be
+-- applied in chunks of their own; whoever stages them drops the table when done
+CREATE OR REPLACE FUNCTION preparar_importacao()
+RETURNS VOID
+AS $$
+BEGIN
+  DROP TABLE IF EXISTS pg_temp.importacao;
+
+  CREATE TEMP TABLE importacao (
+    linha BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    cliente_id TEXT,
+    tipo TEXT,
+    valor TEXT,
+    descricao TEXT,
+    realizada_em TEXT
+  );
+END;
+$$ LANGUAGE plpgsql;
+
+DROP FUNCTION importar(TEXT, INET, TEXT);
+
+-- applies the param_linhas rows of importacao past line param_apos in order through
+-- debitar/creditar, returning the ones rejected and why; rows are checked as the
+-- API checks a transaction request
+CREATE FUNCTION importar(
+  param_request_id TEXT,
+  param_ip INET,
+  param_principal TEXT,
+  param_apos BIGINT,
+  param_linhas BIGINT
+)
+RETURNS TABLE (linha BIGINT, motivo TEXT)
+AS $$
+DECLARE
+  r RECORD;
+  realizada TIMESTAMP;
+  payload TEXT;
+  resultado SMALLINT;
+BEGIN
+  FOR r IN
+    SELECT * FROM importacao i
+    WHERE i.linha > param_apos AND i.linha <= param_apos + param_linhas
+    ORDER BY i.linha
+  LOOP
+    linha := r.linha;
+    motivo := NULL;
+    realizada := como_timestamp(r.realizada_em);
+
+    -- CASE rather than AND, so the casts only see text that matched
+    IF NOT (CASE WHEN r.cliente_id ~ '^[0-9]{1,5}$' THEN r.cliente_id::INTEGER <= 32767 ELSE FALSE END) THEN
+      motivo := 'cliente_invalido';
+    ELSIF NOT COALESCE(r.tipo IN ('c', 'd'), FALSE) THEN
+      motivo := 'tipo_invalido';
+    ELSIF NOT (CASE WHEN r.valor ~ '^-?[0-9]{1,5}$' THEN r.valor::INTEGER BETWEEN -32768 AND 32767 ELSE FALSE END) THEN
+      motivo := 'valor_invalido';
+    ELSIF NOT COALESCE(octet_length(r.descricao) BETWEEN 1 AND 10, FALSE) THEN
+      motivo := 'descricao_invalida';
+    ELSIF r.realizada_em IS NOT NULL AND realizada IS NULL THEN
+      motivo := 'data_invalida';
+    ELSE
+      payload := json_build_object(
+        'cliente_id', r.cliente_id,
+        'tipo', r.tipo,
+        'valor', r.valor,
+        'descricao', r.descricao,
+        'realizada_em', r.realizada_em
+      )::TEXT;
+
+      IF r.tipo = 'c' THEN
+        SELECT c.resultado_codigo INTO resultado
+        FROM creditar(r.cliente_id::SMALLINT, r.valor::SMALLINT, r.descricao, param_request_id, param_ip, param_principal, payload, realizada) c;
+      ELSE
+        SELECT d.resultado_codigo INTO resultado
+        FROM debitar(r.cliente_id::SMALLINT, r.valor::SMALLINT, r.descricao, param_request_id, param_ip, param_principal, payload, realizada) d;
+      END IF;
+
+      motivo := CASE resultado
+        WHEN 1 THEN 'cliente_nao_encontrado'
+        WHEN 2 THEN 'limite_excedido'
+        WHEN 3 THEN 'valor_acima_do_maximo'
+        WHEN 4 THEN 'valor_abaixo_do_minimo'
+        WHEN 5 THEN 'descricao_recusada'
+        WHEN 6 THEN 'credito_recusado'
+      END;
+    END IF;
+
+    IF motivo IS NOT NULL THEN
+      RETURN NEXT;
+    END IF;
+  END LOOP;
+END;
+$$ LANGUAGE plpgsql;
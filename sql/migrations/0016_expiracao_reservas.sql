@@ -0,0 +1,49 @@
+-- holds past expira_em are marked expired by expirar_reservas, so that they leave
+-- the pending index instead of being filtered out of it on every read
+ALTER TABLE reservas ADD COLUMN expirada_em TIMESTAMP;
+
+DROP INDEX idx_reservas_pendentes;
+
+CREATE INDEX idx_reservas_pendentes ON reservas (cliente_id, id)
+WHERE capturada_em IS NULL AND cancelada_em IS NULL AND expirada_em IS NULL;
+
+-- what expirar_reservas looks for, oldest expiry first
+CREATE INDEX idx_reservas_a_expirar ON reservas (expira_em)
+WHERE capturada_em IS NULL AND cancelada_em IS NULL AND expirada_em IS NULL;
+
+-- holds past expira_em set nothing aside even before they are marked, so that a hold
+-- doesn't outlive its validity by up to an interval of the sweep
+CREATE OR REPLACE FUNCTION reservado(param_cliente_id SMALLINT)
+RETURNS INTEGER
+AS $$
+  SELECT COALESCE(SUM(valor), 0)::INTEGER
+  FROM reservas
+  WHERE cliente_id = param_cliente_id
+    AND capturada_em IS NULL
+    AND cancelada_em IS NULL
+    AND expirada_em IS NULL
+    AND expira_em > NOW();
+$$ LANGUAGE sql STABLE;
+
+-- marks up to param_limite holds past expira_em as expired and returns how many;
+-- holds being captured or voided meanwhile are skipped, to be settled by those
+CREATE OR REPLACE FUNCTION expirar_reservas(param_limite INTEGER)
+RETURNS INTEGER
+AS $$
+  WITH expiradas AS (
+    UPDATE reservas SET expirada_em = expira_em
+    WHERE id IN (
+      SELECT id
+      FROM reservas
+      WHERE capturada_em IS NULL
+        AND cancelada_em IS NULL
+        AND expirada_em IS NULL
+        AND expira_em <= NOW()
+      ORDER BY expira_em
+      LIMIT param_limite
+      FOR UPDATE SKIP LOCKED
+    )
+    RETURNING 1
+  )
+  SELECT COUNT(*)::INTEGER FROM expiradas;
+$$ LANGUAGE sql;
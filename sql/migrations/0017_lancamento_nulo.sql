@@ -0,0 +1,25 @@
+-- a transaction of valor 0 moves nothing, so it gets no postings rather than a pair
+-- of zero ones the CHECK on lancamentos.valor refuses, and is applied as it was
+-- before the ledger
+CREATE OR REPLACE FUNCTION lancar(
+  param_origem INTEGER,
+  param_destino INTEGER,
+  param_valor INTEGER,
+  param_transacao_id INTEGER
+)
+RETURNS VOID
+AS $$
+DECLARE
+  movimento_id BIGINT;
+BEGIN
+  IF param_valor = 0 THEN
+    RETURN;
+  END IF;
+
+  movimento_id := nextval('movimentos');
+
+  INSERT INTO lancamentos (movimento, transacao_id, conta_id, valor) VALUES
+    (movimento_id, param_transacao_id, param_origem, -param_valor),
+    (movimento_id, param_transacao_id, param_destino, param_valor);
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,247 @@
+-- keys come from the Idempotency-Key header now rather than from the request id,
+-- which clients send on every request and may reuse; the ones kept so far were
+-- request ids, so they go
+TRUNCATE idempotencia;
+
+-- what was sent under the key, so that a key reused for another request is refused
+-- instead of answered with the outcome of the first
+ALTER TABLE idempotencia ADD COLUMN pedido BYTEA NOT NULL;
+
+CREATE INDEX idx_idempotencia_realizada_em ON idempotencia (realizada_em);
+
+CREATE OR REPLACE FUNCTION pedido(param_tipo CHAR(1), param_valor SMALLINT, param_descricao VARCHAR(10))
+RETURNS BYTEA
+AS $$
+  SELECT sha256(convert_to(param_tipo || ':' || param_valor || ':' || param_descricao, 'UTF8'));
+$$ LANGUAGE sql IMMUTABLE;
+
+-- deletes up to param_limite keys past their day and returns how many
+CREATE OR REPLACE FUNCTION expirar_idempotencia(param_limite INTEGER)
+RETURNS INTEGER
+AS $$
+  WITH expiradas AS (
+    DELETE FROM idempotencia
+    WHERE (cliente_id, chave) IN (
+      SELECT cliente_id, chave
+      FROM idempotencia
+      WHERE realizada_em <= NOW() - INTERVAL '1 day'
+      ORDER BY realizada_em
+      LIMIT param_limite
+      FOR UPDATE SKIP LOCKED
+    )
+    RETURNING 1
+  )
+  SELECT COUNT(*)::INTEGER FROM expiradas;
+$$ LANGUAGE sql;
+
+-- as before, but a key is checked against what was sent under it and lasts a day
+CREATE OR REPLACE FUNCTION debitar(
+  param_cliente_id SMALLINT,
+  param_valor SMALLINT,
+  param_descricao VARCHAR(10),
+  param_request_id TEXT,
+  param_ip INET,
+  param_principal TEXT,
+  param_payload TEXT,
+  param_realizada_em TIMESTAMP DEFAULT NULL,
+  param_chave TEXT DEFAULT NULL,
+  OUT resultado_codigo SMALLINT,
+  OUT resultado_saldo INTEGER,
+  OUT resultado_limite INTEGER
+)
+AS $$
+DECLARE
+  saldo_antes INTEGER;
+  conta INTEGER;
+  transacao INTEGER;
+  anterior idempotencia%ROWTYPE;
+BEGIN
+  -- initialize out parameters
+  resultado_codigo := 0; -- assume success
+  resultado_saldo := NULL;
+  resultado_limite := NULL;
+
+  -- check if the client exists and fetch their balance_limit and account
+  SELECT c.saldo, c.limite, k.id INTO saldo_antes, resultado_limite, conta
+  FROM clientes c JOIN contas k ON k.cliente_id = c.id
+  WHERE c.id = param_cliente_id
+  FOR UPDATE OF c;
+
+  IF resultado_limite IS NULL THEN
+    resultado_codigo := 1; -- client does not exist
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, NULL, NULL);
+    RETURN;
+  END IF;
+
+  -- already made under this key within a day, answered as it was then if it was
+  -- the same request, refused if the key was reused for another one
+  SELECT * INTO anterior
+  FROM idempotencia i
+  WHERE i.cliente_id = param_cliente_id
+    AND i.chave = param_chave
+    AND i.realizada_em > NOW() - INTERVAL '1 day';
+
+  IF FOUND THEN
+    IF anterior.pedido <> pedido('d', param_valor, param_descricao) THEN
+      resultado_codigo := 10; -- key reused for another request
+      PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, saldo_antes, saldo_antes);
+      RETURN;
+    END IF;
+
+    resultado_saldo := anterior.resultado_saldo;
+    resultado_limite := anterior.resultado_limite;
+    RETURN;
+  END IF;
+
+  resultado_codigo := violacao_politica(param_cliente_id, 'd', param_valor, param_descricao);
+
+  IF resultado_codigo <> 0 THEN
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, saldo_antes, saldo_antes);
+    RETURN;
+  END IF;
+
+  -- the new balance, less what is held, has to be within limits; the client is
+  -- locked, so the balance read above is the one the debit applies to
+  IF saldo_antes - param_valor - reservado(param_cliente_id) < -resultado_limite THEN
+    resultado_codigo := 2; -- debit past the limit
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, saldo_antes, saldo_antes);
+  ELSE
+    INSERT INTO transacoes (
+      cliente_id,
+      valor,
+      tipo,
+      descricao,
+      realizada_em)
+    VALUES (
+      param_cliente_id,
+      param_valor,
+      'd',
+      param_descricao,
+      COALESCE(param_realizada_em, NOW())
+    )
+    RETURNING id INTO transacao;
+
+    -- the client pays out to settlement, which is what moves the balance
+    PERFORM lancar(conta, 1, param_valor, transacao);
+    resultado_saldo := saldo_antes - param_valor;
+
+    PERFORM notificar('d', param_cliente_id, param_valor, param_descricao, resultado_saldo, resultado_limite);
+
+    -- a key past its day may still be there, awaiting expirar_idempotencia
+    IF param_chave IS NOT NULL THEN
+      INSERT INTO idempotencia (cliente_id, chave, pedido, resultado_saldo, resultado_limite)
+      VALUES (param_cliente_id, param_chave, pedido('d', param_valor, param_descricao), resultado_saldo, resultado_limite)
+      ON CONFLICT (cliente_id, chave) DO UPDATE
+      SET pedido = EXCLUDED.pedido,
+        resultado_saldo = EXCLUDED.resultado_saldo,
+        resultado_limite = EXCLUDED.resultado_limite,
+        realizada_em = EXCLUDED.realizada_em;
+    END IF;
+
+    resultado_codigo := 0; -- success
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, saldo_antes, resultado_saldo);
+  END IF;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION creditar(
+  param_cliente_id SMALLINT,
+  param_valor SMALLINT,
+  param_descricao VARCHAR(10),
+  param_request_id TEXT,
+  param_ip INET,
+  param_principal TEXT,
+  param_payload TEXT,
+  param_realizada_em TIMESTAMP DEFAULT NULL,
+  param_chave TEXT DEFAULT NULL,
+  OUT resultado_codigo SMALLINT,
+  OUT resultado_saldo INTEGER,
+  OUT resultado_limite INTEGER
+)
+AS $$
+DECLARE
+  saldo_antes INTEGER;
+  conta INTEGER;
+  transacao INTEGER;
+  anterior idempotencia%ROWTYPE;
+BEGIN
+  -- initialize out parameters
+  resultado_codigo := 0; -- assume success
+  resultado_saldo := NULL;
+  resultado_limite := NULL;
+
+  -- check if the client exists and fetch their balance_limit and account
+  SELECT c.saldo, c.limite, k.id INTO saldo_antes, resultado_limite, conta
+  FROM clientes c JOIN contas k ON k.cliente_id = c.id
+  WHERE c.id = param_cliente_id
+  FOR UPDATE OF c;
+
+  IF resultado_limite IS NULL THEN
+    resultado_codigo := 1; -- client does not exist
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, NULL, NULL);
+    RETURN;
+  END IF;
+
+  -- already made under this key within a day, answered as it was then if it was
+  -- the same request, refused if the key was reused for another one
+  SELECT * INTO anterior
+  FROM idempotencia i
+  WHERE i.cliente_id = param_cliente_id
+    AND i.chave = param_chave
+    AND i.realizada_em > NOW() - INTERVAL '1 day';
+
+  IF FOUND THEN
+    IF anterior.pedido <> pedido('c', param_valor, param_descricao) THEN
+      resultado_codigo := 10; -- key reused for another request
+      PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, saldo_antes, saldo_antes);
+      RETURN;
+    END IF;
+
+    resultado_saldo := anterior.resultado_saldo;
+    resultado_limite := anterior.resultado_limite;
+    RETURN;
+  END IF;
+
+  resultado_codigo := violacao_politica(param_cliente_id, 'c', param_valor, param_descricao);
+
+  IF resultado_codigo <> 0 THEN
+    PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, saldo_antes, saldo_antes);
+    RETURN;
+  END IF;
+
+  INSERT INTO transacoes (
+    cliente_id,
+    valor,
+    tipo,
+    descricao,
+    realizada_em)
+  VALUES (
+    param_cliente_id,
+    param_valor,
+    'c',
+    param_descricao,
+    COALESCE(param_realizada_em, NOW())
+  )
+  RETURNING id INTO transacao;
+
+  -- settlement pays in to the client, which is what moves the balance
+  PERFORM lancar(1, conta, param_valor, transacao);
+  resultado_saldo := saldo_antes + param_valor;
+
+  PERFORM notificar('c', param_cliente_id, param_valor, param_descricao, resultado_saldo, resultado_limite);
+
+  -- a key past its day may still be there, awaiting expirar_idempotencia
+  IF param_chave IS NOT NULL THEN
+    INSERT INTO idempotencia (cliente_id, chave, pedido, resultado_saldo, resultado_limite)
+    VALUES (param_cliente_id, param_chave, pedido('c', param_valor, param_descricao), resultado_saldo, resultado_limite)
+    ON CONFLICT (cliente_id, chave) DO UPDATE
+    SET pedido = EXCLUDED.pedido,
+      resultado_saldo = EXCLUDED.resultado_saldo,
+      resultado_limite = EXCLUDED.resultado_limite,
+      realizada_em = EXCLUDED.realizada_em;
+  END IF;
+
+  resultado_codigo := 0; -- success
+  PERFORM auditar(param_cliente_id, param_request_id, param_ip, param_principal, param_payload, resultado_codigo, saldo_antes, resultado_saldo);
+END;
+$$ LANGUAGE plpgsql;
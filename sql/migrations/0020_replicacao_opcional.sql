@@ -0,0 +1,10 @@
+-- logging the tables a replica reads costs every write a WAL record, which only pays
+-- off with a replica; they go back to unlogged and replicate() logs them again where
+-- DB_REPLICA_HOST is set. Tables referencing clientes go first, since a logged table
+-- can't reference an unlogged one
+ALTER TABLE idempotencia SET UNLOGGED;
+ALTER TABLE reservas SET UNLOGGED;
+ALTER TABLE lancamentos SET UNLOGGED;
+ALTER TABLE contas SET UNLOGGED;
+ALTER TABLE transacoes SET UNLOGGED;
+ALTER TABLE clientes SET UNLOGGED;
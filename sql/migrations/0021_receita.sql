@@ -0,0 +1,9 @@
+-- receita is part of the chart of accounts whether or not anything charges fees yet,
+-- so it comes back along with the type the check allows for it
+ALTER TABLE contas
+  DROP CONSTRAINT contas_tipo_check,
+  ADD CONSTRAINT contas_tipo_check
+    CHECK (tipo IN ('cliente', 'liquidacao', 'receita', 'abertura', 'ajuste'));
+
+INSERT INTO contas (id, tipo, nome) VALUES (2, 'receita', 'Receitas de tarifas')
+ON CONFLICT (id) DO NOTHING;
@@ -0,0 +1,7 @@
+-- a run left without an outcome, because the connection was lost mid-transaction or
+-- the scheduler stopped, is claimed again once its lease is up; retomada_em is when
+-- it last was, so that a run being made again isn't claimed twice
+ALTER TABLE execucoes ADD COLUMN retomada_em TIMESTAMP;
+
+CREATE INDEX idx_execucoes_sem_resultado ON execucoes (iniciada_em)
+WHERE resultado IS NULL;
@@ -1,2 +1,9 @@
+pub mod admin;
 pub mod app;
+pub mod deadline;
+pub mod graphql;
+pub mod grpc;
+pub mod holds;
+pub mod limiter;
+pub mod orders;
 pub mod routes;
@@ -0,0 +1,155 @@
+mod audit;
+mod import;
+mod policy;
+mod reconciliation;
+
+use crate::{
+    api::routes::{Principal, PRINCIPAL},
+    persistence::Admin,
+    telemetry,
+};
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+use std::sync::Arc;
+
+/// Whom operators not naming anyone are audited as.
+const ADMIN: &str = "admin";
+
+/// Routes for operators, all requiring `Authorization: Bearer <token>`.
+pub fn new(admin: Arc<dyn Admin>, token: &str) -> Router {
+    Router::new()
+        .route("/admin/auditoria", get(audit::index))
+        .route(
+            "/admin/conciliacao",
+            get(reconciliation::show).post(reconciliation::correct),
+        )
+        .route("/admin/importacao", post(import::create))
+        .route(
+            "/admin/clientes/:id/politica",
+            get(policy::show).put(policy::update),
+        )
+        .layer(middleware::from_fn_with_state(
+            Arc::<str>::from(token),
+            authorize,
+        ))
+        .with_state(admin)
+}
+
+/// Operators are audited as whom they say they act for, or as `admin`.
+async fn authorize(State(token): State<Arc<str>>, mut request: Request, next: Next) -> Response {
+    if !authorized(request.headers(), &token) {
+        telemetry::error!("Unauthorized admin request");
+
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let principal = request
+        .headers()
+        .get(PRINCIPAL)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or(ADMIN)
+        .to_string();
+
+    request.extensions_mut().insert(Principal(principal));
+
+    next.run(request).await
+}
+
+/// Whether `headers` carry `Authorization: Bearer <token>`.
+pub(super) fn authorized(headers: &HeaderMap, token: &str) -> bool {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes()))
+}
+
+/// Compares without returning at the first difference, so response times don't
+/// reveal how much of a guessed token was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::persistence::mock::MockAdmin;
+    use axum::{body::Body, Extension};
+    use http_body_util::BodyExt;
+    use rstest::rstest;
+    use tower::util::ServiceExt;
+
+    #[rstest]
+    #[case::valid(Some("Bearer secret"), StatusCode::OK)]
+    #[case::wrong_token(Some("Bearer secreT"), StatusCode::UNAUTHORIZED)]
+    #[case::wrong_scheme(Some("Basic secret"), StatusCode::UNAUTHORIZED)]
+    #[case::missing(None, StatusCode::UNAUTHORIZED)]
+    #[tokio::test]
+    async fn test_authorize(
+        #[case] authorization: Option<&str>,
+        #[case] expected_status: StatusCode,
+    ) {
+        let app = new(Arc::new(MockAdmin::default()), "secret");
+
+        let mut request = Request::builder().uri("/admin/auditoria");
+
+        if let Some(authorization) = authorization {
+            request = request.header(header::AUTHORIZATION, authorization);
+        }
+
+        let response = app
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), expected_status);
+    }
+
+    #[rstest]
+    #[case::named(Some("migracao"), "migracao")]
+    #[case::unnamed(None, ADMIN)]
+    #[tokio::test]
+    async fn test_authorize_principal(#[case] named: Option<&str>, #[case] expected: &str) {
+        let app = Router::new()
+            .route(
+                "/",
+                get(|Extension(Principal(principal)): Extension<Principal>| async move {
+                    principal
+                }),
+            )
+            .layer(middleware::from_fn_with_state(
+                Arc::<str>::from("secret"),
+                authorize,
+            ));
+
+        let mut request = Request::builder()
+            .uri("/")
+            .header(header::AUTHORIZATION, "Bearer secret");
+
+        if let Some(named) = named {
+            request = request.header(PRINCIPAL, named);
+        }
+
+        let response = app
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        assert_eq!(body, expected.as_bytes());
+    }
+
+    #[rstest]
+    #[case::equal(b"secret", b"secret", true)]
+    #[case::different(b"secret", b"secreT", false)]
+    #[case::prefix(b"secret", b"secre", false)]
+    fn test_constant_time_eq(#[case] a: &[u8], #[case] b: &[u8], #[case] expected: bool) {
+        assert_eq!(constant_time_eq(a, b), expected);
+    }
+}
@@ -0,0 +1,100 @@
+use crate::{models::AuditEntry, persistence::Admin};
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+#[derive(Deserialize)]
+pub struct Params {
+    cliente_id: Option<i16>,
+    quantidade: Option<i64>,
+}
+
+/// The latest audit entries, of every client unless `cliente_id` is given.
+pub async fn index(
+    State(admin): State<Arc<dyn Admin>>,
+    Query(params): Query<Params>,
+) -> Result<Json<Vec<AuditEntry>>, StatusCode> {
+    let limit = params
+        .quantidade
+        .unwrap_or(DEFAULT_LIMIT)
+        .clamp(1, MAX_LIMIT);
+
+    Ok(Json(admin.audit_log(params.cliente_id, limit).await?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::persistence::mock::{Call, MockAdmin};
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use http_body_util::BodyExt;
+    use rstest::rstest;
+    use serde_json::json;
+    use std::time::SystemTime;
+    use tower::util::ServiceExt;
+
+    #[rstest]
+    #[case::defaults("", (None, DEFAULT_LIMIT))]
+    #[case::client("?cliente_id=2", (Some(2), DEFAULT_LIMIT))]
+    #[case::limit("?quantidade=5", (None, 5))]
+    #[case::limit_too_large("?quantidade=5000", (None, MAX_LIMIT))]
+    #[tokio::test]
+    async fn test_index(#[case] query: &str, #[case] expected: (Option<i16>, i64)) {
+        let admin = Arc::new(MockAdmin {
+            audit_log: vec![AuditEntry {
+                id: 1,
+                realizada_em: SystemTime::UNIX_EPOCH,
+                request_id: None,
+                ip: Some([10, 0, 0, 1].into()),
+                principal: None,
+                cliente_id: 1,
+                payload: "{}".into(),
+                resultado_codigo: 2,
+                saldo_antes: Some(-100),
+                saldo_depois: Some(-100),
+            }],
+            ..Default::default()
+        });
+        let app = Router::new()
+            .route("/", get(index))
+            .with_state(admin.clone() as Arc<dyn Admin>);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/{}", query))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(admin.calls(), [Call::AuditLog(expected.0, expected.1)]);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()[0],
+            json!({
+                "id": 1,
+                "realizada_em": SystemTime::UNIX_EPOCH,
+                "request_id": null,
+                "ip": "10.0.0.1",
+                "principal": null,
+                "cliente_id": 1,
+                "payload": "{}",
+                "resultado_codigo": 2,
+                "saldo_antes": -100,
+                "saldo_depois": -100,
+            })
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use crate::{
+    api::routes::audit,
+    models::ImportReport,
+    persistence::{Admin, Error},
+};
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    Json,
+};
+use futures_util::{StreamExt, TryStreamExt};
+use std::sync::Arc;
+
+/// Imports the transactions of the CSV in the body, streamed to the database as
+/// it arrives rather than read whole first.
+pub async fn create(
+    State(admin): State<Arc<dyn Admin>>,
+    request: Request,
+) -> Result<Json<ImportReport>, StatusCode> {
+    let (parts, body) = request.into_parts();
+
+    let csv = body
+        .into_data_stream()
+        .map_err(|err| Error::Internal(format!("Failed to read upload: {}", err)))
+        .boxed();
+
+    Ok(Json(
+        admin
+            .import(csv, audit(&parts.headers, &parts.extensions, String::new()))
+            .await?,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        api::routes::Principal,
+        models::Rejection,
+        persistence::mock::{Call, MockAdmin},
+    };
+    use axum::{body::Body, routing::post, Router};
+    use http_body_util::BodyExt;
+    use serde_json::json;
+    use tower::util::ServiceExt;
+
+    async fn post_csv(admin: Arc<MockAdmin>, csv: &'static str) -> axum::response::Response {
+        let app = Router::new()
+            .route("/", post(create))
+            .with_state(admin as Arc<dyn Admin>);
+
+        app.oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/")
+                // As the admin middleware puts it.
+                .extension(Principal("migracao".into()))
+                .body(Body::from(csv))
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_create() {
+        let admin = Arc::new(MockAdmin {
+            import: Some(ImportReport {
+                aplicadas: 1,
+                rejeitadas: vec![Rejection {
+                    linha: 3,
+                    motivo: "limite_excedido".into(),
+                }],
+            }),
+            ..Default::default()
+        });
+        let csv = "cliente_id,tipo,valor,descricao,realizada_em\n1,c,10,pix,\n1,d,99999,pix,\n";
+
+        let response = post_csv(admin.clone(), csv).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            json!({
+                "aplicadas": 1,
+                "rejeitadas": [{ "linha": 3, "motivo": "limite_excedido" }],
+            })
+        );
+
+        let [Call::Import(received, audit)] = &admin.calls()[..] else {
+            panic!("import wasn't called once");
+        };
+
+        assert_eq!(received, csv.as_bytes());
+        assert_eq!(audit.principal.as_deref(), Some("migracao"));
+    }
+
+    #[tokio::test]
+    async fn test_create_invalid() {
+        let response = post_csv(Arc::new(MockAdmin::default()), "").await;
+
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+}
@@ -0,0 +1,124 @@
+use crate::{models::Policy, persistence::Admin, telemetry};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use std::sync::Arc;
+
+/// The client's policy.
+pub async fn show(
+    State(admin): State<Arc<dyn Admin>>,
+    Path(id): Path<i16>,
+) -> Result<Json<Policy>, StatusCode> {
+    Ok(Json(admin.policy(id).await?))
+}
+
+/// Replaces the client's policy, answering with it as stored.
+pub async fn update(
+    State(admin): State<Arc<dyn Admin>>,
+    Path(id): Path<i16>,
+    Json(policy): Json<Policy>,
+) -> Result<Json<Policy>, StatusCode> {
+    if !policy.is_valid() {
+        telemetry::error!("Invalid policy for client {}", id);
+
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    admin.set_policy(id, &policy).await?;
+
+    Ok(Json(policy))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::persistence::mock::{Call, MockAdmin};
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use http_body_util::BodyExt;
+    use rstest::rstest;
+    use serde_json::{json, Value};
+    use tower::util::ServiceExt;
+
+    fn app(admin: Arc<MockAdmin>) -> Router {
+        Router::new()
+            .route("/:id", get(show).put(update))
+            .with_state(admin as Arc<dyn Admin>)
+    }
+
+    #[rstest]
+    #[case::found("/1", StatusCode::OK)]
+    #[case::not_found("/2", StatusCode::NOT_FOUND)]
+    #[tokio::test]
+    async fn test_show(#[case] uri: &str, #[case] expected_status: StatusCode) {
+        let response = app(Arc::default())
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), expected_status);
+
+        if expected_status == StatusCode::OK {
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+
+            assert_eq!(
+                serde_json::from_slice::<Value>(&body).unwrap(),
+                json!({
+                    "valor_maximo": null,
+                    "valor_minimo": null,
+                    "descricoes_permitidas": null,
+                    "descricoes_bloqueadas": null,
+                    "creditos_permitidos": true,
+                })
+            );
+        }
+    }
+
+    #[rstest]
+    #[case::valid("/1", json!({"valor_maximo": 500, "descricoes_bloqueadas": ["aposta"]}), StatusCode::OK)]
+    #[case::not_found("/2", json!({}), StatusCode::NOT_FOUND)]
+    #[case::minimum_above_maximum("/1", json!({"valor_maximo": 5, "valor_minimo": 10}), StatusCode::UNPROCESSABLE_ENTITY)]
+    #[case::both_lists("/1", json!({"descricoes_permitidas": ["a"], "descricoes_bloqueadas": ["b"]}), StatusCode::UNPROCESSABLE_ENTITY)]
+    #[case::description_too_long("/1", json!({"descricoes_permitidas": ["abcdefghijk"]}), StatusCode::UNPROCESSABLE_ENTITY)]
+    #[tokio::test]
+    async fn test_update(
+        #[case] uri: &str,
+        #[case] body: Value,
+        #[case] expected_status: StatusCode,
+    ) {
+        let admin = Arc::new(MockAdmin::default());
+
+        let response = app(admin.clone())
+            .oneshot(
+                Request::put(uri)
+                    .header("content-type", "application/json")
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), expected_status);
+        assert_eq!(
+            admin
+                .calls()
+                .iter()
+                .any(|call| matches!(call, Call::SetPolicy(1, _))),
+            expected_status == StatusCode::OK
+        );
+    }
+
+    #[test]
+    fn test_defaults_to_credits_allowed() {
+        let policy: Policy = serde_json::from_value(json!({"valor_minimo": 1})).unwrap();
+
+        assert_eq!(
+            policy,
+            Policy {
+                valor_minimo: Some(1),
+                ..Policy::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,51 @@
+use crate::{models::Discrepancy, persistence::Admin};
+use axum::{extract::State, http::StatusCode, Json};
+use std::sync::Arc;
+
+/// Reports the clients failing reconciliation.
+pub async fn show(
+    State(admin): State<Arc<dyn Admin>>,
+) -> Result<Json<Vec<Discrepancy>>, StatusCode> {
+    Ok(Json(admin.reconcile(false).await?))
+}
+
+/// Corrects the balances that drifted, then reports as [`show`] does.
+pub async fn correct(
+    State(admin): State<Arc<dyn Admin>>,
+) -> Result<Json<Vec<Discrepancy>>, StatusCode> {
+    Ok(Json(admin.reconcile(true).await?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::persistence::mock::{Call, MockAdmin};
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use rstest::rstest;
+    use tower::util::ServiceExt;
+
+    #[rstest]
+    #[case::show("GET", false)]
+    #[case::correct("POST", true)]
+    #[tokio::test]
+    async fn test_reconcile(#[case] method: &str, #[case] expected_correct: bool) {
+        let admin = Arc::new(MockAdmin::default());
+        let app = Router::new()
+            .route("/", get(show).post(correct))
+            .with_state(admin.clone() as Arc<dyn Admin>);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri("/")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(admin.calls(), [Call::Reconcile(expected_correct)]);
+    }
+}
@@ -1,14 +1,55 @@
-use super::routes;
-use crate::persistence::Repository;
-use axum::{
-    routing::{get, post},
-    Router,
+use super::{
+    deadline,
+    routes::{self, export, statement, transaction},
 };
-use std::sync::Arc;
+use crate::persistence::Repository;
+use axum::{middleware, routing::get, Router};
+use std::{sync::Arc, time::Duration};
+use utoipa_axum::{router::OpenApiRouter, router::UtoipaMethodRouter, routes};
+
+/// How long each route may take before answering 504, unlimited when `None`.
+#[derive(Clone, Copy, Default)]
+pub struct Deadlines {
+    pub transaction: Option<Duration>,
+    pub statement: Option<Duration>,
+}
+
+pub fn new(repo: Arc<dyn Repository>, deadlines: Deadlines) -> Router {
+    let (router, _) = routes(deadlines).split_for_parts();
 
-pub fn new(repo: Arc<dyn Repository>) -> Router {
-    Router::new()
-        .route("/clientes/:id/transacoes", post(routes::create_transaction))
-        .route("/clientes/:id/extrato", get(routes::show_balance))
+    router
+        .route("/metrics", get(routes::show_metrics))
         .with_state(repo)
+        .merge(routes::docs())
+}
+
+/// The routes of client accounts along with their documentation, which
+/// `routes::docs` collects.
+pub(crate) fn routes(deadlines: Deadlines) -> OpenApiRouter<Arc<dyn Repository>> {
+    OpenApiRouter::new()
+        .routes(with_deadline(
+            routes!(transaction::create),
+            deadlines.transaction,
+        ))
+        .routes(with_deadline(routes!(statement::show), deadlines.statement))
+        // No deadline, an export takes as long as the history is long. The repository
+        // caps how many run at once and gives up on the ones that stall.
+        .routes(routes!(export::export))
+}
+
+fn with_deadline<S>(
+    (schemas, paths, route): UtoipaMethodRouter<S>,
+    deadline: Option<Duration>,
+) -> UtoipaMethodRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    match deadline {
+        Some(deadline) => (
+            schemas,
+            paths,
+            route.layer(middleware::from_fn_with_state(deadline, deadline::enforce)),
+        ),
+        None => (schemas, paths, route),
+    }
 }
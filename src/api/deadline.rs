@@ -0,0 +1,56 @@
+use crate::telemetry;
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::time::Duration;
+
+/// Answers 504 once `deadline` has passed. The handler is dropped along with any
+/// query it was waiting on, which the database repository then cancels, the same
+/// as when the client disconnects.
+pub async fn enforce(State(deadline): State<Duration>, request: Request, next: Next) -> Response {
+    match tokio::time::timeout(deadline, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => {
+            telemetry::error!("Deadline of {:?} exceeded", deadline);
+
+            StatusCode::GATEWAY_TIMEOUT.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{body::Body, middleware, routing::get, Router};
+    use rstest::rstest;
+    use tower::util::ServiceExt;
+
+    #[rstest]
+    #[case::within_deadline(Duration::ZERO, StatusCode::OK)]
+    #[case::past_deadline(Duration::from_secs(60), StatusCode::GATEWAY_TIMEOUT)]
+    #[tokio::test]
+    async fn test_enforce(#[case] latency: Duration, #[case] expected_status: StatusCode) {
+        let app = Router::new()
+            .route(
+                "/",
+                get(move || async move {
+                    tokio::time::sleep(latency).await;
+                    "ok"
+                }),
+            )
+            .layer(middleware::from_fn_with_state(
+                Duration::from_millis(10),
+                enforce,
+            ));
+
+        let response = app
+            .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), expected_status);
+    }
+}
@@ -0,0 +1,644 @@
+use super::routes::{audit, StatementResponse, TransactionRequest};
+use crate::{
+    dates::{Date, DateTime},
+    models,
+    persistence::{self, Audit, Period, Repository},
+    telemetry,
+};
+use async_graphql::{
+    dataloader::{DataLoader, Loader},
+    Context, EmptySubscription, Enum, ErrorExtensions, Object, Schema, SimpleObject,
+};
+use axum::{extract::State, http::request::Parts, routing::post, Json, Router};
+use std::{collections::HashMap, str::FromStr, sync::Arc};
+
+/// Most clients a single `clientes` field may ask for.
+const MAX_CLIENTS: usize = 100;
+
+/// Most transactions a single `transacoes` field returns.
+const MAX_TRANSACTIONS: i32 = 1000;
+
+/// Deepest query accepted. Queries on clients are at most 3 deep, the rest is for
+/// the `ofType` chains of introspection.
+const MAX_DEPTH: usize = 12;
+
+/// Most a query may cost, a field costing 1 unless priced below. Enough for
+/// `clientes` with 100 statements, or one full page of `transacoes`.
+const MAX_COMPLEXITY: usize = 5000;
+
+/// What a `transacoes` field costs on top of its rows, being a query of its own.
+const TRANSACTIONS_COST: usize = 50;
+
+#[derive(Clone)]
+struct Graphql {
+    schema: Schema<Query, Mutation, EmptySubscription>,
+    repo: Arc<dyn Repository>,
+}
+
+/// `POST /graphql`, for clients, their balances and transactions.
+pub fn new(repo: Arc<dyn Repository>) -> Router {
+    let schema = Schema::build(Query, Mutation, EmptySubscription)
+        .data(repo.clone())
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish();
+
+    Router::new()
+        .route("/graphql", post(execute))
+        .with_state(Graphql { schema, repo })
+}
+
+async fn execute(
+    State(graphql): State<Graphql>,
+    parts: Parts,
+    Json(request): Json<async_graphql::Request>,
+) -> Json<async_graphql::Response> {
+    // Per request, so statements are batched within a query but never reused across them.
+    let request = request
+        .data(DataLoader::new(Statements(graphql.repo), tokio::spawn))
+        .data(audit(&parts.headers, &parts.extensions, String::new()));
+
+    Json(graphql.schema.execute(request).await)
+}
+
+/// Loads statements in batches, so many clients cost a single query.
+struct Statements(Arc<dyn Repository>);
+
+impl Loader<i16> for Statements {
+    type Value = StatementResponse;
+    type Error = persistence::Error;
+
+    async fn load(&self, keys: &[i16]) -> Result<HashMap<i16, Self::Value>, Self::Error> {
+        self.0.get_balances(keys).await
+    }
+}
+
+impl From<persistence::Error> for async_graphql::Error {
+    fn from(err: persistence::Error) -> Self {
+        telemetry::error!("Database error: {:?}", err);
+
+        let (message, code) = match err {
+            persistence::Error::ClientNotFound => ("client not found".into(), "NOT_FOUND"),
+            persistence::Error::BalanceConstraintViolation => {
+                ("limit exceeded".into(), "LIMIT_EXCEEDED")
+            }
+            persistence::Error::PolicyViolation(rule) => {
+                return error("refused by policy", "POLICY_VIOLATION")
+                    .extend_with(|_, extensions| extensions.set("rule", rule.code()));
+            }
+            persistence::Error::InvalidInput(message) => (message, "INVALID_INPUT"),
+            persistence::Error::Transient(_)
+            | persistence::Error::ConnectionLost(_)
+            | persistence::Error::Unavailable => ("database unavailable".into(), "UNAVAILABLE"),
+            _ => ("internal error".into(), "INTERNAL"),
+        };
+
+        error(message, code)
+    }
+}
+
+/// An error with `code` in its extensions, for clients to tell errors apart by.
+fn error(message: impl Into<String>, code: &'static str) -> async_graphql::Error {
+    async_graphql::Error::new(message).extend_with(|_, extensions| extensions.set("code", code))
+}
+
+fn invalid(message: impl Into<String>) -> async_graphql::Error {
+    error(message, "INVALID_INPUT")
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Enum)]
+enum Tipo {
+    Credito,
+    Debito,
+}
+
+impl Tipo {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Credito => "c",
+            Self::Debito => "d",
+        }
+    }
+}
+
+impl FromStr for Tipo {
+    type Err = ();
+
+    fn from_str(tipo: &str) -> Result<Self, Self::Err> {
+        match tipo {
+            "c" => Ok(Self::Credito),
+            "d" => Ok(Self::Debito),
+            _ => Err(()),
+        }
+    }
+}
+
+struct Cliente {
+    id: i16,
+    statement: StatementResponse,
+}
+
+#[derive(SimpleObject)]
+struct Saldo {
+    total: i32,
+    /// `total` less what pending holds set aside.
+    disponivel: i32,
+    limite: i32,
+    /// RFC 3339, in UTC.
+    data_extrato: String,
+}
+
+#[derive(SimpleObject)]
+struct Transacao {
+    /// Centavos.
+    valor: i16,
+    tipo: Option<Tipo>,
+    descricao: String,
+    /// RFC 3339, in UTC.
+    realizada_em: String,
+}
+
+impl From<&models::Transaction> for Transacao {
+    fn from(transaction: &models::Transaction) -> Self {
+        Self {
+            valor: transaction.valor,
+            tipo: transaction.tipo.parse().ok(),
+            descricao: transaction.descricao.clone(),
+            realizada_em: DateTime::of(transaction.realizada_em).to_string(),
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+struct ResultadoTransacao {
+    limite: i32,
+    saldo: i32,
+}
+
+#[Object]
+impl Cliente {
+    async fn id(&self) -> i16 {
+        self.id
+    }
+
+    async fn saldo(&self) -> Saldo {
+        Saldo {
+            total: self.statement.saldo.total,
+            disponivel: self.statement.saldo.disponivel,
+            limite: self.statement.saldo.limite,
+            data_extrato: DateTime::of(self.statement.saldo.data_extrato).to_string(),
+        }
+    }
+
+    /// The latest transactions of the statement, newest first.
+    async fn ultimas_transacoes(&self, tipo: Option<Tipo>) -> Vec<Transacao> {
+        self.statement
+            .ultimas_transacoes
+            .iter()
+            .map(Transacao::from)
+            .filter(|transacao| tipo.is_none() || transacao.tipo == tipo)
+            .collect()
+    }
+
+    /// Transactions from `de` through `ate`, `YYYY-MM-DD` in UTC, oldest first. A query
+    /// of its own per client rather than batched, so it costs more than other fields.
+    #[graphql(complexity = "TRANSACTIONS_COST + primeiras.max(0) as usize * child_complexity")]
+    async fn transacoes(
+        &self,
+        ctx: &Context<'_>,
+        de: Option<String>,
+        ate: Option<String>,
+        tipo: Option<Tipo>,
+        #[graphql(default = 100)] primeiras: i32,
+    ) -> async_graphql::Result<Vec<Transacao>> {
+        let date = |date: Option<String>| {
+            date.map(|date| Date::from_str(&date).map_err(|_| invalid("invalid date")))
+                .transpose()
+        };
+
+        if !(0..=MAX_TRANSACTIONS).contains(&primeiras) {
+            return Err(invalid(format!(
+                "primeiras must be from 0 to {}",
+                MAX_TRANSACTIONS
+            )));
+        }
+
+        let period = Period {
+            from: date(de)?.map(|date| date.start()),
+            to: date(ate)?.map(|date| date.end()),
+        };
+
+        let transactions = ctx
+            .data_unchecked::<Arc<dyn Repository>>()
+            .transactions(
+                &self.id,
+                period,
+                tipo.map(|tipo| tipo.code()),
+                primeiras.into(),
+            )
+            .await?;
+
+        Ok(transactions.iter().map(Transacao::from).collect())
+    }
+}
+
+struct Query;
+
+#[Object]
+impl Query {
+    /// A client, null when it doesn't exist.
+    async fn cliente(&self, ctx: &Context<'_>, id: i16) -> async_graphql::Result<Option<Cliente>> {
+        let statement = ctx
+            .data_unchecked::<DataLoader<Statements>>()
+            .load_one(id)
+            .await?;
+
+        Ok(statement.map(|statement| Cliente { id, statement }))
+    }
+
+    /// Several clients in the order asked for, leaving out those that don't exist.
+    #[graphql(complexity = "ids.len() * child_complexity")]
+    async fn clientes(
+        &self,
+        ctx: &Context<'_>,
+        ids: Vec<i16>,
+    ) -> async_graphql::Result<Vec<Cliente>> {
+        if ids.len() > MAX_CLIENTS {
+            return Err(invalid(format!("at most {} ids", MAX_CLIENTS)));
+        }
+
+        let mut statements = ctx
+            .data_unchecked::<DataLoader<Statements>>()
+            .load_many(ids.iter().copied())
+            .await?;
+
+        Ok(ids
+            .into_iter()
+            .filter_map(|id| {
+                statements
+                    .remove(&id)
+                    .map(|statement| Cliente { id, statement })
+            })
+            .collect())
+    }
+}
+
+struct Mutation;
+
+#[Object]
+impl Mutation {
+    /// The same as `POST /clientes/{id}/transacoes`.
+    async fn criar_transacao(
+        &self,
+        ctx: &Context<'_>,
+        cliente_id: i16,
+        valor: i16,
+        tipo: Tipo,
+        descricao: String,
+    ) -> async_graphql::Result<ResultadoTransacao> {
+        // Recorded as its JSON equivalent, like binary bodies over HTTP.
+        let payload = serde_json::json!({
+            "valor": valor,
+            "tipo": tipo.code(),
+            "descricao": descricao,
+        });
+        let data = TransactionRequest {
+            valor,
+            tipo: tipo.code().into(),
+            descricao,
+        };
+        // The request's idempotency key would cover every transaction it makes, so
+        // none are made under it.
+        let audit = Audit {
+            payload: payload.to_string(),
+            idempotency_key: None,
+            ..ctx.data_unchecked::<Audit>().clone()
+        };
+        let repo = ctx.data_unchecked::<Arc<dyn Repository>>();
+
+        if !data.is_valid() {
+            telemetry::error!("Invalid transaction kind or description");
+
+            // Refused either way, failing to record it shouldn't hide why.
+            #[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+            if let Err(err) = repo.reject(&cliente_id, &audit).await {
+                telemetry::error!("Failed to audit an invalid transaction: {:?}", err);
+            }
+
+            return Err(invalid("invalid descricao"));
+        }
+
+        let response = repo.create_transaction(&cliente_id, &data, &audit).await?;
+
+        Ok(ResultadoTransacao {
+            limite: response.limite,
+            saldo: response.saldo,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        api::routes::{Principal, TransactionResponse},
+        persistence::{Error, Export, ReadToken, Rule},
+    };
+    use axum::{async_trait, body::Body};
+    use http_body_util::BodyExt;
+    use serde_json::{json, Value};
+    use std::{sync::Mutex, time::SystemTime};
+    use tower::util::ServiceExt;
+
+    #[derive(Default)]
+    struct MockRepository {
+        batches: Mutex<Vec<Vec<i16>>>,
+        received: Mutex<Option<(TransactionRequest, Audit)>>,
+        rejected: Mutex<Option<Audit>>,
+        transactions: Mutex<Option<(Period, Option<String>, i64)>>,
+    }
+
+    fn transaction(valor: i16, tipo: &str) -> models::Transaction {
+        models::Transaction {
+            valor,
+            tipo: tipo.into(),
+            descricao: "bar".into(),
+            realizada_em: SystemTime::UNIX_EPOCH,
+        }
+    }
+
+    #[async_trait]
+    impl Repository for MockRepository {
+        async fn get_balance(
+            &self,
+            _client_id: &i16,
+            _token: Option<&ReadToken>,
+        ) -> Result<StatementResponse, Error> {
+            unimplemented!("statements are only read in batches")
+        }
+
+        async fn get_balances(
+            &self,
+            client_ids: &[i16],
+        ) -> Result<HashMap<i16, StatementResponse>, Error> {
+            let mut batch = client_ids.to_vec();
+            batch.sort();
+            self.batches.lock().unwrap().push(batch);
+
+            Ok(client_ids
+                .iter()
+                .filter(|id| **id <= 2)
+                .map(|id| {
+                    (
+                        *id,
+                        StatementResponse {
+                            saldo: models::Balance {
+                                total: -i32::from(*id),
+                                disponivel: -i32::from(*id),
+                                data_extrato: SystemTime::UNIX_EPOCH,
+                                limite: 1000,
+                            },
+                            ultimas_transacoes: vec![transaction(1, "d"), transaction(2, "c")],
+                        },
+                    )
+                })
+                .collect())
+        }
+
+        async fn create_transaction(
+            &self,
+            client_id: &i16,
+            data: &TransactionRequest,
+            audit: &Audit,
+        ) -> Result<TransactionResponse, Error> {
+            *self.received.lock().unwrap() = Some((data.clone(), audit.clone()));
+
+            match (client_id, data.valor) {
+                (1, _) if data.descricao == "aposta" => {
+                    Err(Error::PolicyViolation(Rule::DescriptionRefused))
+                }
+                (1, 0..=1000) => Ok(TransactionResponse {
+                    limite: 1000,
+                    saldo: -i32::from(data.valor),
+                    token: None,
+                }),
+                (1, _) => Err(Error::BalanceConstraintViolation),
+                _ => Err(Error::ClientNotFound),
+            }
+        }
+
+        async fn reject(&self, _client_id: &i16, audit: &Audit) -> Result<(), Error> {
+            *self.rejected.lock().unwrap() = Some(audit.clone());
+
+            Ok(())
+        }
+
+        async fn export(&self, _client_id: &i16, _period: Period) -> Result<Export, Error> {
+            unimplemented!("transactions are read in a bounded query")
+        }
+
+        async fn transactions(
+            &self,
+            _client_id: &i16,
+            period: Period,
+            tipo: Option<&str>,
+            limit: i64,
+        ) -> Result<Vec<models::Transaction>, Error> {
+            *self.transactions.lock().unwrap() = Some((period, tipo.map(String::from), limit));
+
+            Ok([(1, "c"), (2, "d"), (3, "c"), (4, "c")]
+                .into_iter()
+                .filter(|(_, kind)| tipo.is_none_or(|tipo| tipo == *kind))
+                .take(limit as usize)
+                .map(|(valor, kind)| transaction(valor, kind))
+                .collect())
+        }
+    }
+
+    async fn execute(repo: Arc<MockRepository>, query: &str) -> Value {
+        let response = new(repo)
+            .oneshot(
+                axum::http::Request::builder()
+                    .method(axum::http::Method::POST)
+                    .uri("/graphql")
+                    .header(axum::http::header::CONTENT_TYPE, "application/json")
+                    .extension(Principal("alice".into()))
+                    .body(Body::from(json!({ "query": query }).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_clients_batched() {
+        let repo = Arc::new(MockRepository::default());
+
+        let response = execute(
+            repo.clone(),
+            r#"{
+                a: cliente(id: 1) { saldo { total limite dataExtrato } }
+                b: cliente(id: 3) { id }
+                clientes(ids: [2, 4, 1]) { id ultimasTransacoes(tipo: DEBITO) { valor tipo } }
+            }"#,
+        )
+        .await;
+
+        assert_eq!(
+            response,
+            json!({
+                "data": {
+                    "a": {
+                        "saldo": {
+                            "total": -1,
+                            "limite": 1000,
+                            "dataExtrato": "1970-01-01T00:00:00.000000Z",
+                        },
+                    },
+                    "b": null,
+                    "clientes": [
+                        { "id": 2, "ultimasTransacoes": [{ "valor": 1, "tipo": "DEBITO" }] },
+                        { "id": 1, "ultimasTransacoes": [{ "valor": 1, "tipo": "DEBITO" }] },
+                    ],
+                },
+            })
+        );
+        assert_eq!(*repo.batches.lock().unwrap(), vec![vec![1, 2, 3, 4]]);
+    }
+
+    #[tokio::test]
+    async fn test_transactions() {
+        let repo = Arc::new(MockRepository::default());
+
+        let response = execute(
+            repo.clone(),
+            r#"{
+                cliente(id: 1) {
+                    transacoes(de: "2024-02-01", ate: "2024-02-29", tipo: CREDITO, primeiras: 2) {
+                        valor
+                    }
+                }
+            }"#,
+        )
+        .await;
+
+        assert_eq!(
+            response,
+            json!({ "data": { "cliente": { "transacoes": [{ "valor": 1 }, { "valor": 3 }] } } })
+        );
+        assert_eq!(
+            *repo.transactions.lock().unwrap(),
+            Some((
+                Period {
+                    from: Some("2024-02-01".parse::<Date>().unwrap().start()),
+                    to: Some("2024-02-29".parse::<Date>().unwrap().end()),
+                },
+                Some("c".into()),
+                2
+            ))
+        );
+    }
+
+    #[rstest::rstest]
+    #[case::too_deep(
+        "{ __schema { types { fields { type { ofType { ofType { ofType { ofType { \
+            ofType { ofType { ofType { ofType { name } } } } } } } } } } } } }",
+        "Query is nested too deep."
+    )]
+    #[case::too_complex(
+        "{ clientes(ids: [1, 2]) { transacoes(primeiras: 1000) { valor tipo descricao } } }",
+        "Query is too complex."
+    )]
+    #[tokio::test]
+    async fn test_limits(#[case] query: &str, #[case] expected: &str) {
+        let repo = Arc::new(MockRepository::default());
+
+        let response = execute(repo.clone(), query).await;
+
+        assert_eq!(response["errors"][0]["message"], expected);
+        assert!(repo.batches.lock().unwrap().is_empty());
+        assert!(repo.transactions.lock().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_full_page_of_transactions() {
+        let response = execute(
+            Arc::default(),
+            "{ cliente(id: 1) { transacoes(primeiras: 1000) { valor tipo descricao realizadaEm } } }",
+        )
+        .await;
+
+        assert_eq!(response["errors"], Value::Null);
+        assert_eq!(response["data"]["cliente"]["transacoes"][3]["valor"], 4);
+    }
+
+    #[tokio::test]
+    async fn test_create_transaction() {
+        let repo = Arc::new(MockRepository::default());
+
+        let response = execute(
+            repo.clone(),
+            r#"mutation {
+                criarTransacao(clienteId: 1, valor: 10, tipo: DEBITO, descricao: "bar") {
+                    limite
+                    saldo
+                }
+            }"#,
+        )
+        .await;
+
+        assert_eq!(
+            response,
+            json!({ "data": { "criarTransacao": { "limite": 1000, "saldo": -10 } } })
+        );
+
+        let (received, audit) = repo.received.lock().unwrap().take().unwrap();
+
+        assert_eq!(received.tipo, "d");
+        assert_eq!(audit.principal.as_deref(), Some("alice"));
+        assert_eq!(
+            serde_json::from_str::<Value>(&audit.payload).unwrap(),
+            json!({ "valor": 10, "tipo": "d", "descricao": "bar" })
+        );
+    }
+
+    #[rstest::rstest]
+    #[case::client_not_found(2, 10, "bar", "NOT_FOUND")]
+    #[case::limit_exceeded(1, 2000, "bar", "LIMIT_EXCEEDED")]
+    #[case::policy_violation(1, 10, "aposta", "POLICY_VIOLATION")]
+    #[case::long_descricao(1, 10, "descricao longa", "INVALID_INPUT")]
+    #[case::blank_descricao(1, 10, "", "INVALID_INPUT")]
+    #[tokio::test]
+    async fn test_create_transaction_rejected(
+        #[case] cliente_id: i16,
+        #[case] valor: i16,
+        #[case] descricao: &str,
+        #[case] expected: &str,
+    ) {
+        let repo = Arc::new(MockRepository::default());
+
+        let response = execute(
+            repo.clone(),
+            &format!(
+                r#"mutation {{
+                    criarTransacao(clienteId: {}, valor: {}, tipo: CREDITO, descricao: "{}") {{
+                        saldo
+                    }}
+                }}"#,
+                cliente_id, valor, descricao
+            ),
+        )
+        .await;
+
+        assert_eq!(response["data"], Value::Null);
+        assert_eq!(response["errors"][0]["extensions"]["code"], expected);
+        assert_eq!(
+            repo.rejected.lock().unwrap().is_some(),
+            expected == "INVALID_INPUT"
+        );
+    }
+}
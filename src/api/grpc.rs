@@ -0,0 +1,620 @@
+use super::{
+    admin,
+    routes::{audit, TransactionRequest},
+};
+use crate::{
+    models,
+    persistence::{
+        self,
+        notifications::{Notification, Notifications},
+        Audit, Repository,
+    },
+    telemetry,
+};
+use axum::Router;
+use futures_util::stream::{self, BoxStream, StreamExt};
+use proto::rinha_server::RinhaServer;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tonic::{server::NamedService, Status};
+
+/// Messages and server of `proto/rinha.proto`, generated by `build.rs`.
+mod proto {
+    tonic::include_proto!("rinha.v1");
+}
+
+type TransactionEvents = BoxStream<'static, Result<proto::TransactionEvent, Status>>;
+
+/// The gRPC service, answering under `/rinha.v1.Rinha/` next to the HTTP routes. Clients
+/// reach it over HTTP/2 without TLS, which the server negotiates per connection.
+///
+/// Watching every client at once takes `admin_token`, and without one isn't served.
+pub fn new(
+    repo: Arc<dyn Repository>,
+    notifications: Notifications,
+    admin_token: Option<&str>,
+) -> Router {
+    Router::new().route_service(
+        &format!("/{}/*method", RinhaServer::<Rinha>::NAME),
+        RinhaServer::new(Rinha {
+            repo,
+            notifications,
+            admin_token: admin_token.map(Arc::from),
+        }),
+    )
+}
+
+struct Rinha {
+    repo: Arc<dyn Repository>,
+    notifications: Notifications,
+    admin_token: Option<Arc<str>>,
+}
+
+impl Rinha {
+    /// Records an invalid transaction in the audit log, answering with why it is.
+    async fn reject(&self, id: i16, audit: &Audit, message: &str) -> Status {
+        // Refused either way, failing to record it shouldn't hide why.
+        #[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+        if let Err(err) = self.repo.reject(&id, audit).await {
+            telemetry::error!("Failed to audit an invalid transaction: {:?}", err);
+        }
+
+        Status::invalid_argument(message)
+    }
+}
+
+#[tonic::async_trait]
+impl proto::rinha_server::Rinha for Rinha {
+    type WatchTransactionsStream = TransactionEvents;
+
+    async fn create_transaction(
+        &self,
+        request: tonic::Request<proto::CreateTransactionRequest>,
+    ) -> Result<tonic::Response<proto::CreateTransactionResponse>, Status> {
+        let message = request.get_ref();
+
+        // Recorded as its JSON equivalent, like binary bodies over HTTP.
+        let payload = serde_json::json!({
+            "valor": message.valor,
+            "tipo": message.tipo,
+            "descricao": message.descricao,
+        });
+        let audit = audit(
+            &request.metadata().clone().into_headers(),
+            request.extensions(),
+            payload.to_string(),
+        );
+
+        let message = request.into_inner();
+        let id = client_id(message.cliente_id)?;
+        let Ok(valor) = i16::try_from(message.valor) else {
+            return Err(self.reject(id, &audit, "valor out of range").await);
+        };
+        let data = TransactionRequest {
+            valor,
+            tipo: message.tipo,
+            descricao: message.descricao,
+        };
+
+        if !data.is_valid() {
+            telemetry::error!("Invalid transaction kind or description");
+
+            return Err(self.reject(id, &audit, "invalid tipo or descricao").await);
+        }
+
+        let response = self.repo.create_transaction(&id, &data, &audit).await?;
+
+        Ok(tonic::Response::new(proto::CreateTransactionResponse {
+            limite: response.limite,
+            saldo: response.saldo,
+            read_token: response
+                .token
+                .map(|token| token.to_string())
+                .unwrap_or_default(),
+        }))
+    }
+
+    async fn get_statement(
+        &self,
+        request: tonic::Request<proto::GetStatementRequest>,
+    ) -> Result<tonic::Response<proto::Statement>, Status> {
+        let message = request.into_inner();
+        let id = client_id(message.cliente_id)?;
+        let token = match message.read_token.as_str() {
+            "" => None,
+            token => Some(token.parse().map_err(|_| {
+                telemetry::error!("Invalid read token");
+
+                Status::invalid_argument("invalid read_token")
+            })?),
+        };
+
+        let statement = self.repo.get_balance(&id, token.as_ref()).await?;
+
+        Ok(tonic::Response::new(proto::Statement {
+            saldo: Some(proto::Balance {
+                total: statement.saldo.total,
+                disponivel: statement.saldo.disponivel,
+                data_extrato: Some(statement.saldo.data_extrato.into()),
+                limite: statement.saldo.limite,
+            }),
+            ultimas_transacoes: statement
+                .ultimas_transacoes
+                .into_iter()
+                .map(proto::Transaction::from)
+                .collect(),
+        }))
+    }
+
+    async fn watch_transactions(
+        &self,
+        request: tonic::Request<proto::WatchTransactionsRequest>,
+    ) -> Result<tonic::Response<TransactionEvents>, Status> {
+        let headers = request.metadata().clone().into_headers();
+        let id = request.into_inner().cliente_id.map(client_id).transpose()?;
+
+        // Every client's transactions are for operators only.
+        if id.is_none()
+            && !self
+                .admin_token
+                .as_ref()
+                .is_some_and(|token| admin::authorized(&headers, token))
+        {
+            telemetry::error!("Unauthorized watch of every client");
+
+            return Err(Status::unauthenticated(
+                "watching every client takes the admin token",
+            ));
+        }
+
+        // Subscribed first, so nothing committed after the check is missed.
+        let receiver = self.notifications.subscribe();
+
+        if let Some(id) = id {
+            self.repo.get_balance(&id, None).await?;
+        }
+
+        let events = stream::unfold(Some(receiver), move |receiver| async move {
+            let mut receiver = receiver?;
+
+            loop {
+                match receiver.recv().await {
+                    Ok(notification) if id.is_none() || id == Some(notification.cliente_id) => {
+                        return Some((Ok(notification.into()), Some(receiver)));
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(missed)) => {
+                        let status =
+                            Status::aborted(format!("fell behind by {} transactions", missed));
+
+                        return Some((Err(status), None));
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+
+        Ok(tonic::Response::new(events.boxed()))
+    }
+}
+
+// `Status` is large, but it's what every handler returns anyway.
+#[allow(clippy::result_large_err)]
+fn client_id(id: i32) -> Result<i16, Status> {
+    i16::try_from(id).map_err(|_| Status::invalid_argument("cliente_id out of range"))
+}
+
+impl From<persistence::Error> for Status {
+    fn from(err: persistence::Error) -> Self {
+        telemetry::error!("Database error: {:?}", err);
+
+        match err {
+            persistence::Error::ClientNotFound => Status::not_found("client not found"),
+            persistence::Error::BalanceConstraintViolation => {
+                Status::failed_precondition("limit exceeded")
+            }
+            persistence::Error::PolicyViolation(rule) => {
+                Status::failed_precondition(format!("refused by policy: {}", rule.code()))
+            }
+            persistence::Error::InvalidInput(message) => Status::invalid_argument(message),
+            persistence::Error::Transient(_)
+            | persistence::Error::ConnectionLost(_)
+            | persistence::Error::Unavailable => Status::unavailable("database unavailable"),
+            _ => Status::internal("internal error"),
+        }
+    }
+}
+
+impl From<models::Transaction> for proto::Transaction {
+    fn from(transaction: models::Transaction) -> Self {
+        Self {
+            valor: transaction.valor.into(),
+            tipo: transaction.tipo,
+            descricao: transaction.descricao,
+            realizada_em: Some(transaction.realizada_em.into()),
+        }
+    }
+}
+
+impl From<Notification> for proto::TransactionEvent {
+    fn from(notification: Notification) -> Self {
+        Self {
+            cliente_id: notification.cliente_id.into(),
+            valor: notification.valor.into(),
+            tipo: notification.tipo,
+            descricao: notification.descricao,
+            saldo: notification.saldo,
+            limite: notification.limite,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        api::routes::{StatementResponse, TransactionResponse},
+        persistence::{Error, Export, Period, ReadToken, Rule},
+    };
+    use axum::{async_trait, http::uri::PathAndQuery};
+    use rstest::rstest;
+    use std::{sync::Mutex, time::SystemTime};
+    use tonic::{client, codec::ProstCodec, Code};
+
+    struct MockRepository {
+        received: Mutex<Option<(TransactionRequest, Audit)>>,
+        rejected: Mutex<Option<Audit>>,
+    }
+
+    #[async_trait]
+    impl Repository for MockRepository {
+        async fn get_balance(
+            &self,
+            client_id: &i16,
+            token: Option<&ReadToken>,
+        ) -> Result<StatementResponse, Error> {
+            if *client_id != 1 {
+                return Err(Error::ClientNotFound);
+            }
+
+            if token.is_some_and(|token| u64::from(*token) != 0x1_0000_0002) {
+                return Err(Error::Internal("unexpected read token".to_string()));
+            }
+
+            Ok(StatementResponse {
+                saldo: models::Balance {
+                    total: -50,
+                    disponivel: -50,
+                    data_extrato: SystemTime::UNIX_EPOCH,
+                    limite: 1000,
+                },
+                ultimas_transacoes: vec![models::Transaction {
+                    valor: 50,
+                    tipo: "d".into(),
+                    descricao: "bar".into(),
+                    realizada_em: SystemTime::UNIX_EPOCH,
+                }],
+            })
+        }
+
+        async fn create_transaction(
+            &self,
+            client_id: &i16,
+            data: &TransactionRequest,
+            audit: &Audit,
+        ) -> Result<TransactionResponse, Error> {
+            *self.received.lock().unwrap() = Some((data.clone(), audit.clone()));
+
+            match (*client_id, data.valor) {
+                (1, 0..=1000) => Ok(TransactionResponse {
+                    limite: 1000,
+                    saldo: -i32::from(data.valor),
+                    token: Some(ReadToken::from(0x1_0000_0002)),
+                }),
+                (1, _) => Err(Error::BalanceConstraintViolation),
+                _ => Err(Error::ClientNotFound),
+            }
+        }
+
+        async fn reject(&self, _client_id: &i16, audit: &Audit) -> Result<(), Error> {
+            *self.rejected.lock().unwrap() = Some(audit.clone());
+
+            Ok(())
+        }
+
+        async fn export(&self, _client_id: &i16, _period: Period) -> Result<Export, Error> {
+            unimplemented!()
+        }
+    }
+
+    fn app() -> (Router, Arc<MockRepository>, Notifications) {
+        let repo = Arc::new(MockRepository {
+            received: Default::default(),
+            rejected: Default::default(),
+        });
+        let notifications = Notifications::new("api01");
+
+        (
+            new(repo.clone(), notifications.clone(), Some("secret")),
+            repo,
+            notifications,
+        )
+    }
+
+    async fn unary<Req, Res>(app: Router, method: &'static str, message: Req) -> Result<Res, Status>
+    where
+        Req: prost::Message + Send + Sync + 'static,
+        Res: prost::Message + Default + Send + Sync + 'static,
+    {
+        let mut grpc = client::Grpc::new(app);
+        let mut request = tonic::Request::new(message);
+
+        request
+            .metadata_mut()
+            .insert("x-principal", "alice".parse().unwrap());
+
+        grpc.ready().await.unwrap();
+        grpc.unary(
+            request,
+            PathAndQuery::from_static(method),
+            ProstCodec::default(),
+        )
+        .await
+        .map(tonic::Response::into_inner)
+    }
+
+    fn notification(cliente_id: i16) -> Notification {
+        Notification {
+            cliente_id,
+            valor: 10,
+            tipo: "d".into(),
+            descricao: "bar".into(),
+            saldo: -10,
+            limite: 1000,
+            origem: "api02".into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_transaction() {
+        let (app, repo, _) = app();
+
+        let response: proto::CreateTransactionResponse = unary(
+            app,
+            "/rinha.v1.Rinha/CreateTransaction",
+            proto::CreateTransactionRequest {
+                cliente_id: 1,
+                valor: 10,
+                tipo: "d".into(),
+                descricao: "descricao".into(),
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            response,
+            proto::CreateTransactionResponse {
+                limite: 1000,
+                saldo: -10,
+                read_token: "1/2".into(),
+            }
+        );
+
+        let (_, audit) = repo.received.lock().unwrap().take().unwrap();
+
+        // Sent, but nothing authenticated it.
+        assert_eq!(audit.principal, None);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&audit.payload).unwrap(),
+            serde_json::json!({ "valor": 10, "tipo": "d", "descricao": "descricao" })
+        );
+    }
+
+    #[rstest]
+    #[case::invalid_tipo(1, 10, "x", "descricao", Code::InvalidArgument, true)]
+    #[case::long_descricao(1, 10, "c", "descricao longa", Code::InvalidArgument, true)]
+    #[case::blank_descricao(1, 10, "c", "", Code::InvalidArgument, true)]
+    #[case::valor_out_of_range(1, 40000, "c", "descricao", Code::InvalidArgument, true)]
+    #[case::cliente_out_of_range(40000, 10, "c", "descricao", Code::InvalidArgument, false)]
+    #[case::client_not_found(2, 10, "c", "descricao", Code::NotFound, false)]
+    #[case::limit_exceeded(1, 2000, "d", "descricao", Code::FailedPrecondition, false)]
+    #[tokio::test]
+    async fn test_create_transaction_rejected(
+        #[case] cliente_id: i32,
+        #[case] valor: i32,
+        #[case] tipo: &str,
+        #[case] descricao: &str,
+        #[case] expected: Code,
+        #[case] audited_as_invalid: bool,
+    ) {
+        let (app, repo, _) = app();
+
+        let status = unary::<_, proto::CreateTransactionResponse>(
+            app,
+            "/rinha.v1.Rinha/CreateTransaction",
+            proto::CreateTransactionRequest {
+                cliente_id,
+                valor,
+                tipo: tipo.into(),
+                descricao: descricao.into(),
+            },
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(status.code(), expected);
+        assert_eq!(repo.rejected.lock().unwrap().is_some(), audited_as_invalid);
+    }
+
+    #[rstest]
+    #[case::without_token(1, "", Code::Ok)]
+    #[case::with_token(1, "1/2", Code::Ok)]
+    #[case::invalid_token(1, "latest", Code::InvalidArgument)]
+    #[case::client_not_found(2, "", Code::NotFound)]
+    #[tokio::test]
+    async fn test_get_statement(
+        #[case] cliente_id: i32,
+        #[case] read_token: &str,
+        #[case] expected: Code,
+    ) {
+        let (app, _, _) = app();
+
+        let response = unary::<_, proto::Statement>(
+            app,
+            "/rinha.v1.Rinha/GetStatement",
+            proto::GetStatementRequest {
+                cliente_id,
+                read_token: read_token.into(),
+            },
+        )
+        .await;
+
+        match response {
+            Ok(statement) => {
+                assert_eq!(expected, Code::Ok);
+                assert_eq!(
+                    statement,
+                    proto::Statement {
+                        saldo: Some(proto::Balance {
+                            total: -50,
+                            disponivel: -50,
+                            data_extrato: Some(SystemTime::UNIX_EPOCH.into()),
+                            limite: 1000,
+                        }),
+                        ultimas_transacoes: vec![proto::Transaction {
+                            valor: 50,
+                            tipo: "d".into(),
+                            descricao: "bar".into(),
+                            realizada_em: Some(SystemTime::UNIX_EPOCH.into()),
+                        }],
+                    }
+                );
+            }
+            Err(status) => assert_eq!(status.code(), expected),
+        }
+    }
+
+    #[rstest]
+    #[case::one_client(Some(1), None, vec![1])]
+    #[case::every_client(None, Some("Bearer secret"), vec![2, 1])]
+    #[tokio::test]
+    async fn test_watch_transactions(
+        #[case] cliente_id: Option<i32>,
+        #[case] authorization: Option<&str>,
+        #[case] expected: Vec<i32>,
+    ) {
+        let (app, _, notifications) = app();
+        let mut grpc = client::Grpc::new(app);
+        let mut request = tonic::Request::new(proto::WatchTransactionsRequest { cliente_id });
+
+        if let Some(authorization) = authorization {
+            request
+                .metadata_mut()
+                .insert("authorization", authorization.parse().unwrap());
+        }
+
+        grpc.ready().await.unwrap();
+
+        let mut events = grpc
+            .server_streaming(
+                request,
+                PathAndQuery::from_static("/rinha.v1.Rinha/WatchTransactions"),
+                ProstCodec::<_, proto::TransactionEvent>::default(),
+            )
+            .await
+            .unwrap()
+            .into_inner();
+
+        notifications.publish(notification(2));
+        notifications.publish(notification(1));
+
+        for cliente_id in expected {
+            assert_eq!(
+                events.message().await.unwrap().unwrap(),
+                proto::TransactionEvent::from(notification(cliente_id as i16))
+            );
+        }
+    }
+
+    #[rstest]
+    #[case::missing(Some("secret"), None)]
+    #[case::wrong(Some("secret"), Some("Bearer secreT"))]
+    #[case::no_admin_token(None, Some("Bearer secret"))]
+    #[tokio::test]
+    async fn test_watch_every_client_unauthenticated(
+        #[case] admin_token: Option<&str>,
+        #[case] authorization: Option<&str>,
+    ) {
+        let (_, repo, notifications) = app();
+        let mut grpc = client::Grpc::new(new(repo, notifications, admin_token));
+        let mut request = tonic::Request::new(proto::WatchTransactionsRequest { cliente_id: None });
+
+        if let Some(authorization) = authorization {
+            request
+                .metadata_mut()
+                .insert("authorization", authorization.parse().unwrap());
+        }
+
+        grpc.ready().await.unwrap();
+
+        let status = grpc
+            .server_streaming(
+                request,
+                PathAndQuery::from_static("/rinha.v1.Rinha/WatchTransactions"),
+                ProstCodec::<_, proto::TransactionEvent>::default(),
+            )
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn test_watch_transactions_client_not_found() {
+        let (app, _, _) = app();
+        let mut grpc = client::Grpc::new(app);
+
+        grpc.ready().await.unwrap();
+
+        let status = grpc
+            .server_streaming(
+                tonic::Request::new(proto::WatchTransactionsRequest {
+                    cliente_id: Some(2),
+                }),
+                PathAndQuery::from_static("/rinha.v1.Rinha/WatchTransactions"),
+                ProstCodec::<_, proto::TransactionEvent>::default(),
+            )
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_method() {
+        let (app, _, _) = app();
+
+        let status = unary::<_, proto::Statement>(
+            app,
+            "/rinha.v1.Rinha/DeleteClient",
+            proto::GetStatementRequest::default(),
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(status.code(), Code::Unimplemented);
+    }
+
+    #[rstest]
+    #[case(Error::ClientNotFound, Code::NotFound)]
+    #[case(Error::BalanceConstraintViolation, Code::FailedPrecondition)]
+    #[case(Error::PolicyViolation(Rule::BelowMinimum), Code::FailedPrecondition)]
+    #[case(Error::InvalidInput("valor".into()), Code::InvalidArgument)]
+    #[case(Error::Transient("deadlock".into()), Code::Unavailable)]
+    #[case(Error::Unavailable, Code::Unavailable)]
+    #[case(Error::Connection, Code::Internal)]
+    fn test_from_persistence_error(#[case] error: Error, #[case] expected: Code) {
+        assert_eq!(Status::from(error).code(), expected);
+    }
+}
@@ -0,0 +1,420 @@
+use crate::{
+    api::routes::{audit, rejection, Encoding, TransactionResponse, Violation},
+    models::{Capture, Hold, NewHold},
+    persistence::Holds,
+    telemetry,
+};
+use axum::{
+    extract::{Path, State},
+    http::{Extensions, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json, Router,
+};
+use serde_json::json;
+use std::sync::Arc;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+/// Routes for placing holds on a client's funds and settling them, as card payments
+/// do before their final amount is known.
+pub fn new(holds: Arc<dyn Holds>) -> Router {
+    let (router, _) = routes().split_for_parts();
+
+    router.with_state(holds)
+}
+
+/// The routes along with their documentation, which `routes::docs` collects.
+pub(crate) fn routes() -> OpenApiRouter<Arc<dyn Holds>> {
+    OpenApiRouter::new()
+        .routes(routes!(index, create))
+        .routes(routes!(void))
+        .routes(routes!(capture))
+}
+
+/// Places a hold, lowering the available balance without a transaction.
+#[utoipa::path(
+    post,
+    path = "/clientes/{id}/reservas",
+    operation_id = "create_hold",
+    tag = "reservas",
+    params(
+        ("id" = i16, Path, description = "Client id."),
+    ),
+    request_body = NewHold,
+    responses(
+        (status = 201, description = "Placed.", body = Hold),
+        (status = 404, description = "No such client."),
+        (
+            status = 422,
+            description = "Invalid hold, or refused as a debit of the same amount would be."
+        ),
+        (status = 503, description = "The database is unavailable, retry later."),
+    ),
+)]
+async fn create(
+    State(holds): State<Arc<dyn Holds>>,
+    Path(id): Path<i16>,
+    Json(hold): Json<NewHold>,
+) -> Result<(StatusCode, Json<Hold>), StatusCode> {
+    if !hold.is_valid() {
+        telemetry::error!("Invalid hold amount, description or validity");
+
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    Ok((
+        StatusCode::CREATED,
+        Json(holds.create_hold(id, &hold).await?),
+    ))
+}
+
+/// The client's pending holds.
+#[utoipa::path(
+    get,
+    path = "/clientes/{id}/reservas",
+    operation_id = "list_holds",
+    tag = "reservas",
+    params(
+        ("id" = i16, Path, description = "Client id."),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "The holds still setting funds aside, oldest first.",
+            body = Vec<Hold>
+        ),
+        (status = 404, description = "No such client."),
+        (status = 503, description = "The database is unavailable, retry later."),
+    ),
+)]
+async fn index(
+    State(holds): State<Arc<dyn Holds>>,
+    Path(id): Path<i16>,
+) -> Result<Json<Vec<Hold>>, StatusCode> {
+    Ok(Json(holds.holds(id).await?))
+}
+
+/// Debits a pending hold, answering with the balance as a transaction does, and
+/// naming the rule broken when the client's policy refuses the debit.
+#[utoipa::path(
+    post,
+    path = "/clientes/{id}/reservas/{reserva_id}/captura",
+    operation_id = "capture_hold",
+    tag = "reservas",
+    params(
+        ("id" = i16, Path, description = "Client id."),
+        ("reserva_id" = i32, Path, description = "Hold id."),
+    ),
+    request_body = Capture,
+    responses(
+        (status = 200, description = "Debited.", body = TransactionResponse),
+        (status = 404, description = "No such client or hold."),
+        (status = 409, description = "The hold was already captured, voided or expired."),
+        (
+            status = 422,
+            description = "Invalid amount, more than was held, a debit past the limit, or \
+                refused by the client's policy, in which case the body names the rule.",
+            body = Violation
+        ),
+        (status = 503, description = "The database is unavailable, retry later."),
+    ),
+)]
+async fn capture(
+    State(holds): State<Arc<dyn Holds>>,
+    Path((id, hold_id)): Path<(i16, i32)>,
+    headers: HeaderMap,
+    extensions: Extensions,
+    Json(capture): Json<Capture>,
+) -> Result<Json<TransactionResponse>, Response> {
+    if capture.valor.is_some_and(|valor| valor <= 0) {
+        telemetry::error!("Invalid capture amount");
+
+        return Err(StatusCode::UNPROCESSABLE_ENTITY.into_response());
+    }
+
+    // Names the hold, which the body alone doesn't.
+    let payload = json!({ "reserva_id": hold_id, "valor": capture.valor }).to_string();
+
+    holds
+        .capture_hold(
+            id,
+            hold_id,
+            capture.valor,
+            &audit(&headers, &extensions, payload),
+        )
+        .await
+        .map(Json)
+        .map_err(|err| rejection(err, Encoding::Json))
+}
+
+/// Releases a pending hold, answering with it as it stands.
+#[utoipa::path(
+    delete,
+    path = "/clientes/{id}/reservas/{reserva_id}",
+    operation_id = "void_hold",
+    tag = "reservas",
+    params(
+        ("id" = i16, Path, description = "Client id."),
+        ("reserva_id" = i32, Path, description = "Hold id."),
+    ),
+    responses(
+        (status = 200, description = "Voided.", body = Hold),
+        (status = 404, description = "No such client or hold."),
+        (status = 409, description = "The hold was already captured, voided or expired."),
+        (status = 503, description = "The database is unavailable, retry later."),
+    ),
+)]
+async fn void(
+    State(holds): State<Arc<dyn Holds>>,
+    Path((id, hold_id)): Path<(i16, i32)>,
+) -> Result<Json<Hold>, StatusCode> {
+    Ok(Json(holds.void_hold(id, hold_id).await?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        api::routes::Principal,
+        persistence::{Audit, Error, Rule},
+    };
+    use axum::{
+        async_trait,
+        body::Body,
+        http::{header, Request},
+    };
+    use http_body_util::BodyExt;
+    use rstest::rstest;
+    use serde_json::Value;
+    use std::{sync::Mutex, time::SystemTime};
+    use tower::util::ServiceExt;
+
+    #[derive(Default)]
+    struct MockHolds {
+        created: Mutex<Option<NewHold>>,
+        captured: Mutex<Option<(Option<i16>, Audit)>>,
+    }
+
+    fn hold(id: i32, situacao: &str) -> Hold {
+        Hold {
+            id,
+            cliente_id: 1,
+            valor: 500,
+            descricao: "hotel".into(),
+            situacao: situacao.into(),
+            criada_em: SystemTime::UNIX_EPOCH,
+            expira_em: SystemTime::UNIX_EPOCH,
+            capturada_em: None,
+            valor_capturado: None,
+            cancelada_em: None,
+            expirada_em: None,
+        }
+    }
+
+    #[async_trait]
+    impl Holds for MockHolds {
+        async fn create_hold(&self, client_id: i16, hold: &NewHold) -> Result<Hold, Error> {
+            match (client_id, hold.valor) {
+                (1, 5000) => Err(Error::BalanceConstraintViolation),
+                (1, _) => {
+                    *self.created.lock().unwrap() = Some(NewHold {
+                        valor: hold.valor,
+                        descricao: hold.descricao.clone(),
+                        validade_s: hold.validade_s,
+                    });
+
+                    Ok(self::hold(1, "pendente"))
+                }
+                _ => Err(Error::ClientNotFound),
+            }
+        }
+
+        async fn holds(&self, client_id: i16) -> Result<Vec<Hold>, Error> {
+            match client_id {
+                1 => Ok(vec![hold(1, "pendente"), hold(2, "pendente")]),
+                _ => Err(Error::ClientNotFound),
+            }
+        }
+
+        async fn capture_hold(
+            &self,
+            client_id: i16,
+            hold_id: i32,
+            valor: Option<i16>,
+            audit: &Audit,
+        ) -> Result<TransactionResponse, Error> {
+            match (client_id, hold_id, valor) {
+                (1, 1, Some(600)) => Err(Error::InvalidInput("more than was held".into())),
+                (1, 1, Some(450)) => Err(Error::PolicyViolation(Rule::AboveMaximum)),
+                (1, 1, _) => {
+                    *self.captured.lock().unwrap() = Some((valor, audit.clone()));
+
+                    Ok(TransactionResponse {
+                        limite: 1000,
+                        saldo: -i32::from(valor.unwrap_or(500)),
+                        token: None,
+                    })
+                }
+                (1, 2, _) => Err(Error::HoldClosed),
+                _ => Err(Error::ClientNotFound),
+            }
+        }
+
+        async fn void_hold(&self, client_id: i16, hold_id: i32) -> Result<Hold, Error> {
+            match (client_id, hold_id) {
+                (1, 1) => Ok(Hold {
+                    cancelada_em: Some(SystemTime::UNIX_EPOCH),
+                    ..hold(1, "cancelada")
+                }),
+                (1, 2) => Err(Error::HoldClosed),
+                _ => Err(Error::ClientNotFound),
+            }
+        }
+
+        async fn expire_holds(&self, _limit: i32) -> Result<i32, Error> {
+            unimplemented!()
+        }
+    }
+
+    async fn send(
+        holds: Arc<MockHolds>,
+        method: &str,
+        uri: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .extension(Principal("loja".into()))
+            .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+            .unwrap();
+
+        let response = new(holds).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    #[rstest]
+    #[case::default_validity(1, json!({ "valor": 500, "descricao": "hotel" }), StatusCode::CREATED)]
+    #[case::validity(1, json!({ "valor": 500, "descricao": "hotel", "validade_s": 3600 }), StatusCode::CREATED)]
+    #[case::client_not_found(2, json!({ "valor": 500, "descricao": "hotel" }), StatusCode::NOT_FOUND)]
+    #[case::limit_exceeded(1, json!({ "valor": 5000, "descricao": "hotel" }), StatusCode::UNPROCESSABLE_ENTITY)]
+    #[case::zero_valor(1, json!({ "valor": 0, "descricao": "hotel" }), StatusCode::UNPROCESSABLE_ENTITY)]
+    #[case::long_descricao(1, json!({ "valor": 500, "descricao": "descricao longa" }), StatusCode::UNPROCESSABLE_ENTITY)]
+    #[case::validity_too_long(1, json!({ "valor": 500, "descricao": "hotel", "validade_s": 31 * 24 * 60 * 60 }), StatusCode::UNPROCESSABLE_ENTITY)]
+    #[tokio::test]
+    async fn test_create(
+        #[case] client_id: i16,
+        #[case] body: Value,
+        #[case] expected_status: StatusCode,
+    ) {
+        let holds = Arc::new(MockHolds::default());
+
+        let (status, body) = send(
+            holds.clone(),
+            "POST",
+            &format!("/clientes/{}/reservas", client_id),
+            Some(body),
+        )
+        .await;
+
+        assert_eq!(status, expected_status);
+        assert_eq!(
+            holds.created.lock().unwrap().is_some(),
+            status == StatusCode::CREATED
+        );
+
+        if status == StatusCode::CREATED {
+            assert_eq!(body["situacao"], "pendente");
+        }
+    }
+
+    #[rstest]
+    #[case::found("/clientes/1/reservas", StatusCode::OK)]
+    #[case::not_found("/clientes/2/reservas", StatusCode::NOT_FOUND)]
+    #[tokio::test]
+    async fn test_index(#[case] uri: &str, #[case] expected_status: StatusCode) {
+        let (status, body) = send(Arc::default(), "GET", uri, None).await;
+
+        assert_eq!(status, expected_status);
+
+        if status == StatusCode::OK {
+            assert_eq!(body[1]["id"], 2);
+        }
+    }
+
+    #[rstest]
+    #[case::full("/clientes/1/reservas/1/captura", json!({}), StatusCode::OK, Some(-500))]
+    #[case::partial("/clientes/1/reservas/1/captura", json!({ "valor": 320 }), StatusCode::OK, Some(-320))]
+    #[case::above_held("/clientes/1/reservas/1/captura", json!({ "valor": 600 }), StatusCode::UNPROCESSABLE_ENTITY, None)]
+    #[case::zero_valor("/clientes/1/reservas/1/captura", json!({ "valor": 0 }), StatusCode::UNPROCESSABLE_ENTITY, None)]
+    #[case::closed("/clientes/1/reservas/2/captura", json!({}), StatusCode::CONFLICT, None)]
+    #[case::not_found("/clientes/1/reservas/3/captura", json!({}), StatusCode::NOT_FOUND, None)]
+    #[tokio::test]
+    async fn test_capture(
+        #[case] uri: &str,
+        #[case] body: Value,
+        #[case] expected_status: StatusCode,
+        #[case] expected_saldo: Option<i32>,
+    ) {
+        let holds = Arc::new(MockHolds::default());
+
+        let (status, body) = send(holds.clone(), "POST", uri, Some(body)).await;
+
+        assert_eq!(status, expected_status);
+        assert_eq!(body["saldo"].as_i64(), expected_saldo.map(i64::from));
+    }
+
+    #[tokio::test]
+    async fn test_capture_policy_violation() {
+        let (status, body) = send(
+            Arc::default(),
+            "POST",
+            "/clientes/1/reservas/1/captura",
+            Some(json!({ "valor": 450 })),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body, json!({ "regra": "valor_acima_do_maximo" }));
+    }
+
+    #[tokio::test]
+    async fn test_capture_audit() {
+        let holds = Arc::new(MockHolds::default());
+
+        send(
+            holds.clone(),
+            "POST",
+            "/clientes/1/reservas/1/captura",
+            Some(json!({ "valor": 320 })),
+        )
+        .await;
+
+        let (valor, audit) = holds.captured.lock().unwrap().take().unwrap();
+
+        assert_eq!(valor, Some(320));
+        assert_eq!(audit.principal.as_deref(), Some("loja"));
+        assert_eq!(
+            serde_json::from_str::<Value>(&audit.payload).unwrap(),
+            json!({ "reserva_id": 1, "valor": 320 })
+        );
+    }
+
+    #[rstest]
+    #[case::pending("/clientes/1/reservas/1", StatusCode::OK)]
+    #[case::closed("/clientes/1/reservas/2", StatusCode::CONFLICT)]
+    #[case::not_found("/clientes/1/reservas/3", StatusCode::NOT_FOUND)]
+    #[tokio::test]
+    async fn test_void(#[case] uri: &str, #[case] expected_status: StatusCode) {
+        let (status, body) = send(Arc::default(), "DELETE", uri, None).await;
+
+        assert_eq!(status, expected_status);
+
+        if status == StatusCode::OK {
+            assert_eq!(body["situacao"], "cancelada");
+        }
+    }
+}
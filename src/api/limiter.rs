@@ -0,0 +1,228 @@
+use crate::telemetry;
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+const INITIAL_LIMIT: f64 = 10.0;
+const MIN_LIMIT: f64 = 1.0;
+const MAX_LIMIT: f64 = 200.0;
+const BACKOFF_RATIO: f64 = 0.9;
+
+struct Usage {
+    limit: f64,
+    in_flight: usize,
+    /// Bumped on every decrease, so a permit knows whether it was taken before it.
+    backoffs: u64,
+}
+
+/// Caps the number of requests in flight with a limit that adapts to latency
+/// (AIMD): every request answered within `target` grows it by `1 / limit`,
+/// so by about one per limit's worth of requests, and a slow or failed one
+/// shrinks it by 10%. Requests taken before the last decrease don't shrink it
+/// again, so a burst of slow answers to the same overload backs off once per
+/// round trip instead of once per request.
+pub struct Limiter {
+    target: Duration,
+    usage: Mutex<Usage>,
+}
+
+/// A slot taken from the [`Limiter`], given back when dropped.
+pub struct Permit<'a> {
+    limiter: &'a Limiter,
+    started: Instant,
+    backoffs: u64,
+}
+
+impl Limiter {
+    pub fn new(target: Duration) -> Self {
+        Self {
+            target,
+            usage: Mutex::new(Usage {
+                limit: INITIAL_LIMIT,
+                in_flight: 0,
+                backoffs: 0,
+            }),
+        }
+    }
+
+    pub fn try_acquire(&self) -> Option<Permit<'_>> {
+        let mut usage = self.usage.lock().unwrap();
+
+        if usage.in_flight as f64 >= usage.limit.floor() {
+            return None;
+        }
+
+        usage.in_flight += 1;
+
+        Some(Permit {
+            limiter: self,
+            started: Instant::now(),
+            backoffs: usage.backoffs,
+        })
+    }
+
+    #[cfg_attr(not(feature = "telemetry"), allow(dead_code))]
+    pub fn limit(&self) -> usize {
+        self.usage.lock().unwrap().limit as usize
+    }
+}
+
+impl Permit<'_> {
+    /// Adjusts the limit from how this request went.
+    pub fn complete(self, overloaded: bool) {
+        let mut usage = self.limiter.usage.lock().unwrap();
+
+        if overloaded || self.started.elapsed() > self.limiter.target {
+            if self.backoffs == usage.backoffs {
+                usage.limit = (usage.limit * BACKOFF_RATIO).max(MIN_LIMIT);
+                usage.backoffs += 1;
+            }
+        } else {
+            usage.limit = (usage.limit + 1.0 / usage.limit).min(MAX_LIMIT);
+        }
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.limiter.usage.lock().unwrap().in_flight -= 1;
+    }
+}
+
+/// Rejects requests with 503 and `Retry-After` once the limit is reached, instead
+/// of letting them queue for a database connection.
+pub async fn limit(State(limiter): State<Arc<Limiter>>, request: Request, next: Next) -> Response {
+    let Some(permit) = limiter.try_acquire() else {
+        telemetry::error!("Concurrency limit of {} reached", limiter.limit());
+
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, "1")],
+        )
+            .into_response();
+    };
+
+    let response = next.run(request).await;
+
+    permit.complete(matches!(
+        response.status(),
+        StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    ));
+
+    response
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::util::ServiceExt;
+
+    #[test]
+    fn test_try_acquire_up_to_limit() {
+        let limiter = Limiter::new(Duration::from_secs(1));
+
+        let permits: Vec<_> = (0..INITIAL_LIMIT as usize)
+            .map(|_| limiter.try_acquire().unwrap())
+            .collect();
+
+        assert!(limiter.try_acquire().is_none());
+
+        drop(permits);
+
+        assert!(limiter.try_acquire().is_some());
+    }
+
+    #[test]
+    fn test_additive_increase() {
+        let limiter = Limiter::new(Duration::from_secs(1));
+
+        for _ in 0..11 {
+            limiter.try_acquire().unwrap().complete(false);
+        }
+
+        assert_eq!(limiter.limit(), INITIAL_LIMIT as usize + 1);
+    }
+
+    #[test]
+    fn test_multiplicative_decrease() {
+        let limiter = Limiter::new(Duration::from_secs(1));
+
+        limiter.try_acquire().unwrap().complete(true);
+        assert_eq!(limiter.limit(), 9);
+
+        for _ in 0..100 {
+            limiter.try_acquire().unwrap().complete(true);
+        }
+
+        assert_eq!(limiter.limit(), MIN_LIMIT as usize);
+    }
+
+    #[test]
+    fn test_burst_of_slow_requests_decreases_once() {
+        let limiter = Limiter::new(Duration::ZERO);
+
+        let permits: Vec<_> = (0..INITIAL_LIMIT as usize)
+            .map(|_| limiter.try_acquire().unwrap())
+            .collect();
+        std::thread::sleep(Duration::from_millis(1));
+
+        for permit in permits {
+            permit.complete(false);
+        }
+
+        assert_eq!(limiter.limit(), 9);
+
+        let permit = limiter.try_acquire().unwrap();
+        std::thread::sleep(Duration::from_millis(1));
+        permit.complete(false);
+
+        assert_eq!(limiter.limit(), 8);
+    }
+
+    #[test]
+    fn test_slow_request_decreases() {
+        let limiter = Limiter::new(Duration::ZERO);
+
+        let permit = limiter.try_acquire().unwrap();
+        std::thread::sleep(Duration::from_millis(1));
+        permit.complete(false);
+
+        assert_eq!(limiter.limit(), 9);
+    }
+
+    #[tokio::test]
+    async fn test_limit() {
+        let limiter = Arc::new(Limiter::new(Duration::from_secs(1)));
+        let app = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(middleware::from_fn_with_state(limiter.clone(), limit));
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let _permits: Vec<_> = (0..limiter.limit())
+            .map(|_| limiter.try_acquire().unwrap())
+            .collect();
+
+        let response = app
+            .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+    }
+}
@@ -0,0 +1,388 @@
+use crate::{
+    models::{NewStandingOrder, Run, StandingOrder},
+    persistence::StandingOrders,
+    telemetry,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json, Router,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+/// Routes for clients to register standing orders, which the scheduler makes
+/// in `jobs::standing_orders`.
+pub fn new(orders: Arc<dyn StandingOrders>) -> Router {
+    let (router, _) = routes().split_for_parts();
+
+    router.with_state(orders)
+}
+
+/// The routes along with their documentation, which `routes::docs` collects.
+pub(crate) fn routes() -> OpenApiRouter<Arc<dyn StandingOrders>> {
+    OpenApiRouter::new()
+        .routes(routes!(index, create))
+        .routes(routes!(cancel))
+        .routes(routes!(runs))
+}
+
+/// Registers a standing order.
+#[utoipa::path(
+    post,
+    path = "/clientes/{id}/ordens",
+    operation_id = "create_standing_order",
+    tag = "ordens",
+    params(
+        ("id" = i16, Path, description = "Client id."),
+    ),
+    request_body = NewStandingOrder,
+    responses(
+        (status = 201, description = "Registered.", body = StandingOrder),
+        (status = 404, description = "No such client."),
+        (
+            status = 422,
+            description = "Invalid kind, description, schedule or dates, or no run before \
+                the end."
+        ),
+        (status = 503, description = "The database is unavailable, retry later."),
+    ),
+)]
+async fn create(
+    State(orders): State<Arc<dyn StandingOrders>>,
+    Path(id): Path<i16>,
+    Json(order): Json<NewStandingOrder>,
+) -> Result<(StatusCode, Json<StandingOrder>), StatusCode> {
+    if !order.is_valid() {
+        telemetry::error!("Invalid standing order kind, description or dates");
+
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    Ok((
+        StatusCode::CREATED,
+        Json(orders.create_order(id, &order).await?),
+    ))
+}
+
+/// The client's standing orders.
+#[utoipa::path(
+    get,
+    path = "/clientes/{id}/ordens",
+    operation_id = "list_standing_orders",
+    tag = "ordens",
+    params(
+        ("id" = i16, Path, description = "Client id."),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "The client's standing orders, cancelled ones included, oldest first.",
+            body = Vec<StandingOrder>
+        ),
+        (status = 404, description = "No such client."),
+        (status = 503, description = "The database is unavailable, retry later."),
+    ),
+)]
+async fn index(
+    State(orders): State<Arc<dyn StandingOrders>>,
+    Path(id): Path<i16>,
+) -> Result<Json<Vec<StandingOrder>>, StatusCode> {
+    Ok(Json(orders.orders(id).await?))
+}
+
+/// Cancels a standing order, answering with it as it stands.
+#[utoipa::path(
+    delete,
+    path = "/clientes/{id}/ordens/{ordem_id}",
+    operation_id = "cancel_standing_order",
+    tag = "ordens",
+    params(
+        ("id" = i16, Path, description = "Client id."),
+        ("ordem_id" = i32, Path, description = "Standing order id."),
+    ),
+    responses(
+        (status = 200, description = "Cancelled.", body = StandingOrder),
+        (status = 404, description = "No such client or standing order."),
+        (status = 503, description = "The database is unavailable, retry later."),
+    ),
+)]
+async fn cancel(
+    State(orders): State<Arc<dyn StandingOrders>>,
+    Path((id, order_id)): Path<(i16, i32)>,
+) -> Result<Json<StandingOrder>, StatusCode> {
+    Ok(Json(orders.cancel_order(id, order_id).await?))
+}
+
+#[derive(Deserialize)]
+struct Params {
+    quantidade: Option<i64>,
+}
+
+/// The latest runs of a standing order and how they went.
+#[utoipa::path(
+    get,
+    path = "/clientes/{id}/ordens/{ordem_id}/execucoes",
+    operation_id = "list_standing_order_runs",
+    tag = "ordens",
+    params(
+        ("id" = i16, Path, description = "Client id."),
+        ("ordem_id" = i32, Path, description = "Standing order id."),
+        (
+            "quantidade" = Option<i64>,
+            Query,
+            description = "How many runs, from 1 to 1000, 100 when left out."
+        ),
+    ),
+    responses(
+        (status = 200, description = "The latest runs, newest first.", body = Vec<Run>),
+        (status = 404, description = "No such client or standing order."),
+        (status = 503, description = "The database is unavailable, retry later."),
+    ),
+)]
+async fn runs(
+    State(orders): State<Arc<dyn StandingOrders>>,
+    Path((id, order_id)): Path<(i16, i32)>,
+    Query(params): Query<Params>,
+) -> Result<Json<Vec<Run>>, StatusCode> {
+    let limit = params
+        .quantidade
+        .unwrap_or(DEFAULT_LIMIT)
+        .clamp(1, MAX_LIMIT);
+
+    Ok(Json(orders.runs(id, order_id, limit).await?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::persistence::{Claim, Error};
+    use axum::{
+        async_trait,
+        body::Body,
+        http::{header, Request},
+    };
+    use http_body_util::BodyExt;
+    use rstest::rstest;
+    use serde_json::{json, Value};
+    use std::{sync::Mutex, time::SystemTime};
+    use tower::util::ServiceExt;
+
+    #[derive(Default)]
+    struct MockOrders {
+        created: Mutex<Option<NewStandingOrder>>,
+        limit: Mutex<Option<i64>>,
+    }
+
+    fn order(id: i32) -> StandingOrder {
+        StandingOrder {
+            id,
+            cliente_id: 1,
+            valor: 990,
+            tipo: "d".into(),
+            descricao: "streaming".into(),
+            agenda: "mensal".parse().unwrap(),
+            inicio: SystemTime::UNIX_EPOCH,
+            fim: None,
+            proxima_execucao: Some(SystemTime::UNIX_EPOCH),
+            cancelada_em: None,
+        }
+    }
+
+    #[async_trait]
+    impl StandingOrders for MockOrders {
+        async fn create_order(
+            &self,
+            client_id: i16,
+            order: &NewStandingOrder,
+        ) -> Result<StandingOrder, Error> {
+            if client_id != 1 {
+                return Err(Error::ClientNotFound);
+            }
+
+            if order.agenda.next(SystemTime::UNIX_EPOCH, None).is_none() {
+                return Err(Error::InvalidInput("no runs".into()));
+            }
+
+            *self.created.lock().unwrap() = Some(NewStandingOrder {
+                valor: order.valor,
+                tipo: order.tipo.clone(),
+                descricao: order.descricao.clone(),
+                agenda: order.agenda.clone(),
+                inicio: order.inicio,
+                fim: order.fim,
+            });
+
+            Ok(self::order(1))
+        }
+
+        async fn orders(&self, client_id: i16) -> Result<Vec<StandingOrder>, Error> {
+            match client_id {
+                1 => Ok(vec![order(1), order(2)]),
+                _ => Err(Error::ClientNotFound),
+            }
+        }
+
+        async fn cancel_order(
+            &self,
+            client_id: i16,
+            order_id: i32,
+        ) -> Result<StandingOrder, Error> {
+            match (client_id, order_id) {
+                (1, 1) => Ok(StandingOrder {
+                    proxima_execucao: None,
+                    cancelada_em: Some(SystemTime::UNIX_EPOCH),
+                    ..order(1)
+                }),
+                _ => Err(Error::ClientNotFound),
+            }
+        }
+
+        async fn runs(
+            &self,
+            _client_id: i16,
+            _order_id: i32,
+            limit: i64,
+        ) -> Result<Vec<Run>, Error> {
+            *self.limit.lock().unwrap() = Some(limit);
+
+            Ok(vec![Run {
+                agendada_para: SystemTime::UNIX_EPOCH,
+                iniciada_em: SystemTime::UNIX_EPOCH,
+                instancia: "api01".into(),
+                resultado: Some("aplicada".into()),
+                saldo: Some(-990),
+            }])
+        }
+
+        async fn claim_due(
+            &self,
+            _now: SystemTime,
+            _instance: &str,
+            _limit: i64,
+        ) -> Result<Vec<Claim>, Error> {
+            unimplemented!()
+        }
+
+        async fn claim_stalled(
+            &self,
+            _stalled_since: SystemTime,
+            _instance: &str,
+            _limit: i64,
+        ) -> Result<Vec<Claim>, Error> {
+            unimplemented!()
+        }
+
+        async fn record_run(
+            &self,
+            _run_id: i64,
+            _resultado: &str,
+            _saldo: Option<i32>,
+        ) -> Result<(), Error> {
+            unimplemented!()
+        }
+    }
+
+    async fn send(
+        orders: Arc<MockOrders>,
+        method: &str,
+        uri: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+            .unwrap();
+
+        let response = new(orders).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    #[rstest]
+    #[case::monthly(1, json!({ "valor": 990, "tipo": "d", "descricao": "streaming", "agenda": "mensal" }), StatusCode::CREATED)]
+    #[case::cron(1, json!({ "valor": 990, "tipo": "c", "descricao": "salario", "agenda": "0 9 5 * *", "inicio": "2024-01-01", "fim": "2024-12-31" }), StatusCode::CREATED)]
+    #[case::client_not_found(2, json!({ "valor": 990, "tipo": "d", "descricao": "streaming", "agenda": "mensal" }), StatusCode::NOT_FOUND)]
+    #[case::invalid_tipo(1, json!({ "valor": 990, "tipo": "x", "descricao": "streaming", "agenda": "mensal" }), StatusCode::UNPROCESSABLE_ENTITY)]
+    #[case::long_descricao(1, json!({ "valor": 990, "tipo": "d", "descricao": "descricao longa", "agenda": "mensal" }), StatusCode::UNPROCESSABLE_ENTITY)]
+    #[case::invalid_agenda(1, json!({ "valor": 990, "tipo": "d", "descricao": "streaming", "agenda": "quinzenal" }), StatusCode::UNPROCESSABLE_ENTITY)]
+    #[case::ends_before_start(1, json!({ "valor": 990, "tipo": "d", "descricao": "streaming", "agenda": "mensal", "inicio": "2024-02-01", "fim": "2024-01-31" }), StatusCode::UNPROCESSABLE_ENTITY)]
+    #[case::never_runs(1, json!({ "valor": 990, "tipo": "d", "descricao": "streaming", "agenda": "0 0 30 2 *" }), StatusCode::UNPROCESSABLE_ENTITY)]
+    #[tokio::test]
+    async fn test_create(
+        #[case] client_id: i16,
+        #[case] body: Value,
+        #[case] expected_status: StatusCode,
+    ) {
+        let orders = Arc::new(MockOrders::default());
+
+        let (status, _) = send(
+            orders.clone(),
+            "POST",
+            &format!("/clientes/{}/ordens", client_id),
+            Some(body),
+        )
+        .await;
+
+        assert_eq!(status, expected_status);
+        assert_eq!(
+            orders.created.lock().unwrap().is_some(),
+            status == StatusCode::CREATED
+        );
+    }
+
+    #[rstest]
+    #[case::found("/clientes/1/ordens", StatusCode::OK)]
+    #[case::not_found("/clientes/2/ordens", StatusCode::NOT_FOUND)]
+    #[tokio::test]
+    async fn test_index(#[case] uri: &str, #[case] expected_status: StatusCode) {
+        let (status, body) = send(Arc::default(), "GET", uri, None).await;
+
+        assert_eq!(status, expected_status);
+
+        if status == StatusCode::OK {
+            assert_eq!(body[1]["id"], 2);
+            assert_eq!(body[0]["agenda"], "mensal");
+        }
+    }
+
+    #[rstest]
+    #[case::found("/clientes/1/ordens/1", StatusCode::OK)]
+    #[case::not_found("/clientes/1/ordens/2", StatusCode::NOT_FOUND)]
+    #[tokio::test]
+    async fn test_cancel(#[case] uri: &str, #[case] expected_status: StatusCode) {
+        let (status, body) = send(Arc::default(), "DELETE", uri, None).await;
+
+        assert_eq!(status, expected_status);
+
+        if status == StatusCode::OK {
+            assert_eq!(body["proxima_execucao"], Value::Null);
+        }
+    }
+
+    #[rstest]
+    #[case::default(None, 100)]
+    #[case::clamped(Some(5000), 1000)]
+    #[tokio::test]
+    async fn test_runs(#[case] quantidade: Option<i64>, #[case] expected_limit: i64) {
+        let orders = Arc::new(MockOrders::default());
+        let uri = match quantidade {
+            Some(quantidade) => format!("/clientes/1/ordens/1/execucoes?quantidade={}", quantidade),
+            None => "/clientes/1/ordens/1/execucoes".into(),
+        };
+
+        let (status, body) = send(orders.clone(), "GET", &uri, None).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body[0]["resultado"], "aplicada");
+        assert_eq!(*orders.limit.lock().unwrap(), Some(expected_limit));
+    }
+}
@@ -1,23 +1,53 @@
-mod statement;
-mod transaction;
+mod content;
+pub(super) mod export;
+mod metrics;
+mod openapi;
+pub(super) mod statement;
+pub(super) mod transaction;
 
-use axum::http::StatusCode;
-pub use statement::show as show_balance;
+use axum::http::{HeaderName, StatusCode};
+pub use content::Encoding;
+pub use metrics::show as show_metrics;
+pub use openapi::{docs, Timestamp};
 pub use statement::Response as StatementResponse;
-pub use transaction::create as create_transaction;
+pub use transaction::audit;
+pub use transaction::rejection;
 pub use transaction::Request as TransactionRequest;
 pub use transaction::Response as TransactionResponse;
+pub use transaction::Violation;
 
 use crate::persistence;
 use crate::telemetry;
 
+/// Identifies the request across services, recorded in the audit log.
+pub const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+pub const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+
+/// Whom an operator authenticated with the admin token acts for, recorded in the
+/// audit log. Ignored on requests that weren't authenticated.
+pub const PRINCIPAL: HeaderName = HeaderName::from_static("x-principal");
+
+/// What a request was authenticated as, put in its extensions by whatever
+/// authenticated it. Only this is recorded in the audit log as the principal.
+#[derive(Clone, Debug)]
+pub struct Principal(pub String);
+
+/// Carries the [`persistence::ReadToken`] of a write, to be sent back on later reads.
+pub const READ_TOKEN: HeaderName = HeaderName::from_static("x-read-token");
+
 impl From<persistence::Error> for StatusCode {
     fn from(err: persistence::Error) -> Self {
         telemetry::error!("Database error: {:?}", err);
 
         match err {
             persistence::Error::ClientNotFound => StatusCode::NOT_FOUND,
-            persistence::Error::BalanceConstraintViolation => StatusCode::UNPROCESSABLE_ENTITY,
+            persistence::Error::HoldClosed => StatusCode::CONFLICT,
+            persistence::Error::BalanceConstraintViolation
+            | persistence::Error::PolicyViolation(_)
+            | persistence::Error::InvalidInput(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            persistence::Error::Transient(_)
+            | persistence::Error::ConnectionLost(_)
+            | persistence::Error::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -30,12 +60,27 @@ mod test {
 
     #[rstest]
     #[case(persistence::Error::ClientNotFound, StatusCode::NOT_FOUND)]
+    #[case(persistence::Error::HoldClosed, StatusCode::CONFLICT)]
     #[case(persistence::Error::Connection, StatusCode::INTERNAL_SERVER_ERROR)]
+    #[case(persistence::Error::Transient("deadlock".into()), StatusCode::SERVICE_UNAVAILABLE)]
+    #[case(
+        persistence::Error::ConnectionLost("closed".into()),
+        StatusCode::SERVICE_UNAVAILABLE
+    )]
+    #[case(persistence::Error::Unavailable, StatusCode::SERVICE_UNAVAILABLE)]
     #[case(persistence::Error::Internal("internal".into()), StatusCode::INTERNAL_SERVER_ERROR)]
+    #[case(
+        persistence::Error::InvalidInput("missing column".into()),
+        StatusCode::UNPROCESSABLE_ENTITY
+    )]
     #[case(
         persistence::Error::BalanceConstraintViolation,
         StatusCode::UNPROCESSABLE_ENTITY
     )]
+    #[case(
+        persistence::Error::PolicyViolation(persistence::Rule::AboveMaximum),
+        StatusCode::UNPROCESSABLE_ENTITY
+    )]
     fn test_from_persistence_error(
         #[case] error: persistence::Error,
         #[case] expected_status: StatusCode,
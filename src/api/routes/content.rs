@@ -0,0 +1,186 @@
+use crate::telemetry;
+use axum::{
+    http::{
+        header::{ACCEPT, CONTENT_TYPE, VARY},
+        HeaderMap, StatusCode,
+    },
+    response::{IntoResponse, Response},
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Body encodings the API speaks, JSON unless the client asks otherwise.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl Encoding {
+    fn parse(media_type: &str) -> Option<Self> {
+        let essence = media_type.split(';').next()?.trim().to_ascii_lowercase();
+
+        match essence.as_str() {
+            "application/json" => Some(Self::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Self::MessagePack)
+            }
+            "application/cbor" => Some(Self::Cbor),
+            _ if essence.starts_with("application/") && essence.ends_with("+json") => {
+                Some(Self::Json)
+            }
+            _ => None,
+        }
+    }
+
+    /// Encoding of the request body, `None` when missing or unsupported.
+    pub fn of_request(headers: &HeaderMap) -> Option<Self> {
+        headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Self::parse)
+    }
+
+    /// Encoding preferred by `Accept`, by quality and then by order. Wildcards, unsupported
+    /// types and a missing header get JSON, never a 406.
+    pub fn accepted(headers: &HeaderMap) -> Self {
+        headers
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|range| {
+                let mut params = range.split(';');
+                let media_type = params.next()?.trim();
+                let quality = params
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .find_map(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                let encoding = match media_type {
+                    "*/*" | "application/*" => Self::Json,
+                    _ => Self::parse(media_type)?,
+                };
+
+                (quality > 0.0).then_some((encoding, quality))
+            })
+            .fold(
+                None::<(Self, f32)>,
+                |best, (encoding, quality)| match best {
+                    Some((_, best_quality)) if best_quality >= quality => best,
+                    _ => Some((encoding, quality)),
+                },
+            )
+            .map_or(Self::Json, |(encoding, _)| encoding)
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::MessagePack => "application/msgpack",
+            Self::Cbor => "application/cbor",
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, String> {
+        match self {
+            Self::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
+            Self::MessagePack => rmp_serde::from_slice(bytes).map_err(|e| e.to_string()),
+            Self::Cbor => cbor4ii::serde::from_slice(bytes).map_err(|e| e.to_string()),
+        }
+    }
+
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            Self::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+            // Named, so that fields are keyed as in JSON rather than positional.
+            Self::MessagePack => rmp_serde::to_vec_named(value).map_err(|e| e.to_string()),
+            Self::Cbor => cbor4ii::serde::to_vec(Vec::new(), value).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// A response body in the encoding the client accepts.
+pub struct Encoded<T>(pub Encoding, pub T);
+
+impl<T: Serialize> IntoResponse for Encoded<T> {
+    fn into_response(self) -> Response {
+        let Self(encoding, value) = self;
+
+        match encoding.encode(&value) {
+            Ok(body) => (
+                [(CONTENT_TYPE, encoding.content_type()), (VARY, "accept")],
+                body,
+            )
+                .into_response(),
+            #[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+            Err(e) => {
+                telemetry::error!("Failed to encode response: {}", e);
+
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::http::HeaderValue;
+    use rstest::rstest;
+    use serde_json::json;
+
+    fn headers(name: axum::http::HeaderName, values: &[&'static str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+
+        for value in values {
+            headers.append(name.clone(), HeaderValue::from_static(value));
+        }
+
+        headers
+    }
+
+    #[rstest]
+    #[case::missing(&[], Encoding::Json)]
+    #[case::json(&["application/json"], Encoding::Json)]
+    #[case::msgpack(&["application/msgpack"], Encoding::MessagePack)]
+    #[case::legacy_msgpack(&["application/x-msgpack"], Encoding::MessagePack)]
+    #[case::cbor(&["application/cbor"], Encoding::Cbor)]
+    #[case::wildcard(&["*/*"], Encoding::Json)]
+    #[case::unsupported(&["text/html"], Encoding::Json)]
+    #[case::first_of_equals(&["application/cbor, application/msgpack"], Encoding::Cbor)]
+    #[case::quality(&["application/cbor;q=0.5, application/msgpack"], Encoding::MessagePack)]
+    #[case::wildcard_lower(&["application/cbor, */*;q=0.1"], Encoding::Cbor)]
+    #[case::refused(&["application/cbor;q=0, text/html"], Encoding::Json)]
+    #[case::several_headers(&["text/html", "application/cbor"], Encoding::Cbor)]
+    fn test_accepted(#[case] accept: &[&'static str], #[case] expected: Encoding) {
+        assert_eq!(Encoding::accepted(&headers(ACCEPT, accept)), expected);
+    }
+
+    #[rstest]
+    #[case::missing(&[], None)]
+    #[case::json(&["application/json; charset=utf-8"], Some(Encoding::Json))]
+    #[case::json_suffix(&["application/problem+json"], Some(Encoding::Json))]
+    #[case::msgpack(&["application/msgpack"], Some(Encoding::MessagePack))]
+    #[case::cbor(&["Application/CBOR"], Some(Encoding::Cbor))]
+    #[case::unsupported(&["text/plain"], None)]
+    fn test_of_request(#[case] content_type: &[&'static str], #[case] expected: Option<Encoding>) {
+        assert_eq!(
+            Encoding::of_request(&headers(CONTENT_TYPE, content_type)),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case::json(Encoding::Json)]
+    #[case::msgpack(Encoding::MessagePack)]
+    #[case::cbor(Encoding::Cbor)]
+    fn test_round_trip(#[case] encoding: Encoding) {
+        let value = json!({ "valor": 10, "tipo": "c", "descricao": "descricao" });
+        let encoded = encoding.encode(&value).unwrap();
+
+        assert_eq!(
+            encoding.decode::<serde_json::Value>(&encoded).unwrap(),
+            value
+        );
+    }
+}
@@ -0,0 +1,479 @@
+mod camt053;
+mod csv;
+mod journal;
+mod ndjson;
+mod ofx;
+
+use crate::{
+    dates::Date,
+    persistence::{Error, Export, Period, Repository},
+    telemetry,
+};
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use futures_util::{stream::BoxStream, StreamExt};
+use serde::Deserialize;
+use std::{io, sync::Arc};
+use utoipa::{IntoParams, ToSchema};
+
+/// Layout of an export: CSV, NDJSON, OFX 2.2, ISO 20022 camt.053, or a beancount or
+/// ledger-cli journal.
+#[derive(Clone, Copy, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Csv,
+    Ndjson,
+    Ofx,
+    Camt053,
+    Beancount,
+    Ledger,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct Params {
+    format: Format,
+    /// First day exported, `YYYY-MM-DD` in UTC.
+    #[param(value_type = Option<String>, format = Date)]
+    from: Option<Date>,
+    /// Last day exported, `YYYY-MM-DD` in UTC.
+    #[param(value_type = Option<String>, format = Date)]
+    to: Option<Date>,
+}
+
+impl Format {
+    fn content_type(&self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Ndjson => "application/x-ndjson",
+            Self::Ofx => "application/x-ofx",
+            Self::Camt053 => "application/xml",
+            Self::Beancount | Self::Ledger => "text/plain; charset=utf-8",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Ndjson => "ndjson",
+            Self::Ofx => "ofx",
+            Self::Camt053 => "xml",
+            Self::Beancount => "beancount",
+            Self::Ledger => "ledger",
+        }
+    }
+
+    fn encode(
+        &self,
+        client_id: i16,
+        period: Period,
+        export: Export,
+    ) -> BoxStream<'static, Result<String, Error>> {
+        match self {
+            Self::Csv => csv::encode(export.entries).boxed(),
+            Self::Ndjson => ndjson::encode(export.entries).boxed(),
+            Self::Ofx => ofx::encode(client_id, period, export).boxed(),
+            Self::Camt053 => camt053::encode(client_id, period, export).boxed(),
+            Self::Beancount => {
+                journal::encode(journal::Dialect::Beancount, client_id, period, export).boxed()
+            }
+            Self::Ledger => {
+                journal::encode(journal::Dialect::Ledger, client_id, period, export).boxed()
+            }
+        }
+    }
+}
+
+/// `valor`s are in centavos, written as reais with two decimals.
+fn amount(centavos: i64) -> String {
+    let sign = if centavos < 0 { "-" } else { "" };
+
+    format!(
+        "{}{}.{:02}",
+        sign,
+        centavos.unsigned_abs() / 100,
+        centavos.unsigned_abs() % 100
+    )
+}
+
+/// Text escaped for XML element content and attributes.
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Every transaction of the client, from `from` through `to`, written out as it
+/// is read from the database.
+///
+/// The status is sent before the rows, so a failure midway can only cut the body
+/// short.
+#[utoipa::path(
+    get,
+    path = "/clientes/{id}/extrato/export",
+    operation_id = "export_statement",
+    tag = "clientes",
+    params(("id" = i16, Path, description = "Client id."), Params),
+    responses(
+        (
+            status = 200,
+            description = "The transactions in the period, as an attachment.",
+            content(
+                (String = "text/csv"),
+                (String = "application/x-ndjson"),
+                (String = "application/x-ofx"),
+                (String = "application/xml"),
+                (String = "text/plain"),
+            ),
+        ),
+        (status = 400, description = "Unknown format or malformed date."),
+        (status = 404, description = "No such client."),
+        (
+            status = 503,
+            description = "The database is unavailable, or too many exports are running, retry later.",
+        ),
+    ),
+)]
+pub async fn export(
+    State(repo): State<Arc<dyn Repository>>,
+    Path(id): Path<i16>,
+    Query(params): Query<Params>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let period = Period {
+        from: params.from.map(|date| date.start()),
+        to: params.to.map(|date| date.end()),
+    };
+
+    let export = repo.export(&id, period).await?;
+
+    let body = params.format.encode(id, period, export).map(|chunk| {
+        chunk.map_err(
+            #[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+            |err| {
+                telemetry::error!("Export failed midway: {:?}", err);
+
+                io::Error::other(format!("{:?}", err))
+            },
+        )
+    });
+
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                params.format.content_type().to_string(),
+            ),
+            (
+                header::CONTENT_DISPOSITION,
+                format!(
+                    r#"attachment; filename="extrato-{}.{}""#,
+                    id,
+                    params.format.extension()
+                ),
+            ),
+        ],
+        Body::from_stream(body),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        api::routes::{StatementResponse, TransactionRequest, TransactionResponse},
+        models::{Entry, Transaction},
+        persistence::{Audit, ReadToken},
+    };
+    use axum::{async_trait, http::Request};
+    use futures_util::stream;
+    use http_body_util::BodyExt;
+    use rstest::rstest;
+    use std::{
+        sync::Mutex,
+        time::{Duration, SystemTime},
+    };
+    use tower::util::ServiceExt;
+
+    struct MockRepository {
+        entries: Vec<Result<Entry, Error>>,
+        period: Mutex<Option<Period>>,
+    }
+
+    #[async_trait]
+    impl Repository for MockRepository {
+        async fn create_transaction(
+            &self,
+            _client_id: &i16,
+            _data: &TransactionRequest,
+            _audit: &Audit,
+        ) -> Result<TransactionResponse, Error> {
+            unimplemented!()
+        }
+
+        async fn get_balance(
+            &self,
+            _client_id: &i16,
+            _token: Option<&ReadToken>,
+        ) -> Result<StatementResponse, Error> {
+            unimplemented!()
+        }
+
+        async fn export(&self, client_id: &i16, period: Period) -> Result<Export, Error> {
+            if *client_id != 1 {
+                return Err(Error::ClientNotFound);
+            }
+
+            *self.period.lock().unwrap() = Some(period);
+
+            Ok(Export {
+                limite: 1000,
+                saldo_inicial: -10,
+                saldo_final: 10,
+                entries: stream::iter(self.entries.clone()).boxed(),
+            })
+        }
+    }
+
+    fn entry(id: i32, descricao: &str) -> Entry {
+        Entry {
+            id,
+            transaction: Transaction {
+                valor: 10,
+                tipo: "c".into(),
+                descricao: descricao.into(),
+                realizada_em: SystemTime::UNIX_EPOCH + Duration::from_secs(86_400),
+            },
+        }
+    }
+
+    async fn get(repo: Arc<MockRepository>, uri: &str) -> (StatusCode, String, String) {
+        let app = crate::api::app::new(repo, Default::default());
+
+        let response = app
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let status = response.status();
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .map(|value| value.to_str().unwrap().to_string())
+            .unwrap_or_default();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        (
+            status,
+            content_type,
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    }
+
+    fn repo(entries: Vec<Result<Entry, Error>>) -> Arc<MockRepository> {
+        Arc::new(MockRepository {
+            entries,
+            period: Default::default(),
+        })
+    }
+
+    #[rstest]
+    #[case::csv(
+        "csv",
+        "text/csv; charset=utf-8",
+        "id,valor,tipo,descricao,realizada_em\n\
+         1,10,c,pix,1970-01-02T00:00:00.000000Z\n\
+         2,10,c,\"a,b\",1970-01-02T00:00:00.000000Z\n"
+    )]
+    #[case::ndjson(
+        "ndjson",
+        "application/x-ndjson",
+        "{\"id\":1,\"valor\":10,\"tipo\":\"c\",\"descricao\":\"pix\",\
+         \"realizada_em\":{\"secs_since_epoch\":86400,\"nanos_since_epoch\":0}}\n\
+         {\"id\":2,\"valor\":10,\"tipo\":\"c\",\"descricao\":\"a,b\",\
+         \"realizada_em\":{\"secs_since_epoch\":86400,\"nanos_since_epoch\":0}}\n"
+    )]
+    #[tokio::test]
+    async fn test_export(
+        #[case] format: &str,
+        #[case] expected_content_type: &str,
+        #[case] expected_body: &str,
+    ) {
+        let repo = repo(vec![Ok(entry(1, "pix")), Ok(entry(2, "a,b"))]);
+
+        let (status, content_type, body) = get(
+            repo.clone(),
+            &format!(
+                "/clientes/1/extrato/export?format={}&from=1970-01-02&to=1970-01-02",
+                format
+            ),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type, expected_content_type);
+        assert_eq!(body, expected_body);
+        assert_eq!(
+            repo.period.lock().unwrap().unwrap(),
+            Period {
+                from: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(86_400)),
+                to: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(2 * 86_400)),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export_ofx() {
+        let (status, content_type, body) = get(
+            repo(vec![Ok(entry(1, "pix"))]),
+            "/clientes/1/extrato/export?format=ofx&to=1970-01-02",
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type, "application/x-ofx");
+        assert!(body.contains("<ACCTID>1</ACCTID>"));
+        assert!(body.contains(
+            "<STMTTRN><TRNTYPE>CREDIT</TRNTYPE><DTPOSTED>19700102000000.000[0:GMT]</DTPOSTED>\
+             <TRNAMT>0.10</TRNAMT><FITID>1</FITID><NAME>pix</NAME></STMTTRN>"
+        ));
+        assert!(body.contains(
+            "<LEDGERBAL><BALAMT>0.10</BALAMT><DTASOF>19700103000000.000[0:GMT]</DTASOF></LEDGERBAL>"
+        ));
+        assert!(body.contains("<AVAILBAL><BALAMT>10.10</BALAMT>"));
+        assert!(body.ends_with("</OFX>\n"));
+    }
+
+    #[tokio::test]
+    async fn test_export_camt053() {
+        let (status, content_type, body) = get(
+            repo(vec![Ok(entry(1, "pix"))]),
+            "/clientes/1/extrato/export?format=camt053&from=1970-01-02&to=1970-01-02",
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type, "application/xml");
+        assert!(body.contains(
+            "<FrToDt><FrDtTm>1970-01-02T00:00:00.000000Z</FrDtTm>\
+             <ToDtTm>1970-01-03T00:00:00.000000Z</ToDtTm></FrToDt>"
+        ));
+        assert!(body.contains(
+            "<Cd>OPBD</Cd></CdOrPrtry></Tp><CdtLine><Incl>true</Incl><Amt Ccy=\"BRL\">10.00</Amt>\
+             </CdtLine><Amt Ccy=\"BRL\">0.10</Amt><CdtDbtInd>DBIT</CdtDbtInd>"
+        ));
+        assert!(body.contains(
+            "<Cd>CLBD</Cd></CdOrPrtry></Tp><CdtLine><Incl>true</Incl><Amt Ccy=\"BRL\">10.00</Amt>\
+             </CdtLine><Amt Ccy=\"BRL\">0.10</Amt><CdtDbtInd>CRDT</CdtDbtInd>"
+        ));
+        assert!(body.contains(
+            "<Ntry><NtryRef>1</NtryRef><Amt Ccy=\"BRL\">0.10</Amt><CdtDbtInd>CRDT</CdtDbtInd>"
+        ));
+        assert!(body.contains("<AddtlNtryInf>pix</AddtlNtryInf></Ntry>"));
+        assert!(body.ends_with("</Document>\n"));
+    }
+
+    #[rstest]
+    #[case::beancount(
+        "beancount",
+        "option \"operating_currency\" \"BRL\"\n\n\
+         1970-01-02 open Assets:Rinha:Cliente1 BRL\n\
+         1970-01-02 open Income:Rinha:Creditos BRL\n\
+         1970-01-02 open Expenses:Rinha:Debitos BRL\n\
+         1970-01-02 open Equity:Rinha:Abertura BRL\n\n\
+         1970-01-02 * \"Saldo de abertura\"\n  Assets:Rinha:Cliente1  -0.10 BRL\n  Equity:Rinha:Abertura\n\n\
+         1970-01-02 * \"pix\"\n  id: 1\n  Assets:Rinha:Cliente1  0.10 BRL\n  Income:Rinha:Creditos\n\n\
+         1970-01-03 balance Assets:Rinha:Cliente1  0.10 BRL\n"
+    )]
+    #[case::ledger(
+        "ledger",
+        "1970-01-02 * Saldo de abertura\n    Assets:Rinha:Cliente1    -0.10 BRL\n    Equity:Rinha:Abertura\n\n\
+         1970-01-02 * (1) pix\n    Assets:Rinha:Cliente1    0.10 BRL\n    Income:Rinha:Creditos\n\n\
+         1970-01-03 * Saldo de fechamento\n    Assets:Rinha:Cliente1    0 BRL = 0.10 BRL\n"
+    )]
+    #[tokio::test]
+    async fn test_export_journal(#[case] format: &str, #[case] expected_body: &str) {
+        let (status, content_type, body) = get(
+            repo(vec![Ok(entry(1, "pix"))]),
+            &format!(
+                "/clientes/1/extrato/export?format={}&from=1970-01-02&to=1970-01-02",
+                format
+            ),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type, "text/plain; charset=utf-8");
+        assert_eq!(body, expected_body);
+    }
+
+    #[rstest]
+    #[case::zero(0, "0.00")]
+    #[case::centavos(5, "0.05")]
+    #[case::reais(12_345, "123.45")]
+    #[case::negative(-1_050, "-10.50")]
+    fn test_amount(#[case] centavos: i64, #[case] expected: &str) {
+        assert_eq!(amount(centavos), expected);
+    }
+
+    #[test]
+    fn test_xml_escape() {
+        assert_eq!(
+            xml_escape(r#"<a & 'b' "c">"#),
+            "&lt;a &amp; &apos;b&apos; &quot;c&quot;&gt;"
+        );
+    }
+
+    #[rstest]
+    #[case::missing_format("/clientes/1/extrato/export", StatusCode::BAD_REQUEST)]
+    #[case::unknown_format("/clientes/1/extrato/export?format=xml", StatusCode::BAD_REQUEST)]
+    #[case::invalid_date(
+        "/clientes/1/extrato/export?format=csv&from=2024-02-30",
+        StatusCode::BAD_REQUEST
+    )]
+    #[case::client_not_found("/clientes/2/extrato/export?format=csv", StatusCode::NOT_FOUND)]
+    #[tokio::test]
+    async fn test_export_rejected(#[case] uri: &str, #[case] expected_status: StatusCode) {
+        let (status, _, _) = get(repo(Vec::new()), uri).await;
+
+        assert_eq!(status, expected_status);
+    }
+
+    #[tokio::test]
+    async fn test_export_failing_midway() {
+        let app = crate::api::app::new(
+            repo(vec![
+                Ok(entry(1, "pix")),
+                Err(Error::ConnectionLost("closed".into())),
+            ]),
+            Default::default(),
+        );
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/clientes/1/extrato/export?format=ndjson")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.into_body().collect().await.is_err());
+    }
+}
@@ -0,0 +1,148 @@
+use super::{amount, xml_escape};
+use crate::{
+    dates::DateTime,
+    models::Entry,
+    persistence::{Error, Export, Period},
+};
+use futures_util::{stream, Stream, StreamExt};
+use std::time::SystemTime;
+
+const NAMESPACE: &str = "urn:iso:std:iso:20022:tech:xsd:camt.053.001.08";
+
+/// An ISO 20022 camt.053 bank-to-customer statement: the opening and closing
+/// booked balances of the period, each with the account limit as credit line,
+/// and an entry per transaction.
+pub fn encode(
+    client_id: i16,
+    period: Period,
+    export: Export,
+) -> impl Stream<Item = Result<String, Error>> {
+    let now = SystemTime::now();
+    let start = period.from.unwrap_or(SystemTime::UNIX_EPOCH);
+    let end = period.to.unwrap_or(now);
+    let created = DateTime::of(now);
+
+    let header = format!(
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<Document xmlns=\"{}\">\n",
+            "<BkToCstmrStmt>\n",
+            "<GrpHdr><MsgId>{}</MsgId><CreDtTm>{}</CreDtTm></GrpHdr>\n",
+            "<Stmt>\n",
+            "<Id>{}</Id><CreDtTm>{}</CreDtTm>",
+            "<FrToDt><FrDtTm>{}</FrDtTm><ToDtTm>{}</ToDtTm></FrToDt>\n",
+            "<Acct><Id><Othr><Id>{}</Id></Othr></Id><Ccy>BRL</Ccy></Acct>\n",
+            "{}{}",
+        ),
+        NAMESPACE,
+        message_id(client_id, &created),
+        created,
+        message_id(client_id, &created),
+        created,
+        DateTime::of(start),
+        DateTime::of(end),
+        client_id,
+        balance("OPBD", export.saldo_inicial, export.limite, start),
+        balance("CLBD", export.saldo_final, export.limite, end),
+    );
+
+    let footer = "</Stmt>\n</BkToCstmrStmt>\n</Document>\n".to_string();
+
+    stream::once(async { Ok(header) })
+        .chain(
+            export
+                .entries
+                .map(|entry| entry.map(|entry| entry_xml(&entry))),
+        )
+        .chain(stream::once(async { Ok(footer) }))
+}
+
+/// Unique per client and second, at most the 35 characters the standard allows.
+fn message_id(client_id: i16, created: &DateTime) -> String {
+    format!(
+        "EXTRATO-{}-{:04}{:02}{:02}{:02}{:02}{:02}",
+        client_id,
+        created.date.year,
+        created.date.month,
+        created.date.day,
+        created.hour,
+        created.minute,
+        created.second
+    )
+}
+
+fn balance(code: &str, saldo: i64, limite: i32, at: SystemTime) -> String {
+    format!(
+        concat!(
+            "<Bal><Tp><CdOrPrtry><Cd>{}</Cd></CdOrPrtry></Tp>",
+            "<CdtLine><Incl>true</Incl><Amt Ccy=\"BRL\">{}</Amt></CdtLine>",
+            "<Amt Ccy=\"BRL\">{}</Amt><CdtDbtInd>{}</CdtDbtInd>",
+            "<Dt><DtTm>{}</DtTm></Dt></Bal>\n",
+        ),
+        code,
+        amount(limite.into()),
+        amount(saldo.abs()),
+        indicator(saldo >= 0),
+        DateTime::of(at),
+    )
+}
+
+fn entry_xml(entry: &Entry) -> String {
+    let transaction = &entry.transaction;
+    let credit = transaction.tipo == "c";
+
+    format!(
+        concat!(
+            "<Ntry><NtryRef>{}</NtryRef><Amt Ccy=\"BRL\">{}</Amt><CdtDbtInd>{}</CdtDbtInd>",
+            "<Sts><Cd>BOOK</Cd></Sts><BookgDt><DtTm>{}</DtTm></BookgDt>",
+            "<AcctSvcrRef>{}</AcctSvcrRef>",
+            "<BkTxCd><Domn><Cd>PMNT</Cd><Fmly><Cd>{}</Cd><SubFmlyCd>OTHR</SubFmlyCd></Fmly></Domn></BkTxCd>",
+            "<AddtlNtryInf>{}</AddtlNtryInf></Ntry>\n",
+        ),
+        entry.id,
+        amount(transaction.valor.into()),
+        indicator(credit),
+        DateTime::of(transaction.realizada_em),
+        entry.id,
+        // Received or issued credit transfers.
+        if credit { "RCDT" } else { "ICDT" },
+        xml_escape(&transaction.descricao),
+    )
+}
+
+fn indicator(credit: bool) -> &'static str {
+    if credit {
+        "CRDT"
+    } else {
+        "DBIT"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+    use std::time::Duration;
+
+    #[rstest]
+    #[case::credit(250, "<Amt Ccy=\"BRL\">2.50</Amt><CdtDbtInd>CRDT</CdtDbtInd>")]
+    #[case::zero(0, "<Amt Ccy=\"BRL\">0.00</Amt><CdtDbtInd>CRDT</CdtDbtInd>")]
+    #[case::debit(-250, "<Amt Ccy=\"BRL\">2.50</Amt><CdtDbtInd>DBIT</CdtDbtInd>")]
+    fn test_balance(#[case] saldo: i64, #[case] expected: &str) {
+        let xml = balance("OPBD", saldo, 1000, SystemTime::UNIX_EPOCH);
+
+        assert!(xml.starts_with(
+            "<Bal><Tp><CdOrPrtry><Cd>OPBD</Cd></CdOrPrtry></Tp>\
+             <CdtLine><Incl>true</Incl><Amt Ccy=\"BRL\">10.00</Amt></CdtLine>"
+        ));
+        assert!(xml.contains(expected));
+        assert!(xml.ends_with("<Dt><DtTm>1970-01-01T00:00:00.000000Z</DtTm></Dt></Bal>\n"));
+    }
+
+    #[test]
+    fn test_message_id_fits() {
+        let created = DateTime::of(SystemTime::UNIX_EPOCH + Duration::from_secs(4_102_444_799));
+
+        assert!(message_id(i16::MIN, &created).len() <= 35);
+    }
+}
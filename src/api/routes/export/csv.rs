@@ -0,0 +1,51 @@
+use crate::{
+    dates::DateTime,
+    models::Entry,
+    persistence::{EntryStream, Error},
+};
+use futures_util::{stream, Stream, StreamExt};
+
+const HEADER: &str = "id,valor,tipo,descricao,realizada_em\n";
+
+/// RFC 4180 rows under a header, with times in RFC 3339.
+pub fn encode(entries: EntryStream) -> impl Stream<Item = Result<String, Error>> {
+    stream::once(async { Ok(HEADER.to_string()) })
+        .chain(entries.map(|entry| entry.map(|entry| row(&entry))))
+}
+
+fn row(entry: &Entry) -> String {
+    let transaction = &entry.transaction;
+
+    format!(
+        "{},{},{},{},{}\n",
+        entry.id,
+        transaction.valor,
+        field(&transaction.tipo),
+        field(&transaction.descricao),
+        DateTime::of(transaction.realizada_em)
+    )
+}
+
+/// Quoted only when it has to be, with quotes doubled.
+fn field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::plain("pix", "pix")]
+    #[case::comma("a,b", "\"a,b\"")]
+    #[case::quote("a\"b", "\"a\"\"b\"")]
+    #[case::newline("a\nb", "\"a\nb\"")]
+    fn test_field(#[case] value: &str, #[case] expected: &str) {
+        assert_eq!(field(value), expected);
+    }
+}
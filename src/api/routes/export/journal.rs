@@ -0,0 +1,238 @@
+use super::amount;
+use crate::{
+    dates::Date,
+    models::Entry,
+    persistence::{Error, Export, Period},
+};
+use futures_util::{stream, Stream, StreamExt};
+use std::time::SystemTime;
+
+/// Counterpart of credits.
+const CREDITS: &str = "Income:Rinha:Creditos";
+/// Counterpart of debits.
+const DEBITS: &str = "Expenses:Rinha:Debitos";
+/// Counterpart of the opening balance.
+const OPENING: &str = "Equity:Rinha:Abertura";
+
+#[derive(Clone, Copy)]
+pub enum Dialect {
+    Beancount,
+    Ledger,
+}
+
+/// A plain-text accounting journal: the balance before the period as an opening
+/// entry against equity, an entry per transaction, and an assertion of the
+/// balance at the end of the period.
+pub fn encode(
+    dialect: Dialect,
+    client_id: i16,
+    period: Period,
+    export: Export,
+) -> impl Stream<Item = Result<String, Error>> {
+    let account = format!("Assets:Rinha:Cliente{}", client_id);
+    let opened = Date::of(period.from.unwrap_or(SystemTime::UNIX_EPOCH));
+    // Assertions hold at the start of their day, so the day after the period.
+    let closed = Date::of(
+        period
+            .to
+            .unwrap_or_else(|| Date::of(SystemTime::now()).end()),
+    );
+
+    let mut header = String::new();
+
+    if let Dialect::Beancount = dialect {
+        header.push_str("option \"operating_currency\" \"BRL\"\n\n");
+
+        for name in [account.as_str(), CREDITS, DEBITS, OPENING] {
+            header.push_str(&format!("{} open {} BRL\n", opened, name));
+        }
+
+        header.push('\n');
+    }
+
+    if export.saldo_inicial != 0 {
+        header.push_str(&transaction_text(
+            dialect,
+            opened,
+            None,
+            "Saldo de abertura",
+            &account,
+            export.saldo_inicial,
+            OPENING,
+        ));
+    }
+
+    let footer = match dialect {
+        Dialect::Beancount => format!(
+            "{} balance {}  {} BRL\n",
+            closed,
+            account,
+            amount(export.saldo_final)
+        ),
+        Dialect::Ledger => format!(
+            "{} * Saldo de fechamento\n    {}    0 BRL = {} BRL\n",
+            closed,
+            account,
+            amount(export.saldo_final)
+        ),
+    };
+
+    let entries = export
+        .entries
+        .map(move |entry| entry.map(|entry| entry_text(dialect, &account, &entry)));
+
+    stream::once(async { Ok(header) })
+        .chain(entries)
+        .chain(stream::once(async { Ok(footer) }))
+}
+
+fn entry_text(dialect: Dialect, account: &str, entry: &Entry) -> String {
+    let transaction = &entry.transaction;
+    let (valor, counterpart) = match transaction.tipo.as_str() {
+        "c" => (i64::from(transaction.valor), CREDITS),
+        _ => (-i64::from(transaction.valor), DEBITS),
+    };
+
+    transaction_text(
+        dialect,
+        Date::of(transaction.realizada_em),
+        Some(entry.id),
+        &transaction.descricao,
+        account,
+        valor,
+        counterpart,
+    )
+}
+
+/// An entry moving `valor` into `account`, balanced by `counterpart`.
+fn transaction_text(
+    dialect: Dialect,
+    date: Date,
+    id: Option<i32>,
+    narration: &str,
+    account: &str,
+    valor: i64,
+    counterpart: &str,
+) -> String {
+    // Both dialects read a line at a time.
+    let narration = narration.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    match dialect {
+        Dialect::Beancount => {
+            let id = id.map(|id| format!("  id: {}\n", id)).unwrap_or_default();
+
+            format!(
+                "{} * \"{}\"\n{}  {}  {} BRL\n  {}\n\n",
+                date,
+                narration.replace('\\', "\\\\").replace('"', "\\\""),
+                id,
+                account,
+                amount(valor),
+                counterpart
+            )
+        }
+        Dialect::Ledger => {
+            let code = id.map(|id| format!("({}) ", id)).unwrap_or_default();
+
+            format!(
+                "{} * {}{}\n    {}    {} BRL\n    {}\n\n",
+                date,
+                code,
+                narration,
+                account,
+                amount(valor),
+                counterpart
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::Transaction;
+    use futures_util::TryStreamExt;
+    use rstest::rstest;
+    use std::time::Duration;
+
+    const ACCOUNT: &str = "Assets:Rinha:Cliente1";
+
+    fn entry(id: i32, valor: i16, tipo: &str) -> Entry {
+        Entry {
+            id,
+            transaction: Transaction {
+                valor,
+                tipo: tipo.into(),
+                descricao: "pix".into(),
+                realizada_em: SystemTime::UNIX_EPOCH + Duration::from_secs(86_400),
+            },
+        }
+    }
+
+    /// `amount` read back into centavos.
+    fn centavos(amount: &str) -> i64 {
+        amount.replace('.', "").parse().unwrap()
+    }
+
+    #[rstest]
+    #[case::beancount(
+        Dialect::Beancount,
+        "1970-01-02 * \"a \\\"b\\\"\"\n  id: 7\n  Assets:Rinha:Cliente1  -1.50 BRL\n  Expenses:Rinha:Debitos\n\n"
+    )]
+    #[case::ledger(
+        Dialect::Ledger,
+        "1970-01-02 * (7) a \"b\"\n    Assets:Rinha:Cliente1    -1.50 BRL\n    Expenses:Rinha:Debitos\n\n"
+    )]
+    fn test_entry(#[case] dialect: Dialect, #[case] expected: &str) {
+        let entry = Entry {
+            id: 7,
+            transaction: Transaction {
+                valor: 150,
+                tipo: "d".into(),
+                descricao: "a\n\"b\"".into(),
+                realizada_em: SystemTime::UNIX_EPOCH + Duration::from_secs(86_400),
+            },
+        };
+
+        assert_eq!(
+            entry_text(dialect, "Assets:Rinha:Cliente1", &entry),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case::beancount_opened(Dialect::Beancount, -500)]
+    #[case::ledger_opened(Dialect::Ledger, -500)]
+    #[case::beancount_from_zero(Dialect::Beancount, 0)]
+    #[case::ledger_from_zero(Dialect::Ledger, 0)]
+    #[tokio::test]
+    async fn test_closing_assertion_holds(#[case] dialect: Dialect, #[case] saldo_inicial: i64) {
+        let export = Export {
+            limite: 1000,
+            saldo_inicial,
+            saldo_final: saldo_inicial + 300 - 150,
+            entries: stream::iter([Ok(entry(1, 300, "c")), Ok(entry(2, 150, "d"))]).boxed(),
+        };
+
+        let journal: String = encode(dialect, 1, Period::default(), export)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap()
+            .concat();
+
+        let mut posted = 0;
+        let mut asserted = None;
+
+        for line in journal.lines() {
+            match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+                [_, "balance", ACCOUNT, amount, "BRL"]
+                | [ACCOUNT, "0", "BRL", "=", amount, "BRL"] => asserted = Some(centavos(amount)),
+                [ACCOUNT, amount, "BRL"] => posted += centavos(amount),
+                _ => {}
+            }
+        }
+
+        assert_eq!(asserted, Some(saldo_inicial + 150));
+        assert_eq!(posted, saldo_inicial + 150);
+    }
+}
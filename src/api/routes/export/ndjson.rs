@@ -0,0 +1,13 @@
+use crate::persistence::{EntryStream, Error};
+use futures_util::{Stream, StreamExt};
+
+/// One JSON object per line, each a transaction as the API returns it plus its id.
+pub fn encode(entries: EntryStream) -> impl Stream<Item = Result<String, Error>> {
+    entries.map(|entry| {
+        let mut line =
+            serde_json::to_string(&entry?).map_err(|err| Error::Internal(err.to_string()))?;
+        line.push('\n');
+
+        Ok(line)
+    })
+}
@@ -0,0 +1,125 @@
+use super::{amount, xml_escape};
+use crate::{
+    dates::DateTime,
+    models::Entry,
+    persistence::{Error, Export, Period},
+};
+use futures_util::{stream, Stream, StreamExt};
+use std::time::SystemTime;
+
+/// Identifies this institution to the finance tools, which key accounts on it.
+const BANK_ID: &str = "RINHA";
+
+/// An OFX 2.2 bank statement: the transactions in the period, the balance at its
+/// end as the ledger balance and that plus the limit as the available balance.
+pub fn encode(
+    client_id: i16,
+    period: Period,
+    export: Export,
+) -> impl Stream<Item = Result<String, Error>> {
+    let now = SystemTime::now();
+    let end = period.to.unwrap_or(now);
+
+    let header = format!(
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n",
+            "<?OFX OFXHEADER=\"200\" VERSION=\"220\" SECURITY=\"NONE\" OLDFILEUID=\"NONE\" NEWFILEUID=\"NONE\"?>\n",
+            "<OFX>\n",
+            "<SIGNONMSGSRSV1><SONRS>",
+            "<STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>",
+            "<DTSERVER>{}</DTSERVER><LANGUAGE>POR</LANGUAGE>",
+            "</SONRS></SIGNONMSGSRSV1>\n",
+            "<BANKMSGSRSV1><STMTTRNRS><TRNUID>0</TRNUID>",
+            "<STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>\n",
+            "<STMTRS><CURDEF>BRL</CURDEF>\n",
+            "<BANKACCTFROM><BANKID>{}</BANKID><ACCTID>{}</ACCTID><ACCTTYPE>CHECKING</ACCTTYPE></BANKACCTFROM>\n",
+            "<BANKTRANLIST><DTSTART>{}</DTSTART><DTEND>{}</DTEND>\n",
+        ),
+        date_time(now),
+        BANK_ID,
+        client_id,
+        date_time(period.from.unwrap_or(SystemTime::UNIX_EPOCH)),
+        date_time(end),
+    );
+
+    let footer = format!(
+        concat!(
+            "</BANKTRANLIST>\n",
+            "<LEDGERBAL><BALAMT>{}</BALAMT><DTASOF>{}</DTASOF></LEDGERBAL>\n",
+            "<AVAILBAL><BALAMT>{}</BALAMT><DTASOF>{}</DTASOF></AVAILBAL>\n",
+            "</STMTRS></STMTTRNRS></BANKMSGSRSV1>\n",
+            "</OFX>\n",
+        ),
+        amount(export.saldo_final),
+        date_time(end),
+        amount(export.saldo_final + i64::from(export.limite)),
+        date_time(end),
+    );
+
+    stream::once(async { Ok(header) })
+        .chain(
+            export
+                .entries
+                .map(|entry| entry.map(|entry| transaction(&entry))),
+        )
+        .chain(stream::once(async { Ok(footer) }))
+}
+
+fn transaction(entry: &Entry) -> String {
+    let transaction = &entry.transaction;
+    let (kind, valor) = match transaction.tipo.as_str() {
+        "c" => ("CREDIT", i64::from(transaction.valor)),
+        _ => ("DEBIT", -i64::from(transaction.valor)),
+    };
+
+    format!(
+        "<STMTTRN><TRNTYPE>{}</TRNTYPE><DTPOSTED>{}</DTPOSTED><TRNAMT>{}</TRNAMT><FITID>{}</FITID><NAME>{}</NAME></STMTTRN>\n",
+        kind,
+        date_time(transaction.realizada_em),
+        amount(valor),
+        entry.id,
+        xml_escape(&transaction.descricao),
+    )
+}
+
+/// OFX's `YYYYMMDDHHMMSS.XXX[gmt offset:tz name]`.
+fn date_time(time: SystemTime) -> String {
+    let time = DateTime::of(time);
+
+    format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}.{:03}[0:GMT]",
+        time.date.year,
+        time.date.month,
+        time.date.day,
+        time.hour,
+        time.minute,
+        time.second,
+        time.micros / 1000
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::Transaction;
+    use std::time::Duration;
+
+    #[test]
+    fn test_transaction() {
+        let entry = Entry {
+            id: 42,
+            transaction: Transaction {
+                valor: 1050,
+                tipo: "d".into(),
+                descricao: "a&b".into(),
+                realizada_em: SystemTime::UNIX_EPOCH + Duration::from_millis(86_400_123),
+            },
+        };
+
+        assert_eq!(
+            transaction(&entry),
+            "<STMTTRN><TRNTYPE>DEBIT</TRNTYPE><DTPOSTED>19700102000000.123[0:GMT]</DTPOSTED>\
+             <TRNAMT>-10.50</TRNAMT><FITID>42</FITID><NAME>a&amp;b</NAME></STMTTRN>\n"
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use std::sync::Arc;
+
+use crate::{metrics, persistence::Repository};
+use axum::{extract::State, http::header, response::IntoResponse};
+
+pub async fn show(State(repo): State<Arc<dyn Repository>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics::render(&repo.metrics()),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        api::routes::{StatementResponse, TransactionRequest, TransactionResponse},
+        metrics::Metric,
+        persistence::{Audit, Error, Export, Period, ReadToken},
+    };
+    use axum::{async_trait, body::Body, http::Request, http::StatusCode};
+    use http_body_util::BodyExt;
+    use tower::util::ServiceExt;
+
+    struct MockRepository;
+
+    #[async_trait]
+    impl Repository for MockRepository {
+        async fn get_balance(
+            &self,
+            _client_id: &i16,
+            _token: Option<&ReadToken>,
+        ) -> Result<StatementResponse, Error> {
+            unimplemented!()
+        }
+
+        async fn create_transaction(
+            &self,
+            _client_id: &i16,
+            _data: &TransactionRequest,
+            _audit: &Audit,
+        ) -> Result<TransactionResponse, Error> {
+            unimplemented!()
+        }
+
+        fn metrics(&self) -> Vec<Metric> {
+            vec![Metric::counter("rinha_test_total", "Test counter.", 7)]
+        }
+
+        async fn export(&self, _client_id: &i16, _period: Period) -> Result<Export, Error> {
+            unimplemented!()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_show() {
+        let app = crate::api::app::new(Arc::new(MockRepository), Default::default());
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/metrics")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        assert_eq!(
+            body,
+            "# HELP rinha_test_total Test counter.\n\
+             # TYPE rinha_test_total counter\n\
+             rinha_test_total 7\n"
+        );
+    }
+}
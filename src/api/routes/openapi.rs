@@ -0,0 +1,259 @@
+use super::export;
+use crate::api::{
+    app::{self, Deadlines},
+    holds, orders,
+};
+use axum::Router;
+use utoipa::{OpenApi, ToSchema};
+use utoipa_swagger_ui::SwaggerUi;
+
+/// How `SystemTime` fields serialize, the time elapsed since the Unix epoch.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct Timestamp {
+    secs_since_epoch: u64,
+    nanos_since_epoch: u32,
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Rinha",
+        description = "Credits, debits, statements, standing orders and holds of client \
+            accounts. Amounts are in centavos.",
+        license(name = "MIT")
+    ),
+    components(schemas(export::Format)),
+    tags(
+        (name = "clientes", description = "A client's account."),
+        (name = "ordens", description = "Transactions made for a client on a schedule."),
+        (name = "reservas", description = "Funds of a client set aside for a later debit."),
+    )
+)]
+pub struct ApiDoc;
+
+/// The OpenAPI document of the routes of client accounts, collected from the
+/// routers that serve them so that no route goes undocumented.
+fn spec() -> utoipa::openapi::OpenApi {
+    let mut spec = ApiDoc::openapi();
+
+    spec.merge(app::routes(Deadlines::default()).into_openapi());
+    spec.merge(orders::routes().into_openapi());
+    spec.merge(holds::routes().into_openapi());
+
+    spec
+}
+
+/// The OpenAPI document at `/openapi.json`, browsable at `/docs`.
+pub fn docs() -> Router {
+    SwaggerUi::new("/docs").url("/openapi.json", spec()).into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        api::routes::{StatementResponse, TransactionRequest, TransactionResponse},
+        models::{Hold, NewHold, NewStandingOrder, Run, StandingOrder},
+        persistence::{
+            Audit, Claim, Error, Export, Holds, Period, ReadToken, Repository, StandingOrders,
+        },
+    };
+    use axum::{
+        async_trait,
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use std::{sync::Arc, time::SystemTime};
+    use tower::ServiceExt;
+
+    const SNAPSHOT: &str = include_str!("../../../openapi.json");
+
+    struct MockRepository;
+
+    #[async_trait]
+    impl Repository for MockRepository {
+        async fn create_transaction(
+            &self,
+            _client_id: &i16,
+            _data: &TransactionRequest,
+            _audit: &Audit,
+        ) -> Result<TransactionResponse, Error> {
+            Err(Error::Unavailable)
+        }
+
+        async fn get_balance(
+            &self,
+            _client_id: &i16,
+            _token: Option<&ReadToken>,
+        ) -> Result<StatementResponse, Error> {
+            Err(Error::Unavailable)
+        }
+
+        async fn export(&self, _client_id: &i16, _period: Period) -> Result<Export, Error> {
+            Err(Error::Unavailable)
+        }
+    }
+
+    #[async_trait]
+    impl StandingOrders for MockRepository {
+        async fn create_order(
+            &self,
+            _client_id: i16,
+            _order: &NewStandingOrder,
+        ) -> Result<StandingOrder, Error> {
+            Err(Error::Unavailable)
+        }
+
+        async fn orders(&self, _client_id: i16) -> Result<Vec<StandingOrder>, Error> {
+            Err(Error::Unavailable)
+        }
+
+        async fn cancel_order(
+            &self,
+            _client_id: i16,
+            _order_id: i32,
+        ) -> Result<StandingOrder, Error> {
+            Err(Error::Unavailable)
+        }
+
+        async fn runs(
+            &self,
+            _client_id: i16,
+            _order_id: i32,
+            _limit: i64,
+        ) -> Result<Vec<Run>, Error> {
+            Err(Error::Unavailable)
+        }
+
+        async fn claim_due(
+            &self,
+            _now: SystemTime,
+            _instance: &str,
+            _limit: i64,
+        ) -> Result<Vec<Claim>, Error> {
+            Err(Error::Unavailable)
+        }
+
+        async fn claim_stalled(
+            &self,
+            _stalled_since: SystemTime,
+            _instance: &str,
+            _limit: i64,
+        ) -> Result<Vec<Claim>, Error> {
+            Err(Error::Unavailable)
+        }
+
+        async fn record_run(
+            &self,
+            _run_id: i64,
+            _resultado: &str,
+            _saldo: Option<i32>,
+        ) -> Result<(), Error> {
+            Err(Error::Unavailable)
+        }
+    }
+
+    #[async_trait]
+    impl Holds for MockRepository {
+        async fn create_hold(&self, _client_id: i16, _hold: &NewHold) -> Result<Hold, Error> {
+            Err(Error::Unavailable)
+        }
+
+        async fn holds(&self, _client_id: i16) -> Result<Vec<Hold>, Error> {
+            Err(Error::Unavailable)
+        }
+
+        async fn capture_hold(
+            &self,
+            _client_id: i16,
+            _hold_id: i32,
+            _valor: Option<i16>,
+            _audit: &Audit,
+        ) -> Result<TransactionResponse, Error> {
+            Err(Error::Unavailable)
+        }
+
+        async fn void_hold(&self, _client_id: i16, _hold_id: i32) -> Result<Hold, Error> {
+            Err(Error::Unavailable)
+        }
+
+        async fn expire_holds(&self, _limit: i32) -> Result<i32, Error> {
+            Err(Error::Unavailable)
+        }
+    }
+
+    /// The routes of client accounts as served, as `main` mounts them.
+    fn app() -> Router {
+        app::new(Arc::new(MockRepository), Deadlines::default())
+            .merge(orders::new(Arc::new(MockRepository)))
+            .merge(holds::new(Arc::new(MockRepository)))
+    }
+
+    /// Regenerate with `UPDATE_OPENAPI=1 cargo test openapi`.
+    #[test]
+    fn test_spec_matches_snapshot() {
+        let spec = spec().to_pretty_json().unwrap() + "\n";
+
+        if std::env::var_os("UPDATE_OPENAPI").is_some() {
+            std::fs::write(concat!(env!("CARGO_MANIFEST_DIR"), "/openapi.json"), &spec).unwrap();
+            return;
+        }
+
+        assert!(
+            spec == SNAPSHOT,
+            "openapi.json is out of date, regenerate it with UPDATE_OPENAPI=1 cargo test openapi"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_documented_routes_exist() {
+        let app = app();
+
+        for (path, item) in spec().paths.paths {
+            let uri = path
+                .replace("{id}", "1")
+                .replace("{ordem_id}", "1")
+                .replace("{reserva_id}", "1");
+            let methods = [
+                ("GET", item.get.is_some()),
+                ("POST", item.post.is_some()),
+                ("PUT", item.put.is_some()),
+                ("DELETE", item.delete.is_some()),
+                ("PATCH", item.patch.is_some()),
+            ];
+
+            for (method, _) in methods.into_iter().filter(|(_, documented)| *documented) {
+                let response = app
+                    .clone()
+                    .oneshot(
+                        Request::builder()
+                            .method(method)
+                            .uri(&uri)
+                            .body(Body::empty())
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+
+                assert!(
+                    ![StatusCode::NOT_FOUND, StatusCode::METHOD_NOT_ALLOWED]
+                        .contains(&response.status()),
+                    "{method} {path} is documented but not routed"
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serves_spec() {
+        let app = app();
+
+        let response = app
+            .oneshot(Request::get("/openapi.json").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -1,24 +1,84 @@
 use std::sync::Arc;
 
-use crate::{models, persistence::Repository};
+use super::{
+    content::{Encoded, Encoding},
+    READ_TOKEN,
+};
+use crate::{models, persistence::Repository, telemetry};
 use axum::{
     extract::{Path, State},
-    http::StatusCode,
-    Json,
+    http::{HeaderMap, StatusCode},
 };
 use serde::Serialize;
+use utoipa::ToSchema;
 
-#[derive(Serialize)]
+/// A client's balance with its latest transactions.
+#[derive(Clone, Serialize, ToSchema)]
+#[schema(as = StatementResponse)]
+#[cfg_attr(test, derive(Debug))]
 pub struct Response {
     pub saldo: models::Balance,
+    /// The last 10 transactions, newest first.
     pub ultimas_transacoes: Vec<models::Transaction>,
 }
 
+/// The client's balance and latest transactions.
+#[utoipa::path(
+    get,
+    path = "/clientes/{id}/extrato",
+    operation_id = "show_statement",
+    tag = "clientes",
+    params(
+        ("id" = i16, Path, description = "Client id."),
+        (
+            "x-read-token" = Option<String>,
+            Header,
+            description = "From a previous transaction, to read a state at least that recent."
+        ),
+        (
+            "accept" = Option<String>,
+            Header,
+            description = "`application/msgpack` or `application/cbor` instead of JSON."
+        ),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "The client's statement.",
+            content(
+                (Response = "application/json"),
+                (Response = "application/msgpack"),
+                (Response = "application/cbor"),
+            ),
+        ),
+        (status = 400, description = "Malformed read token."),
+        (status = 404, description = "No such client."),
+        (status = 503, description = "The database is unavailable, retry later."),
+        (status = 504, description = "Took longer than the deadline."),
+    ),
+)]
 pub async fn show(
     State(repo): State<Arc<dyn Repository>>,
     Path(id): Path<i16>,
-) -> Result<Json<Response>, StatusCode> {
-    Ok(Json(repo.get_balance(&id).await?))
+    headers: HeaderMap,
+) -> Result<Encoded<Response>, StatusCode> {
+    let token = match headers
+        .get(READ_TOKEN)
+        .map(|value| value.to_str().ok().and_then(|value| value.parse().ok()))
+    {
+        Some(Some(token)) => Some(token),
+        Some(None) => {
+            telemetry::error!("Invalid read token");
+
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        None => None,
+    };
+
+    Ok(Encoded(
+        Encoding::accepted(&headers),
+        repo.get_balance(&id, token.as_ref()).await?,
+    ))
 }
 
 #[cfg(test)]
@@ -29,7 +89,7 @@ mod test {
             self,
             routes::{TransactionRequest, TransactionResponse},
         },
-        persistence::Error,
+        persistence::{Audit, Error, Export, Period, ReadToken},
     };
     use axum::{async_trait, body::Body, http::Request};
     use http_body_util::BodyExt;
@@ -61,6 +121,7 @@ mod test {
     fn balance() -> models::Balance {
         models::Balance {
             total: 20,
+            disponivel: 15,
             limite: 1000,
             data_extrato: SystemTime::UNIX_EPOCH,
         }
@@ -74,6 +135,7 @@ mod test {
         json!({
             "saldo": {
                 "total": balance.total,
+                "disponivel": balance.disponivel,
                 "limite": balance.limite,
                 "data_extrato": balance.data_extrato,
             },
@@ -93,6 +155,7 @@ mod test {
         let expected_json = json!({
             "saldo": {
                 "total": balance.total,
+                "disponivel": balance.disponivel,
                 "limite": balance.limite,
                 "data_extrato": balance.data_extrato,
             },
@@ -122,7 +185,15 @@ mod test {
 
     #[async_trait]
     impl Repository for MockRepository {
-        async fn get_balance(&self, _client_id: &i16) -> Result<Response, Error> {
+        async fn get_balance(
+            &self,
+            _client_id: &i16,
+            token: Option<&ReadToken>,
+        ) -> Result<Response, Error> {
+            if token.is_some_and(|token| u64::from(*token) != 0x1_0000_0002) {
+                return Err(Error::Internal("unexpected read token".to_string()));
+            }
+
             match self.scenario {
                 TestScenario::ClientNotFound => Err(Error::ClientNotFound),
                 TestScenario::InternalError => Err(Error::Internal("internal error".to_string())),
@@ -141,9 +212,14 @@ mod test {
             &self,
             _client_id: &i16,
             _data: &TransactionRequest,
+            _audit: &Audit,
         ) -> Result<TransactionResponse, Error> {
             unimplemented!()
         }
+
+        async fn export(&self, _client_id: &i16, _period: Period) -> Result<Export, Error> {
+            unimplemented!()
+        }
     }
 
     #[rstest]
@@ -162,9 +238,12 @@ mod test {
         #[case] expected_status: StatusCode,
         balance: &models::Balance,
     ) {
-        let app = api::app::new(Arc::new(MockRepository {
-            scenario: scenario.clone(),
-        }));
+        let app = api::app::new(
+            Arc::new(MockRepository {
+                scenario: scenario.clone(),
+            }),
+            Default::default(),
+        );
 
         let response = app
             .oneshot(
@@ -184,6 +263,7 @@ mod test {
             let expected_json = json!({
                 "saldo": {
                     "total": balance.total,
+                    "disponivel": balance.disponivel,
                     "limite": balance.limite,
                     "data_extrato": balance.data_extrato,
                 },
@@ -198,4 +278,82 @@ mod test {
             assert!(body.is_empty());
         }
     }
+
+    #[rstest]
+    #[case::json(None, Encoding::Json)]
+    #[case::msgpack(Some("application/msgpack"), Encoding::MessagePack)]
+    #[case::cbor(Some("application/cbor;q=0.9, application/json;q=0.5"), Encoding::Cbor)]
+    #[case::wildcard(Some("*/*"), Encoding::Json)]
+    #[tokio::test]
+    async fn test_show_encoded(
+        #[case] accept: Option<&'static str>,
+        #[case] expected: Encoding,
+        balance: &models::Balance,
+    ) {
+        let app = api::app::new(
+            Arc::new(MockRepository {
+                scenario: TestScenario::Success(transactions),
+            }),
+            Default::default(),
+        );
+
+        let mut request = Request::builder().uri("/clientes/1/extrato");
+
+        if let Some(accept) = accept {
+            request = request.header(axum::http::header::ACCEPT, accept);
+        }
+
+        let response = app
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[axum::http::header::CONTENT_TYPE],
+            expected.content_type()
+        );
+        assert_eq!(response.headers()[axum::http::header::VARY], "accept");
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        assert_eq!(
+            expected.decode::<serde_json::Value>(&body).unwrap(),
+            json!({
+                "saldo": {
+                    "total": balance.total,
+                    "disponivel": balance.disponivel,
+                    "limite": balance.limite,
+                    "data_extrato": balance.data_extrato,
+                },
+                "ultimas_transacoes": transactions(),
+            })
+        );
+    }
+
+    #[rstest]
+    #[case::valid("1/2", StatusCode::OK)]
+    #[case::invalid("latest", StatusCode::BAD_REQUEST)]
+    #[tokio::test]
+    async fn test_show_read_token(#[case] token: &str, #[case] expected_status: StatusCode) {
+        let app = api::app::new(
+            Arc::new(MockRepository {
+                scenario: TestScenario::Success(Vec::new),
+            }),
+            Default::default(),
+        );
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/clientes/1/extrato")
+                    .header(READ_TOKEN, token)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), expected_status);
+    }
 }
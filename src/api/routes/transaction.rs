@@ -1,72 +1,286 @@
 use std::sync::Arc;
 
-use crate::{persistence::Repository, telemetry};
+use super::{
+    content::{Encoded, Encoding},
+    Principal, IDEMPOTENCY_KEY, READ_TOKEN, REQUEST_ID,
+};
+use crate::{
+    persistence::{Audit, Error, ReadToken, Repository},
+    telemetry,
+};
 use axum::{
-    async_trait,
-    extract::{rejection::JsonRejection, FromRequest, Path, Request as AxumRequest, State},
-    http::StatusCode,
-    Json,
+    async_trait, body,
+    extract::{ConnectInfo, FromRequest, Path, Request as AxumRequest, State},
+    http::{Extensions, HeaderMap, StatusCode},
+    response::{AppendHeaders, IntoResponse, Response as AxumResponse},
 };
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use utoipa::ToSchema;
+
+/// Largest body read, the same as axum's default limit for `Json`.
+const MAX_PAYLOAD: usize = 2 * 1024 * 1024;
 
+/// A credit or debit of a client.
 #[cfg_attr(test, derive(Debug, PartialEq))]
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize, Serialize, ToSchema)]
+#[schema(as = TransactionRequest)]
 pub struct Request {
+    /// Centavos.
     pub valor: i16,
+    /// `c` for a credit, `d` for a debit.
+    #[schema(pattern = "^[cd]$")]
     pub tipo: String,
+    #[schema(min_length = 1, max_length = 10)]
     pub descricao: String,
 }
 
-#[derive(Serialize)]
+impl Request {
+    /// Whether `tipo` and `descricao` are acceptable, `valor` being checked by its type.
+    pub fn is_valid(&self) -> bool {
+        matches!(
+            (self.descricao.len(), self.tipo.as_str()),
+            (1..=10, "c" | "d")
+        )
+    }
+}
+
+/// The client's balance right after a transaction.
+#[derive(Serialize, ToSchema)]
+#[schema(as = TransactionResponse)]
+#[cfg_attr(test, derive(Debug))]
 pub struct Response {
     pub limite: i32,
     pub saldo: i32,
+    #[serde(skip)]
+    pub token: Option<ReadToken>,
 }
 
+/// The rule of the client's policy that refused a transaction.
+#[derive(Serialize, ToSchema)]
+#[schema(as = PolicyViolation)]
+#[cfg_attr(test, derive(Debug))]
+pub struct Violation {
+    /// `valor_acima_do_maximo`, `valor_abaixo_do_minimo`, `descricao_recusada` or
+    /// `credito_recusado`.
+    pub regra: &'static str,
+}
+
+/// Credits or debits a client.
+#[utoipa::path(
+    post,
+    path = "/clientes/{id}/transacoes",
+    operation_id = "create_transaction",
+    tag = "clientes",
+    params(
+        ("id" = i16, Path, description = "Client id."),
+        (
+            "accept" = Option<String>,
+            Header,
+            description = "`application/msgpack` or `application/cbor` instead of JSON."
+        ),
+        (
+            "idempotency-key" = Option<String>,
+            Header,
+            description = "Applies the transaction once per key for a day, sending it again \
+                answers with the outcome it had."
+        ),
+    ),
+    request_body(
+        content(
+            (Request = "application/json"),
+            (Request = "application/msgpack"),
+            (Request = "application/cbor"),
+        ),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Applied.",
+            content(
+                (Response = "application/json"),
+                (Response = "application/msgpack"),
+                (Response = "application/cbor"),
+            ),
+            headers(
+                (
+                    "x-read-token" = String,
+                    description = "Sent back on reads to see this transaction, with replicas only."
+                ),
+            ),
+        ),
+        (status = 404, description = "No such client."),
+        (
+            status = 422,
+            description = "Invalid transaction, a debit past the limit, an idempotency key \
+                sent before with another transaction, or refused by the client's policy, in \
+                which case the body names the rule.",
+            content(
+                (Violation = "application/json"),
+                (Violation = "application/msgpack"),
+                (Violation = "application/cbor"),
+            ),
+        ),
+        (status = 503, description = "The database is unavailable, retry later."),
+        (status = 504, description = "Took longer than the deadline, it may have been applied."),
+    ),
+)]
 pub async fn create(
     State(repo): State<Arc<dyn Repository>>,
     Path(id): Path<i16>,
-    ValidateCreate(payload): ValidateCreate<Request>,
-) -> Result<Json<Response>, StatusCode> {
-    Ok(Json(repo.create_transaction(&id, &payload).await?))
+    headers: HeaderMap,
+    validated: Result<ValidateCreate<Request>, Invalid>,
+) -> Result<impl IntoResponse, AxumResponse> {
+    let ValidateCreate(payload, audit) = match validated {
+        Ok(validated) => validated,
+        Err(Invalid(audit)) => {
+            // Refused either way, failing to record it shouldn't hide why.
+            #[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+            if let Err(err) = repo.reject(&id, &audit).await {
+                telemetry::error!("Failed to audit an invalid transaction: {:?}", err);
+            }
+
+            return Err(StatusCode::UNPROCESSABLE_ENTITY.into_response());
+        }
+    };
+
+    let encoding = Encoding::accepted(&headers);
+    let response = repo
+        .create_transaction(&id, &payload, &audit)
+        .await
+        .map_err(|err| rejection(err, encoding))?;
+    let token = response.token.map(|token| (READ_TOKEN, token.to_string()));
+
+    Ok((AppendHeaders(token), Encoded(encoding, response)))
+}
+
+/// The status for `err`, naming the rule broken when the client's policy refused.
+pub fn rejection(err: Error, encoding: Encoding) -> AxumResponse {
+    match err {
+        Error::PolicyViolation(rule) => {
+            telemetry::error!("Refused by policy: {:?}", rule);
+
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Encoded(encoding, Violation { regra: rule.code() }),
+            )
+                .into_response()
+        }
+        err => StatusCode::from(err).into_response(),
+    }
 }
 
-pub struct ValidateCreate<Request>(pub Request);
+/// A transaction that passed validation, with who sent it.
+pub struct ValidateCreate<Request>(pub Request, pub Audit);
+
+/// A transaction refused as invalid, with what to audit it as.
+pub struct Invalid(pub Audit);
+
+impl IntoResponse for Invalid {
+    fn into_response(self) -> AxumResponse {
+        StatusCode::UNPROCESSABLE_ENTITY.into_response()
+    }
+}
 
 #[async_trait]
 impl<S> FromRequest<S> for ValidateCreate<Request>
 where
-    Request: DeserializeOwned,
     S: Send + Sync,
-    Json<Request>: FromRequest<S, Rejection = JsonRejection>,
 {
-    type Rejection = StatusCode;
+    type Rejection = Invalid;
 
-    async fn from_request(req: AxumRequest, state: &S) -> Result<Self, Self::Rejection> {
-        let Json(data) = Json::from_request(req, state).await.map_err(
+    async fn from_request(req: AxumRequest, _state: &S) -> Result<Self, Self::Rejection> {
+        let (parts, body) = req.into_parts();
+        let invalid = |payload: &[u8]| {
+            Invalid(audit(
+                &parts.headers,
+                &parts.extensions,
+                String::from_utf8_lossy(payload).into_owned(),
+            ))
+        };
+
+        let payload = body::to_bytes(body, MAX_PAYLOAD).await.map_err(
             #[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
             |e| {
-                telemetry::error!("Failed to deserialize request JSON: {}", e);
+                telemetry::error!("Failed to read request body: {}", e);
 
-                StatusCode::UNPROCESSABLE_ENTITY
+                invalid(&[])
             },
         )?;
 
-        match (data.descricao.len(), data.tipo.as_str()) {
-            (1..=10, "c" | "d") => Ok(Self(data)),
-            _ => {
-                telemetry::error!("Invalid transaction kind or description");
+        let Some(encoding) = Encoding::of_request(&parts.headers) else {
+            telemetry::error!("Unsupported request content type");
 
-                Err(StatusCode::UNPROCESSABLE_ENTITY)
-            }
+            return Err(invalid(&payload));
+        };
+
+        let data = encoding.decode::<Request>(&payload).map_err(
+            #[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+            |e| {
+                telemetry::error!("Failed to deserialize request body: {}", e);
+
+                invalid(&payload)
+            },
+        )?;
+
+        // Kept as sent for the audit log, binary bodies as their JSON equivalent.
+        let payload = match encoding {
+            Encoding::Json => String::from_utf8_lossy(&payload).into_owned(),
+            _ => serde_json::to_string(&data).expect("request serializes"),
+        };
+        let audit = audit(&parts.headers, &parts.extensions, payload);
+
+        if data.is_valid() {
+            Ok(Self(data, audit))
+        } else {
+            telemetry::error!("Invalid transaction kind or description");
+
+            Err(Invalid(audit))
         }
     }
 }
 
+/// Who sent the request, for the audit log.
+///
+/// The address is the rightmost hop of `X-Forwarded-For`, which the proxy in front
+/// appends, as anything left of it was sent by the client. Without the header it's
+/// the peer's. The principal is only what the request was authenticated as.
+pub fn audit(headers: &HeaderMap, extensions: &Extensions, payload: String) -> Audit {
+    let forwarded_for = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .next_back()
+        .and_then(|value| value.to_str().ok())
+        .and_then(|forwarded| forwarded.rsplit(',').next())
+        .and_then(|ip| ip.trim().parse::<IpAddr>().ok());
+    let peer = extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+
+    Audit {
+        request_id: headers
+            .get(REQUEST_ID)
+            .and_then(|value| value.to_str().ok())
+            .map(ToString::to_string),
+        idempotency_key: headers
+            .get(IDEMPOTENCY_KEY)
+            .and_then(|value| value.to_str().ok())
+            .map(ToString::to_string),
+        ip: forwarded_for.or(peer),
+        principal: extensions
+            .get::<Principal>()
+            .map(|Principal(principal)| principal.clone()),
+        payload,
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::{api::routes::StatementResponse, persistence::Error};
+    use crate::{
+        api::routes::{StatementResponse, PRINCIPAL},
+        persistence::{Export, Period, Rule},
+    };
     use axum::{async_trait, body::Body};
     use http_body_util::BodyExt;
     use rstest::rstest;
@@ -103,7 +317,12 @@ mod test {
         });
 
         assert_eq!(
-            serde_json::to_value(Response { saldo, limite }).unwrap(),
+            serde_json::to_value(Response {
+                saldo,
+                limite,
+                token: Some(ReadToken::from(1))
+            })
+            .unwrap(),
             expected_json
         );
     }
@@ -117,12 +336,18 @@ mod test {
         ClientNotFound,
         InternalError,
         ConnectionError,
+        PolicyViolation(Rule),
         Success(i32, i32),
+        SuccessWithToken(i32, i32, u64),
     }
 
     #[async_trait]
     impl Repository for MockRepository {
-        async fn get_balance(&self, _client_id: &i16) -> Result<StatementResponse, Error> {
+        async fn get_balance(
+            &self,
+            _client_id: &i16,
+            _token: Option<&ReadToken>,
+        ) -> Result<StatementResponse, Error> {
             unimplemented!()
         }
 
@@ -130,14 +355,29 @@ mod test {
             &self,
             _client_id: &i16,
             _data: &Request,
+            _audit: &Audit,
         ) -> Result<Response, Error> {
             match self.scenario {
                 TestScenario::ClientNotFound => Err(Error::ClientNotFound),
                 TestScenario::InternalError => Err(Error::Internal("internal error".to_string())),
                 TestScenario::ConnectionError => Err(Error::Connection),
-                TestScenario::Success(limite, saldo) => Ok(Response { limite, saldo }),
+                TestScenario::PolicyViolation(rule) => Err(Error::PolicyViolation(rule)),
+                TestScenario::Success(limite, saldo) => Ok(Response {
+                    limite,
+                    saldo,
+                    token: None,
+                }),
+                TestScenario::SuccessWithToken(limite, saldo, token) => Ok(Response {
+                    limite,
+                    saldo,
+                    token: Some(ReadToken::from(token)),
+                }),
             }
         }
+
+        async fn export(&self, _client_id: &i16, _period: Period) -> Result<Export, Error> {
+            unimplemented!()
+        }
     }
 
     #[rstest]
@@ -170,9 +410,12 @@ mod test {
         #[case] request_json: serde_json::Value,
         #[case] expected_status: StatusCode,
     ) {
-        let app = crate::api::app::new(Arc::new(MockRepository {
-            scenario: TestScenario::Success(10, 100),
-        }));
+        let app = crate::api::app::new(
+            Arc::new(MockRepository {
+                scenario: TestScenario::Success(10, 100),
+            }),
+            Default::default(),
+        );
 
         let response = app
             .oneshot(
@@ -194,11 +437,18 @@ mod test {
     #[case::internal_error(TestScenario::InternalError, StatusCode::INTERNAL_SERVER_ERROR)]
     #[case::connection_error(TestScenario::ConnectionError, StatusCode::INTERNAL_SERVER_ERROR)]
     #[case::success(TestScenario::Success(10, 100), StatusCode::OK)]
+    #[case::success_with_token(
+        TestScenario::SuccessWithToken(10, 100, 0x1_0000_0002),
+        StatusCode::OK
+    )]
     #[tokio::test]
     async fn test_create(#[case] scenario: TestScenario, #[case] expected_status: StatusCode) {
-        let app = crate::api::app::new(Arc::new(MockRepository {
-            scenario: scenario.clone(),
-        }));
+        let app = crate::api::app::new(
+            Arc::new(MockRepository {
+                scenario: scenario.clone(),
+            }),
+            Default::default(),
+        );
 
         let response = app
             .oneshot(
@@ -216,9 +466,18 @@ mod test {
 
         assert_eq!(response.status(), expected_status);
 
+        let token = response.headers().get(READ_TOKEN).cloned();
         let body = response.into_body().collect().await.unwrap().to_bytes();
 
-        if let TestScenario::Success(limite, saldo) = scenario {
+        if let TestScenario::SuccessWithToken(_, _, _) = scenario {
+            assert_eq!(token.unwrap(), "1/2");
+        } else {
+            assert!(token.is_none());
+        }
+
+        if let TestScenario::Success(limite, saldo)
+        | TestScenario::SuccessWithToken(limite, saldo, _) = scenario
+        {
             let expected_json = json!({
                 "saldo": saldo,
                 "limite": limite,
@@ -232,4 +491,251 @@ mod test {
             assert!(body.is_empty());
         }
     }
+
+    #[rstest]
+    #[case::above_maximum(Rule::AboveMaximum, "valor_acima_do_maximo")]
+    #[case::below_minimum(Rule::BelowMinimum, "valor_abaixo_do_minimo")]
+    #[case::description_refused(Rule::DescriptionRefused, "descricao_recusada")]
+    #[case::credit_refused(Rule::CreditRefused, "credito_recusado")]
+    #[tokio::test]
+    async fn test_create_policy_violation(#[case] rule: Rule, #[case] expected: &str) {
+        let app = crate::api::app::new(
+            Arc::new(MockRepository {
+                scenario: TestScenario::PolicyViolation(rule),
+            }),
+            Default::default(),
+        );
+
+        let response = app
+            .oneshot(
+                axum::http::Request::builder()
+                    .method(axum::http::Method::POST)
+                    .uri("/clientes/1/transacoes")
+                    .header(axum::http::header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        json!({ "valor": 10, "tipo": "c", "descricao": "descricao" }).to_string(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            json!({ "regra": expected })
+        );
+    }
+
+    #[derive(Default)]
+    struct RecordingRepository {
+        received: std::sync::Mutex<Option<(Request, Audit)>>,
+        rejected: std::sync::Mutex<Option<(i16, Audit)>>,
+    }
+
+    #[async_trait]
+    impl Repository for RecordingRepository {
+        async fn get_balance(
+            &self,
+            _client_id: &i16,
+            _token: Option<&ReadToken>,
+        ) -> Result<StatementResponse, Error> {
+            unimplemented!()
+        }
+
+        async fn create_transaction(
+            &self,
+            _client_id: &i16,
+            data: &Request,
+            audit: &Audit,
+        ) -> Result<Response, Error> {
+            *self.received.lock().unwrap() = Some((data.clone(), audit.clone()));
+
+            Err(Error::BalanceConstraintViolation)
+        }
+
+        async fn reject(&self, client_id: &i16, audit: &Audit) -> Result<(), Error> {
+            *self.rejected.lock().unwrap() = Some((*client_id, audit.clone()));
+
+            Ok(())
+        }
+
+        async fn export(&self, _client_id: &i16, _period: Period) -> Result<Export, Error> {
+            unimplemented!()
+        }
+    }
+
+    #[rstest]
+    #[case::unauthenticated(None, None)]
+    #[case::authenticated(Some("alice"), Some("alice"))]
+    #[tokio::test]
+    async fn test_create_audit(
+        #[case] authenticated_as: Option<&str>,
+        #[case] expected_principal: Option<&str>,
+    ) {
+        let repo = Arc::new(RecordingRepository::default());
+        let app = crate::api::app::new(repo.clone(), Default::default());
+        let payload = r#"{"valor": 10, "tipo": "d", "descricao": "descricao"}"#;
+
+        let mut request = axum::http::Request::builder()
+            .method(axum::http::Method::POST)
+            .uri("/clientes/1/transacoes")
+            .header(axum::http::header::CONTENT_TYPE, "application/json")
+            .header(REQUEST_ID, "abc")
+            .header(IDEMPOTENCY_KEY, "k1")
+            // Claimed by the client, which nothing checked.
+            .header(PRINCIPAL, "mallory")
+            // The client sent the first hop, the proxy appended the second.
+            .header("x-forwarded-for", "10.0.0.1, 10.0.0.2");
+
+        if let Some(principal) = authenticated_as {
+            request = request.extension(Principal(principal.into()));
+        }
+
+        let response = app
+            .oneshot(request.body(Body::from(payload)).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            repo.received.lock().unwrap().take().unwrap().1,
+            Audit {
+                request_id: Some("abc".into()),
+                idempotency_key: Some("k1".into()),
+                ip: Some([10, 0, 0, 2].into()),
+                principal: expected_principal.map(Into::into),
+                payload: payload.into(),
+            }
+        );
+    }
+
+    #[rstest]
+    #[case::invalid_tipo(r#"{"valor": 10, "tipo": "x", "descricao": "descricao"}"#)]
+    #[case::invalid_valor(r#"{"valor": 1.2, "tipo": "c", "descricao": "descricao"}"#)]
+    #[case::malformed(r#"{"valor": 10,"#)]
+    #[tokio::test]
+    async fn test_create_invalid_audit(#[case] payload: &str) {
+        let repo = Arc::new(RecordingRepository::default());
+        let app = crate::api::app::new(repo.clone(), Default::default());
+
+        let response = app
+            .oneshot(
+                axum::http::Request::builder()
+                    .method(axum::http::Method::POST)
+                    .uri("/clientes/1/transacoes")
+                    .header(axum::http::header::CONTENT_TYPE, "application/json")
+                    .header(REQUEST_ID, "abc")
+                    .body(Body::from(payload.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(repo.received.lock().unwrap().is_none());
+
+        let (client_id, audit) = repo.rejected.lock().unwrap().take().unwrap();
+
+        assert_eq!(client_id, 1);
+        assert_eq!(audit.request_id.as_deref(), Some("abc"));
+        assert_eq!(audit.payload, payload);
+    }
+
+    #[rstest]
+    #[case::msgpack_to_cbor(Encoding::MessagePack, Encoding::Cbor)]
+    #[case::cbor_to_msgpack(Encoding::Cbor, Encoding::MessagePack)]
+    #[case::msgpack_to_json(Encoding::MessagePack, Encoding::Json)]
+    #[tokio::test]
+    async fn test_create_encoded(#[case] request: Encoding, #[case] accept: Encoding) {
+        let repo = Arc::new(RecordingRepository::default());
+        let app = crate::api::app::new(repo.clone(), Default::default());
+        let payload = json!({ "valor": 10, "tipo": "d", "descricao": "descricao" });
+
+        let response = app
+            .oneshot(
+                axum::http::Request::builder()
+                    .method(axum::http::Method::POST)
+                    .uri("/clientes/1/transacoes")
+                    .header(axum::http::header::CONTENT_TYPE, request.content_type())
+                    .header(axum::http::header::ACCEPT, accept.content_type())
+                    .body(Body::from(request.encode(&payload).unwrap()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let (received, audit) = repo.received.lock().unwrap().take().unwrap();
+
+        assert_eq!(received.valor, 10);
+        assert_eq!(received.descricao, "descricao");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&audit.payload).unwrap(),
+            payload
+        );
+
+        let app = crate::api::app::new(
+            Arc::new(MockRepository {
+                scenario: TestScenario::Success(10, 100),
+            }),
+            Default::default(),
+        );
+
+        let response = app
+            .oneshot(
+                axum::http::Request::builder()
+                    .method(axum::http::Method::POST)
+                    .uri("/clientes/1/transacoes")
+                    .header(axum::http::header::CONTENT_TYPE, request.content_type())
+                    .header(axum::http::header::ACCEPT, accept.content_type())
+                    .body(Body::from(request.encode(&payload).unwrap()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[axum::http::header::CONTENT_TYPE],
+            accept.content_type()
+        );
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        assert_eq!(
+            accept.decode::<serde_json::Value>(&body).unwrap(),
+            json!({ "saldo": 100, "limite": 10 })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_unsupported_content_type() {
+        let app = crate::api::app::new(
+            Arc::new(MockRepository {
+                scenario: TestScenario::Success(10, 100),
+            }),
+            Default::default(),
+        );
+
+        let response = app
+            .oneshot(
+                axum::http::Request::builder()
+                    .method(axum::http::Method::POST)
+                    .uri("/clientes/1/transacoes")
+                    .header(axum::http::header::CONTENT_TYPE, "text/plain")
+                    .body(Body::from(
+                        json!({ "valor": 10, "tipo": "c", "descricao": "descricao" }).to_string(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
 }
@@ -0,0 +1,84 @@
+pub mod healthcheck;
+pub mod import;
+pub mod seed;
+
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: rinha [COMMAND]
+
+Commands:
+  serve             Serve the API (default)
+  migrate           Apply pending database migrations
+  seed <file>       Create the clients listed in a JSON file
+  import <file>     Apply the transactions of a CSV file, reporting rejected rows
+  healthcheck       Probe the instance listening on PORT
+  reconcile [--fix] Report clients whose balance drifted, correcting it with --fix
+  client show <id>  Print a client's statement";
+
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub enum Command {
+    Serve,
+    Migrate,
+    Seed(PathBuf),
+    Import(PathBuf),
+    Healthcheck,
+    Reconcile { fix: bool },
+    ShowClient(i16),
+}
+
+/// Parses the arguments following the program name.
+pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let command = match args.next().as_deref() {
+        None | Some("serve") => Command::Serve,
+        Some("migrate") => Command::Migrate,
+        Some("seed") => Command::Seed(args.next().ok_or("seed requires a file")?.into()),
+        Some("import") => Command::Import(args.next().ok_or("import requires a file")?.into()),
+        Some("healthcheck") => Command::Healthcheck,
+        Some("reconcile") => match args.next().as_deref() {
+            None => Command::Reconcile { fix: false },
+            Some("--fix") => Command::Reconcile { fix: true },
+            Some(arg) => return Err(format!("unexpected argument: {}", arg)),
+        },
+        Some("client") => match (args.next().as_deref(), args.next()) {
+            (Some("show"), Some(id)) => Command::ShowClient(
+                id.parse()
+                    .map_err(|_| format!("invalid client id: {}", id))?,
+            ),
+            _ => return Err("client requires: show <id>".into()),
+        },
+        Some(command) => return Err(format!("unknown command: {}", command)),
+    };
+
+    match args.next() {
+        Some(arg) => Err(format!("unexpected argument: {}", arg)),
+        None => Ok(command),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::default(&[], Ok(Command::Serve))]
+    #[case::serve(&["serve"], Ok(Command::Serve))]
+    #[case::migrate(&["migrate"], Ok(Command::Migrate))]
+    #[case::seed(&["seed", "clients.json"], Ok(Command::Seed("clients.json".into())))]
+    #[case::seed_without_file(&["seed"], Err("seed requires a file".into()))]
+    #[case::import(&["import", "transacoes.csv"], Ok(Command::Import("transacoes.csv".into())))]
+    #[case::import_without_file(&["import"], Err("import requires a file".into()))]
+    #[case::healthcheck(&["healthcheck"], Ok(Command::Healthcheck))]
+    #[case::reconcile(&["reconcile"], Ok(Command::Reconcile { fix: false }))]
+    #[case::reconcile_fix(&["reconcile", "--fix"], Ok(Command::Reconcile { fix: true }))]
+    #[case::reconcile_unknown_flag(&["reconcile", "--all"], Err("unexpected argument: --all".into()))]
+    #[case::client_show(&["client", "show", "1"], Ok(Command::ShowClient(1)))]
+    #[case::client_show_invalid_id(&["client", "show", "a"], Err("invalid client id: a".into()))]
+    #[case::client_without_show(&["client", "1"], Err("client requires: show <id>".into()))]
+    #[case::unknown(&["foo"], Err("unknown command: foo".into()))]
+    #[case::extra_argument(&["migrate", "now"], Err("unexpected argument: now".into()))]
+    fn test_parse(#[case] args: &[&str], #[case] expected: Result<Command, String>) {
+        assert_eq!(parse(args.iter().map(ToString::to_string)), expected);
+    }
+}
@@ -0,0 +1,84 @@
+use std::time::Duration;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Requests `path` from the instance on `port` and fails unless it answers 2xx.
+///
+/// Speaks just enough HTTP/1.1 to read a status line, so the runtime image doesn't
+/// need an HTTP client installed.
+pub async fn probe(port: u16, path: &str) -> Result<(), String> {
+    let status = tokio::time::timeout(TIMEOUT, request(port, path))
+        .await
+        .map_err(|_| format!("no response within {:?}", TIMEOUT))?
+        .map_err(|err| err.to_string())?;
+
+    match status {
+        200..=299 => Ok(()),
+        status => Err(format!("unhealthy, status {}", status)),
+    }
+}
+
+async fn request(port: u16, path: &str) -> std::io::Result<u16> {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).await?;
+
+    stream
+        .write_all(
+            format!(
+                "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                path
+            )
+            .as_bytes(),
+        )
+        .await?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+
+    // "HTTP/1.1 200 OK"
+    String::from_utf8_lossy(&response)
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid response"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+    use tokio::net::TcpListener;
+
+    async fn serve_once(response: &'static str) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+
+            // Enough to have the request line, the rest doesn't matter.
+            let _ = socket.read(&mut request).await.unwrap();
+            socket.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        port
+    }
+
+    #[rstest]
+    #[case::healthy("HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n", Ok(()))]
+    #[case::unhealthy(
+        "HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\n\r\n",
+        Err("unhealthy, status 503".into())
+    )]
+    #[case::invalid("garbage", Err("invalid response".into()))]
+    #[tokio::test]
+    async fn test_probe(#[case] response: &'static str, #[case] expected: Result<(), String>) {
+        let port = serve_once(response).await;
+
+        assert_eq!(probe(port, "/").await, expected);
+    }
+}
@@ -0,0 +1,59 @@
+use crate::persistence::{ByteStream, Error};
+use axum::body::Bytes;
+use futures_util::{stream, StreamExt};
+use std::path::Path;
+use tokio::{fs::File, io::AsyncReadExt};
+
+/// Bytes read from the file per chunk sent to the database.
+const CHUNK: usize = 64 * 1024;
+
+/// Streams the file at `path` a chunk at a time, however large it is.
+pub async fn read(path: &Path) -> Result<ByteStream, String> {
+    let file = File::open(path)
+        .await
+        .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+
+    Ok(stream::unfold(Some(file), |file| async move {
+        let mut file = file?;
+        let mut chunk = vec![0; CHUNK];
+
+        match file.read(&mut chunk).await {
+            Ok(0) => None,
+            Ok(read) => {
+                chunk.truncate(read);
+                Some((Ok(Bytes::from(chunk)), Some(file)))
+            }
+            Err(err) => Some((
+                Err(Error::Internal(format!("Failed to read import: {}", err))),
+                None,
+            )),
+        }
+    })
+    .boxed())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures_util::TryStreamExt;
+
+    #[tokio::test]
+    async fn test_read() {
+        let path = std::env::temp_dir().join(format!("rinha-import-{}.csv", std::process::id()));
+        let contents = "x".repeat(CHUNK + 1);
+
+        std::fs::write(&path, &contents).unwrap();
+
+        let chunks: Vec<Bytes> = read(&path).await.unwrap().try_collect().await.unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks.concat(), contents.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_read_missing() {
+        assert!(read(Path::new("/nonexistent/import.csv")).await.is_err());
+    }
+}
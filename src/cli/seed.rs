@@ -0,0 +1,47 @@
+use crate::models::Client;
+use std::path::Path;
+
+/// Reads clients from a JSON array such as `[{"id": 1, "limite": 100000}]`.
+pub fn read(path: &Path) -> Result<Vec<Client>, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+
+    parse(&contents).map_err(|err| format!("invalid clients in {}: {}", path.display(), err))
+}
+
+fn parse(contents: &str) -> Result<Vec<Client>, serde_json::Error> {
+    serde_json::from_str(contents)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let clients =
+            parse(r#"[{"id": 1, "limite": 1000}, {"id": 2, "limite": 500, "saldo": -10}]"#)
+                .unwrap();
+
+        assert_eq!(
+            clients,
+            vec![
+                Client {
+                    id: 1,
+                    limite: 1000,
+                    saldo: 0
+                },
+                Client {
+                    id: 2,
+                    limite: 500,
+                    saldo: -10
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_missing_limit() {
+        assert!(parse(r#"[{"id": 1}]"#).is_err());
+    }
+}
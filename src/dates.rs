@@ -0,0 +1,260 @@
+use serde::{de, Deserialize, Deserializer};
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// A calendar day in UTC, written `YYYY-MM-DD`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Date {
+    /// The day `time` falls on.
+    pub fn of(time: SystemTime) -> Self {
+        Self::from_days(days_since_epoch(time))
+    }
+
+    /// Midnight at the start of this day. Days before 1970 clamp to the epoch.
+    pub fn start(&self) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(self.days().max(0) as u64 * SECONDS_PER_DAY)
+    }
+
+    /// Midnight at the start of the following day.
+    pub fn end(&self) -> SystemTime {
+        self.start() + Duration::from_secs(SECONDS_PER_DAY)
+    }
+
+    /// Day of the week, from 0 for Sunday to 6 for Saturday.
+    pub fn weekday(&self) -> u32 {
+        // The epoch was a Thursday.
+        (self.days() + 4).rem_euclid(7) as u32
+    }
+
+    /// The same day `months` later, or the month's last day when it is shorter.
+    pub fn add_months(&self, months: u32) -> Self {
+        let month_index = self.month - 1 + months;
+        let year = self.year + (month_index / 12) as i32;
+        let month = month_index % 12 + 1;
+
+        Self {
+            year,
+            month,
+            day: self.day.min(Self::days_in_month(year, month)),
+        }
+    }
+
+    /// The day after this one.
+    pub fn next(&self) -> Self {
+        Self::from_days(self.days() + 1)
+    }
+
+    // Howard Hinnant's days_from_civil.
+    fn days(&self) -> i64 {
+        let year = i64::from(self.year) - i64::from(self.month <= 2);
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let month = i64::from(self.month);
+        let day_of_year =
+            (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + i64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+        era * 146_097 + day_of_era - 719_468
+    }
+
+    // Howard Hinnant's civil_from_days.
+    fn from_days(days: i64) -> Self {
+        let days = days + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        } as u32;
+
+        Self {
+            year: (year_of_era + era * 400) as i32 + i32::from(month <= 2),
+            month,
+            day,
+        }
+    }
+
+    fn days_in_month(year: i32, month: u32) -> u32 {
+        match month {
+            2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+}
+
+impl FromStr for Date {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid date, expected YYYY-MM-DD: {}", s);
+
+        let mut parts = s.splitn(3, '-');
+        let mut next = |len: usize| {
+            parts
+                .next()
+                .filter(|part| part.len() == len && part.bytes().all(|b| b.is_ascii_digit()))
+                .and_then(|part| part.parse().ok())
+                .ok_or_else(invalid)
+        };
+
+        let date = Self {
+            year: next(4)? as i32,
+            month: next(2)?,
+            day: next(2)?,
+        };
+
+        match date.month {
+            1..=12 if (1..=Self::days_in_month(date.year, date.month)).contains(&date.day) => {
+                Ok(date)
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+impl<'de> Deserialize<'de> for Date {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// A point in time broken down to the second, in UTC.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DateTime {
+    pub date: Date,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub micros: u32,
+}
+
+impl DateTime {
+    pub fn of(time: SystemTime) -> Self {
+        let since_epoch = time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let seconds = since_epoch.as_secs() % SECONDS_PER_DAY;
+
+        Self {
+            date: Date::of(time),
+            hour: (seconds / 3600) as u32,
+            minute: (seconds / 60 % 60) as u32,
+            second: (seconds % 60) as u32,
+            micros: since_epoch.subsec_micros(),
+        }
+    }
+}
+
+/// RFC 3339, as in `2024-01-31T12:00:00.000001Z`.
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}T{:02}:{:02}:{:02}.{:06}Z",
+            self.date, self.hour, self.minute, self.second, self.micros
+        )
+    }
+}
+
+fn days_since_epoch(time: SystemTime) -> i64 {
+    (time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / SECONDS_PER_DAY) as i64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    fn date(year: i32, month: u32, day: u32) -> Date {
+        Date { year, month, day }
+    }
+
+    #[rstest]
+    #[case::epoch("1970-01-01", 0)]
+    #[case::leap_day("2024-02-29", 19_782)]
+    #[case::end_of_year("2023-12-31", 19_722)]
+    #[case::after_leap_century("2000-03-01", 11_017)]
+    fn test_days(#[case] s: &str, #[case] days: i64) {
+        let date: Date = s.parse().unwrap();
+
+        assert_eq!(date.days(), days);
+        assert_eq!(Date::from_days(days), date);
+        assert_eq!(date.to_string(), s);
+    }
+
+    #[rstest]
+    #[case::not_a_date("yesterday")]
+    #[case::short_month("2024-1-01")]
+    #[case::month_out_of_range("2024-13-01")]
+    #[case::day_out_of_range("2023-02-29")]
+    #[case::trailing("2024-01-01T00:00")]
+    fn test_parse_invalid(#[case] s: &str) {
+        assert!(s.parse::<Date>().is_err());
+    }
+
+    #[rstest]
+    #[case::epoch(date(1970, 1, 1), 4)]
+    #[case::sunday(date(2024, 3, 3), 0)]
+    #[case::saturday(date(2024, 3, 9), 6)]
+    fn test_weekday(#[case] day: Date, #[case] weekday: u32) {
+        assert_eq!(day.weekday(), weekday);
+    }
+
+    #[rstest]
+    #[case::same_year(date(2024, 1, 15), 1, date(2024, 2, 15))]
+    #[case::next_year(date(2024, 11, 30), 3, date(2025, 2, 28))]
+    #[case::leap_day(date(2024, 1, 31), 1, date(2024, 2, 29))]
+    #[case::none(date(2024, 1, 31), 0, date(2024, 1, 31))]
+    fn test_add_months(#[case] day: Date, #[case] months: u32, #[case] expected: Date) {
+        assert_eq!(day.add_months(months), expected);
+    }
+
+    #[test]
+    fn test_start_and_end() {
+        let leap_day = date(2024, 2, 29);
+
+        assert_eq!(Date::of(leap_day.start()), leap_day);
+        assert_eq!(Date::of(leap_day.end()), date(2024, 3, 1));
+    }
+
+    #[test]
+    fn test_date_time() {
+        let time = date(2024, 1, 31).start() + Duration::from_micros(45_296_000_001);
+
+        assert_eq!(
+            DateTime::of(time).to_string(),
+            "2024-01-31T12:34:56.000001Z"
+        );
+    }
+}
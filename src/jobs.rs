@@ -0,0 +1,4 @@
+pub mod holds;
+pub mod idempotency;
+pub mod reconciliation;
+pub mod standing_orders;
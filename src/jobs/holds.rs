@@ -0,0 +1,113 @@
+use crate::{persistence::Holds, telemetry};
+use std::{sync::Arc, time::Duration};
+use tokio::time::MissedTickBehavior;
+
+/// Most holds marked at a time, further ones are marked once these are.
+const BATCH: i32 = 1000;
+
+/// Marks holds past their expiry as expired every `interval`, so that they stop
+/// weighing on the pending ones. Instances sweeping together skip each other's holds.
+pub fn schedule(holds: Arc<dyn Holds>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+            run(holds.as_ref()).await;
+        }
+    });
+}
+
+async fn run(holds: &dyn Holds) {
+    loop {
+        match holds.expire_holds(BATCH).await {
+            Ok(expired) => {
+                telemetry::debug!("Expired {} holds", expired);
+
+                if expired < BATCH {
+                    return;
+                }
+            }
+            #[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+            Err(err) => {
+                telemetry::error!("Failed to expire holds: {:?}", err);
+
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        api::routes::TransactionResponse,
+        models::{Hold, NewHold},
+        persistence::{Audit, Error},
+    };
+    use axum::async_trait;
+    use rstest::rstest;
+    use std::sync::Mutex;
+
+    /// Holds past expiry, or the error sweeping them fails with.
+    struct MockHolds {
+        expired: Mutex<Result<i32, Error>>,
+        sweeps: Mutex<Vec<i32>>,
+    }
+
+    #[async_trait]
+    impl Holds for MockHolds {
+        async fn create_hold(&self, _client_id: i16, _hold: &NewHold) -> Result<Hold, Error> {
+            unimplemented!()
+        }
+
+        async fn holds(&self, _client_id: i16) -> Result<Vec<Hold>, Error> {
+            unimplemented!()
+        }
+
+        async fn capture_hold(
+            &self,
+            _client_id: i16,
+            _hold_id: i32,
+            _valor: Option<i16>,
+            _audit: &Audit,
+        ) -> Result<TransactionResponse, Error> {
+            unimplemented!()
+        }
+
+        async fn void_hold(&self, _client_id: i16, _hold_id: i32) -> Result<Hold, Error> {
+            unimplemented!()
+        }
+
+        async fn expire_holds(&self, limit: i32) -> Result<i32, Error> {
+            self.sweeps.lock().unwrap().push(limit);
+
+            let mut expired = self.expired.lock().unwrap();
+            let left = expired.clone()?;
+            let marked = left.min(limit);
+
+            *expired = Ok(left - marked);
+
+            Ok(marked)
+        }
+    }
+
+    #[rstest]
+    #[case::none(Ok(0), vec![BATCH])]
+    #[case::one_batch(Ok(BATCH - 1), vec![BATCH])]
+    #[case::several_batches(Ok(2 * BATCH + 1), vec![BATCH, BATCH, BATCH])]
+    #[case::failed(Err(Error::Unavailable), vec![BATCH])]
+    #[tokio::test]
+    async fn test_run(#[case] expired: Result<i32, Error>, #[case] expected: Vec<i32>) {
+        let holds = MockHolds {
+            expired: Mutex::new(expired),
+            sweeps: Mutex::default(),
+        };
+
+        run(&holds).await;
+
+        assert_eq!(*holds.sweeps.lock().unwrap(), expected);
+    }
+}
@@ -0,0 +1,70 @@
+use crate::{persistence::Admin, telemetry};
+use std::{sync::Arc, time::Duration};
+use tokio::time::MissedTickBehavior;
+
+/// Most keys forgotten at a time, further ones are once these are.
+const BATCH: i32 = 1000;
+
+/// Forgets idempotency keys past their day every `interval`, so that the table
+/// keeps to the keys a retry could still be sent under.
+pub fn schedule(admin: Arc<dyn Admin>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+            run(admin.as_ref()).await;
+        }
+    });
+}
+
+async fn run(admin: &dyn Admin) {
+    loop {
+        match admin.expire_idempotency_keys(BATCH).await {
+            Ok(expired) => {
+                telemetry::debug!("Expired {} idempotency keys", expired);
+
+                if expired < BATCH {
+                    return;
+                }
+            }
+            #[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+            Err(err) => {
+                telemetry::error!("Failed to expire idempotency keys: {:?}", err);
+
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::persistence::mock::{Call, MockAdmin};
+    use rstest::rstest;
+    use std::sync::Mutex;
+
+    #[rstest]
+    #[case::none(0, 1)]
+    #[case::one_batch(BATCH - 1, 1)]
+    #[case::several_batches(2 * BATCH + 1, 3)]
+    #[tokio::test]
+    async fn test_run(#[case] keys: i32, #[case] sweeps: usize) {
+        let admin = MockAdmin {
+            idempotency_keys: Mutex::new(keys),
+            ..Default::default()
+        };
+
+        run(&admin).await;
+
+        assert_eq!(
+            admin.calls(),
+            (0..sweeps)
+                .map(|_| Call::ExpireIdempotencyKeys(BATCH))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(*admin.idempotency_keys.lock().unwrap(), 0);
+    }
+}
@@ -0,0 +1,67 @@
+use crate::{persistence::Admin, telemetry};
+use std::{sync::Arc, time::Duration};
+use tokio::time::MissedTickBehavior;
+
+/// Runs reconciliation every `interval` and reports the clients failing it.
+/// Corrections are left to an operator, through `rinha reconcile --fix` or the
+/// admin endpoint.
+pub fn schedule(admin: Arc<dyn Admin>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+            run(admin.as_ref()).await;
+        }
+    });
+}
+
+async fn run(admin: &dyn Admin) {
+    match admin.reconcile(false).await {
+        #[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+        Ok(discrepancies) => {
+            for discrepancy in discrepancies {
+                telemetry::error!(
+                    "Client {} fails reconciliation: saldo {}, esperado {}, razao {}, saldo minimo {} for limite {}",
+                    discrepancy.cliente_id,
+                    discrepancy.saldo,
+                    discrepancy.esperado,
+                    discrepancy.razao,
+                    discrepancy.saldo_minimo,
+                    discrepancy.limite
+                );
+            }
+        }
+        #[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+        Err(err) => {
+            telemetry::error!("Reconciliation failed: {:?}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::persistence::mock::{Call, MockAdmin};
+
+    #[tokio::test(start_paused = true)]
+    async fn test_schedule() {
+        let admin = Arc::new(MockAdmin::default());
+        let interval = Duration::from_millis(10);
+
+        schedule(admin.clone(), interval);
+
+        // The first run is right away, each of the next one interval after the last.
+        for _ in 0..4 {
+            tokio::task::yield_now().await;
+            assert_eq!(admin.calls(), [Call::Reconcile(false)]);
+
+            tokio::time::advance(interval - Duration::from_millis(1)).await;
+            tokio::task::yield_now().await;
+            assert_eq!(admin.calls(), []);
+
+            tokio::time::advance(Duration::from_millis(1)).await;
+        }
+    }
+}
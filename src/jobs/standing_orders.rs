@@ -0,0 +1,390 @@
+use crate::{
+    api::routes::TransactionRequest,
+    dates::DateTime,
+    persistence::{Audit, Claim, Election, Error, Repository, StandingOrders},
+    telemetry,
+};
+use serde_json::json;
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::time::MissedTickBehavior;
+
+/// Most runs claimed at a time, further ones are claimed once these are made.
+const BATCH: i64 = 100;
+
+/// Whom runs are audited as.
+const PRINCIPAL: &str = "agendador";
+
+/// How long a run may go without an outcome before it is claimed again, well past
+/// how long making its transaction may take.
+const LEASE: Duration = Duration::from_secs(300);
+
+/// Checks every `interval` for standing orders due and makes their transactions
+/// through `repo`, as the API would, on the instance `election` makes leader.
+///
+/// Runs missed while no instance led are made when one does, one per missed run.
+/// Runs left without an outcome for [`LEASE`] are made again first.
+pub fn schedule(
+    orders: Arc<dyn StandingOrders>,
+    repo: Arc<dyn Repository>,
+    mut election: impl Election + 'static,
+    instance: String,
+    interval: Duration,
+) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+
+            if election.lead().await {
+                run(orders.as_ref(), repo.as_ref(), &instance).await;
+            }
+        }
+    });
+}
+
+async fn run(orders: &dyn StandingOrders, repo: &dyn Repository, instance: &str) {
+    // Made again under the key they had, so whatever was applied isn't again.
+    while make_claimed(
+        orders,
+        repo,
+        orders
+            .claim_stalled(SystemTime::now() - LEASE, instance, BATCH)
+            .await,
+    )
+    .await
+    {}
+
+    while make_claimed(
+        orders,
+        repo,
+        orders.claim_due(SystemTime::now(), instance, BATCH).await,
+    )
+    .await
+    {}
+}
+
+/// Makes the runs claimed, returning whether a full batch was, so more may be waiting.
+async fn make_claimed(
+    orders: &dyn StandingOrders,
+    repo: &dyn Repository,
+    claims: Result<Vec<Claim>, Error>,
+) -> bool {
+    let claims = match claims {
+        Ok(claims) => claims,
+        #[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+        Err(err) => {
+            telemetry::error!("Failed to claim standing orders: {:?}", err);
+
+            return false;
+        }
+    };
+    let claimed = claims.len() as i64;
+
+    for claim in claims {
+        make(orders, repo, claim).await;
+    }
+
+    claimed == BATCH
+}
+
+/// Makes the transaction of a claimed run and records how it went.
+async fn make(orders: &dyn StandingOrders, repo: &dyn Repository, claim: Claim) {
+    let order = &claim.order;
+    let request = TransactionRequest {
+        valor: order.valor,
+        tipo: order.tipo.clone(),
+        descricao: order.descricao.clone(),
+    };
+    let key = format!("ordem-{}-execucao-{}", order.id, claim.run_id);
+    let audit = Audit {
+        request_id: Some(key.clone()),
+        // A run made again is answered with the outcome it had.
+        idempotency_key: Some(key),
+        ip: None,
+        principal: Some(PRINCIPAL.into()),
+        payload: json!({
+            "ordem_id": order.id,
+            "valor": order.valor,
+            "tipo": order.tipo,
+            "descricao": order.descricao,
+            "agendada_para": DateTime::of(claim.agendada_para).to_string(),
+        })
+        .to_string(),
+    };
+
+    let (resultado, saldo) = match repo
+        .create_transaction(&order.cliente_id, &request, &audit)
+        .await
+    {
+        Ok(response) => ("aplicada", Some(response.saldo)),
+        Err(Error::ClientNotFound) => ("cliente_nao_encontrado", None),
+        Err(Error::BalanceConstraintViolation) => ("limite_excedido", None),
+        Err(Error::PolicyViolation(rule)) => (rule.code(), None),
+        // Whether it was applied is unknown, the run is left without an outcome until
+        // its lease is up.
+        Err(Error::ConnectionLost(_)) => {
+            telemetry::error!(
+                "Standing order {} run {} outcome unknown",
+                order.id,
+                claim.run_id
+            );
+
+            return;
+        }
+        #[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+        Err(err) => {
+            telemetry::error!("Standing order {} run failed: {:?}", order.id, err);
+
+            ("falhou", None)
+        }
+    };
+
+    #[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+    if let Err(err) = orders.record_run(claim.run_id, resultado, saldo).await {
+        telemetry::error!(
+            "Failed to record standing order run {}: {:?}",
+            claim.run_id,
+            err
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        api::routes::{StatementResponse, TransactionResponse},
+        models::{NewStandingOrder, Run, StandingOrder},
+        persistence::{Export, Period, ReadToken, Rule},
+    };
+    use axum::async_trait;
+    use rstest::rstest;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct MockOrders {
+        stalled: Mutex<Vec<Claim>>,
+        due: Mutex<Vec<Claim>>,
+        recorded: Mutex<Vec<(i64, String, Option<i32>)>>,
+    }
+
+    #[async_trait]
+    impl StandingOrders for MockOrders {
+        async fn create_order(
+            &self,
+            _client_id: i16,
+            _order: &NewStandingOrder,
+        ) -> Result<StandingOrder, Error> {
+            unimplemented!()
+        }
+
+        async fn orders(&self, _client_id: i16) -> Result<Vec<StandingOrder>, Error> {
+            unimplemented!()
+        }
+
+        async fn cancel_order(
+            &self,
+            _client_id: i16,
+            _order_id: i32,
+        ) -> Result<StandingOrder, Error> {
+            unimplemented!()
+        }
+
+        async fn runs(
+            &self,
+            _client_id: i16,
+            _order_id: i32,
+            _limit: i64,
+        ) -> Result<Vec<Run>, Error> {
+            unimplemented!()
+        }
+
+        async fn claim_due(
+            &self,
+            _now: SystemTime,
+            _instance: &str,
+            limit: i64,
+        ) -> Result<Vec<Claim>, Error> {
+            let mut due = self.due.lock().unwrap();
+            let claimed = due.len().min(limit as usize);
+
+            Ok(due.drain(..claimed).collect())
+        }
+
+        async fn claim_stalled(
+            &self,
+            _stalled_since: SystemTime,
+            _instance: &str,
+            limit: i64,
+        ) -> Result<Vec<Claim>, Error> {
+            let mut stalled = self.stalled.lock().unwrap();
+            let claimed = stalled.len().min(limit as usize);
+
+            Ok(stalled.drain(..claimed).collect())
+        }
+
+        async fn record_run(
+            &self,
+            run_id: i64,
+            resultado: &str,
+            saldo: Option<i32>,
+        ) -> Result<(), Error> {
+            self.recorded
+                .lock()
+                .unwrap()
+                .push((run_id, resultado.into(), saldo));
+
+            Ok(())
+        }
+    }
+
+    struct MockRepository {
+        result: Result<i32, Error>,
+        received: Mutex<Vec<(i16, TransactionRequest, Audit)>>,
+    }
+
+    impl MockRepository {
+        fn responding(result: Result<i32, Error>) -> Self {
+            Self {
+                result,
+                received: Mutex::default(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Repository for MockRepository {
+        async fn create_transaction(
+            &self,
+            client_id: &i16,
+            data: &TransactionRequest,
+            audit: &Audit,
+        ) -> Result<TransactionResponse, Error> {
+            self.received
+                .lock()
+                .unwrap()
+                .push((*client_id, data.clone(), audit.clone()));
+
+            self.result.clone().map(|saldo| TransactionResponse {
+                limite: 1000,
+                saldo,
+                token: None,
+            })
+        }
+
+        async fn get_balance(
+            &self,
+            _client_id: &i16,
+            _token: Option<&ReadToken>,
+        ) -> Result<StatementResponse, Error> {
+            unimplemented!()
+        }
+
+        async fn export(&self, _client_id: &i16, _period: Period) -> Result<Export, Error> {
+            unimplemented!()
+        }
+    }
+
+    fn claim(run_id: i64) -> Claim {
+        Claim {
+            run_id,
+            agendada_para: SystemTime::UNIX_EPOCH,
+            order: StandingOrder {
+                id: 7,
+                cliente_id: 1,
+                valor: 990,
+                tipo: "d".into(),
+                descricao: "streaming".into(),
+                agenda: "mensal".parse().unwrap(),
+                inicio: SystemTime::UNIX_EPOCH,
+                fim: None,
+                proxima_execucao: None,
+                cancelada_em: None,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let orders = MockOrders::default();
+        let repo = MockRepository::responding(Ok(-990));
+
+        *orders.due.lock().unwrap() = (1..=BATCH + 1).map(claim).collect();
+
+        run(&orders, &repo, "api01").await;
+
+        let received = repo.received.lock().unwrap();
+        let (client_id, request, audit) = &received[0];
+
+        assert_eq!(received.len() as i64, BATCH + 1);
+        assert_eq!(*client_id, 1);
+        assert_eq!(
+            (
+                request.valor,
+                request.tipo.as_str(),
+                request.descricao.as_str()
+            ),
+            (990, "d", "streaming")
+        );
+        assert_eq!(audit.request_id.as_deref(), Some("ordem-7-execucao-1"));
+        assert_eq!(audit.principal.as_deref(), Some(PRINCIPAL));
+        assert_eq!(
+            orders.recorded.lock().unwrap()[0],
+            (1, "aplicada".into(), Some(-990))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_makes_stalled_runs_again() {
+        let orders = MockOrders::default();
+        let repo = MockRepository::responding(Ok(-990));
+
+        *orders.stalled.lock().unwrap() = (1..=BATCH + 1).map(claim).collect();
+        *orders.due.lock().unwrap() = vec![claim(BATCH + 2)];
+
+        run(&orders, &repo, "api01").await;
+
+        let keys = repo
+            .received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, _, audit)| audit.idempotency_key.clone().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            keys,
+            (1..=BATCH + 2)
+                .map(|run_id| format!("ordem-7-execucao-{run_id}"))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(orders.recorded.lock().unwrap().len() as i64, BATCH + 2);
+    }
+
+    #[rstest]
+    #[case::client_not_found(Error::ClientNotFound, Some("cliente_nao_encontrado"))]
+    #[case::limit_exceeded(Error::BalanceConstraintViolation, Some("limite_excedido"))]
+    #[case::policy_violation(
+        Error::PolicyViolation(Rule::AboveMaximum),
+        Some("valor_acima_do_maximo")
+    )]
+    #[case::unavailable(Error::Unavailable, Some("falhou"))]
+    #[case::connection_lost(Error::ConnectionLost("closed".into()), None)]
+    #[tokio::test]
+    async fn test_make_refused(#[case] err: Error, #[case] expected: Option<&str>) {
+        let orders = MockOrders::default();
+        let repo = MockRepository::responding(Err(err));
+
+        make(&orders, &repo, claim(1)).await;
+
+        assert_eq!(
+            orders.recorded.lock().unwrap().first().cloned(),
+            expected.map(|resultado| (1, resultado.into(), None))
+        );
+    }
+}
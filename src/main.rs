@@ -1,10 +1,16 @@
 use std::sync::Arc;
 
 mod api;
+mod cli;
+mod dates;
+mod jobs;
+mod metrics;
 mod models;
 mod persistence;
+mod schedule;
 mod telemetry;
 
+use bb8_postgres::tokio_postgres;
 use hyper::body::Incoming;
 use hyper_util::{
     rt::{TokioExecutor, TokioIo, TokioTimer},
@@ -15,6 +21,7 @@ use tower::Service;
 #[cfg(feature = "telemetry")]
 use {
     axum::{body::Body, http},
+    tokio::sync::broadcast::error::RecvError,
     tower_http::trace::TraceLayer,
     tower_request_id::{RequestId, RequestIdLayer},
     tracing::error_span,
@@ -36,15 +43,360 @@ async fn main() {
         )
         .init();
 
-    let host = option_env!("DB_HOST").unwrap_or("localhost");
+    let command = cli::parse(std::env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{}\n\n{}", err, cli::USAGE);
+        std::process::exit(2);
+    });
 
-    let repo = persistence::database::Repository::new(host)
+    match command {
+        cli::Command::Serve => serve().await,
+        cli::Command::Migrate => migrate().await,
+        cli::Command::Seed(path) => seed(&path).await,
+        cli::Command::Import(path) => import(&path).await,
+        cli::Command::Healthcheck => healthcheck().await,
+        cli::Command::Reconcile { fix } => reconcile(fix).await,
+        cli::Command::ShowClient(id) => show_client(id).await,
+    }
+}
+
+const DB_HOST: &str = match option_env!("DB_HOST") {
+    Some(host) => host,
+    None => "localhost",
+};
+
+/// Connections the server keeps open to each database.
+const POOL_SIZE: u32 = 40;
+
+fn instance() -> String {
+    std::env::var("HOSTNAME").unwrap_or("rinha".to_string())
+}
+
+fn database_config(host: &str) -> tokio_postgres::Config {
+    persistence::database::config(host, &instance())
+        .unwrap_or_else(|_| panic!("invalid postgres configuration for: {}", host))
+}
+
+fn port() -> u16 {
+    let port = std::env::var("PORT").unwrap_or("3000".to_string());
+
+    port.parse()
+        .unwrap_or_else(|_| panic!("invalid PORT: {}", port))
+}
+
+/// Reports a failed command and exits with a non-zero code.
+fn fail(message: impl std::fmt::Display) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+async fn migrate() {
+    let applied = persistence::database::migrate(&database_config(DB_HOST))
+        .await
+        .unwrap_or_else(|err| fail(format!("failed to migrate {}: {:?}", DB_HOST, err)));
+
+    if applied.is_empty() {
+        println!("Database is up to date");
+    } else {
+        println!("Applied migrations: {:?}", applied);
+    }
+
+    if std::env::var("DB_REPLICA_HOST").is_ok() {
+        let logged = persistence::database::replicate(&database_config(DB_HOST))
+            .await
+            .unwrap_or_else(|err| fail(format!("failed to replicate {}: {:?}", DB_HOST, err)));
+
+        println!("Logged tables: {:?}", logged);
+    }
+}
+
+async fn seed(path: &std::path::Path) {
+    let clients = cli::seed::read(path).unwrap_or_else(|err| fail(err));
+
+    let created = persistence::database::seed(&database_config(DB_HOST), &clients)
+        .await
+        .unwrap_or_else(|err| fail(format!("failed to seed {}: {:?}", DB_HOST, err)));
+
+    println!("Created {} of {} clients", created, clients.len());
+}
+
+async fn import(path: &std::path::Path) {
+    use persistence::Admin;
+
+    let csv = cli::import::read(path)
+        .await
+        .unwrap_or_else(|err| fail(err));
+
+    let repo = persistence::database::Repository::new(database_config(DB_HOST), 1)
+        .await
+        .unwrap_or_else(|err| fail(format!("failed to connect to {}: {:?}", DB_HOST, err)));
+
+    let audit = persistence::Audit {
+        principal: Some("cli".into()),
+        ..Default::default()
+    };
+
+    let report = repo
+        .import(csv, audit)
+        .await
+        .unwrap_or_else(|err| fail(format!("failed to import {}: {:?}", path.display(), err)));
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&report).expect("report serializes")
+    );
+
+    if !report.rejeitadas.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+async fn healthcheck() {
+    if let Err(err) = cli::healthcheck::probe(port(), "/clientes/1/extrato").await {
+        fail(err);
+    }
+}
+
+async fn reconcile(fix: bool) {
+    use persistence::Admin;
+
+    let repo = persistence::database::Repository::new(database_config(DB_HOST), 1)
+        .await
+        .unwrap_or_else(|err| fail(format!("failed to connect to {}: {:?}", DB_HOST, err)));
+
+    let discrepancies = repo
+        .reconcile(fix)
+        .await
+        .unwrap_or_else(|err| fail(format!("failed to reconcile: {:?}", err)));
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&discrepancies).expect("discrepancies serialize")
+    );
+
+    if discrepancies
+        .iter()
+        .any(|discrepancy| !discrepancy.corrigido || discrepancy.limit_exceeded())
+    {
+        std::process::exit(1);
+    }
+}
+
+async fn show_client(id: i16) {
+    use persistence::Repository;
+
+    let repo = persistence::database::Repository::new(database_config(DB_HOST), 1)
+        .await
+        .unwrap_or_else(|err| fail(format!("failed to connect to {}: {:?}", DB_HOST, err)));
+
+    match repo.get_balance(&id, None).await {
+        Ok(statement) => println!(
+            "{}",
+            serde_json::to_string_pretty(&statement).expect("statement serializes")
+        ),
+        Err(persistence::Error::ClientNotFound) => fail(format!("client {} not found", id)),
+        Err(err) => fail(format!("failed to fetch client {}: {:?}", id, err)),
+    }
+}
+
+async fn serve() {
+    let host = DB_HOST;
+    let instance = instance();
+    let config = database_config(host);
+
+    if std::env::var("SKIP_MIGRATIONS").is_err() {
+        #[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+        let applied = persistence::database::migrate(&config)
+            .await
+            .unwrap_or_else(|err| {
+                panic!("failed to migrate postgres database on {}: {:?}", host, err)
+            });
+
+        telemetry::debug!("Applied migrations: {:?}", applied);
+
+        // Only a replica needs the tables it reads logged.
+        if std::env::var("DB_REPLICA_HOST").is_ok() {
+            #[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+            let logged = persistence::database::replicate(&config)
+                .await
+                .unwrap_or_else(|err| {
+                    panic!(
+                        "failed to replicate postgres database on {}: {:?}",
+                        host, err
+                    )
+                });
+
+            telemetry::debug!("Logged tables: {:?}", logged);
+        }
+    }
+
+    persistence::database::check(&config)
+        .await
+        .unwrap_or_else(|err| panic!("invalid postgres schema on {}: {:?}", host, err));
+
+    let repo = persistence::database::Repository::new(config.clone(), POOL_SIZE)
         .await
         .unwrap_or_else(|_| panic!("failed to connect to postgres database on: {}", host));
 
-    let port = std::env::var("PORT").unwrap_or("3000".to_string());
+    let repo = match std::env::var("DB_REPLICA_HOST") {
+        Ok(replica) => {
+            persistence::database::check_replicated(&config)
+                .await
+                .unwrap_or_else(|err| panic!("invalid postgres schema on {}: {:?}", host, err));
+
+            repo.with_replica(database_config(&replica))
+                .await
+                .unwrap_or_else(|_| panic!("failed to connect to postgres replica on: {}", replica))
+        }
+        Err(_) => repo,
+    };
+
+    let repo = match std::env::var("BATCH_WINDOW_US") {
+        Ok(window) => repo.with_batching(std::time::Duration::from_micros(
+            window
+                .parse()
+                .unwrap_or_else(|_| panic!("invalid BATCH_WINDOW_US: {}", window)),
+        )),
+        Err(_) => repo,
+    };
+
+    let database = repo.clone();
+
+    if let Ok(interval) = std::env::var("RECONCILIATION_INTERVAL_S") {
+        jobs::reconciliation::schedule(
+            Arc::new(database.clone()),
+            std::time::Duration::from_secs(
+                interval
+                    .parse()
+                    .unwrap_or_else(|_| panic!("invalid RECONCILIATION_INTERVAL_S: {}", interval)),
+            ),
+        );
+    }
 
-    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port))
+    // Unlike the jobs above, always on, as holds left pending once expired would
+    // otherwise pile up among the ones that still set funds aside.
+    jobs::holds::schedule(
+        Arc::new(database.clone()),
+        std::time::Duration::from_secs(
+            std::env::var("HOLDS_EXPIRY_INTERVAL_S")
+                .map(|interval| {
+                    interval
+                        .parse()
+                        .unwrap_or_else(|_| panic!("invalid HOLDS_EXPIRY_INTERVAL_S: {}", interval))
+                })
+                .unwrap_or(60),
+        ),
+    );
+
+    // Always on too, idempotency keys are kept for a day and would otherwise be
+    // kept for good.
+    jobs::idempotency::schedule(
+        Arc::new(database.clone()),
+        std::time::Duration::from_secs(
+            std::env::var("IDEMPOTENCY_EXPIRY_INTERVAL_S")
+                .map(|interval| {
+                    interval.parse().unwrap_or_else(|_| {
+                        panic!("invalid IDEMPOTENCY_EXPIRY_INTERVAL_S: {}", interval)
+                    })
+                })
+                .unwrap_or(3600),
+        ),
+    );
+
+    let notifications = persistence::notifications::Notifications::new(&instance);
+    persistence::database::listen(config.clone(), notifications.clone());
+
+    let repo: Arc<dyn persistence::Repository> = match std::env::var("RETRY_BUDGET_MS") {
+        Ok(budget) => Arc::new(persistence::retry::Repository::new(
+            Arc::new(repo),
+            std::time::Duration::from_millis(
+                budget
+                    .parse()
+                    .unwrap_or_else(|_| panic!("invalid RETRY_BUDGET_MS: {}", budget)),
+            ),
+        )),
+        Err(_) => Arc::new(repo),
+    };
+
+    let repo: Arc<dyn persistence::Repository> = match std::env::var("CIRCUIT_BREAKER_THRESHOLD") {
+        Ok(threshold) => Arc::new(persistence::circuit_breaker::Repository::new(
+            repo,
+            threshold
+                .parse()
+                .unwrap_or_else(|_| panic!("invalid CIRCUIT_BREAKER_THRESHOLD: {}", threshold)),
+            std::time::Duration::from_millis(
+                std::env::var("CIRCUIT_BREAKER_COOLDOWN_MS")
+                    .map(|cooldown| {
+                        cooldown.parse().unwrap_or_else(|_| {
+                            panic!("invalid CIRCUIT_BREAKER_COOLDOWN_MS: {}", cooldown)
+                        })
+                    })
+                    .unwrap_or(1000),
+            ),
+        )),
+        Err(_) => repo,
+    };
+
+    let repo: Arc<dyn persistence::Repository> = match std::env::var("STATEMENT_CACHE_TTL_MS") {
+        Ok(ttl) => {
+            let ttl = ttl
+                .parse()
+                .unwrap_or_else(|_| panic!("invalid STATEMENT_CACHE_TTL_MS: {}", ttl));
+            let cache = Arc::new(persistence::cache::Repository::new(
+                repo,
+                std::time::Duration::from_millis(ttl),
+            ));
+
+            cache.invalidate_on(&notifications);
+            cache
+        }
+        Err(_) => repo,
+    };
+
+    // Made through `repo`, with its retries, circuit breaker and cache invalidation.
+    if let Ok(interval) = std::env::var("STANDING_ORDERS_INTERVAL_S") {
+        jobs::standing_orders::schedule(
+            Arc::new(database.clone()),
+            repo.clone(),
+            persistence::database::Election::new(config),
+            instance,
+            std::time::Duration::from_secs(
+                interval
+                    .parse()
+                    .unwrap_or_else(|_| panic!("invalid STANDING_ORDERS_INTERVAL_S: {}", interval)),
+            ),
+        );
+    }
+
+    #[cfg(feature = "telemetry")]
+    {
+        let mut receiver = notifications.subscribe();
+        let notifications = notifications.clone();
+
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(notification) if !notifications.is_local(&notification) => {
+                        telemetry::debug!(
+                            "Client {} changed by {}: {} {} ({}), saldo {} limite {}",
+                            notification.cliente_id,
+                            notification.origem,
+                            notification.tipo,
+                            notification.valor,
+                            notification.descricao,
+                            notification.saldo,
+                            notification.limite
+                        );
+                    }
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    let port = port();
+
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", port))
         .await
         .unwrap_or_else(|_| panic!("failed to bind listener to port: {}", port));
 
@@ -53,7 +405,41 @@ async fn main() {
         listener.local_addr().expect("failed to get local addr")
     );
 
-    let app = api::app::new(Arc::new(repo));
+    let admin_token = std::env::var("ADMIN_TOKEN").ok();
+
+    let app = api::app::new(
+        repo.clone(),
+        api::app::Deadlines {
+            transaction: deadline("TRANSACTION_DEADLINE_MS"),
+            statement: deadline("STATEMENT_DEADLINE_MS"),
+        },
+    )
+    .merge(api::graphql::new(repo.clone()))
+    .merge(api::orders::new(Arc::new(database.clone())))
+    .merge(api::holds::new(Arc::new(database.clone())))
+    .merge(api::grpc::new(
+        repo,
+        notifications.clone(),
+        admin_token.as_deref(),
+    ));
+
+    let app = match admin_token {
+        Some(token) => app.merge(api::admin::new(Arc::new(database), &token)),
+        None => app,
+    };
+
+    let app =
+        match std::env::var("CONCURRENCY_LIMIT_TARGET_MS") {
+            Ok(target) => app.layer(axum::middleware::from_fn_with_state(
+                Arc::new(api::limiter::Limiter::new(
+                    std::time::Duration::from_millis(target.parse().unwrap_or_else(|_| {
+                        panic!("invalid CONCURRENCY_LIMIT_TARGET_MS: {}", target)
+                    })),
+                )),
+                api::limiter::limit,
+            )),
+            Err(_) => app,
+        };
 
     #[cfg(feature = "telemetry")]
     let app = app
@@ -77,14 +463,18 @@ async fn main() {
 
     // Continuously accept new connections.
     loop {
-        let (socket, _remote_addr) = listener.accept().await.unwrap();
+        let (socket, remote_addr) = listener.accept().await.unwrap();
         let tower_service = app.clone();
 
         tokio::spawn(async move {
             let socket = TokioIo::new(socket);
 
             let hyper_service =
-                hyper::service::service_fn(move |request: axum::extract::Request<Incoming>| {
+                hyper::service::service_fn(move |mut request: axum::extract::Request<Incoming>| {
+                    request
+                        .extensions_mut()
+                        .insert(axum::extract::ConnectInfo(remote_addr));
+
                     tower_service.clone().call(request)
                 });
 
@@ -102,3 +492,13 @@ async fn main() {
         });
     }
 }
+
+fn deadline(variable: &str) -> Option<std::time::Duration> {
+    std::env::var(variable).ok().map(|deadline| {
+        std::time::Duration::from_millis(
+            deadline
+                .parse()
+                .unwrap_or_else(|_| panic!("invalid {}: {}", variable, deadline)),
+        )
+    })
+}
@@ -0,0 +1,83 @@
+use std::fmt::Write;
+
+#[derive(Clone, Copy)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub enum Kind {
+    Counter,
+    Gauge,
+}
+
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub struct Metric {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub kind: Kind,
+    pub value: i64,
+}
+
+impl Metric {
+    pub fn counter(name: &'static str, help: &'static str, value: u64) -> Self {
+        Self {
+            name,
+            help,
+            kind: Kind::Counter,
+            value: value as i64,
+        }
+    }
+
+    pub fn gauge(name: &'static str, help: &'static str, value: i64) -> Self {
+        Self {
+            name,
+            help,
+            kind: Kind::Gauge,
+            value,
+        }
+    }
+}
+
+/// Renders metrics in the Prometheus text exposition format.
+pub fn render(metrics: &[Metric]) -> String {
+    let mut output = String::new();
+
+    for metric in metrics {
+        let kind = match metric.kind {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+        };
+
+        // Writing to a String never fails.
+        let _ = writeln!(output, "# HELP {} {}", metric.name, metric.help);
+        let _ = writeln!(output, "# TYPE {} {}", metric.name, kind);
+        let _ = writeln!(output, "{} {}", metric.name, metric.value);
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = vec![
+            Metric::counter("rinha_hits_total", "Hits.", 3),
+            Metric::gauge("rinha_state", "State.", -1),
+        ];
+
+        assert_eq!(
+            render(&metrics),
+            "# HELP rinha_hits_total Hits.\n\
+             # TYPE rinha_hits_total counter\n\
+             rinha_hits_total 3\n\
+             # HELP rinha_state State.\n\
+             # TYPE rinha_state gauge\n\
+             rinha_state -1\n"
+        );
+    }
+
+    #[test]
+    fn test_render_empty() {
+        assert_eq!(render(&[]), "");
+    }
+}
@@ -1,26 +1,317 @@
-use serde::Serialize;
-use std::time::SystemTime;
+use crate::{dates::Date, schedule::Schedule};
+use serde::{Deserialize, Serialize};
+use std::{net::IpAddr, time::SystemTime};
+use utoipa::ToSchema;
 
-#[derive(Serialize)]
+#[derive(Clone, Serialize, ToSchema)]
 #[cfg_attr(test, derive(Debug))]
 pub struct Transaction {
+    /// Centavos.
     pub valor: i16,
+    /// `c` for a credit, `d` for a debit.
+    #[schema(pattern = "^[cd]$")]
     pub tipo: String,
     pub descricao: String,
+    #[schema(value_type = crate::api::routes::Timestamp)]
     pub realizada_em: SystemTime,
 }
 
-#[derive(Serialize)]
-#[cfg_attr(test, derive(Debug, Clone))]
+/// A transaction with its id, as exported.
+#[derive(Clone, Serialize)]
+#[cfg_attr(test, derive(Debug))]
+pub struct Entry {
+    pub id: i32,
+    #[serde(flatten)]
+    pub transaction: Transaction,
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+#[cfg_attr(test, derive(Debug))]
 pub struct Balance {
+    /// Centavos, negative when the client is using its limit.
     pub total: i32,
+    /// `total` less what pending holds set aside, which is what debits can use.
+    pub disponivel: i32,
+    #[schema(value_type = crate::api::routes::Timestamp)]
     pub data_extrato: SystemTime,
+    /// How far below zero `total` may go, in centavos.
+    pub limite: i32,
+}
+
+/// A client account, as created by `rinha seed`.
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub struct Client {
+    pub id: i16,
+    pub limite: i32,
+    #[serde(default)]
+    pub saldo: i32,
+}
+
+/// A transaction attempt, successful or not, as recorded in the audit log.
+#[derive(Serialize)]
+#[cfg_attr(test, derive(Clone, Debug))]
+pub struct AuditEntry {
+    pub id: i64,
+    pub realizada_em: SystemTime,
+    pub request_id: Option<String>,
+    pub ip: Option<IpAddr>,
+    pub principal: Option<String>,
+    pub cliente_id: i16,
+    pub payload: String,
+    /// `resultado_codigo` of `debitar`/`creditar`, or 9 when refused as invalid
+    /// before either was called.
+    pub resultado_codigo: i16,
+    pub saldo_antes: Option<i32>,
+    pub saldo_depois: Option<i32>,
+}
+
+/// Outcome of a bulk import.
+#[derive(Serialize)]
+#[cfg_attr(test, derive(Clone, Debug, PartialEq))]
+pub struct ImportReport {
+    pub aplicadas: u64,
+    pub rejeitadas: Vec<Rejection>,
+}
+
+/// A row left out of an import.
+#[derive(Serialize)]
+#[cfg_attr(test, derive(Clone, Debug, PartialEq))]
+pub struct Rejection {
+    /// Line of the row in the file, the header being line 1.
+    pub linha: i64,
+    /// Why, such as `valor_invalido` or `limite_excedido`.
+    pub motivo: String,
+}
+
+/// A client failing reconciliation.
+#[derive(Serialize)]
+#[cfg_attr(test, derive(Clone, Debug, PartialEq))]
+pub struct Discrepancy {
+    pub cliente_id: i16,
+    pub saldo: i32,
     pub limite: i32,
+    /// Opening balance plus the signed sum of the client's transactions.
+    pub esperado: i64,
+    /// Sum of the postings on the client's ledger account.
+    pub razao: i64,
+    /// Lowest balance the transactions ever took the client to.
+    pub saldo_minimo: i64,
+    /// Whether correcting entries brought `saldo` and `razao` to `esperado`.
+    pub corrigido: bool,
+}
+
+impl Discrepancy {
+    pub fn balance_drifted(&self) -> bool {
+        i64::from(self.saldo) != self.esperado || self.razao != self.esperado
+    }
+
+    pub fn limit_exceeded(&self) -> bool {
+        self.saldo_minimo < -i64::from(self.limite)
+    }
+}
+
+/// Rules a client's transactions have to follow besides the limit, checked before
+/// the balance is touched. Rules left out don't apply.
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub struct Policy {
+    /// Largest amount of a single transaction, in centavos.
+    pub valor_maximo: Option<i16>,
+    /// Smallest amount of a single transaction, in centavos.
+    pub valor_minimo: Option<i16>,
+    /// The only descriptions accepted.
+    pub descricoes_permitidas: Option<Vec<String>>,
+    /// Descriptions refused, exclusive with `descricoes_permitidas`.
+    pub descricoes_bloqueadas: Option<Vec<String>>,
+    #[serde(default = "allowed")]
+    pub creditos_permitidos: bool,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            valor_maximo: None,
+            valor_minimo: None,
+            descricoes_permitidas: None,
+            descricoes_bloqueadas: None,
+            creditos_permitidos: true,
+        }
+    }
+}
+
+fn allowed() -> bool {
+    true
+}
+
+impl Policy {
+    /// Whether the rules can be stored: a minimum up to the maximum, one list of
+    /// descriptions at most, and descriptions a transaction could have.
+    pub fn is_valid(&self) -> bool {
+        let descriptions = match (&self.descricoes_permitidas, &self.descricoes_bloqueadas) {
+            (Some(_), Some(_)) => return false,
+            (Some(descriptions), None) | (None, Some(descriptions)) => descriptions.as_slice(),
+            (None, None) => &[],
+        };
+
+        !matches!(self.valor_minimo.zip(self.valor_maximo), Some((min, max)) if min > max)
+            && descriptions
+                .iter()
+                .all(|descricao| (1..=10).contains(&descricao.len()))
+    }
+}
+
+/// A transaction made for a client on a schedule.
+#[derive(Clone, Serialize, ToSchema)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub struct StandingOrder {
+    pub id: i32,
+    pub cliente_id: i16,
+    /// Centavos.
+    pub valor: i16,
+    /// `c` for a credit, `d` for a debit.
+    #[schema(pattern = "^[cd]$")]
+    pub tipo: String,
+    pub descricao: String,
+    #[schema(value_type = String, example = "mensal")]
+    pub agenda: Schedule,
+    #[schema(value_type = crate::api::routes::Timestamp)]
+    pub inicio: SystemTime,
+    /// Runs are made before it.
+    #[schema(value_type = Option<crate::api::routes::Timestamp>)]
+    pub fim: Option<SystemTime>,
+    /// `None` once the schedule ran out or the order was cancelled.
+    #[schema(value_type = Option<crate::api::routes::Timestamp>)]
+    pub proxima_execucao: Option<SystemTime>,
+    #[schema(value_type = Option<crate::api::routes::Timestamp>)]
+    pub cancelada_em: Option<SystemTime>,
+}
+
+/// What a standing order is registered with.
+#[derive(Deserialize, ToSchema)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub struct NewStandingOrder {
+    /// Centavos.
+    pub valor: i16,
+    /// `c` for a credit, `d` for a debit.
+    #[schema(pattern = "^[cd]$")]
+    pub tipo: String,
+    #[schema(min_length = 1, max_length = 10)]
+    pub descricao: String,
+    /// `diaria`, `semanal`, `mensal`, or a five-field cron expression, in UTC.
+    #[schema(value_type = String, example = "mensal")]
+    pub agenda: Schedule,
+    /// First day runs can be made on, today when left out.
+    #[schema(value_type = Option<String>, format = Date)]
+    pub inicio: Option<Date>,
+    /// Last day runs can be made on.
+    #[schema(value_type = Option<String>, format = Date)]
+    pub fim: Option<Date>,
+}
+
+impl NewStandingOrder {
+    /// Whether `tipo` and `descricao` are what a transaction accepts.
+    pub fn is_valid(&self) -> bool {
+        matches!(
+            (self.descricao.len(), self.tipo.as_str()),
+            (1..=10, "c" | "d")
+        ) && self
+            .inicio
+            .zip(self.fim)
+            .is_none_or(|(inicio, fim)| inicio <= fim)
+    }
+}
+
+/// A run of a standing order.
+#[derive(Serialize, ToSchema)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub struct Run {
+    #[schema(value_type = crate::api::routes::Timestamp)]
+    pub agendada_para: SystemTime,
+    #[schema(value_type = crate::api::routes::Timestamp)]
+    pub iniciada_em: SystemTime,
+    /// The instance that made it.
+    pub instancia: String,
+    /// `aplicada`, or why not as in import reports, `falhou` when the database
+    /// failed and `None` when the outcome isn't known.
+    pub resultado: Option<String>,
+    /// Balance right after, when applied.
+    pub saldo: Option<i32>,
+}
+
+/// How long a hold lasts when placed without `validade_s`, a week.
+pub const DEFAULT_HOLD_VALIDITY_S: i32 = 7 * 24 * 60 * 60;
+
+/// Longest a hold can last, 30 days.
+pub const MAX_HOLD_VALIDITY_S: i32 = 30 * 24 * 60 * 60;
+
+/// Funds of a client set aside for a debit whose final amount isn't known yet.
+#[derive(Serialize, ToSchema)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub struct Hold {
+    pub id: i32,
+    pub cliente_id: i16,
+    /// Centavos.
+    pub valor: i16,
+    /// What the debit is described as once captured.
+    pub descricao: String,
+    /// `pendente` while it sets funds aside, then `capturada`, `cancelada` or `expirada`.
+    pub situacao: String,
+    #[schema(value_type = crate::api::routes::Timestamp)]
+    pub criada_em: SystemTime,
+    #[schema(value_type = crate::api::routes::Timestamp)]
+    pub expira_em: SystemTime,
+    #[schema(value_type = Option<crate::api::routes::Timestamp>)]
+    pub capturada_em: Option<SystemTime>,
+    /// What the capture debited, the rest was released.
+    pub valor_capturado: Option<i16>,
+    #[schema(value_type = Option<crate::api::routes::Timestamp>)]
+    pub cancelada_em: Option<SystemTime>,
+    /// Set to `expira_em` once the hold is swept as expired.
+    #[schema(value_type = Option<crate::api::routes::Timestamp>)]
+    pub expirada_em: Option<SystemTime>,
+}
+
+/// What a hold is placed with.
+#[derive(Deserialize, ToSchema)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub struct NewHold {
+    /// Centavos.
+    #[schema(minimum = 1)]
+    pub valor: i16,
+    #[schema(min_length = 1, max_length = 10)]
+    pub descricao: String,
+    /// Seconds until it expires unless captured or voided first, a week when left
+    /// out and 30 days at most.
+    #[schema(minimum = 1, maximum = 2592000)]
+    pub validade_s: Option<i32>,
+}
+
+impl NewHold {
+    /// Whether `valor` is positive, `descricao` is what a transaction accepts and
+    /// `validade_s` is at most [`MAX_HOLD_VALIDITY_S`].
+    pub fn is_valid(&self) -> bool {
+        self.valor > 0
+            && (1..=10).contains(&self.descricao.len())
+            && self
+                .validade_s
+                .is_none_or(|validade| (1..=MAX_HOLD_VALIDITY_S).contains(&validade))
+    }
+}
+
+/// What a hold is captured with.
+#[derive(Deserialize, ToSchema)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub struct Capture {
+    /// Centavos to debit, up to what was held, all of it when left out.
+    #[schema(minimum = 1)]
+    pub valor: Option<i16>,
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use rstest::rstest;
     use serde_json::json;
 
     #[test]
@@ -45,6 +336,7 @@ mod test {
     fn test_serialization_balance() {
         let balance = serde_json::to_value(Balance {
             total: 20,
+            disponivel: 15,
             limite: 1000,
             data_extrato: SystemTime::UNIX_EPOCH,
         })
@@ -53,8 +345,35 @@ mod test {
         assert_eq!(
             balance,
             json!({
-                "total": 20, "limite": 1000, "data_extrato": SystemTime::UNIX_EPOCH,
+                "total": 20, "disponivel": 15, "limite": 1000, "data_extrato": SystemTime::UNIX_EPOCH,
             })
         );
     }
+
+    #[rstest]
+    #[case::consistent(0, 0, 0, 0, false, false)]
+    #[case::balance_edited(10, 0, 0, 0, true, false)]
+    #[case::ledger_missing_postings(0, 0, 10, 0, true, false)]
+    #[case::past_limit(-10, -10, -10, -200, false, true)]
+    fn test_discrepancy(
+        #[case] saldo: i32,
+        #[case] esperado: i64,
+        #[case] razao: i64,
+        #[case] saldo_minimo: i64,
+        #[case] drifted: bool,
+        #[case] exceeded: bool,
+    ) {
+        let discrepancy = Discrepancy {
+            cliente_id: 1,
+            saldo,
+            limite: 100,
+            esperado,
+            razao,
+            saldo_minimo,
+            corrigido: false,
+        };
+
+        assert_eq!(discrepancy.balance_drifted(), drifted);
+        assert_eq!(discrepancy.limit_exceeded(), exceeded);
+    }
 }
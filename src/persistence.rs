@@ -1,4 +1,13 @@
+pub mod cache;
+pub mod circuit_breaker;
 pub mod database;
+#[cfg(test)]
+pub mod mock;
+pub mod notifications;
 mod repository;
+pub mod retry;
 
-pub use repository::{Error, Repository};
+pub use repository::{
+    Admin, Audit, ByteStream, Claim, Election, EntryStream, Error, Export, Holds, Period,
+    ReadToken, Repository, Rule, StandingOrders,
+};
@@ -0,0 +1,559 @@
+use crate::{
+    api::routes::{StatementResponse, TransactionRequest, TransactionResponse},
+    metrics::Metric,
+    models::Transaction,
+    persistence::{
+        notifications::{Notification, Notifications},
+        Audit, Error, Export, Period, ReadToken, Repository as RepositoryTrait,
+    },
+};
+use axum::async_trait;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::sync::broadcast::error::RecvError;
+
+/// Number of transactions `GetBalance` returns, and so how many are kept per client.
+const LAST_TRANSACTIONS: usize = 10;
+
+#[derive(Default)]
+struct Slot {
+    /// Bumped on every write and invalidation, so a read that started before one
+    /// of them doesn't store what it fetched.
+    version: u64,
+    entry: Option<(Instant, StatementResponse)>,
+    /// Read token of the latest local write applied to `entry`.
+    token: Option<ReadToken>,
+}
+
+/// Keeps each client's statement in memory, in front of another repository.
+///
+/// Local writes update the cached statement in place, entries expire after `ttl`
+/// and changes made by other instances evict them. Reads asking for a newer state
+/// than the entry is known to hold go to the wrapped repository.
+///
+/// Holds placed, voided or expired aren't notified, so an entry's `disponivel` can
+/// lag behind them by up to `ttl`. Captures are debits and evict it as those do.
+pub struct Repository {
+    inner: Arc<dyn RepositoryTrait>,
+    ttl: Duration,
+    slots: Mutex<HashMap<i16, Slot>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Repository {
+    pub fn new(inner: Arc<dyn RepositoryTrait>, ttl: Duration) -> Self {
+        Self {
+            inner,
+            ttl,
+            slots: Default::default(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Evicts entries as `notifications` reports changes this cache didn't see.
+    pub fn invalidate_on(self: &Arc<Self>, notifications: &Notifications) {
+        let cache = Arc::downgrade(self);
+        let notifications = notifications.clone();
+        let mut receiver = notifications.subscribe();
+
+        tokio::spawn(async move {
+            loop {
+                let received = receiver.recv().await;
+
+                let Some(cache) = Weak::upgrade(&cache) else {
+                    break;
+                };
+
+                match received {
+                    Ok(notification) => {
+                        cache.invalidate(&notification, notifications.is_local(&notification))
+                    }
+                    Err(RecvError::Lagged(_)) => cache.clear(),
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    fn invalidate(&self, notification: &Notification, local: bool) {
+        let mut slots = self.slots.lock().unwrap();
+        let slot = slots.entry(notification.cliente_id).or_default();
+
+        // Local writes were already applied, unless they bypassed this cache.
+        let applied = matches!(
+            &slot.entry,
+            Some((_, statement)) if statement.saldo.total == notification.saldo
+        );
+
+        if !local || !applied {
+            slot.version += 1;
+            slot.entry = None;
+            slot.token = None;
+        }
+    }
+
+    /// The cached statement, or the slot's version to store a fetched one under.
+    fn lookup(
+        &self,
+        slots: &mut HashMap<i16, Slot>,
+        client_id: &i16,
+        token: Option<&ReadToken>,
+    ) -> Result<StatementResponse, u64> {
+        let slot = slots.entry(*client_id).or_default();
+
+        let stale = token.is_some_and(|token| slot.token < Some(*token));
+
+        match &slot.entry {
+            Some((cached_at, statement)) if !stale && cached_at.elapsed() < self.ttl => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+
+                let mut statement = statement.clone();
+                statement.saldo.data_extrato = SystemTime::now();
+
+                Ok(statement)
+            }
+            _ => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+
+                Err(slot.version)
+            }
+        }
+    }
+
+    fn clear(&self) {
+        for slot in self.slots.lock().unwrap().values_mut() {
+            slot.version += 1;
+            slot.entry = None;
+            slot.token = None;
+        }
+    }
+}
+
+#[async_trait]
+impl RepositoryTrait for Repository {
+    async fn create_transaction(
+        &self,
+        client_id: &i16,
+        data: &TransactionRequest,
+        audit: &Audit,
+    ) -> Result<TransactionResponse, Error> {
+        let result = self.inner.create_transaction(client_id, data, audit).await;
+
+        let mut slots = self.slots.lock().unwrap();
+        let slot = slots.entry(*client_id).or_default();
+
+        slot.version += 1;
+
+        match (&result, slot.entry.as_mut()) {
+            (Ok(response), Some((_, statement))) => {
+                // Holds are unchanged, so what is available moves with the balance.
+                statement.saldo.disponivel += response.saldo - statement.saldo.total;
+                statement.saldo.total = response.saldo;
+                statement.saldo.limite = response.limite;
+                statement.ultimas_transacoes.insert(
+                    0,
+                    Transaction {
+                        valor: data.valor,
+                        tipo: data.tipo.clone(),
+                        descricao: data.descricao.clone(),
+                        realizada_em: SystemTime::now(),
+                    },
+                );
+                statement.ultimas_transacoes.truncate(LAST_TRANSACTIONS);
+                slot.token = slot.token.max(response.token);
+            }
+            (Err(Error::ClientNotFound | Error::BalanceConstraintViolation), _) => {}
+            // The write may or may not have been applied.
+            (Err(_), _) => {
+                slot.entry = None;
+                slot.token = None;
+            }
+            (Ok(_), None) => {}
+        }
+
+        result
+    }
+
+    async fn reject(&self, client_id: &i16, audit: &Audit) -> Result<(), Error> {
+        self.inner.reject(client_id, audit).await
+    }
+
+    async fn get_balance(
+        &self,
+        client_id: &i16,
+        token: Option<&ReadToken>,
+    ) -> Result<StatementResponse, Error> {
+        let version = match self.lookup(&mut self.slots.lock().unwrap(), client_id, token) {
+            Ok(statement) => return Ok(statement),
+            Err(version) => version,
+        };
+
+        let statement = self.inner.get_balance(client_id, token).await?;
+
+        let mut slots = self.slots.lock().unwrap();
+        let slot = slots.entry(*client_id).or_default();
+
+        if slot.version == version {
+            slot.entry = Some((Instant::now(), statement.clone()));
+            slot.token = token.copied().max(slot.token);
+        }
+
+        Ok(statement)
+    }
+
+    /// Hits are served from memory and the misses fetched together.
+    async fn get_balances(
+        &self,
+        client_ids: &[i16],
+    ) -> Result<HashMap<i16, StatementResponse>, Error> {
+        let mut statements = HashMap::with_capacity(client_ids.len());
+        let mut misses = Vec::new();
+
+        {
+            let mut slots = self.slots.lock().unwrap();
+
+            for client_id in client_ids {
+                match self.lookup(&mut slots, client_id, None) {
+                    Ok(statement) => {
+                        statements.insert(*client_id, statement);
+                    }
+                    Err(version) => misses.push((*client_id, version)),
+                }
+            }
+        }
+
+        if misses.is_empty() {
+            return Ok(statements);
+        }
+
+        let ids: Vec<i16> = misses.iter().map(|(client_id, _)| *client_id).collect();
+        let fetched = self.inner.get_balances(&ids).await?;
+
+        let mut slots = self.slots.lock().unwrap();
+
+        for (client_id, version) in misses {
+            let Some(statement) = fetched.get(&client_id) else {
+                continue;
+            };
+            let slot = slots.entry(client_id).or_default();
+
+            if slot.version == version {
+                slot.entry = Some((Instant::now(), statement.clone()));
+            }
+        }
+
+        statements.extend(fetched);
+
+        Ok(statements)
+    }
+
+    /// Exports aren't cached, they read the whole history.
+    async fn export(&self, client_id: &i16, period: Period) -> Result<Export, Error> {
+        self.inner.export(client_id, period).await
+    }
+
+    async fn transactions(
+        &self,
+        client_id: &i16,
+        period: Period,
+        tipo: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<Transaction>, Error> {
+        self.inner
+            .transactions(client_id, period, tipo, limit)
+            .await
+    }
+
+    fn metrics(&self) -> Vec<Metric> {
+        let mut metrics = self.inner.metrics();
+
+        metrics.push(Metric::counter(
+            "rinha_statement_cache_hits_total",
+            "Statements served from the in-process cache.",
+            self.hits.load(Ordering::Relaxed),
+        ));
+        metrics.push(Metric::counter(
+            "rinha_statement_cache_misses_total",
+            "Statements fetched from the wrapped repository.",
+            self.misses.load(Ordering::Relaxed),
+        ));
+
+        metrics
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::Balance;
+    use rstest::rstest;
+
+    #[derive(Default)]
+    struct MockRepository {
+        reads: AtomicU64,
+    }
+
+    #[async_trait]
+    impl RepositoryTrait for MockRepository {
+        async fn get_balance(
+            &self,
+            client_id: &i16,
+            _token: Option<&ReadToken>,
+        ) -> Result<StatementResponse, Error> {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+
+            match client_id {
+                1 => Ok(StatementResponse {
+                    saldo: Balance {
+                        total: 0,
+                        // A hold of 5 is pending.
+                        disponivel: -5,
+                        limite: 1000,
+                        data_extrato: SystemTime::UNIX_EPOCH,
+                    },
+                    ultimas_transacoes: Vec::new(),
+                }),
+                _ => Err(Error::ClientNotFound),
+            }
+        }
+
+        async fn create_transaction(
+            &self,
+            client_id: &i16,
+            data: &TransactionRequest,
+            _audit: &Audit,
+        ) -> Result<TransactionResponse, Error> {
+            match (client_id, data.valor) {
+                (1, 2000) => Err(Error::BalanceConstraintViolation),
+                (1, 666) => Err(Error::Connection),
+                (1, valor) => Ok(TransactionResponse {
+                    limite: 1000,
+                    saldo: -(valor as i32),
+                    token: Some(ReadToken::from(valor as u64)),
+                }),
+                _ => Err(Error::ClientNotFound),
+            }
+        }
+
+        async fn export(&self, _client_id: &i16, _period: Period) -> Result<Export, Error> {
+            unimplemented!()
+        }
+    }
+
+    fn cache(ttl: Duration) -> (Arc<MockRepository>, Repository) {
+        let inner = Arc::new(MockRepository::default());
+
+        (inner.clone(), Repository::new(inner, ttl))
+    }
+
+    fn debit(valor: i16) -> TransactionRequest {
+        TransactionRequest {
+            valor,
+            tipo: "d".into(),
+            descricao: "bar".into(),
+        }
+    }
+
+    fn notification(saldo: i32, origem: &str) -> Notification {
+        Notification {
+            cliente_id: 1,
+            valor: 10,
+            tipo: "d".into(),
+            descricao: "bar".into(),
+            saldo,
+            limite: 1000,
+            origem: origem.into(),
+        }
+    }
+
+    fn counters(cache: &Repository) -> (i64, i64) {
+        let metrics = cache.metrics();
+
+        (metrics[0].value, metrics[1].value)
+    }
+
+    #[tokio::test]
+    async fn test_get_balance_hit() {
+        let (inner, cache) = cache(Duration::from_secs(60));
+
+        cache.get_balance(&1, None).await.unwrap();
+        let statement = cache.get_balance(&1, None).await.unwrap();
+
+        assert_eq!(inner.reads.load(Ordering::Relaxed), 1);
+        assert_eq!(counters(&cache), (1, 1));
+        assert!(statement.saldo.data_extrato > SystemTime::UNIX_EPOCH);
+    }
+
+    #[tokio::test]
+    async fn test_get_balance_expired() {
+        let (inner, cache) = cache(Duration::ZERO);
+
+        cache.get_balance(&1, None).await.unwrap();
+        cache.get_balance(&1, None).await.unwrap();
+
+        assert_eq!(inner.reads.load(Ordering::Relaxed), 2);
+        assert_eq!(counters(&cache), (0, 2));
+    }
+
+    #[tokio::test]
+    async fn test_get_balance_not_found() {
+        let (inner, cache) = cache(Duration::from_secs(60));
+
+        assert!(matches!(
+            cache.get_balance(&2, None).await,
+            Err(Error::ClientNotFound)
+        ));
+        assert!(matches!(
+            cache.get_balance(&2, None).await,
+            Err(Error::ClientNotFound)
+        ));
+        assert_eq!(inner.reads.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_get_balances() {
+        let (inner, cache) = cache(Duration::from_secs(60));
+
+        let statements = cache.get_balances(&[1, 2]).await.unwrap();
+
+        assert_eq!(statements.keys().collect::<Vec<_>>(), vec![&1]);
+        assert_eq!(inner.reads.load(Ordering::Relaxed), 2);
+        assert_eq!(counters(&cache), (0, 2));
+
+        // Client 1 was stored, only the missing client 2 is asked for again.
+        cache.get_balance(&1, None).await.unwrap();
+        let statements = cache.get_balances(&[1, 2]).await.unwrap();
+
+        assert_eq!(statements.keys().collect::<Vec<_>>(), vec![&1]);
+        assert_eq!(inner.reads.load(Ordering::Relaxed), 3);
+        assert_eq!(counters(&cache), (2, 3));
+    }
+
+    #[tokio::test]
+    async fn test_create_transaction_writes_through() {
+        let (inner, cache) = cache(Duration::from_secs(60));
+
+        cache.get_balance(&1, None).await.unwrap();
+        cache
+            .create_transaction(&1, &debit(10), &Audit::default())
+            .await
+            .unwrap();
+        cache
+            .create_transaction(&1, &debit(2000), &Audit::default())
+            .await
+            .unwrap_err();
+
+        let statement = cache.get_balance(&1, None).await.unwrap();
+
+        assert_eq!(inner.reads.load(Ordering::Relaxed), 1);
+        assert_eq!(statement.saldo.total, -10);
+        assert_eq!(statement.saldo.disponivel, -15);
+        assert_eq!(statement.ultimas_transacoes.len(), 1);
+        assert_eq!(statement.ultimas_transacoes[0].descricao, "bar");
+    }
+
+    #[tokio::test]
+    async fn test_create_transaction_keeps_last_transactions() {
+        let (_, cache) = cache(Duration::from_secs(60));
+
+        cache.get_balance(&1, None).await.unwrap();
+
+        for valor in 1..=(LAST_TRANSACTIONS as i16 + 1) {
+            cache
+                .create_transaction(&1, &debit(valor), &Audit::default())
+                .await
+                .unwrap();
+        }
+
+        let statement = cache.get_balance(&1, None).await.unwrap();
+
+        assert_eq!(statement.ultimas_transacoes.len(), LAST_TRANSACTIONS);
+        assert_eq!(
+            statement.ultimas_transacoes[0].valor,
+            LAST_TRANSACTIONS as i16 + 1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_transaction_unknown_outcome() {
+        let (inner, cache) = cache(Duration::from_secs(60));
+
+        cache.get_balance(&1, None).await.unwrap();
+        cache
+            .create_transaction(&1, &debit(666), &Audit::default())
+            .await
+            .unwrap_err();
+        cache.get_balance(&1, None).await.unwrap();
+
+        assert_eq!(inner.reads.load(Ordering::Relaxed), 2);
+    }
+
+    #[rstest]
+    #[case::remote(0, "api02", 2)]
+    #[case::local_applied(0, "api01", 1)]
+    #[case::local_not_applied(-10, "api01", 2)]
+    #[tokio::test]
+    async fn test_invalidate(
+        #[case] saldo: i32,
+        #[case] origem: &str,
+        #[case] expected_reads: u64,
+    ) {
+        let (inner, cache) = cache(Duration::from_secs(60));
+
+        cache.get_balance(&1, None).await.unwrap();
+        cache.invalidate(&notification(saldo, origem), origem == "api01");
+        cache.get_balance(&1, None).await.unwrap();
+
+        assert_eq!(inner.reads.load(Ordering::Relaxed), expected_reads);
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_on() {
+        let (inner, cache) = cache(Duration::from_secs(60));
+        let cache = Arc::new(cache);
+        let notifications = Notifications::new("api01");
+
+        cache.invalidate_on(&notifications);
+        cache.get_balance(&1, None).await.unwrap();
+
+        notifications.publish(notification(-10, "api02"));
+
+        while cache.slots.lock().unwrap()[&1].entry.is_some() {
+            tokio::task::yield_now().await;
+        }
+
+        cache.get_balance(&1, None).await.unwrap();
+
+        assert_eq!(inner.reads.load(Ordering::Relaxed), 2);
+    }
+
+    #[rstest]
+    #[case::without_token(None, 1)]
+    #[case::older_token(Some(5), 1)]
+    #[case::same_token(Some(10), 1)]
+    #[case::newer_token(Some(11), 2)]
+    #[tokio::test]
+    async fn test_get_balance_read_token(#[case] token: Option<u64>, #[case] expected_reads: u64) {
+        let (inner, cache) = cache(Duration::from_secs(60));
+
+        cache.get_balance(&1, None).await.unwrap();
+        cache
+            .create_transaction(&1, &debit(10), &Audit::default())
+            .await
+            .unwrap();
+        cache
+            .get_balance(&1, token.map(ReadToken::from).as_ref())
+            .await
+            .unwrap();
+
+        assert_eq!(inner.reads.load(Ordering::Relaxed), expected_reads);
+    }
+}
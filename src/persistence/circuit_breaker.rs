@@ -0,0 +1,370 @@
+use crate::{
+    api::routes::{StatementResponse, TransactionRequest, TransactionResponse},
+    metrics::Metric,
+    models::Transaction,
+    persistence::{Audit, Error, Export, Period, ReadToken, Repository as RepositoryTrait},
+    telemetry,
+};
+use axum::async_trait;
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Copy)]
+enum State {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// A single probe call is in flight, everything else is rejected.
+    HalfOpen,
+}
+
+/// Stops calling another repository after `threshold` consecutive failures and
+/// fails fast with [`Error::Unavailable`] instead.
+///
+/// Once `cooldown` has passed, the next call goes through as a probe: closing the
+/// breaker if it succeeds, opening it again if it fails.
+pub struct Repository {
+    inner: Arc<dyn RepositoryTrait>,
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<State>,
+    rejections: AtomicU64,
+}
+
+/// Admission to call the wrapped repository. A probe that is dropped before
+/// reporting its outcome lets the next call probe instead.
+struct Permit<'a> {
+    breaker: &'a Repository,
+    probe: bool,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if self.probe {
+            *self.breaker.state.lock().unwrap() = State::Open {
+                until: Instant::now(),
+            };
+        }
+    }
+}
+
+impl Repository {
+    pub fn new(inner: Arc<dyn RepositoryTrait>, threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            inner,
+            threshold,
+            cooldown,
+            state: Mutex::new(State::Closed { failures: 0 }),
+            rejections: AtomicU64::new(0),
+        }
+    }
+
+    fn acquire(&self) -> Result<Permit<'_>, Error> {
+        let mut state = self.state.lock().unwrap();
+
+        match *state {
+            State::Closed { .. } => Ok(Permit {
+                breaker: self,
+                probe: false,
+            }),
+            State::Open { until } if Instant::now() >= until => {
+                *state = State::HalfOpen;
+
+                Ok(Permit {
+                    breaker: self,
+                    probe: true,
+                })
+            }
+            State::Open { .. } | State::HalfOpen => {
+                self.rejections.fetch_add(1, Ordering::Relaxed);
+
+                Err(Error::Unavailable)
+            }
+        }
+    }
+
+    fn record(&self, mut permit: Permit<'_>, failed: bool) {
+        let mut state = self.state.lock().unwrap();
+
+        *state = match (*state, failed) {
+            (_, false) => State::Closed { failures: 0 },
+            (State::Closed { failures }, true) if failures + 1 < self.threshold => State::Closed {
+                failures: failures + 1,
+            },
+            (State::Closed { .. } | State::HalfOpen, true) => {
+                telemetry::error!("Circuit breaker open for {:?}", self.cooldown);
+
+                State::Open {
+                    until: Instant::now() + self.cooldown,
+                }
+            }
+            // Another call already opened it.
+            (state @ State::Open { .. }, true) => state,
+        };
+
+        permit.probe = false;
+    }
+
+    async fn call<T, Fut>(&self, operation: Fut) -> Result<T, Error>
+    where
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let permit = self.acquire()?;
+        let result = operation.await;
+
+        self.record(permit, result.as_ref().is_err_and(is_failure));
+
+        result
+    }
+}
+
+/// Whether the error says something about the database's health, rather than
+/// about the request.
+fn is_failure(err: &Error) -> bool {
+    !matches!(
+        err,
+        Error::ClientNotFound
+            | Error::BalanceConstraintViolation
+            | Error::PolicyViolation(_)
+            | Error::InvalidInput(_)
+            | Error::HoldClosed
+    )
+}
+
+#[async_trait]
+impl RepositoryTrait for Repository {
+    async fn create_transaction(
+        &self,
+        client_id: &i16,
+        data: &TransactionRequest,
+        audit: &Audit,
+    ) -> Result<TransactionResponse, Error> {
+        self.call(self.inner.create_transaction(client_id, data, audit))
+            .await
+    }
+
+    async fn reject(&self, client_id: &i16, audit: &Audit) -> Result<(), Error> {
+        self.call(self.inner.reject(client_id, audit)).await
+    }
+
+    async fn get_balance(
+        &self,
+        client_id: &i16,
+        token: Option<&ReadToken>,
+    ) -> Result<StatementResponse, Error> {
+        self.call(self.inner.get_balance(client_id, token)).await
+    }
+
+    async fn get_balances(
+        &self,
+        client_ids: &[i16],
+    ) -> Result<HashMap<i16, StatementResponse>, Error> {
+        self.call(self.inner.get_balances(client_ids)).await
+    }
+
+    async fn export(&self, client_id: &i16, period: Period) -> Result<Export, Error> {
+        self.call(self.inner.export(client_id, period)).await
+    }
+
+    async fn transactions(
+        &self,
+        client_id: &i16,
+        period: Period,
+        tipo: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<Transaction>, Error> {
+        self.call(self.inner.transactions(client_id, period, tipo, limit))
+            .await
+    }
+
+    fn metrics(&self) -> Vec<Metric> {
+        let mut metrics = self.inner.metrics();
+
+        let state = match *self.state.lock().unwrap() {
+            State::Closed { .. } => 0,
+            State::Open { .. } => 1,
+            State::HalfOpen => 2,
+        };
+
+        metrics.push(Metric::gauge(
+            "rinha_circuit_breaker_state",
+            "Circuit breaker state: 0 closed, 1 open, 2 half-open.",
+            state,
+        ));
+        metrics.push(Metric::counter(
+            "rinha_circuit_breaker_rejections_total",
+            "Calls rejected while the circuit breaker was open.",
+            self.rejections.load(Ordering::Relaxed),
+        ));
+
+        metrics
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::Balance;
+    use rstest::rstest;
+    use std::time::SystemTime;
+
+    struct MockRepository {
+        result: Mutex<Result<(), Error>>,
+        calls: AtomicU64,
+    }
+
+    impl MockRepository {
+        fn respond_with(&self, result: Result<(), Error>) {
+            *self.result.lock().unwrap() = result;
+        }
+    }
+
+    #[async_trait]
+    impl RepositoryTrait for MockRepository {
+        async fn get_balance(
+            &self,
+            _client_id: &i16,
+            _token: Option<&ReadToken>,
+        ) -> Result<StatementResponse, Error> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+
+            self.result
+                .lock()
+                .unwrap()
+                .clone()
+                .map(|_| StatementResponse {
+                    saldo: Balance {
+                        total: 0,
+                        disponivel: 0,
+                        limite: 1000,
+                        data_extrato: SystemTime::UNIX_EPOCH,
+                    },
+                    ultimas_transacoes: Vec::new(),
+                })
+        }
+
+        async fn create_transaction(
+            &self,
+            _client_id: &i16,
+            _data: &TransactionRequest,
+            _audit: &Audit,
+        ) -> Result<TransactionResponse, Error> {
+            unimplemented!()
+        }
+
+        async fn export(&self, _client_id: &i16, _period: Period) -> Result<Export, Error> {
+            unimplemented!()
+        }
+    }
+
+    fn breaker(cooldown: Duration) -> (Arc<MockRepository>, Repository) {
+        let inner = Arc::new(MockRepository {
+            result: Mutex::new(Err(Error::Connection)),
+            calls: AtomicU64::new(0),
+        });
+
+        (inner.clone(), Repository::new(inner, 2, cooldown))
+    }
+
+    fn state(breaker: &Repository) -> i64 {
+        breaker.metrics()[0].value
+    }
+
+    #[tokio::test]
+    async fn test_opens_after_threshold() {
+        let (inner, breaker) = breaker(Duration::from_secs(60));
+
+        breaker.get_balance(&1, None).await.unwrap_err();
+        assert_eq!(state(&breaker), 0);
+
+        breaker.get_balance(&1, None).await.unwrap_err();
+        assert_eq!(state(&breaker), 1);
+
+        assert!(matches!(
+            breaker.get_balance(&1, None).await,
+            Err(Error::Unavailable)
+        ));
+        assert_eq!(inner.calls.load(Ordering::Relaxed), 2);
+        assert_eq!(breaker.metrics()[1].value, 1);
+    }
+
+    #[rstest]
+    #[case::client_not_found(Error::ClientNotFound)]
+    #[case::balance_constraint_violation(Error::BalanceConstraintViolation)]
+    #[case::policy_violation(Error::PolicyViolation(crate::persistence::Rule::CreditRefused))]
+    #[case::invalid_input(Error::InvalidInput("missing column".into()))]
+    // What a data exception or constraint violation comes back as.
+    #[case::rejected_by_database(Error::InvalidInput("division by zero".into()))]
+    #[case::hold_closed(Error::HoldClosed)]
+    #[tokio::test]
+    async fn test_ignores_request_errors(#[case] err: Error) {
+        let (inner, breaker) = breaker(Duration::from_secs(60));
+
+        inner.respond_with(Err(err));
+
+        for _ in 0..3 {
+            breaker.get_balance(&1, None).await.unwrap_err();
+        }
+
+        assert_eq!(state(&breaker), 0);
+        assert_eq!(inner.calls.load(Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn test_success_resets_failures() {
+        let (inner, breaker) = breaker(Duration::from_secs(60));
+
+        breaker.get_balance(&1, None).await.unwrap_err();
+        inner.respond_with(Ok(()));
+        breaker.get_balance(&1, None).await.unwrap();
+        inner.respond_with(Err(Error::Connection));
+        breaker.get_balance(&1, None).await.unwrap_err();
+
+        assert_eq!(state(&breaker), 0);
+    }
+
+    #[rstest]
+    #[case::probe_succeeds(Ok(()), 0)]
+    #[case::probe_fails(Err(Error::Connection), 1)]
+    #[tokio::test]
+    async fn test_probe(#[case] result: Result<(), Error>, #[case] expected_state: i64) {
+        let (inner, breaker) = breaker(Duration::ZERO);
+
+        breaker.get_balance(&1, None).await.unwrap_err();
+        breaker.get_balance(&1, None).await.unwrap_err();
+        assert_eq!(state(&breaker), 1);
+
+        inner.respond_with(result);
+        breaker.get_balance(&1, None).await.ok();
+
+        assert_eq!(state(&breaker), expected_state);
+        assert_eq!(inner.calls.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn test_half_open_admits_single_probe() {
+        let (_, breaker) = breaker(Duration::ZERO);
+
+        *breaker.state.lock().unwrap() = State::Open {
+            until: Instant::now(),
+        };
+
+        let probe = breaker.acquire().unwrap();
+
+        assert!(matches!(breaker.acquire(), Err(Error::Unavailable)));
+
+        drop(probe);
+
+        assert!(breaker.acquire().is_ok());
+    }
+}
@@ -1,3 +1,5 @@
 mod postgres;
 
-pub use postgres::Repository;
+pub use postgres::{
+    check, check_replicated, config, listen, migrate, replicate, seed, Election, Repository,
+};
@@ -1,4 +1,19 @@
+mod admin;
+mod batch;
+mod cancel;
+mod election;
+mod export;
+mod holds;
+mod import;
+mod listener;
+mod migrations;
+mod orders;
 mod repository;
+mod seed;
 mod statements_cache;
 
-pub use repository::Repository;
+pub use election::Election;
+pub use listener::spawn as listen;
+pub use migrations::{check, check_replicated, migrate, replicate};
+pub use repository::{config, Repository};
+pub use seed::seed;
@@ -0,0 +1,382 @@
+use super::{import::import, repository::Repository};
+use crate::{
+    models::{AuditEntry, Discrepancy, ImportReport, Policy},
+    persistence::{Admin, Audit, ByteStream, Error},
+};
+use axum::async_trait;
+
+#[async_trait]
+impl Admin for Repository {
+    async fn audit_log(
+        &self,
+        client_id: Option<i16>,
+        limit: i64,
+    ) -> Result<Vec<AuditEntry>, Error> {
+        let conn = self.connection().await?;
+
+        let rows = conn
+            .query(
+                r#"
+                    SELECT
+                        id,
+                        realizada_em,
+                        request_id,
+                        ip,
+                        principal,
+                        cliente_id,
+                        payload,
+                        resultado_codigo,
+                        saldo_antes,
+                        saldo_depois
+                    FROM
+                        auditoria
+                    WHERE
+                        $1::SMALLINT IS NULL OR cliente_id = $1
+                    ORDER BY
+                        id DESC
+                    LIMIT $2;
+                "#,
+                &[&client_id, &limit],
+            )
+            .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(AuditEntry {
+                    id: row.try_get("id")?,
+                    realizada_em: row.try_get("realizada_em")?,
+                    request_id: row.try_get("request_id")?,
+                    ip: row.try_get("ip")?,
+                    principal: row.try_get("principal")?,
+                    cliente_id: row.try_get("cliente_id")?,
+                    payload: row.try_get("payload")?,
+                    resultado_codigo: row.try_get("resultado_codigo")?,
+                    saldo_antes: row.try_get("saldo_antes")?,
+                    saldo_depois: row.try_get("saldo_depois")?,
+                })
+            })
+            .collect()
+    }
+
+    async fn reconcile(&self, correct: bool) -> Result<Vec<Discrepancy>, Error> {
+        let conn = self.connection().await?;
+
+        let rows = conn
+            .query(
+                r#"
+                    SELECT
+                        cliente_id,
+                        saldo,
+                        limite,
+                        esperado,
+                        razao,
+                        saldo_minimo
+                    FROM
+                        conciliacao
+                    WHERE
+                        saldo <> esperado
+                        OR razao <> esperado
+                        OR saldo_minimo < -limite
+                    ORDER BY
+                        cliente_id;
+                "#,
+                &[],
+            )
+            .await?;
+
+        let mut discrepancies = Vec::with_capacity(rows.len());
+
+        for row in rows {
+            let mut discrepancy = Discrepancy {
+                cliente_id: row.try_get("cliente_id")?,
+                saldo: row.try_get("saldo")?,
+                limite: row.try_get("limite")?,
+                esperado: row.try_get("esperado")?,
+                razao: row.try_get("razao")?,
+                saldo_minimo: row.try_get("saldo_minimo")?,
+                corrigido: false,
+            };
+
+            // Going past the limit is history, there's nothing to correct.
+            if correct && discrepancy.balance_drifted() {
+                discrepancy.corrigido = conn
+                    .query_one("SELECT corrigir_saldo($1);", &[&discrepancy.cliente_id])
+                    .await?
+                    .try_get(0)?;
+            }
+
+            discrepancies.push(discrepancy);
+        }
+
+        Ok(discrepancies)
+    }
+    async fn import(&self, csv: ByteStream, audit: Audit) -> Result<ImportReport, Error> {
+        let mut conn = self.connection().await?;
+
+        import(&mut conn, csv, audit).await
+    }
+
+    async fn policy(&self, client_id: i16) -> Result<Policy, Error> {
+        let conn = self.connection().await?;
+
+        let row = conn
+            .query_opt(
+                r#"
+                    SELECT
+                        p.valor_maximo,
+                        p.valor_minimo,
+                        p.descricoes_permitidas,
+                        p.descricoes_bloqueadas,
+                        p.creditos_permitidos
+                    FROM
+                        clientes c
+                        LEFT JOIN politicas p ON p.cliente_id = c.id
+                    WHERE
+                        c.id = $1;
+                "#,
+                &[&client_id],
+            )
+            .await?
+            .ok_or(Error::ClientNotFound)?;
+
+        Ok(Policy {
+            valor_maximo: row.try_get("valor_maximo")?,
+            valor_minimo: row.try_get("valor_minimo")?,
+            descricoes_permitidas: row.try_get("descricoes_permitidas")?,
+            descricoes_bloqueadas: row.try_get("descricoes_bloqueadas")?,
+            // NULL without a policy
+            creditos_permitidos: row
+                .try_get::<_, Option<bool>>("creditos_permitidos")?
+                .unwrap_or(true),
+        })
+    }
+
+    async fn set_policy(&self, client_id: i16, policy: &Policy) -> Result<(), Error> {
+        let conn = self.connection().await?;
+
+        let updated = conn
+            .execute(
+                r#"
+                    INSERT INTO politicas (
+                        cliente_id,
+                        valor_maximo,
+                        valor_minimo,
+                        descricoes_permitidas,
+                        descricoes_bloqueadas,
+                        creditos_permitidos)
+                    SELECT
+                        id,
+                        $2::SMALLINT,
+                        $3::SMALLINT,
+                        $4::VARCHAR(10)[],
+                        $5::VARCHAR(10)[],
+                        $6::BOOLEAN
+                    FROM
+                        clientes
+                    WHERE
+                        id = $1
+                    ON CONFLICT (cliente_id) DO UPDATE SET
+                        valor_maximo = EXCLUDED.valor_maximo,
+                        valor_minimo = EXCLUDED.valor_minimo,
+                        descricoes_permitidas = EXCLUDED.descricoes_permitidas,
+                        descricoes_bloqueadas = EXCLUDED.descricoes_bloqueadas,
+                        creditos_permitidos = EXCLUDED.creditos_permitidos;
+                "#,
+                &[
+                    &client_id,
+                    &policy.valor_maximo,
+                    &policy.valor_minimo,
+                    &policy.descricoes_permitidas,
+                    &policy.descricoes_bloqueadas,
+                    &policy.creditos_permitidos,
+                ],
+            )
+            .await?;
+
+        match updated {
+            0 => Err(Error::ClientNotFound),
+            _ => Ok(()),
+        }
+    }
+
+    async fn expire_idempotency_keys(&self, limit: i32) -> Result<i32, Error> {
+        let conn = self.connection().await?;
+
+        Ok(conn
+            .query_one("SELECT expirar_idempotencia($1);", &[&limit])
+            .await?
+            .try_get(0)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::repository::{config, connect, refusal};
+    use crate::persistence::Error;
+    use bb8_postgres::tokio_postgres::Transaction;
+    use rstest::rstest;
+
+    /// How far the client's cached balance and ledger are from its history.
+    async fn drift(transaction: &Transaction<'_>) -> (i64, i64) {
+        let row = transaction
+            .query_one(
+                "SELECT saldo - esperado, razao - esperado FROM conciliacao WHERE cliente_id = 1;",
+                &[],
+            )
+            .await
+            .unwrap();
+
+        (row.get(0), row.get(1))
+    }
+
+    /// Run with `cargo test -- --ignored` against a database on localhost.
+    #[rstest]
+    #[case::cached_balance("UPDATE clientes SET saldo = saldo + 7 WHERE id = 1;", (7, 0))]
+    #[case::ledger(
+        "INSERT INTO transacoes (cliente_id, valor, tipo, descricao) VALUES (1, 7, 'c', 'perdida');",
+        (-7, -7)
+    )]
+    #[tokio::test]
+    #[ignore = "needs the rinha database"]
+    async fn test_drift_is_found_and_corrected(
+        #[case] statement: &str,
+        #[case] expected_drift: (i64, i64),
+    ) {
+        let config = config("localhost", "test").unwrap();
+        let mut client = connect(&config).await.unwrap();
+        // Rolled back when dropped, the database is left as it was.
+        let transaction = client.transaction().await.unwrap();
+
+        assert_eq!(drift(&transaction).await, (0, 0));
+
+        transaction.batch_execute(statement).await.unwrap();
+
+        assert_eq!(drift(&transaction).await, expected_drift);
+
+        let corrected: bool = transaction
+            .query_one("SELECT corrigir_saldo(1::SMALLINT);", &[])
+            .await
+            .unwrap()
+            .get(0);
+
+        assert!(corrected);
+        assert_eq!(drift(&transaction).await, (0, 0));
+
+        // Nothing is left to correct.
+        let corrected: bool = transaction
+            .query_one("SELECT corrigir_saldo(1::SMALLINT);", &[])
+            .await
+            .unwrap()
+            .get(0);
+
+        assert!(!corrected);
+    }
+
+    /// Run with `cargo test -- --ignored` against a database on localhost.
+    #[rstest]
+    #[case::above_maximum(
+        "valor_maximo = 500",
+        "creditar",
+        600,
+        "pix",
+        Some("valor_acima_do_maximo")
+    )]
+    #[case::below_minimum(
+        "valor_minimo = 10",
+        "debitar",
+        5,
+        "pix",
+        Some("valor_abaixo_do_minimo")
+    )]
+    #[case::description_not_allowed(
+        "descricoes_permitidas = '{pix}'",
+        "creditar",
+        10,
+        "ted",
+        Some("descricao_recusada")
+    )]
+    #[case::description_blocked(
+        "descricoes_bloqueadas = '{aposta}'",
+        "debitar",
+        10,
+        "aposta",
+        Some("descricao_recusada")
+    )]
+    #[case::credit_refused(
+        "creditos_permitidos = FALSE",
+        "creditar",
+        10,
+        "pix",
+        Some("credito_recusado")
+    )]
+    #[case::within_policy(
+        "valor_maximo = 500, creditos_permitidos = FALSE",
+        "debitar",
+        10,
+        "pix",
+        None
+    )]
+    #[tokio::test]
+    #[ignore = "needs the rinha database"]
+    async fn test_policy_is_enforced(
+        #[case] policy: &str,
+        #[case] function: &str,
+        #[case] valor: i16,
+        #[case] descricao: &str,
+        #[case] expected_rule: Option<&str>,
+    ) {
+        let config = config("localhost", "test").unwrap();
+        let mut client = connect(&config).await.unwrap();
+        // Rolled back when dropped, the database is left as it was.
+        let transaction = client.transaction().await.unwrap();
+
+        transaction
+            .batch_execute(&format!(
+                r#"
+                    DELETE FROM politicas WHERE cliente_id = 1;
+                    INSERT INTO politicas (cliente_id) VALUES (1);
+                    UPDATE politicas SET {policy} WHERE cliente_id = 1;
+                "#
+            ))
+            .await
+            .unwrap();
+
+        let transactions = "SELECT COUNT(*) FROM transacoes WHERE cliente_id = 1;";
+        let before: i64 = transaction
+            .query_one(transactions, &[])
+            .await
+            .unwrap()
+            .get(0);
+
+        let code: i16 = transaction
+            .query_one(
+                &format!(
+                    "SELECT resultado_codigo FROM {function}(1::SMALLINT, $1, $2, NULL, NULL, NULL, '{{}}');"
+                ),
+                &[&valor, &descricao],
+            )
+            .await
+            .unwrap()
+            .get(0);
+
+        let after: i64 = transaction
+            .query_one(transactions, &[])
+            .await
+            .unwrap()
+            .get(0);
+
+        match expected_rule {
+            Some(expected) => {
+                assert!(
+                    matches!(refusal(code), Error::PolicyViolation(rule) if rule.code() == expected),
+                    "{code}"
+                );
+                assert_eq!(after, before);
+            }
+            None => {
+                assert_eq!(code, 0);
+                assert_eq!(after, before + 1);
+            }
+        }
+    }
+}
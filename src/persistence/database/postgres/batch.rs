@@ -0,0 +1,236 @@
+use super::{
+    repository::{current_lsn, transaction_params},
+    statements_cache::{ConnectionPool, Statement},
+};
+use crate::{
+    api::routes::{TransactionRequest, TransactionResponse},
+    metrics::Metric,
+    persistence::{Audit, Error},
+};
+use futures_util::future::{join, join_all, OptionFuture};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::{mpsc, oneshot};
+
+const CAPACITY: usize = 1024;
+const MAX_BATCH_SIZE: usize = 64;
+
+struct Job {
+    statement: Statement,
+    client_id: i16,
+    data: TransactionRequest,
+    audit: Audit,
+    reply: oneshot::Sender<Result<TransactionResponse, Error>>,
+}
+
+#[derive(Default)]
+struct Counters {
+    batches: AtomicU64,
+    transactions: AtomicU64,
+}
+
+/// Coalesces transactions that arrive within `window` of each other and runs them
+/// pipelined on a single pooled connection, so a burst of writes costs one
+/// checkout and one round trip instead of one of each per transaction.
+///
+/// Every transaction is still its own statement, so each one commits, fails or
+/// is rejected independently. With `track_lsn`, the batch ends with a read of the
+/// WAL position, which becomes the read token of every transaction in it.
+#[derive(Clone)]
+pub struct Batcher {
+    sender: mpsc::Sender<Job>,
+    counters: Arc<Counters>,
+}
+
+impl Batcher {
+    pub fn spawn(pool: ConnectionPool, window: Duration, track_lsn: bool) -> Self {
+        let (sender, mut receiver) = mpsc::channel(CAPACITY);
+        let counters = Arc::new(Counters::default());
+
+        let batch_counters = counters.clone();
+        tokio::spawn(async move {
+            while let Some(jobs) = collect(&mut receiver, window, MAX_BATCH_SIZE).await {
+                batch_counters.batches.fetch_add(1, Ordering::Relaxed);
+                batch_counters
+                    .transactions
+                    .fetch_add(jobs.len() as u64, Ordering::Relaxed);
+
+                // Keep collecting the next batch while this one runs.
+                tokio::spawn(run(pool.clone(), jobs, track_lsn));
+            }
+        });
+
+        Self { sender, counters }
+    }
+
+    pub async fn submit(
+        &self,
+        statement: Statement,
+        client_id: i16,
+        data: TransactionRequest,
+        audit: Audit,
+    ) -> Result<TransactionResponse, Error> {
+        let (reply, response) = oneshot::channel();
+
+        self.sender
+            .send(Job {
+                statement,
+                client_id,
+                data,
+                audit,
+                reply,
+            })
+            .await
+            .map_err(|_| Error::Internal("Batcher stopped".into()))?;
+
+        response
+            .await
+            .map_err(|_| Error::Internal("Batch dropped the transaction".into()))?
+    }
+
+    pub fn metrics(&self) -> Vec<Metric> {
+        vec![
+            Metric::counter(
+                "rinha_batches_total",
+                "Batches of transactions sent to the database.",
+                self.counters.batches.load(Ordering::Relaxed),
+            ),
+            Metric::counter(
+                "rinha_batched_transactions_total",
+                "Transactions sent to the database as part of a batch.",
+                self.counters.transactions.load(Ordering::Relaxed),
+            ),
+        ]
+    }
+}
+
+/// Waits for a first item, then gathers whatever else arrives within `window`,
+/// up to `max` items. Returns `None` once the channel is closed and drained.
+async fn collect<T>(
+    receiver: &mut mpsc::Receiver<T>,
+    window: Duration,
+    max: usize,
+) -> Option<Vec<T>> {
+    let mut items = vec![receiver.recv().await?];
+
+    let deadline = tokio::time::sleep(window);
+    tokio::pin!(deadline);
+
+    while items.len() < max {
+        tokio::select! {
+            _ = &mut deadline => break,
+            item = receiver.recv() => match item {
+                Some(item) => items.push(item),
+                None => break,
+            },
+        }
+    }
+
+    Some(items)
+}
+
+async fn run(pool: ConnectionPool, jobs: Vec<Job>, track_lsn: bool) {
+    let conn = match pool.get().await {
+        Ok(conn) => conn,
+        Err(err) => {
+            let err = Error::from(err);
+
+            for job in jobs {
+                let _ = job.reply.send(Err(err.clone()));
+            }
+
+            return;
+        }
+    };
+
+    // Queries issued together are pipelined by tokio_postgres, in order.
+    let transactions = join_all(jobs.iter().map(|job| async {
+        let statement = conn
+            .statements
+            .get(&job.statement)
+            .ok_or(Error::Internal("Statement not found".into()))?;
+
+        conn.query_one(
+            statement,
+            &transaction_params(&job.client_id, &job.data, &job.audit),
+        )
+        .await?
+        .try_into()
+    }));
+    let lsn = OptionFuture::from(track_lsn.then(|| current_lsn(&conn)));
+
+    let (results, lsn) = join(transactions, lsn).await;
+
+    // The transactions are committed by now, so failing to read the WAL position
+    // only costs their read-your-writes guarantee.
+    let token = lsn.and_then(Result::ok);
+
+    for (job, result) in jobs.into_iter().zip(results) {
+        let result = result.map(|mut response: TransactionResponse| {
+            response.token = token;
+            response
+        });
+
+        // The caller may have gone away, there's nobody left to tell.
+        let _ = job.reply.send(result);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_collect_within_window() {
+        let (sender, mut receiver) = mpsc::channel(10);
+
+        for item in 0..3 {
+            sender.send(item).await.unwrap();
+        }
+
+        assert_eq!(
+            collect(&mut receiver, Duration::from_millis(1), 10).await,
+            Some(vec![0, 1, 2])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_collect_up_to_max() {
+        let (sender, mut receiver) = mpsc::channel(10);
+
+        for item in 0..5 {
+            sender.send(item).await.unwrap();
+        }
+
+        assert_eq!(
+            collect(&mut receiver, Duration::from_secs(60), 2).await,
+            Some(vec![0, 1])
+        );
+        assert_eq!(
+            collect(&mut receiver, Duration::from_secs(60), 2).await,
+            Some(vec![2, 3])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_collect_closed() {
+        let (sender, mut receiver) = mpsc::channel(10);
+
+        sender.send(1).await.unwrap();
+        drop(sender);
+
+        assert_eq!(
+            collect(&mut receiver, Duration::from_secs(60), 10).await,
+            Some(vec![1])
+        );
+        assert_eq!(
+            collect::<i32>(&mut receiver, Duration::from_secs(60), 10).await,
+            None
+        );
+    }
+}
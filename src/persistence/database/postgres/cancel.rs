@@ -0,0 +1,43 @@
+use super::statements_cache::Connection;
+use crate::telemetry;
+use bb8_postgres::tokio_postgres::{CancelToken, NoTls};
+use std::future::Future;
+
+/// Cancels the query running on a connection when dropped, unless disarmed.
+struct Guard<'a> {
+    conn: &'a Connection,
+    armed: bool,
+}
+
+impl Drop for Guard<'_> {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+
+        // The server may finish the query before the cancel request reaches it, and
+        // would then cancel whatever runs next on that connection instead.
+        self.conn.mark_broken();
+
+        tokio::spawn(cancel(self.conn.cancel_token()));
+    }
+}
+
+#[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+async fn cancel(token: CancelToken) {
+    if let Err(err) = token.cancel_query(NoTls).await {
+        telemetry::error!("Failed to cancel query: {:?}", err);
+    }
+}
+
+/// Runs `future`, which queries `conn`. If it's dropped before completing, because
+/// its deadline passed or the client went away, the query is cancelled on the
+/// server instead of holding the connection until it finishes.
+pub async fn cancel_on_drop<F: Future>(conn: &Connection, future: F) -> F::Output {
+    let mut guard = Guard { conn, armed: true };
+    let output = future.await;
+
+    guard.armed = false;
+
+    output
+}
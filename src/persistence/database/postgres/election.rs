@@ -0,0 +1,122 @@
+use super::repository::connect;
+use crate::{
+    persistence::{Election as ElectionTrait, Error},
+    telemetry,
+};
+use axum::async_trait;
+use bb8_postgres::tokio_postgres::{self, Client};
+use std::time::Duration;
+
+/// Key ("ordens" in ASCII) of the advisory lock held by the leader.
+const LOCK_KEY: i64 = 0x6f72_6465_6e73;
+
+/// Longest wait on the database before taking the connection for lost.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Leads while holding a session advisory lock on a dedicated connection, outside
+/// the pool. Postgres releases the lock when the connection drops, letting another
+/// instance take over.
+pub struct Election {
+    config: tokio_postgres::Config,
+    client: Option<Client>,
+    leading: bool,
+}
+
+impl Election {
+    pub fn new(config: tokio_postgres::Config) -> Self {
+        Self {
+            config,
+            client: None,
+            leading: false,
+        }
+    }
+
+    async fn campaign(&mut self) -> Result<bool, Error> {
+        let client = match self.client.take() {
+            Some(client) if !client.is_closed() => client,
+            _ => {
+                self.leading = false;
+
+                connect(&self.config).await?
+            }
+        };
+
+        // The lock lasts as long as the session, all the leader has to check is that
+        // the session is still there.
+        let query = if self.leading {
+            client.query_one("SELECT TRUE;", &[])
+        } else {
+            client.query_one("SELECT pg_try_advisory_lock($1);", &[&LOCK_KEY])
+        };
+
+        let row = tokio::time::timeout(TIMEOUT, query)
+            .await
+            .map_err(|_| Error::ConnectionLost("leader election timed out".into()))??;
+
+        self.leading = row.try_get(0)?;
+        self.client = Some(client);
+
+        Ok(self.leading)
+    }
+}
+
+#[async_trait]
+impl ElectionTrait for Election {
+    async fn lead(&mut self) -> bool {
+        let was_leading = self.leading;
+
+        match self.campaign().await {
+            Ok(leading) => {
+                if leading && !was_leading {
+                    telemetry::debug!("Elected leader");
+                }
+
+                leading
+            }
+            #[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+            Err(err) => {
+                telemetry::error!("Leader election failed: {:?}", err);
+
+                // Dropping the connection gives up the lock, if the server still sees it.
+                self.client = None;
+                self.leading = false;
+
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Run with `cargo test -- --ignored` against a database on localhost.
+    #[tokio::test]
+    #[ignore = "needs the rinha database"]
+    async fn test_one_leader_at_a_time() {
+        let config = super::super::repository::config("localhost", "test").unwrap();
+        let mut first = Election::new(config.clone());
+        let mut second = Election::new(config);
+
+        assert!(first.lead().await);
+        assert!(!second.lead().await);
+        assert!(first.lead().await);
+
+        drop(first);
+
+        // The lock goes once the server sees the session gone.
+        let mut elected = false;
+
+        for _ in 0..50 {
+            if second.lead().await {
+                elected = true;
+                break;
+            }
+
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        assert!(elected);
+    }
+}
@@ -0,0 +1,400 @@
+use super::statements_cache::{Connection, ConnectionPool};
+use crate::{
+    models::{Entry, Transaction},
+    persistence::{Error, Export, Period},
+};
+use bb8_postgres::tokio_postgres::{types::ToSql, IsolationLevel, Transaction as DbTransaction};
+use futures_util::{stream, StreamExt, TryStreamExt};
+use std::{future::Future, pin::pin, sync::Arc, time::Duration};
+use tokio::sync::{mpsc, oneshot, Semaphore};
+
+/// Rows fetched from the portal per round trip, and so at most held in memory.
+const BATCH: i32 = 1000;
+
+/// Exports running at once. Each pins a connection for as long as it is read, so
+/// they get a few connections of the pool rather than all of it.
+pub(super) const EXPORTS: usize = 4;
+
+/// How long a fetch from the portal, or the reader taking a row, may take before
+/// the export is given up and its connection returned.
+const IDLE: Duration = Duration::from_secs(30);
+
+/// The limit and the balances around the period, counted the way `conciliacao`
+/// does: opening movements plus the signed transactions.
+const SUMMARY: &str = r#"
+    SELECT
+        c.limite,
+        (a.abertura + t.antes)::BIGINT AS saldo_inicial,
+        (a.abertura + t.ate_o_fim)::BIGINT AS saldo_final
+    FROM
+        clientes c
+    JOIN contas k ON k.cliente_id = c.id
+    CROSS JOIN LATERAL (
+        SELECT COALESCE(SUM(l.valor), 0) AS abertura
+        FROM lancamentos l
+        WHERE l.conta_id = k.id
+            AND EXISTS (
+                SELECT 1 FROM lancamentos o WHERE o.movimento = l.movimento AND o.conta_id = 3
+            )
+    ) a
+    CROSS JOIN LATERAL (
+        SELECT
+            COALESCE(SUM(s.valor) FILTER (WHERE s.realizada_em < $2), 0) AS antes,
+            COALESCE(SUM(s.valor) FILTER (WHERE $3::TIMESTAMP IS NULL OR s.realizada_em < $3), 0)
+                AS ate_o_fim
+        FROM (
+            SELECT CASE tipo WHEN 'c' THEN valor ELSE -valor END AS valor, realizada_em
+            FROM transacoes
+            WHERE cliente_id = c.id
+        ) s
+    ) t
+    WHERE
+        c.id = $1;
+"#;
+
+const ENTRIES: &str = r#"
+    SELECT
+        id,
+        valor,
+        tipo,
+        descricao,
+        realizada_em
+    FROM
+        transacoes
+    WHERE
+        cliente_id = $1
+        AND ($2::TIMESTAMP IS NULL OR realizada_em >= $2)
+        AND ($3::TIMESTAMP IS NULL OR realizada_em < $3)
+    ORDER BY
+        id;
+"#;
+
+struct Summary {
+    limite: i32,
+    saldo_inicial: i64,
+    saldo_final: i64,
+}
+
+/// Streams the client's transactions in `period` from a portal, fetching
+/// [`BATCH`] rows at a time on a connection of its own.
+///
+/// The rows are read by a task feeding a bounded channel, so a slow reader holds
+/// the portal back instead of the rows piling up. The task stops and gives the
+/// connection back once the stream is dropped, or once either side stalls for
+/// [`IDLE`]. The balances and the rows come from the same snapshot, so they always
+/// add up.
+///
+/// Only as many exports as `exports` has permits run at once, the others are
+/// refused as [`Error::Unavailable`] rather than queued behind them.
+pub(super) async fn export(
+    pool: &ConnectionPool,
+    exports: &Arc<Semaphore>,
+    client_id: i16,
+    period: Period,
+) -> Result<Export, Error> {
+    let permit = exports
+        .clone()
+        .try_acquire_owned()
+        .map_err(|_| Error::Unavailable)?;
+    let mut conn = pool.get_owned().await?;
+
+    let (summary_sender, summary) = oneshot::channel();
+    let (sender, receiver) = mpsc::channel(BATCH as usize);
+
+    tokio::spawn(async move {
+        let _permit = permit;
+
+        let transaction = match begin(&mut conn, client_id, period).await {
+            Ok((transaction, summary)) => match summary_sender.send(Ok(summary)) {
+                Ok(()) => transaction,
+                Err(_) => return,
+            },
+            Err(err) => {
+                let _ = summary_sender.send(Err(err));
+                return;
+            }
+        };
+
+        if let Err(err) = send(&transaction, client_id, period, &sender).await {
+            let _ = idle(sender.send(Err(err))).await;
+        }
+    });
+
+    let summary = summary
+        .await
+        .map_err(|_| Error::Internal("Export task ended early".into()))??;
+
+    Ok(Export {
+        limite: summary.limite,
+        saldo_inicial: summary.saldo_inicial,
+        saldo_final: summary.saldo_final,
+        entries: stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|entry| (entry, receiver))
+        })
+        .boxed(),
+    })
+}
+
+async fn begin(
+    conn: &mut Connection,
+    client_id: i16,
+    period: Period,
+) -> Result<(DbTransaction<'_>, Summary), Error> {
+    // Rolled back when dropped, which closes the portal.
+    let transaction = conn
+        .build_transaction()
+        .isolation_level(IsolationLevel::RepeatableRead)
+        .read_only(true)
+        .start()
+        .await?;
+
+    let row = transaction
+        .query_opt(SUMMARY, &[&client_id, &period.from, &period.to])
+        .await?
+        .ok_or(Error::ClientNotFound)?;
+
+    let summary = Summary {
+        limite: row.try_get("limite")?,
+        saldo_inicial: row.try_get("saldo_inicial")?,
+        saldo_final: row.try_get("saldo_final")?,
+    };
+
+    Ok((transaction, summary))
+}
+
+async fn send(
+    transaction: &DbTransaction<'_>,
+    client_id: i16,
+    period: Period,
+    sender: &mpsc::Sender<Result<Entry, Error>>,
+) -> Result<(), Error> {
+    let params: [&(dyn ToSql + Sync); 3] = [&client_id, &period.from, &period.to];
+    let portal = transaction.bind(ENTRIES, &params).await?;
+
+    loop {
+        let mut rows = pin!(idle(transaction.query_portal_raw(&portal, BATCH)).await??);
+        let mut fetched = 0;
+
+        while let Some(row) = idle(rows.try_next()).await?? {
+            fetched += 1;
+
+            let entry = Entry {
+                id: row.try_get("id")?,
+                transaction: Transaction {
+                    valor: row.try_get("valor")?,
+                    tipo: row.try_get("tipo")?,
+                    descricao: row.try_get("descricao")?,
+                    realizada_em: row.try_get("realizada_em")?,
+                },
+            };
+
+            if idle(sender.send(Ok(entry))).await?.is_err() {
+                return Ok(());
+            }
+        }
+
+        if fetched < BATCH {
+            return Ok(());
+        }
+    }
+}
+
+/// `future`, unless it takes longer than [`IDLE`].
+async fn idle<F: Future>(future: F) -> Result<F::Output, Error> {
+    tokio::time::timeout(IDLE, future)
+        .await
+        .map_err(|_| Error::Internal("Export stalled".into()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+    use std::time::SystemTime;
+
+    /// 2100-01-01, past anything the tests share the database with.
+    const PERIOD_START: Duration = Duration::from_secs(4_102_444_800);
+    const DAY: Duration = Duration::from_secs(86_400);
+
+    fn day(days: u32) -> SystemTime {
+        SystemTime::UNIX_EPOCH + PERIOD_START + DAY * days
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle() {
+        assert!(matches!(idle(async { 1 }).await, Ok(1)));
+        assert!(matches!(
+            idle(std::future::pending::<()>()).await,
+            Err(Error::Internal(_))
+        ));
+    }
+
+    /// Run with `cargo test -- --ignored` against a database on localhost.
+    #[tokio::test]
+    #[ignore = "needs the rinha database"]
+    async fn test_exports_are_capped() {
+        let config = super::super::repository::config("localhost", "test").unwrap();
+        let pool = super::super::repository::pool(config, 1).await.unwrap();
+        let exports = Arc::new(Semaphore::new(1));
+
+        let running = exports.clone().try_acquire_owned().unwrap();
+
+        assert!(matches!(
+            export(&pool, &exports, 1, Period::default()).await,
+            Err(Error::Unavailable)
+        ));
+
+        drop(running);
+
+        let export = export(&pool, &exports, 1, Period::default()).await.unwrap();
+
+        assert_eq!(exports.available_permits(), 0);
+
+        // Read through, the export gives its permit and connection back.
+        export.entries.for_each(|_| async {}).await;
+        tokio::task::yield_now().await;
+
+        assert_eq!(exports.available_permits(), 1);
+        assert_eq!(pool.state().idle_connections, 1);
+    }
+
+    /// Run with `cargo test -- --ignored` against a database on localhost.
+    #[tokio::test]
+    #[ignore = "needs the rinha database"]
+    async fn test_entries_of_the_period_are_streamed_in_batches() {
+        let config = super::super::repository::config("localhost", "test").unwrap();
+        let mut client = super::super::repository::connect(&config).await.unwrap();
+        // Rolled back when dropped, the database is left as it was.
+        let transaction = client.transaction().await.unwrap();
+
+        // Over two batches within the period, and one on each side of it.
+        let within = 2 * BATCH + 1;
+
+        transaction
+            .execute(
+                r#"
+                    INSERT INTO transacoes (cliente_id, valor, tipo, descricao, realizada_em)
+                    SELECT 1, 1, 'c', 'lote', $1
+                    FROM generate_series(1, $2);
+                "#,
+                &[&day(1), &within],
+            )
+            .await
+            .unwrap();
+        transaction
+            .execute(
+                r#"
+                    INSERT INTO transacoes (cliente_id, valor, tipo, descricao, realizada_em)
+                    VALUES (1, 1, 'c', 'antes', $1), (1, 1, 'c', 'depois', $2);
+                "#,
+                &[&day(0), &day(2)],
+            )
+            .await
+            .unwrap();
+
+        let (sender, mut receiver) = mpsc::channel(within as usize + 1);
+        let period = Period {
+            from: Some(day(1)),
+            to: Some(day(2)),
+        };
+
+        send(&transaction, 1, period, &sender).await.unwrap();
+        drop(sender);
+
+        let mut entries = Vec::new();
+
+        while let Some(entry) = receiver.recv().await {
+            entries.push(entry.unwrap());
+        }
+
+        assert_eq!(entries.len() as i32, within);
+        assert!(entries
+            .iter()
+            .all(|entry| entry.transaction.descricao == "lote"));
+        assert!(entries.windows(2).all(|pair| pair[0].id < pair[1].id));
+    }
+
+    /// A credit of 300 on day 1 and a debit of 100 on day 3 for client 1.
+    async fn movements(transaction: &DbTransaction<'_>) {
+        transaction
+            .execute(
+                r#"
+                    INSERT INTO transacoes (cliente_id, valor, tipo, descricao, realizada_em)
+                    VALUES (1, 300, 'c', 'entrada', $1), (1, 100, 'd', 'saida', $2);
+                "#,
+                &[&day(1), &day(3)],
+            )
+            .await
+            .unwrap();
+    }
+
+    async fn summary(
+        transaction: &DbTransaction<'_>,
+        from: Option<SystemTime>,
+        to: Option<SystemTime>,
+    ) -> Summary {
+        let row = transaction
+            .query_one(SUMMARY, &[&1_i16, &from, &to])
+            .await
+            .unwrap();
+
+        Summary {
+            limite: row.get("limite"),
+            saldo_inicial: row.get("saldo_inicial"),
+            saldo_final: row.get("saldo_final"),
+        }
+    }
+
+    /// Run with `cargo test -- --ignored` against a database on localhost.
+    #[tokio::test]
+    #[ignore = "needs the rinha database"]
+    async fn test_final_balance_counts_up_to_the_period_end() {
+        let config = super::super::repository::config("localhost", "test").unwrap();
+        let mut client = super::super::repository::connect(&config).await.unwrap();
+        // Rolled back when dropped, the database is left as it was.
+        let transaction = client.transaction().await.unwrap();
+
+        movements(&transaction).await;
+
+        let limite: i32 = transaction
+            .query_one("SELECT limite FROM clientes WHERE id = 1;", &[])
+            .await
+            .unwrap()
+            .get(0);
+        let before = summary(&transaction, None, Some(day(1))).await;
+        let credited = summary(&transaction, None, Some(day(2))).await;
+        let all = summary(&transaction, None, None).await;
+
+        assert_eq!(all.limite, limite);
+        assert_eq!(credited.saldo_final - before.saldo_final, 300);
+        assert_eq!(all.saldo_final - before.saldo_final, 200);
+    }
+
+    /// Run with `cargo test -- --ignored` against a database on localhost.
+    #[rstest]
+    #[case::whole_period(1, 4, 0, 200)]
+    #[case::credit_only(1, 2, 0, 300)]
+    #[case::debit_only(2, 4, 300, -100)]
+    #[case::nothing_within(4, 5, 200, 0)]
+    #[tokio::test]
+    #[ignore = "needs the rinha database"]
+    async fn test_balances_add_up_over_the_period(
+        #[case] from: u32,
+        #[case] to: u32,
+        #[case] moved_before: i64,
+        #[case] moved: i64,
+    ) {
+        let config = super::super::repository::config("localhost", "test").unwrap();
+        let mut client = super::super::repository::connect(&config).await.unwrap();
+        // Rolled back when dropped, the database is left as it was.
+        let transaction = client.transaction().await.unwrap();
+
+        movements(&transaction).await;
+
+        let before = summary(&transaction, None, Some(day(0))).await;
+        let period = summary(&transaction, Some(day(from)), Some(day(to))).await;
+
+        assert_eq!(period.saldo_inicial - before.saldo_final, moved_before);
+        assert_eq!(period.saldo_final - period.saldo_inicial, moved);
+    }
+}
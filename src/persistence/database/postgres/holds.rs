@@ -0,0 +1,320 @@
+use super::repository::{refusal, Repository};
+use crate::{
+    api::routes::TransactionResponse,
+    models::{Hold, NewHold, DEFAULT_HOLD_VALIDITY_S},
+    persistence::{Audit, Error, Holds},
+};
+use axum::async_trait;
+use bb8_postgres::tokio_postgres::Row;
+
+const COLUMNS: &str = r#"
+    r.id,
+    r.cliente_id,
+    r.valor,
+    r.descricao,
+    CASE
+        WHEN r.capturada_em IS NOT NULL THEN 'capturada'
+        WHEN r.cancelada_em IS NOT NULL THEN 'cancelada'
+        WHEN r.expirada_em IS NOT NULL OR r.expira_em <= NOW() THEN 'expirada'
+        ELSE 'pendente'
+    END AS situacao,
+    r.criada_em,
+    r.expira_em,
+    r.capturada_em,
+    r.valor_capturado,
+    r.cancelada_em,
+    r.expirada_em
+"#;
+
+/// Holds that still set funds aside, for rows of `reservas r`.
+const PENDING: &str = r#"
+    r.capturada_em IS NULL
+    AND r.cancelada_em IS NULL
+    AND r.expirada_em IS NULL
+    AND r.expira_em > NOW()
+"#;
+
+fn hold(row: &Row) -> Result<Hold, Error> {
+    Ok(Hold {
+        id: row.try_get("id")?,
+        cliente_id: row.try_get("cliente_id")?,
+        valor: row.try_get("valor")?,
+        descricao: row.try_get("descricao")?,
+        situacao: row.try_get("situacao")?,
+        criada_em: row.try_get("criada_em")?,
+        expira_em: row.try_get("expira_em")?,
+        capturada_em: row.try_get("capturada_em")?,
+        valor_capturado: row.try_get("valor_capturado")?,
+        cancelada_em: row.try_get("cancelada_em")?,
+        expirada_em: row.try_get("expirada_em")?,
+    })
+}
+
+#[async_trait]
+impl Holds for Repository {
+    async fn create_hold(&self, client_id: i16, hold: &NewHold) -> Result<Hold, Error> {
+        let conn = self.connection().await?;
+
+        let row = conn
+            .query_one(
+                "SELECT * FROM reservar($1, $2, $3, $4);",
+                &[
+                    &client_id,
+                    &hold.valor,
+                    &hold.descricao,
+                    &hold.validade_s.unwrap_or(DEFAULT_HOLD_VALIDITY_S),
+                ],
+            )
+            .await?;
+
+        match row.try_get::<_, i16>("resultado_codigo")? {
+            0 => {}
+            code => return Err(refusal(code)),
+        }
+
+        let hold_id: i32 = row.try_get("resultado_reserva")?;
+
+        let row = conn
+            .query_one(
+                &format!("SELECT {COLUMNS} FROM reservas r WHERE r.id = $1;"),
+                &[&hold_id],
+            )
+            .await?;
+
+        self::hold(&row)
+    }
+
+    async fn holds(&self, client_id: i16) -> Result<Vec<Hold>, Error> {
+        let conn = self.connection().await?;
+
+        let rows = conn
+            .query(
+                &format!(
+                    r#"
+                        SELECT
+                            {COLUMNS}
+                        FROM
+                            clientes c
+                            LEFT JOIN reservas r ON r.cliente_id = c.id AND {PENDING}
+                        WHERE
+                            c.id = $1
+                        ORDER BY
+                            r.id;
+                    "#
+                ),
+                &[&client_id],
+            )
+            .await?;
+
+        if rows.is_empty() {
+            return Err(Error::ClientNotFound);
+        }
+
+        // A client without holds comes back as a single row of NULLs.
+        rows.iter()
+            .filter(|row| {
+                row.try_get::<_, Option<i32>>("id")
+                    .is_ok_and(|id| id.is_some())
+            })
+            .map(hold)
+            .collect()
+    }
+
+    async fn capture_hold(
+        &self,
+        client_id: i16,
+        hold_id: i32,
+        valor: Option<i16>,
+        audit: &Audit,
+    ) -> Result<TransactionResponse, Error> {
+        let conn = self.connection().await?;
+
+        conn.query_one(
+            "SELECT * FROM capturar($1, $2, $3, $4, $5, $6, $7);",
+            &[
+                &client_id,
+                &hold_id,
+                &valor,
+                &audit.request_id,
+                &audit.ip,
+                &audit.principal,
+                &audit.payload,
+            ],
+        )
+        .await?
+        .try_into()
+    }
+
+    async fn void_hold(&self, client_id: i16, hold_id: i32) -> Result<Hold, Error> {
+        let conn = self.connection().await?;
+
+        let row = conn
+            .query_opt(
+                &format!(
+                    r#"
+                        UPDATE
+                            reservas r
+                        SET
+                            cancelada_em = NOW()
+                        WHERE
+                            r.cliente_id = $1
+                            AND r.id = $2
+                            AND {PENDING}
+                        RETURNING {COLUMNS};
+                    "#
+                ),
+                &[&client_id, &hold_id],
+            )
+            .await?;
+
+        if let Some(row) = row {
+            return hold(&row);
+        }
+
+        let exists = conn
+            .query_opt(
+                "SELECT 1 FROM reservas WHERE cliente_id = $1 AND id = $2;",
+                &[&client_id, &hold_id],
+            )
+            .await?;
+
+        Err(match exists {
+            Some(_) => Error::HoldClosed,
+            None => Error::ClientNotFound,
+        })
+    }
+
+    async fn expire_holds(&self, limit: i32) -> Result<i32, Error> {
+        let conn = self.connection().await?;
+
+        Ok(conn
+            .query_one("SELECT expirar_reservas($1);", &[&limit])
+            .await?
+            .try_get(0)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::repository::{config, connect};
+    use bb8_postgres::tokio_postgres::Transaction;
+    use rstest::rstest;
+
+    /// Client 1's balance and what its holds set aside.
+    async fn funds(transaction: &Transaction<'_>) -> (i32, i32) {
+        let row = transaction
+            .query_one(
+                "SELECT saldo, reservado(1::SMALLINT) FROM clientes WHERE id = 1;",
+                &[],
+            )
+            .await
+            .unwrap();
+
+        (row.get(0), row.get(1))
+    }
+
+    async fn capture(transaction: &Transaction<'_>, hold: i32, valor: Option<i16>) -> i16 {
+        transaction
+            .query_one(
+                "SELECT resultado_codigo FROM capturar(1::SMALLINT, $1, $2, NULL, NULL, NULL, '{}');",
+                &[&hold, &valor],
+            )
+            .await
+            .unwrap()
+            .get(0)
+    }
+
+    /// Run with `cargo test -- --ignored` against a database on localhost.
+    #[rstest]
+    #[case::full(None, 0, (-500, 0))]
+    #[case::partial(Some(320), 0, (-320, 0))]
+    #[case::above_held(Some(600), 8, (0, 500))]
+    #[tokio::test]
+    #[ignore = "needs the rinha database"]
+    async fn test_capture(
+        #[case] valor: Option<i16>,
+        #[case] expected_code: i16,
+        #[case] expected_change: (i32, i32),
+    ) {
+        let config = config("localhost", "test").unwrap();
+        let mut client = connect(&config).await.unwrap();
+        // Rolled back when dropped, the database is left as it was.
+        let transaction = client.transaction().await.unwrap();
+
+        let (saldo, reservado) = funds(&transaction).await;
+
+        let hold: i32 = transaction
+            .query_one(
+                "SELECT resultado_reserva FROM reservar(1::SMALLINT, 500::SMALLINT, 'hotel', 3600);",
+                &[],
+            )
+            .await
+            .unwrap()
+            .get(0);
+
+        assert_eq!(funds(&transaction).await, (saldo, reservado + 500));
+        assert_eq!(capture(&transaction, hold, valor).await, expected_code);
+
+        let (saldo_after, reservado_after) = funds(&transaction).await;
+
+        assert_eq!(
+            (saldo_after - saldo, reservado_after - reservado),
+            expected_change
+        );
+
+        // Once captured, the hold is closed.
+        if expected_code == 0 {
+            assert_eq!(capture(&transaction, hold, None).await, 7);
+        }
+    }
+
+    /// Run with `cargo test -- --ignored` against a database on localhost.
+    #[tokio::test]
+    #[ignore = "needs the rinha database"]
+    async fn test_expired_hold_sets_nothing_aside() {
+        let config = config("localhost", "test").unwrap();
+        let mut client = connect(&config).await.unwrap();
+        // Rolled back when dropped, the database is left as it was.
+        let transaction = client.transaction().await.unwrap();
+
+        let (_, reservado) = funds(&transaction).await;
+
+        let hold: i32 = transaction
+            .query_one(
+                "SELECT resultado_reserva FROM reservar(1::SMALLINT, 500::SMALLINT, 'hotel', 3600);",
+                &[],
+            )
+            .await
+            .unwrap()
+            .get(0);
+
+        transaction
+            .execute(
+                "UPDATE reservas SET expira_em = NOW() - INTERVAL '1 second' WHERE id = $1;",
+                &[&hold],
+            )
+            .await
+            .unwrap();
+
+        // Released before the sweep gets to it, and marked expired once it does.
+        assert_eq!(funds(&transaction).await.1, reservado);
+        assert_eq!(capture(&transaction, hold, None).await, 7);
+
+        let expired: i32 = transaction
+            .query_one("SELECT expirar_reservas(1000000);", &[])
+            .await
+            .unwrap()
+            .get(0);
+        let marked: bool = transaction
+            .query_one(
+                "SELECT expirada_em IS NOT NULL FROM reservas WHERE id = $1;",
+                &[&hold],
+            )
+            .await
+            .unwrap()
+            .get(0);
+
+        assert!(expired >= 1);
+        assert!(marked);
+    }
+}
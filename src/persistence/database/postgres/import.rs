@@ -0,0 +1,176 @@
+use super::statements_cache::Connection;
+use crate::{
+    models::{ImportReport, Rejection},
+    persistence::{Audit, ByteStream, Error},
+    telemetry,
+};
+use bb8_postgres::tokio_postgres::{self, error::SqlState};
+use futures_util::{SinkExt, StreamExt};
+use std::pin::pin;
+
+const COPY: &str = "COPY importacao (cliente_id, tipo, valor, descricao, realizada_em) \
+                    FROM STDIN WITH (FORMAT csv, HEADER true);";
+
+/// Rows of the staging table `importar` applies per transaction, and so the most
+/// an import holds locked at once.
+const CHUNK: i64 = 1000;
+
+/// Loads `csv` into the `importacao` staging table through `COPY` in one
+/// transaction, then has `importar` apply it [`CHUNK`] rows at a time, each chunk
+/// committed on its own.
+///
+/// The upload ending early aborts the copy, and with it the whole import. A chunk
+/// failing stops the import there, leaving the chunks before it applied.
+pub(super) async fn import(
+    conn: &mut Connection,
+    csv: ByteStream,
+    audit: Audit,
+) -> Result<ImportReport, Error> {
+    let report = match stage(conn, csv).await {
+        Ok(staged) => apply(conn, staged, &audit).await,
+        Err(err) => Err(err),
+    };
+
+    // The staging table outlives transactions, so it's dropped however the import
+    // went rather than left on the pooled connection.
+    let dropped = conn
+        .batch_execute("DROP TABLE IF EXISTS pg_temp.importacao;")
+        .await;
+
+    let report = report?;
+    dropped?;
+
+    Ok(report)
+}
+
+/// Copies `csv` into the staging table, returning how many rows it has.
+async fn stage(conn: &mut Connection, mut csv: ByteStream) -> Result<u64, Error> {
+    let transaction = conn.transaction().await?;
+
+    transaction
+        .execute("SELECT preparar_importacao();", &[])
+        .await?;
+
+    let mut sink = pin!(transaction.copy_in(COPY).await?);
+
+    while let Some(chunk) = csv.next().await {
+        sink.send(chunk?).await.map_err(rejected)?;
+    }
+
+    let staged = sink.as_mut().finish().await.map_err(rejected)?;
+
+    transaction.commit().await?;
+
+    Ok(staged)
+}
+
+async fn apply(conn: &Connection, staged: u64, audit: &Audit) -> Result<ImportReport, Error> {
+    let mut rejeitadas = Vec::new();
+    // Lines of the staging table, which count from 1.
+    let lines = staged as i64;
+    let mut applied = 0;
+
+    while applied < lines {
+        let rows = conn
+            .query(
+                "SELECT linha, motivo FROM importar($1, $2, $3, $4, $5);",
+                &[
+                    &audit.request_id,
+                    &audit.ip,
+                    &audit.principal,
+                    &applied,
+                    &CHUNK,
+                ],
+            )
+            .await
+            .inspect_err(|_| {
+                telemetry::error!(
+                    "Import stopped at line {}, the lines before it were applied",
+                    // Past the header.
+                    applied + 2
+                );
+            })?;
+
+        for row in rows {
+            rejeitadas.push(Rejection {
+                // Past the header.
+                linha: row.try_get::<_, i64>("linha")? + 1,
+                motivo: row.try_get("motivo")?,
+            });
+        }
+
+        applied = lines.min(applied + CHUNK);
+
+        telemetry::debug!(
+            "Imported {} of {} rows, {} rejected",
+            applied,
+            staged,
+            rejeitadas.len()
+        );
+    }
+
+    Ok(ImportReport {
+        aplicadas: staged - rejeitadas.len() as u64,
+        rejeitadas,
+    })
+}
+
+/// A CSV `COPY` can't parse fails the import as a whole.
+fn rejected(err: tokio_postgres::Error) -> Error {
+    match err.code() {
+        Some(
+            &SqlState::BAD_COPY_FILE_FORMAT
+            | &SqlState::CHARACTER_NOT_IN_REPERTOIRE
+            | &SqlState::UNTRANSLATABLE_CHARACTER,
+        ) => Error::InvalidInput(err.as_db_error().map_or_else(
+            || err.to_string(),
+            |db| match db.where_() {
+                Some(context) => format!("{} ({})", db.message(), context),
+                None => db.message().to_string(),
+            },
+        )),
+        _ => err.into(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::body::Bytes;
+
+    /// Run with `cargo test -- --ignored` against a database on localhost.
+    #[tokio::test]
+    #[ignore = "needs the rinha database"]
+    async fn test_amounts_up_to_zero_are_rejected_per_row() {
+        let config = super::super::repository::config("localhost", "test").unwrap();
+        let pool = super::super::repository::pool(config, 1).await.unwrap();
+        let mut conn = pool.get().await.unwrap();
+
+        // The credit and the debit applied cancel out.
+        let csv = "cliente_id,tipo,valor,descricao,realizada_em\n\
+                   1,c,7,importado,\n\
+                   1,c,0,importado,\n\
+                   1,d,-7,importado,\n\
+                   1,d,7,importado,\n";
+        let csv: ByteStream = Box::pin(futures_util::stream::iter([Ok(Bytes::from_static(
+            csv.as_bytes(),
+        ))]));
+
+        let report = import(&mut conn, csv, Audit::default()).await.unwrap();
+
+        assert_eq!(report.aplicadas, 2);
+        assert_eq!(
+            report.rejeitadas,
+            [
+                Rejection {
+                    linha: 3,
+                    motivo: "valor_invalido".into(),
+                },
+                Rejection {
+                    linha: 4,
+                    motivo: "valor_invalido".into(),
+                },
+            ]
+        );
+    }
+}
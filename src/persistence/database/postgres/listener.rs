@@ -0,0 +1,71 @@
+use crate::{
+    persistence::notifications::{self, Notification, Notifications},
+    telemetry,
+};
+use bb8_postgres::tokio_postgres::{self, AsyncMessage};
+use std::{future::poll_fn, time::Duration};
+
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Keeps a dedicated connection, outside the pool, listening on
+/// [`notifications::CHANNEL`] and publishes what it receives to `notifications`.
+///
+/// The connection is re-established with exponential backoff whenever it drops.
+pub fn spawn(config: tokio_postgres::Config, notifications: Notifications) {
+    tokio::spawn(async move {
+        let mut backoff = MIN_BACKOFF;
+
+        loop {
+            #[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+            if let Err(err) = listen(&config, &notifications, &mut backoff).await {
+                telemetry::error!("Notification listener error: {:?}", err);
+            }
+
+            telemetry::debug!("Reconnecting notification listener in {:?}", backoff);
+
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    });
+}
+
+async fn listen(
+    config: &tokio_postgres::Config,
+    notifications: &Notifications,
+    backoff: &mut Duration,
+) -> Result<(), tokio_postgres::Error> {
+    let (client, mut connection) = config.connect(tokio_postgres::NoTls).await?;
+
+    // The connection has to be polled for `LISTEN` to go through, so both run together.
+    let query = format!("LISTEN {}", notifications::CHANNEL);
+    let listen = client.batch_execute(&query);
+    tokio::pin!(listen);
+    let mut listening = false;
+
+    loop {
+        tokio::select! {
+            result = &mut listen, if !listening => {
+                result?;
+                listening = true;
+                *backoff = MIN_BACKOFF;
+
+                telemetry::debug!("Listening on {}", notifications::CHANNEL);
+            }
+            message = poll_fn(|cx| connection.poll_message(cx)) => match message {
+                Some(Ok(AsyncMessage::Notification(notification))) => {
+                    match serde_json::from_str::<Notification>(notification.payload()) {
+                        Ok(notification) => notifications.publish(notification),
+                        #[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+                        Err(err) => {
+                            telemetry::error!("Invalid notification payload: {}", err);
+                        }
+                    }
+                }
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Err(err),
+                None => return Ok(()),
+            }
+        }
+    }
+}
@@ -0,0 +1,714 @@
+use super::{repository::connect, statements_cache};
+use crate::{persistence::Error, telemetry};
+use bb8_postgres::tokio_postgres::{self, Client};
+
+/// Key ("rinha" in ASCII) of the advisory lock serializing instances migrating at once.
+const LOCK_KEY: i64 = 0x0072_696e_6861;
+
+struct Migration {
+    version: i32,
+    name: &'static str,
+    sql: &'static str,
+}
+
+/// Every migration, in the order they apply. Versions only ever get appended.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "schema",
+        sql: include_str!("../../../../sql/migrations/0001_schema.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "functions",
+        sql: include_str!("../../../../sql/migrations/0002_functions.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "clientes",
+        sql: include_str!("../../../../sql/migrations/0003_clientes.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "auditoria",
+        sql: include_str!("../../../../sql/migrations/0004_auditoria.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "razao",
+        sql: include_str!("../../../../sql/migrations/0005_razao.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "conciliacao",
+        sql: include_str!("../../../../sql/migrations/0006_conciliacao.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "extrato",
+        sql: include_str!("../../../../sql/migrations/0007_extrato.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "importacao",
+        sql: include_str!("../../../../sql/migrations/0008_importacao.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "politicas",
+        sql: include_str!("../../../../sql/migrations/0009_politicas.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "ordens",
+        sql: include_str!("../../../../sql/migrations/0010_ordens.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "reservas",
+        sql: include_str!("../../../../sql/migrations/0011_reservas.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "replicacao",
+        sql: include_str!("../../../../sql/migrations/0012_replicacao.sql"),
+    },
+    Migration {
+        version: 13,
+        name: "idempotencia",
+        sql: include_str!("../../../../sql/migrations/0013_idempotencia.sql"),
+    },
+    Migration {
+        version: 14,
+        name: "saldo",
+        sql: include_str!("../../../../sql/migrations/0014_saldo.sql"),
+    },
+    Migration {
+        version: 15,
+        name: "importacao_em_lotes",
+        sql: include_str!("../../../../sql/migrations/0015_importacao_em_lotes.sql"),
+    },
+    Migration {
+        version: 16,
+        name: "expiracao_reservas",
+        sql: include_str!("../../../../sql/migrations/0016_expiracao_reservas.sql"),
+    },
+    Migration {
+        version: 17,
+        name: "lancamento_nulo",
+        sql: include_str!("../../../../sql/migrations/0017_lancamento_nulo.sql"),
+    },
+    Migration {
+        version: 18,
+        name: "importacao_valor",
+        sql: include_str!("../../../../sql/migrations/0018_importacao_valor.sql"),
+    },
+    Migration {
+        version: 19,
+        name: "chave_idempotencia",
+        sql: include_str!("../../../../sql/migrations/0019_chave_idempotencia.sql"),
+    },
+    Migration {
+        version: 20,
+        name: "replicacao_opcional",
+        sql: include_str!("../../../../sql/migrations/0020_replicacao_opcional.sql"),
+    },
+    Migration {
+        version: 21,
+        name: "receita",
+        sql: include_str!("../../../../sql/migrations/0021_receita.sql"),
+    },
+    Migration {
+        version: 22,
+        name: "execucoes_retomadas",
+        sql: include_str!("../../../../sql/migrations/0022_execucoes_retomadas.sql"),
+    },
+];
+
+/// Tables statements read from a replica, which only has what the WAL carries, in
+/// the order they can be logged.
+const REPLICATED: &[&str] = &[
+    "clientes",
+    "transacoes",
+    "contas",
+    "lancamentos",
+    "reservas",
+];
+
+/// Applies the migrations not yet recorded in `schema_migrations`, each in its
+/// own transaction, and returns the versions it applied.
+///
+/// Instances starting together wait on each other, so each migration runs once.
+pub async fn migrate(config: &tokio_postgres::Config) -> Result<Vec<i32>, Error> {
+    let mut client = connect(config).await?;
+
+    client
+        .execute("SELECT pg_advisory_lock($1);", &[&LOCK_KEY])
+        .await?;
+
+    let applied = apply(&mut client).await;
+
+    client
+        .execute("SELECT pg_advisory_unlock($1);", &[&LOCK_KEY])
+        .await?;
+
+    applied
+}
+
+async fn apply(client: &mut Client) -> Result<Vec<i32>, Error> {
+    client
+        .batch_execute(
+            r#"
+                CREATE TABLE IF NOT EXISTS schema_migrations (
+                    version INTEGER PRIMARY KEY,
+                    name TEXT NOT NULL,
+                    applied_at TIMESTAMP NOT NULL DEFAULT NOW()
+                );
+            "#,
+        )
+        .await?;
+
+    let applied: Vec<i32> = client
+        .query("SELECT version FROM schema_migrations;", &[])
+        .await?
+        .iter()
+        .map(|row| row.try_get(0))
+        .collect::<Result<_, _>>()?;
+
+    let mut versions = Vec::new();
+
+    for migration in pending(&applied) {
+        telemetry::debug!(
+            "Applying migration {} {}",
+            migration.version,
+            migration.name
+        );
+
+        let transaction = client.transaction().await?;
+
+        transaction.batch_execute(migration.sql).await?;
+        transaction
+            .execute(
+                "INSERT INTO schema_migrations (version, name) VALUES ($1, $2);",
+                &[&migration.version, &migration.name],
+            )
+            .await?;
+        transaction.commit().await?;
+
+        versions.push(migration.version);
+    }
+
+    Ok(versions)
+}
+
+fn pending(applied: &[i32]) -> impl Iterator<Item = &'static Migration> + '_ {
+    MIGRATIONS
+        .iter()
+        .filter(|migration| !applied.contains(&migration.version))
+}
+
+/// Logs the tables statements read from a replica that are unlogged, and returns
+/// them. The migrations leave them unlogged, so this is only run where a replica
+/// reads them.
+pub async fn replicate(config: &tokio_postgres::Config) -> Result<Vec<String>, Error> {
+    let mut client = connect(config).await?;
+
+    client
+        .execute("SELECT pg_advisory_lock($1);", &[&LOCK_KEY])
+        .await?;
+
+    let logged = log(&mut client).await;
+
+    client
+        .execute("SELECT pg_advisory_unlock($1);", &[&LOCK_KEY])
+        .await?;
+
+    logged
+}
+
+async fn log(client: &mut Client) -> Result<Vec<String>, Error> {
+    let mut logged = Vec::new();
+
+    for table in REPLICATED {
+        let persistence: String = client
+            .query_one(
+                "SELECT relpersistence::TEXT FROM pg_class WHERE relname = $1 AND relkind = 'r';",
+                &[table],
+            )
+            .await?
+            .try_get(0)?;
+
+        if persistence != "p" {
+            telemetry::debug!("Logging table {}", table);
+
+            client
+                .batch_execute(&format!("ALTER TABLE {table} SET LOGGED;"))
+                .await?;
+
+            logged.push(table.to_string());
+        }
+    }
+
+    Ok(logged)
+}
+
+/// Fails with the signatures of the functions the prepared statements call that
+/// the database lacks, which otherwise only shows up as the pool failing to connect.
+pub async fn check(config: &tokio_postgres::Config) -> Result<(), Error> {
+    let client = connect(config).await?;
+
+    let missing: Vec<String> = client
+        .query(
+            r#"
+                SELECT function
+                FROM unnest($1::TEXT[]) AS function
+                WHERE to_regprocedure(function) IS NULL;
+            "#,
+            &[&statements_cache::FUNCTIONS],
+        )
+        .await?
+        .iter()
+        .map(|row| row.try_get(0))
+        .collect::<Result<_, _>>()?;
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Internal(format!(
+            "missing database functions {}, run the migrations",
+            missing.join(", ")
+        )))
+    }
+}
+
+/// Fails with the tables statements read from a replica that are unlogged, which
+/// the replica would serve as empty.
+pub async fn check_replicated(config: &tokio_postgres::Config) -> Result<(), Error> {
+    let client = connect(config).await?;
+
+    let unlogged: Vec<String> = client
+        .query(
+            r#"
+                SELECT relname::TEXT
+                FROM pg_class
+                WHERE relname = ANY($1::TEXT[])
+                  AND relkind = 'r'
+                  AND relpersistence <> 'p';
+            "#,
+            &[&REPLICATED],
+        )
+        .await?
+        .iter()
+        .map(|row| row.try_get(0))
+        .collect::<Result<_, _>>()?;
+
+    if unlogged.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Internal(format!(
+            "tables {} aren't replicated, run the migrations with DB_REPLICA_HOST set",
+            unlogged.join(", ")
+        )))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[test]
+    fn test_versions_are_sequential() {
+        for (index, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, index as i32 + 1);
+        }
+    }
+
+    #[rstest]
+    #[case::none_applied(&[], vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22])]
+    #[case::some_applied(&[1, 2], vec![3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22])]
+    #[case::all_applied(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22], vec![])]
+    fn test_pending(#[case] applied: &[i32], #[case] expected: Vec<i32>) {
+        assert_eq!(
+            pending(applied)
+                .map(|migration| migration.version)
+                .collect::<Vec<_>>(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_replicated_tables_end_up_unlogged() {
+        let sql: String = MIGRATIONS.iter().map(|migration| migration.sql).collect();
+
+        for table in REPLICATED {
+            let logged = sql.rfind(&format!("ALTER TABLE {table} SET LOGGED;"));
+            let unlogged = sql.rfind(&format!("ALTER TABLE {table} SET UNLOGGED;"));
+
+            assert!(logged < unlogged, "{table} is left logged");
+        }
+    }
+
+    /// Run with `cargo test -- --ignored` against a database on localhost.
+    #[tokio::test]
+    #[ignore = "needs the rinha database"]
+    async fn test_replicated_tables_are_logged() {
+        let config = super::super::repository::config("localhost", "test").unwrap();
+
+        migrate(&config).await.unwrap();
+        replicate(&config).await.unwrap();
+
+        let client = connect(&config).await.unwrap();
+
+        for table in REPLICATED {
+            let persistence: String = client
+                .query_one(
+                    "SELECT relpersistence::TEXT FROM pg_class WHERE relname = $1 AND relkind = 'r';",
+                    &[table],
+                )
+                .await
+                .unwrap()
+                .get(0);
+
+            assert_eq!(persistence, "p", "{table} is unlogged");
+        }
+    }
+
+    /// Run with `cargo test -- --ignored` against a database on localhost.
+    #[tokio::test]
+    #[ignore = "needs the rinha database"]
+    async fn test_unbalanced_movement_is_refused() {
+        let config = super::super::repository::config("localhost", "test").unwrap();
+
+        migrate(&config).await.unwrap();
+
+        let mut client = connect(&config).await.unwrap();
+        let transaction = client.transaction().await.unwrap();
+
+        transaction
+            .batch_execute("SET CONSTRAINTS lancamentos_balanceados IMMEDIATE;")
+            .await
+            .unwrap();
+
+        let result = transaction
+            .execute(
+                "INSERT INTO lancamentos (movimento, conta_id, valor) VALUES (nextval('movimentos'), 1, 100);",
+                &[],
+            )
+            .await;
+
+        assert!(result.is_err(), "a single posting was accepted");
+    }
+
+    /// Run with `cargo test -- --ignored` against a database on localhost.
+    #[rstest]
+    #[case::opening_balance(500, vec![(-500, 3), (500, 0)])]
+    #[case::overdrawn(-200, vec![(-200, 0), (200, 3)])]
+    #[case::nothing_to_open(0, vec![])]
+    #[tokio::test]
+    #[ignore = "needs the rinha database"]
+    async fn test_new_client_opens_its_account(
+        #[case] saldo: i32,
+        #[case] expected: Vec<(i32, i32)>,
+    ) {
+        let config = super::super::repository::config("localhost", "test").unwrap();
+
+        migrate(&config).await.unwrap();
+
+        let mut client = connect(&config).await.unwrap();
+        // Rolled back when dropped, the database is left as it was.
+        let transaction = client.transaction().await.unwrap();
+
+        let id: i16 = transaction
+            .query_one(
+                r#"
+                    INSERT INTO clientes (id, saldo, limite)
+                    SELECT (MAX(id) + 1)::SMALLINT, $1, 1000 FROM clientes
+                    RETURNING id;
+                "#,
+                &[&saldo],
+            )
+            .await
+            .unwrap()
+            .get(0);
+
+        // The client's account shows as 0, the system account by its id.
+        let postings = transaction
+            .query(
+                r#"
+                    SELECT l.valor, CASE WHEN k.cliente_id IS NULL THEN k.id ELSE 0 END
+                    FROM lancamentos l JOIN contas k ON k.id = l.conta_id
+                    WHERE l.movimento IN (
+                        SELECT l.movimento
+                        FROM lancamentos l JOIN contas k ON k.id = l.conta_id
+                        WHERE k.cliente_id = $1
+                    )
+                    ORDER BY l.valor;
+                "#,
+                &[&id],
+            )
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| (row.get::<_, i32>(0), row.get::<_, i32>(1)))
+            .collect::<Vec<_>>();
+        let cached: i32 = transaction
+            .query_one("SELECT saldo FROM clientes WHERE id = $1;", &[&id])
+            .await
+            .unwrap()
+            .get(0);
+
+        assert_eq!(postings, expected);
+        assert_eq!(cached, saldo);
+    }
+
+    /// Run with `cargo test -- --ignored` against a database on localhost.
+    #[tokio::test]
+    #[ignore = "needs the rinha database"]
+    async fn test_system_accounts() {
+        let config = super::super::repository::config("localhost", "test").unwrap();
+
+        migrate(&config).await.unwrap();
+
+        let client = connect(&config).await.unwrap();
+
+        let accounts = client
+            .query(
+                "SELECT id, tipo FROM contas WHERE cliente_id IS NULL ORDER BY id;",
+                &[],
+            )
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| (row.get::<_, i32>(0), row.get::<_, String>(1)))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            accounts,
+            [
+                (1, "liquidacao".to_string()),
+                (2, "receita".to_string()),
+                (3, "abertura".to_string()),
+                (4, "ajuste".to_string()),
+            ]
+        );
+    }
+
+    /// Run with `cargo test -- --ignored` against a database on localhost.
+    #[rstest]
+    #[case::credit("creditar", 100)]
+    #[case::debit("debitar", -100)]
+    #[tokio::test]
+    #[ignore = "needs the rinha database"]
+    async fn test_transaction_posts_one_movement(#[case] function: &str, #[case] signed: i32) {
+        let config = super::super::repository::config("localhost", "test").unwrap();
+
+        migrate(&config).await.unwrap();
+
+        let mut client = connect(&config).await.unwrap();
+        // Rolled back when dropped, the database is left as it was.
+        let transaction = client.transaction().await.unwrap();
+
+        let row = transaction
+            .query_one(
+                &format!(
+                    "SELECT * FROM {function}(1::SMALLINT, 100::SMALLINT, 'razao', NULL, NULL, NULL, '{{}}');"
+                ),
+                &[],
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(row.get::<_, i16>("resultado_codigo"), 0);
+
+        let postings = transaction
+            .query(
+                r#"
+                    SELECT l.movimento, l.conta_id, l.valor, k.cliente_id
+                    FROM lancamentos l JOIN contas k ON k.id = l.conta_id
+                    WHERE l.transacao_id = (SELECT MAX(id) FROM transacoes WHERE cliente_id = 1)
+                    ORDER BY l.id;
+                "#,
+                &[],
+            )
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| {
+                (
+                    row.get::<_, i64>("movimento"),
+                    row.get::<_, Option<i16>>("cliente_id"),
+                    row.get::<_, i32>("conta_id"),
+                    row.get::<_, i32>("valor"),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(postings.len(), 2, "{postings:?}");
+        assert_eq!(postings[0].0, postings[1].0, "{postings:?}");
+
+        let client = postings.iter().find(|posting| posting.1 == Some(1));
+        let settlement = postings.iter().find(|posting| posting.2 == 1);
+
+        assert_eq!(client.map(|posting| posting.3), Some(signed));
+        assert_eq!(settlement.map(|posting| posting.3), Some(-signed));
+
+        let (saldo, razao): (i32, i64) = transaction
+            .query_one(
+                r#"
+                    SELECT c.saldo, SUM(l.valor)::BIGINT
+                    FROM clientes c
+                    JOIN contas k ON k.cliente_id = c.id
+                    JOIN lancamentos l ON l.conta_id = k.id
+                    WHERE c.id = 1
+                    GROUP BY c.saldo;
+                "#,
+                &[],
+            )
+            .await
+            .map(|row| (row.get(0), row.get(1)))
+            .unwrap();
+
+        assert_eq!(i64::from(saldo), razao);
+        assert_eq!(row.get::<_, i32>("resultado_saldo"), saldo);
+    }
+
+    /// Run with `cargo test -- --ignored` against a database on localhost.
+    #[rstest]
+    #[case::credit("creditar")]
+    #[case::debit("debitar")]
+    #[tokio::test]
+    #[ignore = "needs the rinha database"]
+    async fn test_zero_transaction_posts_nothing(#[case] function: &str) {
+        let config = super::super::repository::config("localhost", "test").unwrap();
+
+        migrate(&config).await.unwrap();
+
+        let mut client = connect(&config).await.unwrap();
+        // Rolled back when dropped, the database is left as it was.
+        let transaction = client.transaction().await.unwrap();
+
+        let saldo: i32 = transaction
+            .query_one("SELECT saldo FROM clientes WHERE id = 1;", &[])
+            .await
+            .unwrap()
+            .get(0);
+
+        let row = transaction
+            .query_one(
+                &format!(
+                    "SELECT * FROM {function}(1::SMALLINT, 0::SMALLINT, 'nada', NULL, NULL, NULL, '{{}}');"
+                ),
+                &[],
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(row.get::<_, i16>("resultado_codigo"), 0);
+        assert_eq!(row.get::<_, i32>("resultado_saldo"), saldo);
+
+        let postings: i64 = transaction
+            .query_one(
+                r#"
+                    SELECT COUNT(*)
+                    FROM lancamentos
+                    WHERE transacao_id = (SELECT MAX(id) FROM transacoes WHERE cliente_id = 1);
+                "#,
+                &[],
+            )
+            .await
+            .unwrap()
+            .get(0);
+
+        assert_eq!(postings, 0);
+    }
+
+    /// Run with `cargo test -- --ignored` against a database on localhost.
+    #[tokio::test]
+    #[ignore = "needs the rinha database"]
+    async fn test_idempotency_key() {
+        let config = super::super::repository::config("localhost", "test").unwrap();
+
+        migrate(&config).await.unwrap();
+
+        let mut client = connect(&config).await.unwrap();
+        // Rolled back when dropped, the database is left as it was.
+        let transaction = client.transaction().await.unwrap();
+
+        let credit = |valor: i16| {
+            format!(
+                "SELECT * FROM creditar(1::SMALLINT, {valor}::SMALLINT, 'chave', NULL, NULL, NULL, '{{}}', param_chave => 'teste-chave');"
+            )
+        };
+        let transactions = "SELECT COUNT(*) FROM transacoes WHERE cliente_id = 1;";
+
+        let first = transaction.query_one(&credit(100), &[]).await.unwrap();
+        let made: i64 = transaction
+            .query_one(transactions, &[])
+            .await
+            .unwrap()
+            .get(0);
+
+        assert_eq!(first.get::<_, i16>("resultado_codigo"), 0);
+
+        // Sent again, answered as it was then and not made twice.
+        let again = transaction.query_one(&credit(100), &[]).await.unwrap();
+
+        assert_eq!(again.get::<_, i16>("resultado_codigo"), 0);
+        assert_eq!(
+            again.get::<_, i32>("resultado_saldo"),
+            first.get::<_, i32>("resultado_saldo")
+        );
+        assert_eq!(
+            transaction
+                .query_one(transactions, &[])
+                .await
+                .unwrap()
+                .get::<_, i64>(0),
+            made
+        );
+
+        // Reused for another transaction, refused.
+        let reused = transaction.query_one(&credit(200), &[]).await.unwrap();
+
+        assert_eq!(reused.get::<_, i16>("resultado_codigo"), 10);
+        assert_eq!(
+            transaction
+                .query_one(transactions, &[])
+                .await
+                .unwrap()
+                .get::<_, i64>(0),
+            made
+        );
+
+        // Past its day, the key is forgotten and can be used again.
+        transaction
+            .execute(
+                "UPDATE idempotencia SET realizada_em = NOW() - INTERVAL '2 days' WHERE chave = 'teste-chave';",
+                &[],
+            )
+            .await
+            .unwrap();
+
+        let expired: i32 = transaction
+            .query_one("SELECT expirar_idempotencia(1000000);", &[])
+            .await
+            .unwrap()
+            .get(0);
+        let kept: i64 = transaction
+            .query_one(
+                "SELECT COUNT(*) FROM idempotencia WHERE chave = 'teste-chave';",
+                &[],
+            )
+            .await
+            .unwrap()
+            .get(0);
+
+        assert!(expired >= 1);
+        assert_eq!(kept, 0);
+
+        let later = transaction.query_one(&credit(200), &[]).await.unwrap();
+
+        assert_eq!(later.get::<_, i16>("resultado_codigo"), 0);
+    }
+}
@@ -0,0 +1,541 @@
+use super::repository::Repository;
+use crate::{
+    dates::Date,
+    models::{NewStandingOrder, Run, StandingOrder},
+    persistence::{Claim, Error, StandingOrders},
+};
+use axum::async_trait;
+use bb8_postgres::tokio_postgres::{Row, Transaction};
+use std::time::SystemTime;
+
+const COLUMNS: &str = r#"
+    o.id,
+    o.cliente_id,
+    o.valor,
+    o.tipo,
+    o.descricao,
+    o.agenda,
+    o.inicio,
+    o.fim,
+    o.proxima_execucao,
+    o.cancelada_em
+"#;
+
+/// Claims runs without an outcome last claimed by `$1` for `$2`, up to `$3` of them.
+/// Idempotency keys last a day, so older runs are left as they are.
+const STALLED: &str = r#"
+    UPDATE execucoes e
+    SET retomada_em = NOW(), instancia = $2
+    FROM ordens o
+    WHERE o.id = e.ordem_id
+      AND e.id IN (
+        SELECT id
+        FROM execucoes
+        WHERE resultado IS NULL
+          AND COALESCE(retomada_em, iniciada_em) <= $1
+          AND iniciada_em > NOW() - INTERVAL '1 day'
+        ORDER BY iniciada_em
+        LIMIT $3
+        FOR UPDATE SKIP LOCKED
+      )
+    RETURNING e.id AS execucao_id, e.agendada_para,
+"#;
+
+fn standing_order(row: &Row) -> Result<StandingOrder, Error> {
+    Ok(StandingOrder {
+        id: row.try_get("id")?,
+        cliente_id: row.try_get("cliente_id")?,
+        valor: row.try_get("valor")?,
+        tipo: row.try_get("tipo")?,
+        descricao: row.try_get("descricao")?,
+        agenda: row
+            .try_get::<_, &str>("agenda")?
+            .parse()
+            .map_err(Error::Internal)?,
+        inicio: row.try_get("inicio")?,
+        fim: row.try_get("fim")?,
+        proxima_execucao: row.try_get("proxima_execucao")?,
+        cancelada_em: row.try_get("cancelada_em")?,
+    })
+}
+
+/// The run after `after`, if it comes before the order ends.
+fn next_run(order: &StandingOrder, after: Option<SystemTime>) -> Option<SystemTime> {
+    order
+        .agenda
+        .next(order.inicio, after)
+        .filter(|run| order.fim.is_none_or(|fim| *run < fim))
+}
+
+/// Claims the runs due as [`StandingOrders::claim_due`] describes, within `transaction`.
+async fn claim_due(
+    transaction: &Transaction<'_>,
+    now: SystemTime,
+    instance: &str,
+    limit: i64,
+) -> Result<Vec<Claim>, Error> {
+    // Locked so that a concurrent claim skips these orders rather than running them too.
+    let rows = transaction
+        .query(
+            &format!(
+                r#"
+                    SELECT
+                        {COLUMNS}
+                    FROM
+                        ordens o
+                    WHERE
+                        o.proxima_execucao <= $1
+                    ORDER BY
+                        o.proxima_execucao
+                    LIMIT $2
+                    FOR UPDATE SKIP LOCKED;
+                "#
+            ),
+            &[&now, &limit],
+        )
+        .await?;
+
+    let mut claims = Vec::with_capacity(rows.len());
+
+    for row in rows {
+        let mut order = standing_order(&row)?;
+        let agendada_para = order
+            .proxima_execucao
+            .ok_or(Error::Internal("Due order without a run".into()))?;
+
+        order.proxima_execucao = next_run(&order, Some(agendada_para));
+
+        transaction
+            .execute(
+                "UPDATE ordens SET proxima_execucao = $2 WHERE id = $1;",
+                &[&order.id, &order.proxima_execucao],
+            )
+            .await?;
+
+        let run = transaction
+            .query_opt(
+                r#"
+                    INSERT INTO execucoes (ordem_id, agendada_para, instancia)
+                    VALUES ($1, $2, $3)
+                    ON CONFLICT (ordem_id, agendada_para) DO NOTHING
+                    RETURNING id;
+                "#,
+                &[&order.id, &agendada_para, &instance],
+            )
+            .await?;
+
+        // The lock keeps schedulers out of each other's way, the unique key is what
+        // guarantees a run is only made once.
+        if let Some(run) = run {
+            claims.push(Claim {
+                run_id: run.try_get("id")?,
+                agendada_para,
+                order,
+            });
+        }
+    }
+
+    Ok(claims)
+}
+
+#[async_trait]
+impl StandingOrders for Repository {
+    async fn create_order(
+        &self,
+        client_id: i16,
+        order: &NewStandingOrder,
+    ) -> Result<StandingOrder, Error> {
+        let inicio = order
+            .inicio
+            .unwrap_or_else(|| Date::of(SystemTime::now()))
+            .start();
+        let fim = order.fim.map(|fim| fim.end());
+
+        let draft = StandingOrder {
+            id: 0,
+            cliente_id: client_id,
+            valor: order.valor,
+            tipo: order.tipo.clone(),
+            descricao: order.descricao.clone(),
+            agenda: order.agenda.clone(),
+            inicio,
+            fim,
+            proxima_execucao: None,
+            cancelada_em: None,
+        };
+        // Runs the start puts before now aren't made, only missed ones are caught up.
+        let first = next_run(&draft, Some(SystemTime::now()))
+            .ok_or_else(|| Error::InvalidInput("the schedule has no run before fim".into()))?;
+
+        let conn = self.connection().await?;
+
+        let row = conn
+            .query_opt(
+                &format!(
+                    r#"
+                        INSERT INTO ordens AS o (
+                            cliente_id,
+                            tipo,
+                            valor,
+                            descricao,
+                            agenda,
+                            inicio,
+                            fim,
+                            proxima_execucao)
+                        SELECT
+                            id,
+                            $2::CHAR(1),
+                            $3::SMALLINT,
+                            $4::VARCHAR(10),
+                            $5::TEXT,
+                            $6::TIMESTAMP,
+                            $7::TIMESTAMP,
+                            $8::TIMESTAMP
+                        FROM
+                            clientes
+                        WHERE
+                            id = $1
+                        RETURNING {COLUMNS};
+                    "#
+                ),
+                &[
+                    &client_id,
+                    &draft.tipo,
+                    &draft.valor,
+                    &draft.descricao,
+                    &draft.agenda.to_string(),
+                    &inicio,
+                    &fim,
+                    &first,
+                ],
+            )
+            .await?
+            .ok_or(Error::ClientNotFound)?;
+
+        standing_order(&row)
+    }
+
+    async fn orders(&self, client_id: i16) -> Result<Vec<StandingOrder>, Error> {
+        let conn = self.connection().await?;
+
+        let rows = conn
+            .query(
+                &format!(
+                    r#"
+                        SELECT
+                            {COLUMNS}
+                        FROM
+                            clientes c
+                            LEFT JOIN ordens o ON o.cliente_id = c.id
+                        WHERE
+                            c.id = $1
+                        ORDER BY
+                            o.id;
+                    "#
+                ),
+                &[&client_id],
+            )
+            .await?;
+
+        if rows.is_empty() {
+            return Err(Error::ClientNotFound);
+        }
+
+        // A client without orders comes back as a single row of NULLs.
+        rows.iter()
+            .filter(|row| {
+                row.try_get::<_, Option<i32>>("id")
+                    .is_ok_and(|id| id.is_some())
+            })
+            .map(standing_order)
+            .collect()
+    }
+
+    async fn cancel_order(&self, client_id: i16, order_id: i32) -> Result<StandingOrder, Error> {
+        let conn = self.connection().await?;
+
+        let row = conn
+            .query_opt(
+                &format!(
+                    r#"
+                        UPDATE
+                            ordens o
+                        SET
+                            proxima_execucao = NULL,
+                            cancelada_em = COALESCE(o.cancelada_em, NOW())
+                        WHERE
+                            o.cliente_id = $1
+                            AND o.id = $2
+                        RETURNING {COLUMNS};
+                    "#
+                ),
+                &[&client_id, &order_id],
+            )
+            .await?
+            .ok_or(Error::ClientNotFound)?;
+
+        standing_order(&row)
+    }
+
+    async fn runs(&self, client_id: i16, order_id: i32, limit: i64) -> Result<Vec<Run>, Error> {
+        let conn = self.connection().await?;
+
+        let rows = conn
+            .query(
+                r#"
+                    SELECT
+                        e.agendada_para,
+                        e.iniciada_em,
+                        e.instancia,
+                        e.resultado,
+                        e.saldo
+                    FROM
+                        ordens o
+                        LEFT JOIN LATERAL (
+                            SELECT
+                                *
+                            FROM
+                                execucoes
+                            WHERE
+                                ordem_id = o.id
+                            ORDER BY
+                                id DESC
+                            LIMIT $3
+                        ) e ON TRUE
+                    WHERE
+                        o.cliente_id = $1
+                        AND o.id = $2;
+                "#,
+                &[&client_id, &order_id, &limit],
+            )
+            .await?;
+
+        if rows.is_empty() {
+            return Err(Error::ClientNotFound);
+        }
+
+        // An order that never ran comes back as a single row of NULLs.
+        rows.iter()
+            .filter(|row| {
+                row.try_get::<_, Option<SystemTime>>("agendada_para")
+                    .is_ok_and(|run| run.is_some())
+            })
+            .map(|row| {
+                Ok(Run {
+                    agendada_para: row.try_get("agendada_para")?,
+                    iniciada_em: row.try_get("iniciada_em")?,
+                    instancia: row.try_get("instancia")?,
+                    resultado: row.try_get("resultado")?,
+                    saldo: row.try_get("saldo")?,
+                })
+            })
+            .collect()
+    }
+
+    async fn claim_due(
+        &self,
+        now: SystemTime,
+        instance: &str,
+        limit: i64,
+    ) -> Result<Vec<Claim>, Error> {
+        let mut conn = self.connection().await?;
+        let transaction = conn.transaction().await?;
+
+        let claims = claim_due(&transaction, now, instance, limit).await?;
+
+        transaction.commit().await?;
+
+        Ok(claims)
+    }
+
+    async fn claim_stalled(
+        &self,
+        stalled_since: SystemTime,
+        instance: &str,
+        limit: i64,
+    ) -> Result<Vec<Claim>, Error> {
+        let conn = self.connection().await?;
+
+        conn.query(
+            &format!("{STALLED} {COLUMNS};"),
+            &[&stalled_since, &instance, &limit],
+        )
+        .await?
+        .iter()
+        .map(|row| {
+            Ok(Claim {
+                run_id: row.try_get("execucao_id")?,
+                agendada_para: row.try_get("agendada_para")?,
+                order: standing_order(row)?,
+            })
+        })
+        .collect()
+    }
+
+    async fn record_run(
+        &self,
+        run_id: i64,
+        resultado: &str,
+        saldo: Option<i32>,
+    ) -> Result<(), Error> {
+        let conn = self.connection().await?;
+
+        conn.execute(
+            "UPDATE execucoes SET resultado = $2, saldo = $3 WHERE id = $1;",
+            &[&run_id, &resultado, &saldo],
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::repository::{config, connect};
+    use super::*;
+    use crate::dates::DateTime;
+    use std::time::Duration;
+
+    /// Run with `cargo test -- --ignored` against a database on localhost.
+    #[tokio::test]
+    #[ignore = "needs the rinha database"]
+    async fn test_due_runs_are_claimed_once() {
+        let config = config("localhost", "test").unwrap();
+        let mut client = connect(&config).await.unwrap();
+        // Rolled back when dropped, the database is left as it was.
+        let transaction = client.transaction().await.unwrap();
+
+        // Daily, with runs on the 1st and the 2nd before it ends on the 3rd.
+        let order: i32 = transaction
+            .query_one(
+                r#"
+                    INSERT INTO ordens (cliente_id, tipo, valor, descricao, agenda, inicio, fim, proxima_execucao)
+                    VALUES (1, 'd', 990, 'streaming', 'diaria', '2020-01-01', '2020-01-03', '2020-01-01')
+                    RETURNING id;
+                "#,
+                &[],
+            )
+            .await
+            .unwrap()
+            .get(0);
+
+        let claim = || async {
+            claim_due(&transaction, SystemTime::now(), "api01", 1000)
+                .await
+                .unwrap()
+                .into_iter()
+                .filter(|claim| claim.order.id == order)
+                .map(|claim| {
+                    (
+                        DateTime::of(claim.agendada_para).to_string(),
+                        claim
+                            .order
+                            .proxima_execucao
+                            .map(|next| DateTime::of(next).to_string()),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            claim().await,
+            [(
+                "2020-01-01T00:00:00.000000Z".to_string(),
+                Some("2020-01-02T00:00:00.000000Z".to_string())
+            )]
+        );
+        assert_eq!(
+            claim().await,
+            [("2020-01-02T00:00:00.000000Z".to_string(), None)]
+        );
+        assert_eq!(claim().await, []);
+
+        // Due again, a run already claimed isn't claimed twice.
+        transaction
+            .execute(
+                "UPDATE ordens SET proxima_execucao = '2020-01-01' WHERE id = $1;",
+                &[&order],
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(claim().await, []);
+    }
+
+    /// Run with `cargo test -- --ignored` against a database on localhost.
+    #[tokio::test]
+    #[ignore = "needs the rinha database"]
+    async fn test_stalled_runs_are_claimed_once() {
+        let config = config("localhost", "test").unwrap();
+        let mut client = connect(&config).await.unwrap();
+        // Rolled back when dropped, the database is left as it was.
+        let transaction = client.transaction().await.unwrap();
+
+        let order: i32 = transaction
+            .query_one(
+                r#"
+                    INSERT INTO ordens (cliente_id, tipo, valor, descricao, agenda, inicio)
+                    VALUES (1, 'd', 990, 'streaming', 'diaria', NOW())
+                    RETURNING id;
+                "#,
+                &[],
+            )
+            .await
+            .unwrap()
+            .get(0);
+
+        // Which of these runs are stalled: only the first.
+        let runs = transaction
+            .query(
+                r#"
+                    INSERT INTO execucoes (ordem_id, agendada_para, iniciada_em, instancia, resultado, retomada_em)
+                    VALUES
+                        ($1, NOW() - INTERVAL '5 days', NOW() - INTERVAL '10 minutes', 'api01', NULL, NULL),
+                        ($1, NOW() - INTERVAL '4 days', NOW() - INTERVAL '10 minutes', 'api01', 'aplicada', NULL),
+                        ($1, NOW() - INTERVAL '3 days', NOW(), 'api01', NULL, NULL),
+                        ($1, NOW() - INTERVAL '2 days', NOW() - INTERVAL '2 days', 'api01', NULL, NULL),
+                        ($1, NOW() - INTERVAL '1 day', NOW() - INTERVAL '10 minutes', 'api01', NULL, NOW())
+                    RETURNING id;
+                "#,
+                &[&order],
+            )
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.get::<_, i64>(0))
+            .collect::<Vec<_>>();
+
+        let claim = || async {
+            transaction
+                .query(
+                    &format!("{STALLED} {COLUMNS};"),
+                    &[
+                        &(SystemTime::now() - Duration::from_secs(300)),
+                        &"api02",
+                        &1000_i64,
+                    ],
+                )
+                .await
+                .unwrap()
+                .iter()
+                .filter(|row| row.get::<_, i32>("id") == order)
+                .map(|row| row.get::<_, i64>("execucao_id"))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(claim().await, [runs[0]]);
+        assert_eq!(claim().await, Vec::<i64>::new());
+
+        let instance: String = transaction
+            .query_one(
+                "SELECT instancia FROM execucoes WHERE id = $1;",
+                &[&runs[0]],
+            )
+            .await
+            .unwrap()
+            .get(0);
+
+        assert_eq!(instance, "api02");
+    }
+}
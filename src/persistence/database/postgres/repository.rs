@@ -1,41 +1,85 @@
-use super::statements_cache;
+use super::{
+    batch::Batcher,
+    cancel::cancel_on_drop,
+    export::{export, EXPORTS},
+    statements_cache::{self, ConnectionPool},
+};
 use crate::{
     api::routes::{StatementResponse, TransactionRequest, TransactionResponse},
+    metrics::Metric,
     models::{Balance, Transaction},
-    persistence::{Error, Repository as RepositoryTrait},
+    persistence::{Audit, Error, Export, Period, ReadToken, Repository as RepositoryTrait, Rule},
     telemetry,
 };
 use axum::async_trait;
 use bb8_postgres::{
     bb8::{self, Pool, PooledConnection},
-    tokio_postgres::{self},
+    tokio_postgres::{self, error::SqlState, types::ToSql},
 };
-use std::str::FromStr;
+use std::{collections::HashMap, error::Error as _, str::FromStr, sync::Arc};
+use tokio::sync::Semaphore;
+
+/// `resultado_codigo` audited for a transaction refused as invalid, past those of
+/// `debitar`/`creditar`.
+const REJECTED: i16 = 9;
 
 #[derive(Clone)]
 pub struct Repository {
-    pool: Pool<statements_cache::ConnectionManager<tokio_postgres::NoTls>>,
+    size: u32,
+    pool: ConnectionPool,
+    replica: Option<ConnectionPool>,
+    batcher: Option<Batcher>,
+    exports: Arc<Semaphore>,
+}
+
+/// Connection settings for the rinha database on `host`. Every connection is tagged
+/// with `instance` as its `application_name`, which is how notifications tell the
+/// instances apart.
+pub fn config(host: &str, instance: &str) -> Result<tokio_postgres::Config, Error> {
+    let mut config = tokio_postgres::Config::from_str(&format!(
+        "host={} user=admin password=123 dbname=rinha",
+        host
+    ))?;
+
+    config.application_name(instance);
+
+    Ok(config)
 }
 
 impl Repository {
-    pub async fn new(host: &str) -> Result<Self, Error> {
-        let manager = statements_cache::ConnectionManager::new(
-            tokio_postgres::Config::from_str(&format!(
-                "host={} user=admin password=123 dbname=rinha",
-                host
-            ))?,
-            tokio_postgres::NoTls,
-        );
+    /// Opens a pool of `size` connections, a replica gets one of the same size.
+    pub async fn new(config: tokio_postgres::Config, size: u32) -> Result<Self, Error> {
+        Ok(Self {
+            size,
+            pool: pool(config, size).await?,
+            replica: None,
+            batcher: None,
+            exports: Arc::new(Semaphore::new(EXPORTS)),
+        })
+    }
 
-        let pool = Pool::builder()
-            .max_size(40)
-            .min_idle(Some(40))
-            .connection_customizer(Box::new(statements_cache::Cache))
-            .connection_timeout(std::time::Duration::from_secs(5))
-            .build(manager)
-            .await?;
+    /// Serves statements from the replica at `config`, falling back to the primary
+    /// when the replica hasn't replayed up to the reader's [`ReadToken`] yet.
+    ///
+    /// Writes then return the primary's WAL position as their read token. Unlogged
+    /// tables aren't replicated, so the tables it reads have to be logged, which
+    /// [`check_replicated`](super::check_replicated) verifies.
+    pub async fn with_replica(mut self, config: tokio_postgres::Config) -> Result<Self, Error> {
+        self.replica = Some(pool(config, self.size).await?);
+        Ok(self)
+    }
 
-        Ok(Self { pool })
+    /// Sends transactions through a [`Batcher`] collecting them for `window`. Call
+    /// it after [`Repository::with_replica`], so batches hand out read tokens.
+    ///
+    /// A batch is shared, so its queries aren't cancelled when one caller goes away.
+    pub fn with_batching(mut self, window: std::time::Duration) -> Self {
+        self.batcher = Some(Batcher::spawn(
+            self.pool.clone(),
+            window,
+            self.replica.is_some(),
+        ));
+        self
     }
 
     pub async fn connection(
@@ -55,63 +99,363 @@ impl RepositoryTrait for Repository {
         &self,
         client_id: &i16,
         data: &TransactionRequest,
+        audit: &Audit,
     ) -> Result<TransactionResponse, Error> {
-        let conn = self.connection().await?;
-
         let stmt = match data.tipo.as_str() {
             "c" => statements_cache::Statement::CreateCreditTransaction,
             "d" => statements_cache::Statement::CreateDebitTransaction,
             _ => return Err(Error::Internal("Invalid transaction type".into())),
         };
 
-        let row = conn
-            .query_one(
-                conn.statements
-                    .get(&stmt)
-                    .ok_or(Error::Internal("Statement not found".into()))?,
-                &[&client_id, &data.valor, &data.descricao],
-            )
-            .await?;
+        if let Some(batcher) = &self.batcher {
+            return batcher
+                .submit(stmt, *client_id, data.clone(), audit.clone())
+                .await;
+        }
+
+        let conn = self.connection().await?;
+
+        let stmt = conn
+            .statements
+            .get(&stmt)
+            .ok_or(Error::Internal("Statement not found".into()))?;
+        let params = transaction_params(client_id, data, audit);
+
+        if self.replica.is_none() {
+            return cancel_on_drop(&conn, conn.query_one(stmt, &params))
+                .await?
+                .try_into();
+        }
+
+        // Pipelined, so the WAL position is read right after the transaction commits.
+        let (row, lsn) = cancel_on_drop(&conn, async {
+            tokio::join!(conn.query_one(stmt, &params), current_lsn(&conn))
+        })
+        .await;
+        let mut response: TransactionResponse = row?.try_into()?;
+
+        // The transaction is committed by now, so failing to read the WAL position
+        // only costs its read-your-writes guarantee.
+        response.token = lsn.ok();
+
+        Ok(response)
+    }
+
+    async fn get_balance(
+        &self,
+        client_id: &i16,
+        token: Option<&ReadToken>,
+    ) -> Result<StatementResponse, Error> {
+        let Some(replica) = &self.replica else {
+            let conn = self.connection().await?;
+
+            return cancel_on_drop(&conn, balance(&conn, client_id)).await;
+        };
+
+        let conn = replica.get().await?;
+
+        let Some(token) = token else {
+            return cancel_on_drop(&conn, balance(&conn, client_id)).await;
+        };
+
+        let replayed = conn
+            .statements
+            .get(&statements_cache::Statement::ReplayedLsn)
+            .ok_or(Error::Internal("Statement not found".into()))?;
+
+        let (replayed, statement) = cancel_on_drop(&conn, async {
+            tokio::join!(conn.query_one(replayed, &[]), balance(&conn, client_id))
+        })
+        .await;
+
+        // Nothing was replayed when the "replica" is in fact a primary.
+        match replayed?.try_get::<_, Option<ReadToken>>(0)? {
+            Some(replayed) if replayed < *token => {
+                drop(conn);
+
+                let conn = self.connection().await?;
+
+                cancel_on_drop(&conn, balance(&conn, client_id)).await
+            }
+            _ => statement,
+        }
+    }
+
+    /// Served from the replica when there is one, in a single query for all clients.
+    async fn get_balances(
+        &self,
+        client_ids: &[i16],
+    ) -> Result<HashMap<i16, StatementResponse>, Error> {
+        let conn = match &self.replica {
+            Some(replica) => replica.get().await?,
+            None => self.connection().await?,
+        };
+
+        let stmt = conn
+            .statements
+            .get(&statements_cache::Statement::GetBalances)
+            .ok_or(Error::Internal("Statement not found".into()))?;
+        let rows = cancel_on_drop(&conn, conn.query(stmt, &[&client_ids])).await?;
+
+        let mut statements = HashMap::with_capacity(client_ids.len());
+        let mut rows = rows.into_iter().peekable();
+
+        // Rows come ordered by client, each client's being a statement of its own.
+        while let Some(row) = rows.next() {
+            let client_id: i16 = row.try_get("cliente_id")?;
+            let mut client_rows = vec![row];
+
+            while let Some(row) = rows.next_if(|row| {
+                row.try_get::<_, i16>("cliente_id")
+                    .is_ok_and(|id| id == client_id)
+            }) {
+                client_rows.push(row);
+            }
+
+            statements.insert(client_id, client_rows.try_into()?);
+        }
+
+        Ok(statements)
+    }
 
-        row.try_into()
+    /// Served from the replica when there is one, so exports may lag behind writes.
+    async fn export(&self, client_id: &i16, period: Period) -> Result<Export, Error> {
+        export(
+            self.replica.as_ref().unwrap_or(&self.pool),
+            &self.exports,
+            *client_id,
+            period,
+        )
+        .await
     }
 
-    async fn get_balance(&self, client_id: &i16) -> Result<StatementResponse, Error> {
+    /// Served from the replica when there is one, like exports.
+    async fn transactions(
+        &self,
+        client_id: &i16,
+        period: Period,
+        tipo: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<Transaction>, Error> {
+        let conn = match &self.replica {
+            Some(replica) => replica.get().await?,
+            None => self.connection().await?,
+        };
+
+        let rows = cancel_on_drop(
+            &conn,
+            conn.query(
+                r#"
+                    SELECT
+                        t.valor,
+                        t.tipo,
+                        t.descricao,
+                        t.realizada_em
+                    FROM
+                        clientes c
+                        LEFT JOIN LATERAL (
+                            SELECT
+                                *
+                            FROM
+                                transacoes
+                            WHERE
+                                cliente_id = c.id
+                                AND ($2::TIMESTAMP IS NULL OR realizada_em >= $2)
+                                AND ($3::TIMESTAMP IS NULL OR realizada_em < $3)
+                                AND ($4::CHAR(1) IS NULL OR tipo = $4)
+                            ORDER BY
+                                id
+                            LIMIT $5
+                        ) t ON TRUE
+                    WHERE
+                        c.id = $1;
+                "#,
+                &[client_id, &period.from, &period.to, &tipo, &limit],
+            ),
+        )
+        .await?;
+
+        if rows.is_empty() {
+            return Err(Error::ClientNotFound);
+        }
+
+        // A client without transactions comes back as a single row of NULLs.
+        rows.iter()
+            .filter(|row| {
+                row.try_get::<_, Option<i16>>("valor")
+                    .is_ok_and(|valor| valor.is_some())
+            })
+            .map(|row| {
+                Ok(Transaction {
+                    valor: row.try_get("valor")?,
+                    tipo: row.try_get("tipo")?,
+                    descricao: row.try_get("descricao")?,
+                    realizada_em: row.try_get("realizada_em")?,
+                })
+            })
+            .collect()
+    }
+
+    async fn reject(&self, client_id: &i16, audit: &Audit) -> Result<(), Error> {
         let conn = self.connection().await?;
 
-        let rows = conn
-            .query(
-                conn.statements
-                    .get(&statements_cache::Statement::GetBalance)
-                    .ok_or(Error::Internal("Statement not found".into()))?,
-                &[&client_id],
-            )
-            .await?;
+        conn.execute(
+            "SELECT auditar($1, $2, $3, $4, $5, $6, NULL, NULL);",
+            &[
+                client_id,
+                &audit.request_id,
+                &audit.ip,
+                &audit.principal,
+                &audit.payload,
+                &REJECTED,
+            ],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    fn metrics(&self) -> Vec<Metric> {
+        let state = self.pool.state();
 
-        rows.try_into()
+        let mut metrics = vec![
+            Metric::gauge(
+                "rinha_pool_connections",
+                "Connections currently held by the pool.",
+                state.connections.into(),
+            ),
+            Metric::gauge(
+                "rinha_pool_idle_connections",
+                "Idle connections in the pool.",
+                state.idle_connections.into(),
+            ),
+        ];
+
+        if let Some(batcher) = &self.batcher {
+            metrics.extend(batcher.metrics());
+        }
+
+        metrics
     }
 }
 
+pub(super) async fn pool(
+    config: tokio_postgres::Config,
+    size: u32,
+) -> Result<ConnectionPool, Error> {
+    let manager = statements_cache::ConnectionManager::new(config, tokio_postgres::NoTls);
+
+    let pool = Pool::builder()
+        .max_size(size)
+        .min_idle(Some(size))
+        .connection_customizer(Box::new(statements_cache::Cache))
+        .connection_timeout(std::time::Duration::from_secs(5))
+        .build(manager)
+        .await?;
+
+    Ok(pool)
+}
+
+/// Arguments of `debitar`/`creditar`.
+pub(super) fn transaction_params<'a>(
+    client_id: &'a i16,
+    data: &'a TransactionRequest,
+    audit: &'a Audit,
+) -> [&'a (dyn ToSql + Sync); 8] {
+    [
+        client_id,
+        &data.valor,
+        &data.descricao,
+        &audit.request_id,
+        &audit.ip,
+        &audit.principal,
+        &audit.payload,
+        &audit.idempotency_key,
+    ]
+}
+
+/// A single connection outside the pool, for one-off work.
+pub(super) async fn connect(
+    config: &tokio_postgres::Config,
+) -> Result<tokio_postgres::Client, Error> {
+    let (client, connection) = config.connect(tokio_postgres::NoTls).await?;
+
+    tokio::spawn(async move {
+        #[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+        if let Err(err) = connection.await {
+            telemetry::error!("Postgres connection error: {:?}", err);
+        }
+    });
+
+    Ok(client)
+}
+
+async fn balance(
+    conn: &statements_cache::Connection,
+    client_id: &i16,
+) -> Result<StatementResponse, Error> {
+    let rows = conn
+        .query(
+            conn.statements
+                .get(&statements_cache::Statement::GetBalance)
+                .ok_or(Error::Internal("Statement not found".into()))?,
+            &[&client_id],
+        )
+        .await?;
+
+    rows.try_into()
+}
+
+/// The primary's WAL position, past the commit of anything already run on `conn`.
+pub(super) async fn current_lsn(conn: &statements_cache::Connection) -> Result<ReadToken, Error> {
+    let row = conn
+        .query_one(
+            conn.statements
+                .get(&statements_cache::Statement::CurrentLsn)
+                .ok_or(Error::Internal("Statement not found".into()))?,
+            &[],
+        )
+        .await?;
+
+    Ok(row.try_get(0)?)
+}
+
 impl TryFrom<tokio_postgres::Row> for TransactionResponse {
     type Error = Error;
 
     fn try_from(row: tokio_postgres::Row) -> Result<Self, Self::Error> {
         let result: i16 = row.try_get("resultado_codigo")?;
 
-        match result {
-            0 => {
-                let balance: i32 = row.try_get("resultado_saldo")?;
-                let limit: i32 = row.try_get("resultado_limite")?;
-
-                Ok(Self {
-                    saldo: balance,
-                    limite: limit,
-                })
-            }
-            1 => Err(Error::ClientNotFound),
-            2 => Err(Error::BalanceConstraintViolation),
-            _ => Err(Error::Internal("Unknown result code".into())),
+        if result != 0 {
+            return Err(refusal(result));
         }
+
+        let balance: i32 = row.try_get("resultado_saldo")?;
+        let limit: i32 = row.try_get("resultado_limite")?;
+
+        Ok(Self {
+            saldo: balance,
+            limite: limit,
+            token: None,
+        })
+    }
+}
+
+/// Why `debitar`, `creditar`, `reservar` or `capturar` refused, from their nonzero
+/// `resultado_codigo`.
+pub(super) fn refusal(code: i16) -> Error {
+    match code {
+        1 => Error::ClientNotFound,
+        2 => Error::BalanceConstraintViolation,
+        3 => Error::PolicyViolation(Rule::AboveMaximum),
+        4 => Error::PolicyViolation(Rule::BelowMinimum),
+        5 => Error::PolicyViolation(Rule::DescriptionRefused),
+        6 => Error::PolicyViolation(Rule::CreditRefused),
+        // Only captures answer with these.
+        7 => Error::HoldClosed,
+        8 => Error::InvalidInput("more than was held".into()),
+        10 => Error::InvalidInput("idempotency key used for another transaction".into()),
+        _ => Error::Internal("Unknown result code".into()),
     }
 }
 
@@ -126,6 +470,8 @@ impl TryFrom<Vec<tokio_postgres::Row>> for StatementResponse {
 
         let limit: i32 = rows.first().unwrap().try_get("limite")?;
 
+        let available: i32 = rows.first().unwrap().try_get("disponivel")?;
+
         let amount = rows.first().unwrap().try_get::<_, i16>("valor");
 
         let mut transactions = Vec::with_capacity(rows.len());
@@ -144,6 +490,7 @@ impl TryFrom<Vec<tokio_postgres::Row>> for StatementResponse {
         Ok(Self {
             saldo: Balance {
                 total: balance,
+                disponivel: available,
                 data_extrato: std::time::SystemTime::now(),
                 limite: limit,
             },
@@ -156,9 +503,9 @@ impl From<bb8::RunError<tokio_postgres::Error>> for Error {
     fn from(err: bb8::RunError<tokio_postgres::Error>) -> Self {
         telemetry::error!("Postgres error: {:?}", err);
 
+        // Either way the pool handed out no connection, so nothing was sent.
         match err {
-            bb8::RunError::User(e) => Self::Internal(e.to_string()),
-            bb8::RunError::TimedOut => Self::Connection,
+            bb8::RunError::User(_) | bb8::RunError::TimedOut => Self::Connection,
         }
     }
 }
@@ -167,6 +514,54 @@ impl From<tokio_postgres::Error> for Error {
     fn from(err: tokio_postgres::Error) -> Self {
         telemetry::error!("Postgres error: {:?}", err);
 
-        Self::Internal(err.to_string())
+        match err.code() {
+            Some(
+                &SqlState::T_R_SERIALIZATION_FAILURE
+                | &SqlState::T_R_DEADLOCK_DETECTED
+                | &SqlState::ADMIN_SHUTDOWN
+                | &SqlState::CRASH_SHUTDOWN
+                | &SqlState::CANNOT_CONNECT_NOW,
+            ) => Self::Transient(err.to_string()),
+            // Data exceptions and integrity constraint violations, which the values sent
+            // caused rather than the database.
+            Some(code) if code.code().starts_with("22") || code.code().starts_with("23") => {
+                Self::InvalidInput(
+                    err.as_db_error()
+                        .map_or_else(|| err.to_string(), |db| db.message().to_string()),
+                )
+            }
+            _ if err.is_closed() || err.source().is_some_and(|e| e.is::<std::io::Error>()) => {
+                Self::ConnectionLost(err.to_string())
+            }
+            _ => Self::Internal(err.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    /// Run with `cargo test -- --ignored` against a database on localhost.
+    #[rstest]
+    #[case::data_exception("SELECT 'x'::INTEGER;")]
+    #[case::division_by_zero("SELECT 1 / 0;")]
+    #[case::check_violation("UPDATE contas SET tipo = 'outro' WHERE cliente_id = 1;")]
+    #[case::unique_violation("INSERT INTO contas (cliente_id, tipo) SELECT cliente_id, tipo FROM contas WHERE cliente_id = 1;")]
+    #[tokio::test]
+    #[ignore = "needs the rinha database"]
+    async fn test_input_errors_are_invalid_input(#[case] statement: &str) {
+        let config = config("localhost", "test").unwrap();
+        let mut client = connect(&config).await.unwrap();
+        // Rolled back when dropped, the database is left as it was.
+        let transaction = client.transaction().await.unwrap();
+
+        let err = transaction.batch_execute(statement).await.unwrap_err();
+
+        assert!(
+            matches!(Error::from(err), Error::InvalidInput(_)),
+            "{statement}"
+        );
     }
 }
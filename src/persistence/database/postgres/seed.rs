@@ -0,0 +1,26 @@
+use super::repository::connect;
+use crate::{models::Client, persistence::Error};
+use bb8_postgres::tokio_postgres;
+
+/// Creates the given clients in one transaction, leaving any that already exist
+/// untouched, and returns how many were created.
+pub async fn seed(config: &tokio_postgres::Config, clients: &[Client]) -> Result<u64, Error> {
+    let client = connect(config).await?;
+
+    let ids: Vec<i16> = clients.iter().map(|client| client.id).collect();
+    let limits: Vec<i32> = clients.iter().map(|client| client.limite).collect();
+    let balances: Vec<i32> = clients.iter().map(|client| client.saldo).collect();
+
+    let created = client
+        .execute(
+            r#"
+                INSERT INTO clientes (id, limite, saldo)
+                SELECT * FROM unnest($1::SMALLINT[], $2::INTEGER[], $3::INTEGER[])
+                ON CONFLICT (id) DO NOTHING;
+            "#,
+            &[&ids, &limits, &balances],
+        )
+        .await?;
+
+    Ok(created)
+}
@@ -1,32 +1,52 @@
-use std::{collections::BTreeMap, ops::Deref};
+use std::{
+    collections::BTreeMap,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 use axum::async_trait;
 use bb8_postgres::{
-    bb8::{self, CustomizeConnection},
+    bb8::{self, CustomizeConnection, Pool},
     tokio_postgres, PostgresConnectionManager,
 };
 
-#[derive(Ord, PartialOrd, Eq, PartialEq)]
+pub type ConnectionPool = Pool<ConnectionManager<tokio_postgres::NoTls>>;
+
+#[derive(Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
 pub enum Statement {
     CreateDebitTransaction,
     CreateCreditTransaction,
     GetBalance,
+    GetBalances,
+    CurrentLsn,
+    ReplayedLsn,
 }
 
+/// Signatures of the database functions the statements below call, which the
+/// migrations create.
+pub const FUNCTIONS: &[&str] = &[
+    "debitar(SMALLINT, SMALLINT, VARCHAR, TEXT, INET, TEXT, TEXT, TIMESTAMP, TEXT)",
+    "creditar(SMALLINT, SMALLINT, VARCHAR, TEXT, INET, TEXT, TEXT, TIMESTAMP, TEXT)",
+    "reservado(SMALLINT)",
+];
+
 #[derive(Debug)]
 pub struct Cache;
 
 #[async_trait]
 impl CustomizeConnection<Connection, tokio_postgres::Error> for Cache {
     async fn on_acquire(&self, conn: &mut Connection) -> Result<(), tokio_postgres::Error> {
+        // A transaction sent again under its idempotency key isn't applied twice.
         conn.statements.insert(
             Statement::CreateDebitTransaction,
-            conn.prepare("SELECT * FROM debitar($1, $2, $3);").await?,
+            conn.prepare("SELECT * FROM debitar($1, $2, $3, $4, $5, $6, $7, param_chave => $8);")
+                .await?,
         );
 
         conn.statements.insert(
             Statement::CreateCreditTransaction,
-            conn.prepare("SELECT * FROM creditar($1, $2, $3);").await?,
+            conn.prepare("SELECT * FROM creditar($1, $2, $3, $4, $5, $6, $7, param_chave => $8);")
+                .await?,
         );
 
         conn.statements.insert(
@@ -35,6 +55,7 @@ impl CustomizeConnection<Connection, tokio_postgres::Error> for Cache {
                 r#"
                     SELECT
                         c.saldo,
+                        c.saldo - reservado(c.id) AS disponivel,
                         c.limite,
                         t.valor,
                         t.tipo,
@@ -64,6 +85,56 @@ impl CustomizeConnection<Connection, tokio_postgres::Error> for Cache {
             .await?,
         );
 
+        conn.statements.insert(
+            Statement::GetBalances,
+            conn.prepare(
+                r#"
+                    SELECT
+                        c.id AS cliente_id,
+                        c.saldo,
+                        c.saldo - reservado(c.id) AS disponivel,
+                        c.limite,
+                        t.valor,
+                        t.tipo,
+                        t.descricao,
+                        t.realizada_em
+                    FROM
+                        clientes c
+                    LEFT JOIN LATERAL (
+                        SELECT
+                            id,
+                            valor,
+                            tipo,
+                            descricao,
+                            realizada_em
+                        FROM
+                            transacoes
+                        WHERE
+                            cliente_id = c.id
+                        ORDER BY
+                            id DESC
+                        LIMIT 10
+                    ) AS t ON TRUE
+                    WHERE
+                        c.id = ANY($1)
+                    ORDER BY
+                        c.id,
+                        t.id DESC;
+                "#,
+            )
+            .await?,
+        );
+
+        conn.statements.insert(
+            Statement::CurrentLsn,
+            conn.prepare("SELECT pg_current_wal_insert_lsn();").await?,
+        );
+
+        conn.statements.insert(
+            Statement::ReplayedLsn,
+            conn.prepare("SELECT pg_last_wal_replay_lsn();").await?,
+        );
+
         Ok(())
     }
 }
@@ -71,6 +142,7 @@ impl CustomizeConnection<Connection, tokio_postgres::Error> for Cache {
 pub struct Connection {
     inner: tokio_postgres::Client,
     pub statements: BTreeMap<Statement, tokio_postgres::Statement>,
+    broken: AtomicBool,
 }
 
 impl Connection {
@@ -78,8 +150,14 @@ impl Connection {
         Self {
             inner,
             statements: Default::default(),
+            broken: AtomicBool::new(false),
         }
     }
+
+    /// Keeps the pool from handing this connection out again.
+    pub fn mark_broken(&self) {
+        self.broken.store(true, Ordering::Relaxed);
+    }
 }
 
 impl Deref for Connection {
@@ -90,6 +168,12 @@ impl Deref for Connection {
     }
 }
 
+impl DerefMut for Connection {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
 pub struct ConnectionManager<Tls>
 where
     Tls: tokio_postgres::tls::MakeTlsConnect<tokio_postgres::Socket>,
@@ -129,6 +213,6 @@ where
     }
 
     fn has_broken(&self, conn: &mut Self::Connection) -> bool {
-        self.inner.has_broken(&mut conn.inner)
+        conn.broken.load(Ordering::Relaxed) || self.inner.has_broken(&mut conn.inner)
     }
 }
@@ -0,0 +1,117 @@
+//! An [`Admin`] for tests, answering with what it was given and recording the
+//! calls made to it.
+
+use super::{Admin, Audit, ByteStream, Error};
+use crate::models::{AuditEntry, Discrepancy, ImportReport, Policy};
+use axum::async_trait;
+use futures_util::TryStreamExt;
+use std::sync::Mutex;
+
+/// Client 1 is the only one that exists.
+pub const CLIENT: i16 = 1;
+
+/// A call made to [`MockAdmin`], with its arguments.
+#[derive(Debug, PartialEq)]
+pub enum Call {
+    AuditLog(Option<i16>, i64),
+    Reconcile(bool),
+    /// The CSV read whole.
+    Import(Vec<u8>, Audit),
+    Policy(i16),
+    SetPolicy(i16, Policy),
+    ExpireIdempotencyKeys(i32),
+}
+
+/// Answers every call with the matching field, and [`Error::ClientNotFound`] for
+/// clients other than [`CLIENT`]. An empty CSV is refused as [`Error::InvalidInput`],
+/// as one without a header would be.
+#[derive(Default)]
+pub struct MockAdmin {
+    pub audit_log: Vec<AuditEntry>,
+    pub discrepancies: Vec<Discrepancy>,
+    pub import: Option<ImportReport>,
+    pub policy: Policy,
+    /// Idempotency keys past their day, forgotten as they are expired.
+    pub idempotency_keys: Mutex<i32>,
+    /// Calls not yet taken by [`MockAdmin::calls`].
+    pub recorded: Mutex<Vec<Call>>,
+}
+
+impl MockAdmin {
+    /// The calls made so far, oldest first, taken out of the mock.
+    pub fn calls(&self) -> Vec<Call> {
+        std::mem::take(&mut self.recorded.lock().unwrap())
+    }
+
+    fn record(&self, call: Call) {
+        self.recorded.lock().unwrap().push(call);
+    }
+}
+
+#[async_trait]
+impl Admin for MockAdmin {
+    async fn audit_log(
+        &self,
+        client_id: Option<i16>,
+        limit: i64,
+    ) -> Result<Vec<AuditEntry>, Error> {
+        self.record(Call::AuditLog(client_id, limit));
+
+        Ok(self.audit_log.clone())
+    }
+
+    async fn reconcile(&self, correct: bool) -> Result<Vec<Discrepancy>, Error> {
+        self.record(Call::Reconcile(correct));
+
+        Ok(self.discrepancies.clone())
+    }
+
+    async fn import(&self, csv: ByteStream, audit: Audit) -> Result<ImportReport, Error> {
+        let csv = csv
+            .try_fold(Vec::new(), |mut csv, chunk| async move {
+                csv.extend_from_slice(&chunk);
+                Ok(csv)
+            })
+            .await?;
+
+        if csv.is_empty() {
+            return Err(Error::InvalidInput("missing header".into()));
+        }
+
+        self.record(Call::Import(csv, audit));
+
+        Ok(self.import.clone().unwrap_or(ImportReport {
+            aplicadas: 0,
+            rejeitadas: Vec::new(),
+        }))
+    }
+
+    async fn policy(&self, client_id: i16) -> Result<Policy, Error> {
+        self.record(Call::Policy(client_id));
+
+        match client_id {
+            CLIENT => Ok(self.policy.clone()),
+            _ => Err(Error::ClientNotFound),
+        }
+    }
+
+    async fn set_policy(&self, client_id: i16, policy: &Policy) -> Result<(), Error> {
+        self.record(Call::SetPolicy(client_id, policy.clone()));
+
+        match client_id {
+            CLIENT => Ok(()),
+            _ => Err(Error::ClientNotFound),
+        }
+    }
+
+    async fn expire_idempotency_keys(&self, limit: i32) -> Result<i32, Error> {
+        self.record(Call::ExpireIdempotencyKeys(limit));
+
+        let mut left = self.idempotency_keys.lock().unwrap();
+        let expired = (*left).min(limit);
+
+        *left -= expired;
+
+        Ok(expired)
+    }
+}
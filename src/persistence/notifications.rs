@@ -0,0 +1,101 @@
+use serde::Deserialize;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+/// Postgres channel `debitar`/`creditar` notify on after a successful transaction.
+pub const CHANNEL: &str = "transacoes";
+
+const CAPACITY: usize = 1024;
+
+/// A committed transaction, as published by `notificar` in `sql/migrations/0002_functions.sql`.
+#[derive(Clone, Deserialize)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub struct Notification {
+    pub cliente_id: i16,
+    pub valor: i16,
+    pub tipo: String,
+    pub descricao: String,
+    pub saldo: i32,
+    pub limite: i32,
+    /// `application_name` of the connection that ran the transaction.
+    pub origem: String,
+}
+
+/// Fans notifications received from Postgres out to every subscriber in the process.
+#[derive(Clone)]
+pub struct Notifications {
+    sender: broadcast::Sender<Notification>,
+    instance: Arc<str>,
+}
+
+impl Notifications {
+    pub fn new(instance: &str) -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+
+        Self {
+            sender,
+            instance: instance.into(),
+        }
+    }
+
+    pub fn publish(&self, notification: Notification) {
+        // No subscribers is not an error, the notification is simply dropped.
+        let _ = self.sender.send(notification);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Notification> {
+        self.sender.subscribe()
+    }
+
+    /// Whether the notification was caused by a transaction of this instance.
+    pub fn is_local(&self, notification: &Notification) -> bool {
+        *self.instance == notification.origem
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    fn notification(origem: &str) -> Notification {
+        Notification {
+            cliente_id: 1,
+            valor: 10,
+            tipo: "d".into(),
+            descricao: "bar".into(),
+            saldo: -10,
+            limite: 1000,
+            origem: origem.into(),
+        }
+    }
+
+    #[test]
+    fn test_deserialization() {
+        let payload = r#"{"cliente_id" : 1, "valor" : 10, "tipo" : "d", "descricao" : "bar", "saldo" : -10, "limite" : 1000, "origem" : "api01"}"#;
+
+        assert_eq!(
+            serde_json::from_str::<Notification>(payload).unwrap(),
+            notification("api01")
+        );
+    }
+
+    #[rstest]
+    #[case::local("api01", true)]
+    #[case::remote("api02", false)]
+    fn test_is_local(#[case] origem: &str, #[case] expected: bool) {
+        let notifications = Notifications::new("api01");
+
+        assert_eq!(notifications.is_local(&notification(origem)), expected);
+    }
+
+    #[tokio::test]
+    async fn test_publish() {
+        let notifications = Notifications::new("api01");
+        let mut receiver = notifications.subscribe();
+
+        notifications.publish(notification("api02"));
+
+        assert_eq!(receiver.recv().await.unwrap(), notification("api02"));
+    }
+}
@@ -1,12 +1,95 @@
-use crate::api::routes::{StatementResponse, TransactionRequest, TransactionResponse};
-use axum::async_trait;
+use crate::{
+    api::routes::{StatementResponse, TransactionRequest, TransactionResponse},
+    metrics::Metric,
+    models::{
+        AuditEntry, Discrepancy, Entry, Hold, ImportReport, NewHold, NewStandingOrder, Policy, Run,
+        StandingOrder, Transaction,
+    },
+};
+use axum::{async_trait, body::Bytes};
+use futures_util::{future, stream::BoxStream, StreamExt, TryStreamExt};
+use std::{collections::HashMap, net::IpAddr, time::SystemTime};
 
-#[derive(Debug)]
+/// Position in the primary's WAL that a read has to observe, handed out after
+/// writes so clients can read their own writes from a replica.
+pub use postgres_types::PgLsn as ReadToken;
+
+#[derive(Clone, Debug)]
 pub enum Error {
+    /// No connection could be obtained, nothing was sent to the database.
     Connection,
+    /// The database rolled the statement back and it can be run again.
+    Transient(String),
+    /// The connection broke mid-statement, whether it was applied is unknown.
+    ConnectionLost(String),
+    /// The database is considered down and wasn't called.
+    Unavailable,
     Internal(String),
+    /// The input was rejected as a whole, nothing was applied.
+    InvalidInput(String),
     ClientNotFound,
     BalanceConstraintViolation,
+    /// The client's policy refused the transaction, nothing was applied.
+    PolicyViolation(Rule),
+    /// The hold was already captured, voided or expired, nothing was applied.
+    HoldClosed,
+}
+
+/// A rule of a client's [`Policy`] a transaction can break.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rule {
+    AboveMaximum,
+    BelowMinimum,
+    DescriptionRefused,
+    CreditRefused,
+}
+
+impl Rule {
+    /// How the rule is named to clients, and in import reports.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::AboveMaximum => "valor_acima_do_maximo",
+            Self::BelowMinimum => "valor_abaixo_do_minimo",
+            Self::DescriptionRefused => "descricao_recusada",
+            Self::CreditRefused => "credito_recusado",
+        }
+    }
+}
+
+/// Who attempted a transaction and what they sent, recorded with its outcome.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(test, derive(PartialEq))]
+pub struct Audit {
+    pub request_id: Option<String>,
+    /// Under which the transaction is applied once, sending it again answering with
+    /// the outcome it had. Not audited.
+    pub idempotency_key: Option<String>,
+    pub ip: Option<IpAddr>,
+    pub principal: Option<String>,
+    pub payload: String,
+}
+
+/// Half-open time range `[from, to)`, unbounded on the sides left out.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Period {
+    pub from: Option<SystemTime>,
+    pub to: Option<SystemTime>,
+}
+
+/// Transactions read one at a time, oldest first.
+pub type EntryStream = BoxStream<'static, Result<Entry, Error>>;
+
+/// Raw bytes read a chunk at a time, as uploaded.
+pub type ByteStream = BoxStream<'static, Result<Bytes, Error>>;
+
+/// A client's transactions in a [`Period`], with the balances around them.
+pub struct Export {
+    pub limite: i32,
+    /// Balance before the period's first transaction.
+    pub saldo_inicial: i64,
+    /// Balance after the period's last transaction.
+    pub saldo_final: i64,
+    pub entries: EntryStream,
 }
 
 #[async_trait]
@@ -15,7 +98,189 @@ pub trait Repository: Send + Sync {
         &self,
         client_id: &i16,
         data: &TransactionRequest,
+        audit: &Audit,
+    ) -> Result<TransactionResponse, Error>;
+
+    /// Records in the audit log a transaction refused as invalid before it was
+    /// attempted. The default records nothing.
+    async fn reject(&self, _client_id: &i16, _audit: &Audit) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Fetches the statement, from a state at least as recent as `token` if given.
+    async fn get_balance(
+        &self,
+        client_id: &i16,
+        token: Option<&ReadToken>,
+    ) -> Result<StatementResponse, Error>;
+
+    /// Statements of several clients, leaving out those that don't exist. Meant to
+    /// fetch them together, the default asks for one at a time.
+    async fn get_balances(
+        &self,
+        client_ids: &[i16],
+    ) -> Result<HashMap<i16, StatementResponse>, Error> {
+        let mut statements = HashMap::with_capacity(client_ids.len());
+
+        for client_id in client_ids {
+            match self.get_balance(client_id, None).await {
+                Ok(statement) => {
+                    statements.insert(*client_id, statement);
+                }
+                Err(Error::ClientNotFound) => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(statements)
+    }
+
+    /// Every transaction of the client in `period`, streamed as the database
+    /// returns them rather than collected first.
+    async fn export(&self, client_id: &i16, period: Period) -> Result<Export, Error>;
+
+    /// The first `limit` transactions of the client in `period`, oldest first, only
+    /// those of kind `tipo` when given. Meant to be a single bounded query, the
+    /// default reads them off an export.
+    async fn transactions(
+        &self,
+        client_id: &i16,
+        period: Period,
+        tipo: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<Transaction>, Error> {
+        self.export(client_id, period)
+            .await?
+            .entries
+            .map_ok(|entry| entry.transaction)
+            .try_filter(|transaction| {
+                future::ready(tipo.is_none_or(|tipo| transaction.tipo == tipo))
+            })
+            .take(limit.max(0) as usize)
+            .try_collect()
+            .await
+    }
+
+    /// Operational metrics of this repository and of any repository it wraps.
+    fn metrics(&self) -> Vec<Metric> {
+        Vec::new()
+    }
+}
+
+/// Operations for operators rather than clients, served straight from the database.
+#[async_trait]
+pub trait Admin: Send + Sync {
+    /// The latest `limit` audit entries, newest first, of one client or of all.
+    async fn audit_log(&self, client_id: Option<i16>, limit: i64)
+        -> Result<Vec<AuditEntry>, Error>;
+
+    /// Clients whose balance disagrees with their history or went past their limit.
+    /// With `correct`, balances are brought back in line with the history first.
+    async fn reconcile(&self, correct: bool) -> Result<Vec<Discrepancy>, Error>;
+
+    /// Applies the transactions of a CSV with a header and the columns `cliente_id`,
+    /// `tipo`, `valor`, `descricao` and `realizada_em`, in order. The file is staged
+    /// as a whole, then applied in chunks committed one at a time, so a failure
+    /// partway leaves the chunks before it applied. Rows the API would refuse are
+    /// skipped and reported. Each applied row is audited under `audit`, with the
+    /// row as its payload.
+    async fn import(&self, csv: ByteStream, audit: Audit) -> Result<ImportReport, Error>;
+
+    /// The client's policy, the default one when none was set.
+    async fn policy(&self, client_id: i16) -> Result<Policy, Error>;
+
+    /// Replaces the client's policy, applying to the transactions that follow.
+    async fn set_policy(&self, client_id: i16, policy: &Policy) -> Result<(), Error>;
+
+    /// Forgets up to `limit` idempotency keys past their day, returning how many.
+    async fn expire_idempotency_keys(&self, limit: i32) -> Result<i32, Error>;
+}
+
+/// A run of a standing order, claimed for the instance to make.
+#[cfg_attr(test, derive(Clone, Debug))]
+pub struct Claim {
+    pub run_id: i64,
+    pub agendada_para: SystemTime,
+    pub order: StandingOrder,
+}
+
+/// Standing orders and the history of their runs, served straight from the database.
+#[async_trait]
+pub trait StandingOrders: Send + Sync {
+    /// Registers a standing order, whose first run has to come before its end.
+    async fn create_order(
+        &self,
+        client_id: i16,
+        order: &NewStandingOrder,
+    ) -> Result<StandingOrder, Error>;
+
+    /// The client's standing orders, cancelled ones included, oldest first.
+    async fn orders(&self, client_id: i16) -> Result<Vec<StandingOrder>, Error>;
+
+    /// Stops the order from running again, a run already claimed still goes ahead.
+    async fn cancel_order(&self, client_id: i16, order_id: i32) -> Result<StandingOrder, Error>;
+
+    /// The latest `limit` runs of the order, newest first.
+    async fn runs(&self, client_id: i16, order_id: i32, limit: i64) -> Result<Vec<Run>, Error>;
+
+    /// Claims up to `limit` runs due by `now` for `instance`, moving their orders
+    /// on to the next run. A run is only ever claimed once, whoever asks.
+    async fn claim_due(
+        &self,
+        now: SystemTime,
+        instance: &str,
+        limit: i64,
+    ) -> Result<Vec<Claim>, Error>;
+
+    /// Claims again for `instance` up to `limit` runs left without an outcome that
+    /// were last claimed by `stalled_since`, so that they are made once more under
+    /// the idempotency key they had. Runs started over a day ago aren't, their key
+    /// may be gone.
+    async fn claim_stalled(
+        &self,
+        stalled_since: SystemTime,
+        instance: &str,
+        limit: i64,
+    ) -> Result<Vec<Claim>, Error>;
+
+    /// Records how a claimed run went, as described for [`Run::resultado`].
+    async fn record_run(
+        &self,
+        run_id: i64,
+        resultado: &str,
+        saldo: Option<i32>,
+    ) -> Result<(), Error>;
+}
+
+/// Picks the one instance among several that runs what must only run once.
+#[async_trait]
+pub trait Election: Send {
+    /// Whether this instance leads, running for it when no instance does.
+    async fn lead(&mut self) -> bool;
+}
+
+/// Holds on clients' funds, served straight from the database.
+#[async_trait]
+pub trait Holds: Send + Sync {
+    /// Sets funds aside, refused as a debit of the same amount would be.
+    async fn create_hold(&self, client_id: i16, hold: &NewHold) -> Result<Hold, Error>;
+
+    /// The client's pending holds, oldest first.
+    async fn holds(&self, client_id: i16) -> Result<Vec<Hold>, Error>;
+
+    /// Debits `valor` of a pending hold, all of it when `None`, and releases the rest.
+    /// The debit goes through the same checks and audit log as any other.
+    async fn capture_hold(
+        &self,
+        client_id: i16,
+        hold_id: i32,
+        valor: Option<i16>,
+        audit: &Audit,
     ) -> Result<TransactionResponse, Error>;
 
-    async fn get_balance(&self, client_id: &i16) -> Result<StatementResponse, Error>;
+    /// Releases a pending hold.
+    async fn void_hold(&self, client_id: i16, hold_id: i32) -> Result<Hold, Error>;
+
+    /// Marks up to `limit` holds past `expira_em` as expired, returning how many.
+    async fn expire_holds(&self, limit: i32) -> Result<i32, Error>;
 }
@@ -0,0 +1,341 @@
+use crate::{
+    api::routes::{StatementResponse, TransactionRequest, TransactionResponse},
+    metrics::Metric,
+    models::Transaction,
+    persistence::{Audit, Error, Export, Period, ReadToken, Repository as RepositoryTrait},
+    telemetry,
+};
+use axum::async_trait;
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+const BASE_DELAY: Duration = Duration::from_millis(10);
+
+/// Retries transient failures of another repository with jittered exponential
+/// backoff, for as long as the next attempt still fits in `budget`.
+///
+/// Reads are retried on any transient failure. Writes without an idempotency key
+/// only when the failure guarantees the transaction wasn't applied: a connection
+/// that broke mid-statement may have committed it, and retrying could apply it
+/// twice. Those with one are retried as reads are, the database answering a key it
+/// already applied with the outcome it had.
+pub struct Repository {
+    inner: Arc<dyn RepositoryTrait>,
+    budget: Duration,
+    retries: AtomicU64,
+}
+
+impl Repository {
+    pub fn new(inner: Arc<dyn RepositoryTrait>, budget: Duration) -> Self {
+        Self {
+            inner,
+            budget,
+            retries: AtomicU64::new(0),
+        }
+    }
+
+    async fn retry<T, F, Fut>(
+        &self,
+        retryable: fn(&Error) -> bool,
+        operation: F,
+    ) -> Result<T, Error>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let started = Instant::now();
+        let mut delay = BASE_DELAY;
+
+        loop {
+            match operation().await {
+                Err(err) if retryable(&err) => {
+                    let backoff = jitter(delay);
+
+                    if started.elapsed() + backoff >= self.budget {
+                        return Err(err);
+                    }
+
+                    telemetry::debug!("Retrying in {:?} after: {:?}", backoff, err);
+
+                    self.retries.fetch_add(1, Ordering::Relaxed);
+                    tokio::time::sleep(backoff).await;
+                    delay *= 2;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+fn retryable_read(err: &Error) -> bool {
+    matches!(
+        err,
+        Error::Connection | Error::Transient(_) | Error::ConnectionLost(_)
+    )
+}
+
+fn retryable_write(err: &Error) -> bool {
+    matches!(err, Error::Connection | Error::Transient(_))
+}
+
+/// A random duration up to `delay` ("full jitter"), so clients that failed
+/// together don't retry together.
+fn jitter(delay: Duration) -> Duration {
+    // Every `RandomState` is seeded differently, which is all the randomness needed here.
+    let random = RandomState::new().build_hasher().finish();
+
+    delay.mul_f64(random as f64 / u64::MAX as f64)
+}
+
+#[async_trait]
+impl RepositoryTrait for Repository {
+    async fn create_transaction(
+        &self,
+        client_id: &i16,
+        data: &TransactionRequest,
+        audit: &Audit,
+    ) -> Result<TransactionResponse, Error> {
+        let retryable = match audit.idempotency_key {
+            Some(_) => retryable_read,
+            None => retryable_write,
+        };
+
+        self.retry(retryable, || {
+            self.inner.create_transaction(client_id, data, audit)
+        })
+        .await
+    }
+
+    /// Not retried, a rejection only needs recording where that's cheap.
+    async fn reject(&self, client_id: &i16, audit: &Audit) -> Result<(), Error> {
+        self.inner.reject(client_id, audit).await
+    }
+
+    async fn get_balance(
+        &self,
+        client_id: &i16,
+        token: Option<&ReadToken>,
+    ) -> Result<StatementResponse, Error> {
+        self.retry(retryable_read, || self.inner.get_balance(client_id, token))
+            .await
+    }
+
+    async fn get_balances(
+        &self,
+        client_ids: &[i16],
+    ) -> Result<HashMap<i16, StatementResponse>, Error> {
+        self.retry(retryable_read, || self.inner.get_balances(client_ids))
+            .await
+    }
+
+    /// Only opening the stream is retried, rows already sent can't be taken back.
+    async fn export(&self, client_id: &i16, period: Period) -> Result<Export, Error> {
+        self.retry(retryable_read, || self.inner.export(client_id, period))
+            .await
+    }
+
+    async fn transactions(
+        &self,
+        client_id: &i16,
+        period: Period,
+        tipo: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<Transaction>, Error> {
+        self.retry(retryable_read, || {
+            self.inner.transactions(client_id, period, tipo, limit)
+        })
+        .await
+    }
+
+    fn metrics(&self) -> Vec<Metric> {
+        let mut metrics = self.inner.metrics();
+
+        metrics.push(Metric::counter(
+            "rinha_retries_total",
+            "Repository calls retried after a transient failure.",
+            self.retries.load(Ordering::Relaxed),
+        ));
+
+        metrics
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::Balance;
+    use rstest::rstest;
+    use std::{sync::Mutex, time::SystemTime};
+
+    struct MockRepository {
+        failures: Mutex<Vec<Error>>,
+        calls: AtomicU64,
+    }
+
+    impl MockRepository {
+        fn failing_with(failures: Vec<Error>) -> Self {
+            Self {
+                failures: Mutex::new(failures),
+                calls: AtomicU64::new(0),
+            }
+        }
+
+        fn next(&self) -> Result<(), Error> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+
+            match self.failures.lock().unwrap().pop() {
+                Some(err) => Err(err),
+                None => Ok(()),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl RepositoryTrait for MockRepository {
+        async fn get_balance(
+            &self,
+            _client_id: &i16,
+            _token: Option<&ReadToken>,
+        ) -> Result<StatementResponse, Error> {
+            self.next().map(|_| StatementResponse {
+                saldo: Balance {
+                    total: 0,
+                    disponivel: 0,
+                    limite: 1000,
+                    data_extrato: SystemTime::UNIX_EPOCH,
+                },
+                ultimas_transacoes: Vec::new(),
+            })
+        }
+
+        async fn create_transaction(
+            &self,
+            _client_id: &i16,
+            _data: &TransactionRequest,
+            _audit: &Audit,
+        ) -> Result<TransactionResponse, Error> {
+            self.next().map(|_| TransactionResponse {
+                limite: 1000,
+                saldo: -10,
+                token: None,
+            })
+        }
+
+        async fn export(&self, _client_id: &i16, _period: Period) -> Result<Export, Error> {
+            unimplemented!()
+        }
+    }
+
+    fn retry(failures: Vec<Error>, budget: Duration) -> (Arc<MockRepository>, Repository) {
+        let inner = Arc::new(MockRepository::failing_with(failures));
+
+        (inner.clone(), Repository::new(inner, budget))
+    }
+
+    fn debit() -> TransactionRequest {
+        TransactionRequest {
+            valor: 10,
+            tipo: "d".into(),
+            descricao: "bar".into(),
+        }
+    }
+
+    #[rstest]
+    #[case::connection(Error::Connection, None, None, true)]
+    #[case::transient(Error::Transient("deadlock".into()), None, None, true)]
+    #[case::connection_lost(Error::ConnectionLost("closed".into()), None, None, false)]
+    #[case::connection_lost_with_request_id(Error::ConnectionLost("closed".into()), Some("abc"), None, false)]
+    #[case::connection_lost_with_idempotency_key(Error::ConnectionLost("closed".into()), None, Some("abc"), true)]
+    #[case::internal(Error::Internal("internal".into()), None, Some("abc"), false)]
+    #[case::client_not_found(Error::ClientNotFound, None, None, false)]
+    #[case::balance_constraint_violation(
+        Error::BalanceConstraintViolation,
+        None,
+        Some("abc"),
+        false
+    )]
+    #[tokio::test]
+    async fn test_create_transaction(
+        #[case] failure: Error,
+        #[case] request_id: Option<&str>,
+        #[case] idempotency_key: Option<&str>,
+        #[case] retried: bool,
+    ) {
+        let (inner, retry) = retry(vec![failure], Duration::from_secs(1));
+
+        let audit = Audit {
+            request_id: request_id.map(Into::into),
+            idempotency_key: idempotency_key.map(Into::into),
+            ..Default::default()
+        };
+
+        let result = retry.create_transaction(&1, &debit(), &audit).await;
+
+        assert_eq!(result.is_ok(), retried);
+        assert_eq!(
+            inner.calls.load(Ordering::Relaxed),
+            if retried { 2 } else { 1 }
+        );
+    }
+
+    #[rstest]
+    #[case::connection(Error::Connection, true)]
+    #[case::transient(Error::Transient("deadlock".into()), true)]
+    #[case::connection_lost(Error::ConnectionLost("closed".into()), true)]
+    #[case::internal(Error::Internal("internal".into()), false)]
+    #[case::client_not_found(Error::ClientNotFound, false)]
+    #[tokio::test]
+    async fn test_get_balance(#[case] failure: Error, #[case] retried: bool) {
+        let (inner, retry) = retry(vec![failure], Duration::from_secs(1));
+
+        let result = retry.get_balance(&1, None).await;
+
+        assert_eq!(result.is_ok(), retried);
+        assert_eq!(
+            inner.calls.load(Ordering::Relaxed),
+            if retried { 2 } else { 1 }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_budget_exhausted() {
+        let (inner, retry) = retry(vec![Error::Connection; 3], Duration::ZERO);
+
+        assert!(matches!(
+            retry.get_balance(&1, None).await,
+            Err(Error::Connection)
+        ));
+        assert_eq!(inner.calls.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let (_, retry) = retry(vec![Error::Connection; 2], Duration::from_secs(1));
+
+        retry.get_balance(&1, None).await.unwrap();
+
+        assert_eq!(
+            retry.metrics(),
+            vec![Metric::counter(
+                "rinha_retries_total",
+                "Repository calls retried after a transient failure.",
+                2
+            )]
+        );
+    }
+
+    #[test]
+    fn test_jitter() {
+        for _ in 0..100 {
+            assert!(jitter(BASE_DELAY) <= BASE_DELAY);
+        }
+    }
+}
@@ -0,0 +1,342 @@
+use crate::dates::Date;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    fmt,
+    ops::RangeInclusive,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+const MINUTE: Duration = Duration::from_secs(60);
+
+/// How many days ahead a cron expression is searched, past which it's taken as
+/// never matching again, as `0 0 30 2 *` does.
+const CRON_HORIZON_DAYS: u32 = 5 * 366;
+
+/// When a standing order runs, in UTC: every day, week or month counting from its
+/// start, or at the minutes a cron expression matches.
+///
+/// Written `diaria`, `semanal`, `mensal`, or as the cron expression.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Schedule {
+    Daily,
+    Weekly,
+    /// On the start's day of the month, or the month's last day when it is shorter.
+    Monthly,
+    Cron(Cron),
+}
+
+impl Schedule {
+    /// The first run at or after `start` that is later than `after`, `None` when
+    /// there is none.
+    pub fn next(&self, start: SystemTime, after: Option<SystemTime>) -> Option<SystemTime> {
+        let after = after.filter(|after| *after >= start);
+
+        match self {
+            Self::Daily => Some(every(DAY, start, after)),
+            Self::Weekly => Some(every(7 * DAY, start, after)),
+            Self::Monthly => Some(monthly(start, after)),
+            Self::Cron(cron) => {
+                let from = match after {
+                    Some(after) => floor_minute(after) + MINUTE,
+                    None => ceil_minute(start),
+                };
+
+                cron.first_from(from)
+            }
+        }
+    }
+}
+
+fn every(period: Duration, start: SystemTime, after: Option<SystemTime>) -> SystemTime {
+    match after {
+        Some(after) => {
+            let elapsed = after.duration_since(start).unwrap_or_default();
+            let periods = elapsed.as_secs() / period.as_secs() + 1;
+
+            start + Duration::from_secs(period.as_secs() * periods)
+        }
+        None => start,
+    }
+}
+
+fn monthly(start: SystemTime, after: Option<SystemTime>) -> SystemTime {
+    let Some(after) = after else {
+        return start;
+    };
+
+    let first = Date::of(start);
+    let time_of_day = start.duration_since(first.start()).unwrap_or_default();
+    let last = Date::of(after);
+    // Months from the start to `after`'s month, whose run may still be ahead of it.
+    let mut months =
+        ((last.year - first.year) * 12 + last.month as i32 - first.month as i32).max(0) as u32;
+
+    loop {
+        let run = first.add_months(months).start() + time_of_day;
+
+        if run > after {
+            return run;
+        }
+
+        months += 1;
+    }
+}
+
+fn since_epoch(time: SystemTime) -> Duration {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+fn floor_minute(time: SystemTime) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(since_epoch(time).as_secs() / 60 * 60)
+}
+
+fn ceil_minute(time: SystemTime) -> SystemTime {
+    let floor = floor_minute(time);
+
+    if floor == time {
+        floor
+    } else {
+        floor + MINUTE
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "diaria" => Ok(Self::Daily),
+            "semanal" => Ok(Self::Weekly),
+            "mensal" => Ok(Self::Monthly),
+            _ => s.parse().map(Self::Cron),
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Daily => f.write_str("diaria"),
+            Self::Weekly => f.write_str("semanal"),
+            Self::Monthly => f.write_str("mensal"),
+            Self::Cron(cron) => f.write_str(&cron.expression),
+        }
+    }
+}
+
+impl Serialize for Schedule {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Schedule {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// A five-field cron expression: minute, hour, day of the month, month and day of
+/// the week, from 0 for Sunday (7 too). Fields take `*`, numbers, ranges `a-b`,
+/// steps `/n` of either, and lists of those separated by commas.
+///
+/// As in Vixie cron, when both days are restricted a day matching either runs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cron {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Cron {
+    /// The first minute at or after `from`, itself on a minute, the expression matches.
+    fn first_from(&self, from: SystemTime) -> Option<SystemTime> {
+        let mut date = Date::of(from);
+        let mut minute_of_day = (since_epoch(from).as_secs() % DAY.as_secs() / 60) as u32;
+
+        for _ in 0..CRON_HORIZON_DAYS {
+            if self.matches(date) {
+                let minute = (minute_of_day..24 * 60)
+                    .find(|minute| bit(self.hours, minute / 60) && bit(self.minutes, minute % 60));
+
+                if let Some(minute) = minute {
+                    return Some(date.start() + MINUTE * minute);
+                }
+            }
+
+            date = date.next();
+            minute_of_day = 0;
+        }
+
+        None
+    }
+
+    fn matches(&self, date: Date) -> bool {
+        let day = bit(self.days, date.day);
+        // 7 is folded into 0 when parsing.
+        let weekday = bit(self.weekdays, date.weekday());
+
+        bit(self.months, date.month)
+            && match (self.any_day, self.any_weekday) {
+                (false, false) => day || weekday,
+                _ => day && weekday,
+            }
+    }
+}
+
+fn bit(set: u64, n: u32) -> bool {
+    set & (1 << n) != 0
+}
+
+impl FromStr for Cron {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields = s.split_whitespace().collect::<Vec<_>>();
+
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!(
+                "invalid schedule, expected diaria, semanal, mensal or five cron fields: {}",
+                s
+            ));
+        };
+
+        let mut weekdays_set = field(weekdays, 0..=7)?;
+
+        if bit(weekdays_set, 7) {
+            weekdays_set = weekdays_set & !(1 << 7) | 1;
+        }
+
+        Ok(Self {
+            expression: fields.join(" "),
+            minutes: field(minutes, 0..=59)?,
+            hours: field(hours, 0..=23)?,
+            days: field(days, 1..=31)?,
+            months: field(months, 1..=12)?,
+            weekdays: weekdays_set,
+            any_day: days.starts_with('*'),
+            any_weekday: weekdays.starts_with('*'),
+        })
+    }
+}
+
+/// The values a field matches, as bits.
+fn field(s: &str, range: RangeInclusive<u32>) -> Result<u64, String> {
+    let invalid = || format!("invalid cron field, expected values in {:?}: {}", range, s);
+    let number = |s: &str| {
+        s.parse::<u32>()
+            .ok()
+            .filter(|n| range.contains(n))
+            .ok_or_else(invalid)
+    };
+
+    let mut set = 0;
+
+    for item in s.split(',') {
+        let (values, step) = match item.split_once('/') {
+            Some((values, step)) => (
+                values,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(invalid)?,
+            ),
+            None => (item, 1),
+        };
+
+        let (from, to) = match values.split_once('-') {
+            _ if values == "*" => (*range.start(), *range.end()),
+            Some((from, to)) => (number(from)?, number(to)?),
+            // `n/step` runs from n to the end of the range.
+            None if step > 1 => (number(values)?, *range.end()),
+            None => (number(values)?, number(values)?),
+        };
+
+        if from > to {
+            return Err(invalid());
+        }
+
+        for n in (from..=to).step_by(step as usize) {
+            set |= 1 << n;
+        }
+    }
+
+    Ok(set)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    fn at(date: &str, hour: u64, minute: u64) -> SystemTime {
+        date.parse::<Date>().unwrap().start() + Duration::from_secs(hour * 3600 + minute * 60)
+    }
+
+    #[rstest]
+    #[case::daily("diaria")]
+    #[case::weekly("semanal")]
+    #[case::monthly("mensal")]
+    #[case::cron("*/15 9-17 * * 1-5")]
+    fn test_round_trip(#[case] s: &str) {
+        assert_eq!(s.parse::<Schedule>().unwrap().to_string(), s);
+    }
+
+    #[rstest]
+    #[case::too_few_fields("* * * *")]
+    #[case::minute_out_of_range("60 * * * *")]
+    #[case::day_zero("0 0 0 * *")]
+    #[case::reversed_range("0 17-9 * * *")]
+    #[case::zero_step("*/0 * * * *")]
+    #[case::name("0 0 * * MON")]
+    fn test_parse_invalid(#[case] s: &str) {
+        assert!(s.parse::<Schedule>().is_err());
+    }
+
+    #[rstest]
+    #[case::daily_first("diaria", None, at("2024-01-31", 9, 0))]
+    #[case::daily_before_start("diaria", Some(at("2024-01-01", 0, 0)), at("2024-01-31", 9, 0))]
+    #[case::daily_on_run("diaria", Some(at("2024-01-31", 9, 0)), at("2024-02-01", 9, 0))]
+    #[case::daily_skipping("diaria", Some(at("2024-02-03", 12, 0)), at("2024-02-04", 9, 0))]
+    #[case::weekly("semanal", Some(at("2024-02-01", 0, 0)), at("2024-02-07", 9, 0))]
+    #[case::monthly_short_month("mensal", Some(at("2024-01-31", 9, 0)), at("2024-02-29", 9, 0))]
+    #[case::monthly_back_to_day("mensal", Some(at("2024-02-29", 9, 0)), at("2024-03-31", 9, 0))]
+    #[case::monthly_next_year("mensal", Some(at("2024-12-31", 10, 0)), at("2025-01-31", 9, 0))]
+    #[case::cron_first("30 8 * * *", None, at("2024-02-01", 8, 30))]
+    #[case::cron_same_day(
+        "*/20 * * * *",
+        Some(at("2024-02-01", 10, 5)),
+        at("2024-02-01", 10, 20)
+    )]
+    #[case::cron_on_run("0 * * * *", Some(at("2024-02-01", 10, 0)), at("2024-02-01", 11, 0))]
+    #[case::cron_weekday("0 9 * * 1", Some(at("2024-02-01", 0, 0)), at("2024-02-05", 9, 0))]
+    #[case::cron_sunday_as_seven("0 9 * * 7", Some(at("2024-02-01", 0, 0)), at("2024-02-04", 9, 0))]
+    #[case::cron_either_day("0 0 15 * 5", Some(at("2024-02-01", 0, 0)), at("2024-02-02", 0, 0))]
+    #[case::cron_leap_day("0 0 29 2 *", Some(at("2024-03-01", 0, 0)), at("2028-02-29", 0, 0))]
+    fn test_next(
+        #[case] schedule: &str,
+        #[case] after: Option<SystemTime>,
+        #[case] expected: SystemTime,
+    ) {
+        let schedule: Schedule = schedule.parse().unwrap();
+
+        assert_eq!(schedule.next(at("2024-01-31", 9, 0), after), Some(expected));
+    }
+
+    #[test]
+    fn test_next_never() {
+        let schedule: Schedule = "0 0 30 2 *".parse().unwrap();
+
+        assert_eq!(schedule.next(at("2024-01-31", 9, 0), None), None);
+    }
+}